@@ -3,13 +3,21 @@ use std::process::Command;
 fn main() {
     // 获取 git 版本信息
     let output = Command::new("git")
-        .args(&["describe", "--tags", "--always", "--dirty"])
+        .args(["describe", "--tags", "--always", "--dirty"])
         .output()
         .unwrap();
     let git_hash = String::from_utf8(output.stdout).unwrap();
     
     // 将版本信息传递给编译器
     println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+
+    // 获取构建时间 (UTC)
+    let build_time = Command::new("date")
+        .args(["-u", "+%Y-%m-%dT%H:%M:%SZ"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=BUILD_TIME={}", build_time);
     
     // 确保 ffmpeg 可用
     let ffmpeg_check = Command::new("ffmpeg")
@@ -0,0 +1,178 @@
+use serde::Serialize;
+
+use crate::schedule::types::TranscribeResult;
+
+/// 两次转写结果的差异报告，`a` 作为参考结果
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ComparisonReport {
+    /// 词错误率，中日文按字计算
+    pub word_error_rate: f64,
+    pub reference_words: usize,
+    pub substitutions: usize,
+    pub deletions: usize,
+    pub insertions: usize,
+    /// `b` 的分段数减去 `a` 的分段数
+    pub segment_count_delta: i64,
+    /// 按顺序配对的分段开始、结束时间的平均偏差（毫秒）
+    pub mean_timing_drift_ms: f64,
+    pub max_timing_drift_ms: f64,
+}
+
+/// 比较两次转写结果，用于调整预处理或模型参数时量化变化
+pub fn compare(a: &TranscribeResult, b: &TranscribeResult) -> ComparisonReport {
+    let reference = tokenize(&a.text);
+    let hypothesis = tokenize(&b.text);
+    let (substitutions, deletions, insertions) = edit_counts(&reference, &hypothesis);
+    let errors = substitutions + deletions + insertions;
+    let word_error_rate = if reference.is_empty() {
+        if hypothesis.is_empty() { 0.0 } else { 1.0 }
+    } else {
+        errors as f64 / reference.len() as f64
+    };
+
+    // 分段时间为 whisper 的 10ms 单位
+    let drifts: Vec<f64> = a.segments.iter()
+        .zip(&b.segments)
+        .flat_map(|(x, y)| {
+            [
+                (x.start_time - y.start_time).abs() * 10.0,
+                (x.end_time - y.end_time).abs() * 10.0,
+            ]
+        })
+        .collect();
+    let mean_timing_drift_ms = if drifts.is_empty() {
+        0.0
+    } else {
+        drifts.iter().sum::<f64>() / drifts.len() as f64
+    };
+    let max_timing_drift_ms = drifts.iter().copied().fold(0.0, f64::max);
+
+    ComparisonReport {
+        word_error_rate,
+        reference_words: reference.len(),
+        substitutions,
+        deletions,
+        insertions,
+        segment_count_delta: b.segments.len() as i64 - a.segments.len() as i64,
+        mean_timing_drift_ms,
+        max_timing_drift_ms,
+    }
+}
+
+// 忽略大小写和标点，空白分词；中日韩字符没有空格分隔，每个字单独作为一个词
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            tokens.push(c.to_string());
+        } else if c.is_alphanumeric() || c == '\'' {
+            word.extend(c.to_lowercase());
+        } else if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'   // 平假名、片假名
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{ac00}'..='\u{d7af}' // 韩文
+    )
+}
+
+// 编辑距离，返回 (替换, 删除, 插入) 次数
+fn edit_counts(reference: &[String], hypothesis: &[String]) -> (usize, usize, usize) {
+    let columns = hypothesis.len() + 1;
+    // 每个单元格保存 (总代价, 替换, 删除, 插入)
+    let mut previous: Vec<(usize, usize, usize, usize)> =
+        (0..columns).map(|j| (j, 0, 0, j)).collect();
+    for (i, r) in reference.iter().enumerate() {
+        let mut current = Vec::with_capacity(columns);
+        current.push((i + 1, 0, i + 1, 0));
+        for (j, h) in hypothesis.iter().enumerate() {
+            let diagonal = previous[j];
+            let candidate = if r == h {
+                diagonal
+            } else {
+                (diagonal.0 + 1, diagonal.1 + 1, diagonal.2, diagonal.3)
+            };
+            let up = previous[j + 1];
+            let deletion = (up.0 + 1, up.1, up.2 + 1, up.3);
+            let left = current[j];
+            let insertion = (left.0 + 1, left.1, left.2, left.3 + 1);
+            let best = [candidate, deletion, insertion]
+                .into_iter()
+                .min_by_key(|cell| cell.0)
+                .unwrap_or(candidate);
+            current.push(best);
+        }
+        previous = current;
+    }
+    let (_, substitutions, deletions, insertions) = previous[columns - 1];
+    (substitutions, deletions, insertions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::types::TranscribeSegment;
+
+    fn result(segments: &[(&str, f64, f64)]) -> TranscribeResult {
+        TranscribeResult {
+            text: segments.iter().map(|(text, _, _)| *text).collect::<Vec<_>>().join(" "),
+            segments: segments.iter()
+                .map(|(text, start_time, end_time)| TranscribeSegment {
+                    text: text.to_string(),
+                    speaker_id: None,
+                    start_time: *start_time,
+                    end_time: *end_time,
+                    words: Vec::new(),
+                    confidence: None,
+                })
+                .collect(),
+            detected_language: "en".to_string(),
+            language: "en".to_string(),
+            language_probability: 1.0,
+            source: None,
+            timings_ms: None,
+            dropped_segments: None,
+        }
+    }
+
+    #[test]
+    fn test_compare_identical() {
+        let a = result(&[("Hello world.", 0.0, 120.0), ("今天天气很好", 120.0, 300.0)]);
+        let report = compare(&a, &a.clone());
+        assert_eq!(report.word_error_rate, 0.0);
+        assert_eq!(report.reference_words, 8);
+        assert_eq!(report.segment_count_delta, 0);
+        assert_eq!(report.max_timing_drift_ms, 0.0);
+    }
+
+    #[test]
+    fn test_compare_modified() {
+        let a = result(&[("the quick brown fox", 0.0, 100.0), ("jumps over", 100.0, 200.0)]);
+        let b = result(&[("the quick red fox", 0.0, 110.0), ("jumps", 105.0, 200.0), ("again", 200.0, 250.0)]);
+        let report = compare(&a, &b);
+        assert!(report.word_error_rate > 0.0);
+        assert_eq!(report.substitutions, 2);
+        assert_eq!(report.deletions, 0);
+        assert_eq!(report.insertions, 0);
+        // "brown" -> "red", "over" -> "again"
+        assert!((report.word_error_rate - 2.0 / 6.0).abs() < 1e-9);
+        assert_eq!(report.segment_count_delta, 1);
+        assert_eq!(report.max_timing_drift_ms, 100.0);
+        assert_eq!(report.mean_timing_drift_ms, 37.5);
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::asr::TranscribeResult;
+
+/// 不以空格分词的语言，敏感词按子串匹配
+const UNSEGMENTED_LANGUAGES: &[&str] = &["zh", "yue", "ja", "ko", "th"];
+
+/// 敏感词的匹配策略
+pub trait WordMatcher: Debug + Send + Sync {
+    /// 返回 `text` 中所有匹配 `word` 的区间，两者均已经过 `normalize`
+    fn find(&self, text: &[char], word: &[char]) -> Vec<Range<usize>>;
+}
+
+/// 整词匹配，用于以空格分词的语言，避免误伤包含敏感词的其他单词
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WordBoundaryMatcher;
+
+impl WordMatcher for WordBoundaryMatcher {
+    fn find(&self, text: &[char], word: &[char]) -> Vec<Range<usize>> {
+        let len = word.len();
+        let mut ranges = Vec::new();
+        let mut i = 0;
+        while i + len <= text.len() {
+            if text[i..i + len] == word[..] && is_boundary(text, i, len) {
+                ranges.push(i..i + len);
+                i += len;
+            } else {
+                i += 1;
+            }
+        }
+        ranges
+    }
+}
+
+/// 子串匹配，用于中日文等没有词边界的语言，忽略词中间插入的空白
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubstringMatcher;
+
+impl WordMatcher for SubstringMatcher {
+    fn find(&self, text: &[char], word: &[char]) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut i = 0;
+        while i < text.len() {
+            match match_ignoring_whitespace(text, i, word) {
+                Some(end) if end > i => {
+                    ranges.push(i..end);
+                    i = end;
+                }
+                _ => i += 1,
+            }
+        }
+        ranges
+    }
+}
+
+// 从 start 开始匹配 word，跳过文本中的空白，返回匹配结束的位置
+fn match_ignoring_whitespace(text: &[char], start: usize, word: &[char]) -> Option<usize> {
+    let mut i = start;
+    for (n, c) in word.iter().filter(|c| !c.is_whitespace()).enumerate() {
+        if n > 0 {
+            while text.get(i).is_some_and(|t| t.is_whitespace()) {
+                i += 1;
+            }
+        }
+        if text.get(i) != Some(c) {
+            return None;
+        }
+        i += 1;
+    }
+    Some(i)
+}
+
+/// 敏感词过滤器
+///
+/// 按语言加载敏感词表，将文本中匹配到的词替换为等长的 `*`。
+/// 匹配策略按语言选择，可通过 `set_matcher` 替换
+#[derive(Debug, Clone, Default)]
+pub struct DirtyWordFilter {
+    // 语言代码 -> 归一化后的敏感词
+    words: HashMap<String, Vec<Vec<char>>>,
+    // 语言代码 -> 自定义的匹配策略
+    matchers: HashMap<String, Arc<dyn WordMatcher>>,
+}
+
+impl DirtyWordFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从目录加载敏感词表
+    ///
+    /// 目录下每个 `<language>.txt` 文件为一种语言的词表，每行一个词，`#` 开头的行为注释
+    pub fn load_from_dir(dir: &Path) -> Result<Self> {
+        let mut filter = Self::new();
+        if !dir.exists() {
+            warn!("Dirty word directory {:?} doesn't exist, filter is empty", dir);
+            return Ok(filter);
+        }
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("txt") {
+                continue;
+            }
+            let language = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(language) => language.to_string(),
+                None => continue,
+            };
+            let content = fs::read_to_string(&path)?;
+            let words: Vec<&str> = content
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .collect();
+            info!("Loaded {} dirty words for language {}", words.len(), language);
+            filter.add_words(&language, words);
+        }
+
+        Ok(filter)
+    }
+
+    pub fn add_words<I, S>(&mut self, language: &str, words: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let entry = self.words.entry(language.to_string()).or_default();
+        for word in words {
+            let word: Vec<char> = word.as_ref().trim().chars().map(normalize).collect();
+            if !word.is_empty() {
+                entry.push(word);
+            }
+        }
+    }
+
+    /// 为某种语言指定匹配策略
+    pub fn set_matcher(&mut self, language: &str, matcher: Arc<dyn WordMatcher>) {
+        self.matchers.insert(language.to_string(), matcher);
+    }
+
+    /// 该语言使用的匹配策略，未指定时中日文等按子串匹配，其余按整词匹配
+    pub fn matcher(&self, language: &str) -> Arc<dyn WordMatcher> {
+        match self.matchers.get(language) {
+            Some(matcher) => matcher.clone(),
+            None if UNSEGMENTED_LANGUAGES.contains(&language) => Arc::new(SubstringMatcher),
+            None => Arc::new(WordBoundaryMatcher),
+        }
+    }
+
+    /// 屏蔽文本中该语言的敏感词，词中的空白保留
+    pub fn filter(&self, language: &str, text: &str) -> String {
+        let words = match self.words.get(language) {
+            Some(words) if !words.is_empty() => words,
+            _ => return text.to_string(),
+        };
+        let matcher = self.matcher(language);
+
+        let mut chars: Vec<char> = text.chars().collect();
+        let normalized: Vec<char> = chars.iter().copied().map(normalize).collect();
+
+        for word in words {
+            for range in matcher.find(&normalized, word) {
+                chars[range].iter_mut()
+                    .filter(|c| !c.is_whitespace())
+                    .for_each(|c| *c = '*');
+            }
+        }
+
+        chars.into_iter().collect()
+    }
+
+    /// 过滤转写结果中每个分段及全文，分段时间保持不变
+    pub fn apply(&self, language: &str, result: &mut TranscribeResult) {
+        for segment in result.segments.iter_mut() {
+            segment.text = self.filter(language, &segment.text);
+        }
+        result.full_text = self.filter(language, &result.full_text);
+    }
+}
+
+// 逐字符归一化，保持字符数不变，匹配位置可直接对应原文：全角字母数字和空格转为半角，再转小写
+fn normalize(c: char) -> char {
+    let c = match c {
+        '\u{3000}' => ' ',
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        _ => c,
+    };
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+// 匹配的前后不能紧挨着字母或数字，否则只是其他单词的一部分
+fn is_boundary(text: &[char], start: usize, len: usize) -> bool {
+    let is_word_char = |c: &char| c.is_alphanumeric();
+    let before = start == 0 || !is_word_char(&text[start - 1]) || !is_word_char(&text[start]);
+    let end = start + len;
+    let after = end == text.len() || !is_word_char(&text[end]) || !is_word_char(&text[end - 1]);
+    before && after
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asr::TranscribeSegment;
+
+    fn test_filter() -> DirtyWordFilter {
+        let mut filter = DirtyWordFilter::new();
+        filter.add_words("en", ["damn", "ass"]);
+        filter.add_words("zh", ["笨蛋"]);
+        filter
+    }
+
+    #[test]
+    fn test_filter_whole_words() {
+        let filter = test_filter();
+        assert_eq!(filter.filter("en", "Damn, that class was an ass"), "****, that class was an ***");
+        assert_eq!(filter.filter("zh", "你这个笨蛋啊"), "你这个**啊");
+        // words of other languages are left untouched
+        assert_eq!(filter.filter("ja", "damn"), "damn");
+    }
+
+    #[test]
+    fn test_filter_unsegmented_languages() {
+        let filter = test_filter();
+        // no spaces around the word, full-width and inserted spaces are normalized away
+        assert_eq!(filter.filter("zh", "他说你是笨蛋我不信"), "他说你是**我不信");
+        assert_eq!(filter.filter("zh", "笨 蛋！笨蛋"), "* *！**");
+
+        // space delimited languages still need whole words, also beyond ascii
+        let mut filter = test_filter();
+        filter.add_words("de", ["arsch"]);
+        assert_eq!(filter.filter("de", "Arsch, Arsche, Arsché"), "*****, Arsche, Arsché");
+        assert_eq!(filter.filter("en", "ＤＡＭＮ"), "****");
+        assert_eq!(filter.filter("en", "damnation"), "damnation");
+
+        // the strategy can be replaced per language
+        filter.set_matcher("en", Arc::new(SubstringMatcher));
+        assert_eq!(filter.filter("en", "damnation"), "****nation");
+    }
+
+    #[test]
+    fn test_apply_keeps_timings() {
+        let filter = test_filter();
+        let mut result = TranscribeResult {
+            segments: vec![TranscribeSegment {
+                text: "damn it".to_string(),
+                speaker_id: 0,
+                start: 10.0,
+                end: 120.0,
+                words: Vec::new(),
+                confidence: 1.0,
+            }],
+            full_text: "damn it".to_string(),
+            detected_language: "en".to_string(),
+            language: "en".to_string(),
+            language_probability: 1.0,
+        };
+
+        filter.apply("en", &mut result);
+        assert_eq!(result.segments[0].text, "**** it");
+        assert_eq!(result.segments[0].start, 10.0);
+        assert_eq!(result.segments[0].end, 120.0);
+        assert_eq!(result.full_text, "**** it");
+    }
+}
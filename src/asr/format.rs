@@ -0,0 +1,228 @@
+use std::fmt::Write;
+use serde::{Deserialize, Serialize};
+
+use crate::schedule::types::{TranscribeResult, TranscribeSegment};
+
+/// 字幕格式
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+    /// 每个分段一条 cue，词之间带 `<c>` 时间标签，用于卡拉 OK 式逐词高亮，需要词级时间戳
+    #[serde(rename = "vtt-words")]
+    VttWords,
+}
+
+impl SubtitleFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "application/x-subrip",
+            SubtitleFormat::Vtt | SubtitleFormat::VttWords => "text/vtt",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt | SubtitleFormat::VttWords => "vtt",
+        }
+    }
+
+    pub fn render(&self, result: &TranscribeResult) -> String {
+        match self {
+            SubtitleFormat::Srt => to_srt(result),
+            SubtitleFormat::Vtt => to_vtt(result),
+            SubtitleFormat::VttWords => to_vtt_words(result),
+        }
+    }
+
+    /// 是否需要转写时开启 `token_timestamps`
+    pub fn requires_word_timestamps(&self) -> bool {
+        matches!(self, SubtitleFormat::VttWords)
+    }
+}
+
+/// 生成 SRT 字幕，时间格式为 `HH:MM:SS,mmm`
+pub fn to_srt(result: &TranscribeResult) -> String {
+    let mut output = String::new();
+    for (index, (start, end, text)) in cues(result).enumerate() {
+        let _ = write!(
+            output,
+            "{}\n{} --> {}\n{}\n\n",
+            index + 1,
+            format_timestamp(start, ','),
+            format_timestamp(end, ','),
+            text,
+        );
+    }
+    output
+}
+
+/// 生成 WebVTT 字幕，时间格式为 `HH:MM:SS.mmm`
+pub fn to_vtt(result: &TranscribeResult) -> String {
+    let mut output = String::from("WEBVTT\n\n");
+    for (index, (start, end, text)) in cues(result).enumerate() {
+        let _ = write!(
+            output,
+            "{}\n{} --> {}\n{}\n\n",
+            index + 1,
+            format_timestamp(start, '.'),
+            format_timestamp(end, '.'),
+            text,
+        );
+    }
+    output
+}
+
+/// 生成逐词计时的 WebVTT，第一个词之后的每个词前有 `<HH:MM:SS.mmm>` 时间标签，
+/// 没有词级时间戳的分段退化为普通 cue
+pub fn to_vtt_words(result: &TranscribeResult) -> String {
+    let mut output = String::from("WEBVTT\n\n");
+    let mut index = 0;
+    for segment in &result.segments {
+        let Some((start, end, text)) = cue(segment) else {
+            continue;
+        };
+        let words: Vec<_> = segment.words.iter()
+            .filter(|word| !word.text.trim().is_empty())
+            .collect();
+
+        let mut line = String::new();
+        for (i, word) in words.iter().enumerate() {
+            if i > 0 {
+                // 时间标签必须落在 cue 的时间范围内
+                let at = ((word.start.max(0.0) * 10.0).round() as u64).clamp(start, end);
+                let _ = write!(line, " <{}>", format_timestamp(at, '.'));
+            }
+            let _ = write!(line, "<c>{}</c>", word.text.trim());
+        }
+        if line.is_empty() {
+            line = text.to_string();
+        }
+
+        index += 1;
+        let _ = write!(
+            output,
+            "{}\n{} --> {}\n{}\n\n",
+            index,
+            format_timestamp(start, '.'),
+            format_timestamp(end, '.'),
+            line,
+        );
+    }
+    output
+}
+
+// 将分段转换为 (开始毫秒, 结束毫秒, 文本)，跳过空文本，结束时间不早于开始时间
+// 分段时间为 whisper 的 10ms 单位
+fn cues(result: &TranscribeResult) -> impl Iterator<Item = (u64, u64, &str)> {
+    result.segments.iter().filter_map(cue)
+}
+
+fn cue(segment: &TranscribeSegment) -> Option<(u64, u64, &str)> {
+    let text = segment.text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let start = (segment.start_time.max(0.0) * 10.0).round() as u64;
+    let end = ((segment.end_time.max(0.0) * 10.0).round() as u64).max(start);
+    Some((start, end, text))
+}
+
+fn format_timestamp(ms: u64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        (ms / 60_000) % 60,
+        (ms / 1000) % 60,
+        separator,
+        ms % 1000,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asr::WordTiming;
+
+    fn segment(text: &str, start_time: f64, end_time: f64) -> TranscribeSegment {
+        TranscribeSegment {
+            text: text.to_string(),
+            speaker_id: Some(0),
+            start_time,
+            end_time,
+            words: Vec::new(),
+            confidence: None,
+        }
+    }
+
+    fn result(segments: Vec<TranscribeSegment>) -> TranscribeResult {
+        TranscribeResult {
+            text: String::new(),
+            segments,
+            detected_language: "en".to_string(),
+            language: "en".to_string(),
+            language_probability: 1.0,
+            source: None,
+            timings_ms: None,
+            dropped_segments: None,
+        }
+    }
+
+    #[test]
+    fn test_srt_cues() {
+        let result = result(vec![
+            segment(" Hello", 0.0, 250.0),
+            segment(" world", 250.0, 366123.4),
+        ]);
+        assert_eq!(
+            to_srt(&result),
+            "1\n00:00:00,000 --> 00:00:02,500\nHello\n\n2\n00:00:02,500 --> 01:01:01,234\nworld\n\n"
+        );
+    }
+
+    #[test]
+    fn test_vtt_cues() {
+        let result = result(vec![segment(" Hello", 12.0, 150.0)]);
+        assert_eq!(
+            to_vtt(&result),
+            "WEBVTT\n\n1\n00:00:00.120 --> 00:00:01.500\nHello\n\n"
+        );
+    }
+
+    #[test]
+    fn test_vtt_words_cues() {
+        let word = |text: &str, start: f64, end: f64| WordTiming { text: text.to_string(), start, end, probability: 0.9 };
+        let mut karaoke = segment(" Hello big world", 100.0, 250.0);
+        karaoke.words = vec![word("Hello", 100.0, 140.0), word(" big", 150.0, 180.0), word(" world", 190.0, 250.0)];
+        let result = result(vec![karaoke, segment(" no words", 300.0, 400.0)]);
+
+        assert_eq!(
+            to_vtt_words(&result),
+            "WEBVTT\n\n\
+             1\n00:00:01.000 --> 00:00:02.500\n<c>Hello</c> <00:00:01.500><c>big</c> <00:00:01.900><c>world</c>\n\n\
+             2\n00:00:03.000 --> 00:00:04.000\nno words\n\n"
+        );
+        assert_eq!("\"vtt-words\"", serde_json::to_string(&SubtitleFormat::VttWords).unwrap());
+    }
+
+    #[test]
+    fn test_overlapping_and_zero_length_segments() {
+        let result = result(vec![
+            segment("first", 0.0, 300.0),
+            segment("overlap", 200.0, 400.0),
+            segment("zero", 400.0, 400.0),
+            segment("  ", 400.0, 500.0),
+            segment("reversed", 600.0, 500.0),
+        ]);
+        let srt = to_srt(&result);
+        let numbers: Vec<&str> = srt.split("\n\n")
+            .filter(|cue| !cue.is_empty())
+            .map(|cue| cue.lines().next().unwrap())
+            .collect();
+        assert_eq!(numbers, vec!["1", "2", "3", "4"]);
+        assert!(srt.contains("3\n00:00:04,000 --> 00:00:04,000\nzero"));
+        assert!(srt.contains("4\n00:00:06,000 --> 00:00:06,000\nreversed"));
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{AsrEngine, AsrParams};
+
+/// 输入采样率，与 `arecord -f S16_LE -r 16000 -c 1` 的输出一致
+pub const LISTEN_SAMPLE_RATE: usize = 16000;
+
+/// 默认每读满 5 秒音频转写一次
+pub const DEFAULT_LISTEN_CHUNK_SECONDS: u32 = 5;
+
+/// 从 `input` 持续读取 16kHz 单声道 16 位小端 PCM，每读满 `chunk_seconds` 秒转写一次，
+/// 并将识别文本逐行写入 `output`。输入结束时转写剩余的音频，返回转写的段数
+pub async fn listen<R, W>(
+    engine: &dyn AsrEngine,
+    params: AsrParams,
+    chunk_seconds: u32,
+    mut input: R,
+    mut output: W,
+) -> Result<usize>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if chunk_seconds == 0 {
+        return Err(anyhow::anyhow!("Invalid chunk size: 0"));
+    }
+    let chunk_len = chunk_seconds as usize * LISTEN_SAMPLE_RATE;
+
+    let mut buf = vec![0u8; 8192];
+    // 上次读取剩下的半个采样
+    let mut pending: Option<u8> = None;
+    let mut samples: Vec<f32> = Vec::with_capacity(chunk_len);
+    let mut chunks = 0;
+
+    loop {
+        let n = input.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+
+        let mut bytes = &buf[..n];
+        if let Some(low) = pending.take() {
+            samples.push(i16::from_le_bytes([low, bytes[0]]) as f32 / 32768.0);
+            bytes = &bytes[1..];
+        }
+        let mut pairs = bytes.chunks_exact(2);
+        samples.extend(pairs.by_ref().map(|pair| i16::from_le_bytes([pair[0], pair[1]]) as f32 / 32768.0));
+        pending = pairs.remainder().first().copied();
+
+        while samples.len() >= chunk_len {
+            let chunk: Vec<f32> = samples.drain(..chunk_len).collect();
+            transcribe_chunk(engine, &params, chunk, &mut output).await?;
+            chunks += 1;
+        }
+    }
+
+    // 输入结束，转写剩余音频。whisper 不接受短于 1 秒的音频，不足时补静音
+    if !samples.is_empty() {
+        if samples.len() < LISTEN_SAMPLE_RATE {
+            samples.resize(LISTEN_SAMPLE_RATE, 0.0);
+        }
+        transcribe_chunk(engine, &params, samples, &mut output).await?;
+        chunks += 1;
+    }
+    output.flush().await?;
+    Ok(chunks)
+}
+
+async fn transcribe_chunk<W: AsyncWrite + Unpin>(
+    engine: &dyn AsrEngine,
+    params: &AsrParams,
+    chunk: Vec<f32>,
+    output: &mut W,
+) -> Result<()> {
+    let result = engine.transcribe(chunk, params.clone()).await?;
+    let text = result.full_text.trim();
+    if !text.is_empty() {
+        output.write_all(text.as_bytes()).await?;
+        output.write_all(b"\n").await?;
+        output.flush().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use crate::asr::TranscribeResult;
+
+    // 返回音频时长（秒）和首个采样值作为识别文本
+    struct DescribingEngine;
+
+    #[async_trait]
+    impl AsrEngine for DescribingEngine {
+        async fn transcribe(&self, audio: Vec<f32>, _params: AsrParams) -> Result<TranscribeResult> {
+            Ok(TranscribeResult {
+                segments: Vec::new(),
+                full_text: format!("{}s starting at {}", audio.len() / LISTEN_SAMPLE_RATE, audio[0]),
+                detected_language: "en".to_string(),
+                language: "en".to_string(),
+                language_probability: 1.0,
+            })
+        }
+    }
+
+    fn pcm(seconds: &[(i16, usize)]) -> Vec<u8> {
+        seconds.iter()
+            .flat_map(|&(sample, samples)| std::iter::repeat(sample.to_le_bytes()).take(samples))
+            .flatten()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_listen_prints_each_chunk_and_flushes_on_eof() -> Result<()> {
+        // 两段完整的 1 秒音频，结尾剩余半秒
+        let input = pcm(&[
+            (16384, LISTEN_SAMPLE_RATE),
+            (-16384, LISTEN_SAMPLE_RATE),
+            (8192, LISTEN_SAMPLE_RATE / 2),
+        ]);
+        let mut output = Vec::new();
+
+        let chunks = listen(&DescribingEngine, AsrParams::new(), 1, input.as_slice(), &mut output).await?;
+
+        assert_eq!(chunks, 3);
+        assert_eq!(
+            String::from_utf8(output)?,
+            "1s starting at 0.5\n1s starting at -0.5\n1s starting at 0.25\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_listen_handles_samples_split_across_reads() -> Result<()> {
+        // 第一次读取在一个采样的中间结束
+        let input = pcm(&[(16384, LISTEN_SAMPLE_RATE)]);
+        let (head, tail) = input.split_at(4095);
+        let mut output = Vec::new();
+
+        listen(&DescribingEngine, AsrParams::new(), 1, head.chain(tail), &mut output).await?;
+
+        assert_eq!(String::from_utf8(output)?, "1s starting at 0.5\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_listen_without_input_prints_nothing() -> Result<()> {
+        let mut output = Vec::new();
+        let chunks = listen(&DescribingEngine, AsrParams::new(), 1, &b""[..], &mut output).await?;
+        assert_eq!(chunks, 0);
+        assert!(output.is_empty());
+        Ok(())
+    }
+}
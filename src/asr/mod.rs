@@ -1,16 +1,84 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use anyhow::Result;
 use serde::{Serialize, Deserialize};
 use async_trait::async_trait;
+use tracing::warn;
 
 pub mod whisper;    
+pub mod filter;
+pub mod format;
+pub mod compare;
+pub mod registry;
+pub mod listen;
+
+/// 初始提示词的最大长度（字符数）
+pub const MAX_INITIAL_PROMPT_LEN: usize = 1024;
+
+/// 转写过程中的实时事件
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum TranscribeProgress {
+    /// 解码进度百分比
+    Progress(i32),
+    /// 刚解码出的分段，时间单位与 `TranscribeSegment` 相同。
+    /// 尚未经过无语音过滤和敏感词过滤
+    Segment { text: String, start: f64, end: f64 },
+}
+
+/// 接收 `TranscribeProgress` 的通道
+pub type ProgressSender = tokio::sync::mpsc::UnboundedSender<TranscribeProgress>;
+
+/// 被丢弃时置位中止标记，让仍在阻塞线程上运行的转写尽快结束
+pub struct AbortOnDrop(pub Arc<AtomicBool>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct AsrParams {
     pub language: Option<String>,
+    // 为 true 时整段音频只输出一个分段
     pub single_segment: bool,
     pub speaker_diarization: bool,
     pub emotion_recognition: bool,
     pub filter_dirty_words: bool,
+    pub token_timestamps: bool,
+    pub sampling: SamplingMode,
+    pub initial_prompt: Option<String>,
+    // 以下参数若设置，则覆盖 `WhisperConfig` 中的引擎默认值
+    pub n_threads: Option<i32>,
+    pub temperature: Option<f32>,
+    pub translate: Option<bool>,
+    // 使用的模型 id，未指定时使用 `ModelRegistry` 的默认模型
+    pub model: Option<String>,
+    // 无语音概率超过该阈值的分段会被丢弃，默认 1.0 即保留全部分段
+    pub no_speech_threshold: f32,
+    // 自动检测的语言概率低于 `min_language_probability` 时改用该语言
+    pub fallback_language: Option<String>,
+    pub min_language_probability: f32,
+    // 设置后进度和新分段发送到该通道，而不是打印到标准输出
+    pub progress: Option<ProgressSender>,
+    // 输入音频原本的时长（秒），设置后用于检查传入的样本是否为 16kHz
+    pub expected_duration: Option<f64>,
+    // 置为 true 后引擎在下一次检查时中止解码，返回错误
+    pub abort: Option<Arc<AtomicBool>>,
+}
+
+/// 解码采样策略，对应 whisper 的 `SamplingStrategy`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SamplingMode {
+    Greedy { best_of: i32 },
+    // beam search 更慢但对困难音频更准确，patience 为 -1.0 时使用 whisper 默认值
+    BeamSearch { beam_size: i32, patience: f32 },
+}
+
+impl Default for SamplingMode {
+    fn default() -> Self {
+        SamplingMode::Greedy { best_of: 1 }
+    }
 }
 
 impl AsrParams {
@@ -21,6 +89,19 @@ impl AsrParams {
             speaker_diarization: false,
             emotion_recognition: false,
             filter_dirty_words: false,
+            token_timestamps: false,
+            sampling: SamplingMode::default(),
+            initial_prompt: None,
+            n_threads: None,
+            temperature: None,
+            translate: None,
+            model: None,
+            no_speech_threshold: 1.0,
+            fallback_language: None,
+            min_language_probability: 0.0,
+            progress: None,
+            expected_duration: None,
+            abort: None,
         }
     }
 
@@ -48,6 +129,139 @@ impl AsrParams {
         self.filter_dirty_words = filter_dirty_words;
         self
     }
+
+    pub fn set_token_timestamps(&mut self, token_timestamps: bool) -> &Self {
+        self.token_timestamps = token_timestamps;
+        self
+    }
+
+    pub fn set_sampling(&mut self, sampling: SamplingMode) -> &Self {
+        self.sampling = sampling;
+        self
+    }
+
+    pub fn set_initial_prompt(&mut self, initial_prompt: Option<String>) -> &Self {
+        self.initial_prompt = initial_prompt;
+        self
+    }
+
+    pub fn set_n_threads(&mut self, n_threads: Option<i32>) -> &Self {
+        self.n_threads = n_threads;
+        self
+    }
+
+    pub fn set_temperature(&mut self, temperature: Option<f32>) -> &Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn set_translate(&mut self, translate: Option<bool>) -> &Self {
+        self.translate = translate;
+        self
+    }
+
+    pub fn set_model(&mut self, model: Option<String>) -> &Self {
+        self.model = model;
+        self
+    }
+
+    pub fn set_progress(&mut self, progress: Option<ProgressSender>) -> &Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn set_no_speech_threshold(&mut self, no_speech_threshold: f32) -> &Self {
+        self.no_speech_threshold = no_speech_threshold;
+        self
+    }
+
+    pub fn set_expected_duration(&mut self, expected_duration: Option<f64>) -> &Self {
+        self.expected_duration = expected_duration;
+        self
+    }
+
+    pub fn set_abort(&mut self, abort: Option<Arc<AtomicBool>>) -> &Self {
+        self.abort = abort;
+        self
+    }
+
+    pub fn set_language_fallback(&mut self, fallback_language: Option<String>, min_language_probability: f32) -> &Self {
+        self.fallback_language = fallback_language;
+        self.min_language_probability = min_language_probability;
+        self
+    }
+
+    /// 根据检测结果选择解码语言
+    ///
+    /// 检测概率低于 `min_language_probability` 且配置了回退语言时使用回退语言，否则使用检测到的语言
+    pub fn resolve_language(&self, detected: &str, probability: f32) -> String {
+        match &self.fallback_language {
+            Some(fallback) if probability < self.min_language_probability => fallback.clone(),
+            _ => detected.to_string(),
+        }
+    }
+
+    /// 校验参数取值，避免非法值传入 whisper 后出现难以排查的错误
+    pub fn validate(&self) -> Result<()> {
+        if let Some(lang) = &self.language {
+            if lang.contains('\0') || whisper_rs::get_lang_id(lang).is_none() {
+                return Err(anyhow::anyhow!("Unsupported language: {}", lang));
+            }
+        }
+
+        if let Some(lang) = &self.fallback_language {
+            if lang.contains('\0') || whisper_rs::get_lang_id(lang).is_none() {
+                return Err(anyhow::anyhow!("Unsupported fallback language: {}", lang));
+            }
+        }
+
+        if !(0.0..=1.0).contains(&self.min_language_probability) {
+            return Err(anyhow::anyhow!(
+                "Invalid minimum language probability: {}, must be between 0 and 1",
+                self.min_language_probability
+            ));
+        }
+
+        if let Some(n_threads) = self.n_threads {
+            if n_threads < 1 {
+                return Err(anyhow::anyhow!("Invalid thread count: {}, must be at least 1", n_threads));
+            }
+        }
+
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=1.0).contains(&temperature) {
+                return Err(anyhow::anyhow!("Invalid temperature: {}, must be between 0 and 1", temperature));
+            }
+        }
+
+        match self.sampling {
+            SamplingMode::Greedy { best_of } if best_of < 1 => {
+                return Err(anyhow::anyhow!("Invalid best_of: {}, must be at least 1", best_of));
+            }
+            SamplingMode::BeamSearch { beam_size, .. } if beam_size < 1 => {
+                return Err(anyhow::anyhow!("Invalid beam size: {}, must be at least 1", beam_size));
+            }
+            _ => {}
+        }
+
+        if let Some(prompt) = &self.initial_prompt {
+            if prompt.chars().count() > MAX_INITIAL_PROMPT_LEN {
+                return Err(anyhow::anyhow!("Initial prompt exceeds {} characters", MAX_INITIAL_PROMPT_LEN));
+            }
+            if prompt.contains('\0') {
+                return Err(anyhow::anyhow!("Initial prompt contains a null byte"));
+            }
+        }
+
+        if !(0.0..=1.0).contains(&self.no_speech_threshold) {
+            return Err(anyhow::anyhow!(
+                "Invalid no speech threshold: {}, must be between 0 and 1",
+                self.no_speech_threshold
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -56,15 +270,177 @@ pub struct TranscribeSegment {
     pub speaker_id: usize,    
     pub start: f64,    
     pub end: f64,      
+    // only populated when `AsrParams::token_timestamps` is enabled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<WordTiming>,
+    // 分段内文本 token 的平均概率
+    #[serde(default)]
+    pub confidence: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WordTiming {
+    pub text: String,
+    pub start: f64,
+    pub end: f64,
+    pub probability: f32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TranscribeResult {
     pub segments: Vec<TranscribeSegment>,
     pub full_text: String,
+    pub detected_language: String,
+    // 实际用于解码的语言，检测置信度过低时为回退语言
+    #[serde(default)]
+    pub language: String,
+    pub language_probability: f32,
+}
+
+/// whisper 要求的输入采样率
+pub const WHISPER_SAMPLE_RATE: u32 = 16000;
+
+/// 样本数对应的时长超过预期时长的该倍数时，认为输入不是 16kHz
+const MAX_DURATION_RATIO: f64 = 1.5;
+/// 低于该倍数时同样警告。预处理的 VAD 会裁掉静音，因此下限放得很宽
+const MIN_DURATION_RATIO: f64 = 0.25;
+
+/// 检查按 16kHz 计算的样本时长是否与预期时长大致相符
+///
+/// 明显不符时多半是音频未重采样到 16kHz 就交给了引擎，此时记录警告并返回警告内容。
+/// 这只是粗略检查，样本本身无法说明采样率，whisper 仍会照常解码
+pub fn check_sample_rate(audio: &[f32], expected_seconds: f64) -> Option<String> {
+    if !expected_seconds.is_finite() || expected_seconds <= 0.0 {
+        return None;
+    }
+    let actual_seconds = audio.len() as f64 / WHISPER_SAMPLE_RATE as f64;
+    let ratio = actual_seconds / expected_seconds;
+    if (MIN_DURATION_RATIO..=MAX_DURATION_RATIO).contains(&ratio) {
+        return None;
+    }
+    let message = format!(
+        "Audio of {} samples lasts {:.2}s at {} Hz but {:.2}s were expected, the input is probably not {} Hz mono",
+        audio.len(), actual_seconds, WHISPER_SAMPLE_RATE, expected_seconds, WHISPER_SAMPLE_RATE,
+    );
+    warn!("{}", message);
+    Some(message)
 }
 
 #[async_trait]
 pub trait AsrEngine: Send + Sync {
+    /// `audio` 必须是 16kHz 单声道、取值在 [-1, 1] 的 f32 样本，引擎不会重采样。
+    /// 设置了 `AsrParams::expected_duration` 时，时长明显不符会记录警告
     async fn transcribe(&self, audio: Vec<f32>, params: AsrParams) -> Result<TranscribeResult>;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_invalid(params: AsrParams, message: &str) {
+        let err = params.validate().unwrap_err().to_string();
+        assert!(err.contains(message), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(AsrParams::new().validate().is_ok());
+
+        let mut params = AsrParams::new();
+        params.set_language(Some("en".to_string()));
+        params.set_n_threads(Some(4));
+        params.set_temperature(Some(0.0));
+        params.set_sampling(SamplingMode::BeamSearch { beam_size: 5, patience: -1.0 });
+        params.set_initial_prompt(Some("ACME Cloud".to_string()));
+        params.set_no_speech_threshold(0.6);
+        assert!(params.validate().is_ok());
+
+        let mut params = AsrParams::new();
+        params.set_language(Some("klingon".to_string()));
+        assert_invalid(params, "Unsupported language: klingon");
+
+        let mut params = AsrParams::new();
+        params.set_n_threads(Some(0));
+        assert_invalid(params, "Invalid thread count: 0");
+
+        let mut params = AsrParams::new();
+        params.set_temperature(Some(1.5));
+        assert_invalid(params, "Invalid temperature: 1.5");
+
+        let mut params = AsrParams::new();
+        params.set_sampling(SamplingMode::Greedy { best_of: 0 });
+        assert_invalid(params, "Invalid best_of: 0");
+
+        let mut params = AsrParams::new();
+        params.set_sampling(SamplingMode::BeamSearch { beam_size: 0, patience: -1.0 });
+        assert_invalid(params, "Invalid beam size: 0");
+
+        let mut params = AsrParams::new();
+        params.set_initial_prompt(Some("a".repeat(MAX_INITIAL_PROMPT_LEN + 1)));
+        assert_invalid(params, "Initial prompt exceeds");
+
+        let mut params = AsrParams::new();
+        params.set_initial_prompt(Some("a\0b".to_string()));
+        assert_invalid(params, "null byte");
+
+        let mut params = AsrParams::new();
+        params.set_no_speech_threshold(-0.1);
+        assert_invalid(params, "Invalid no speech threshold");
+
+        let mut params = AsrParams::new();
+        params.set_language_fallback(Some("klingon".to_string()), 0.5);
+        assert_invalid(params, "Unsupported fallback language: klingon");
+
+        let mut params = AsrParams::new();
+        params.set_language_fallback(Some("en".to_string()), 1.5);
+        assert_invalid(params, "Invalid minimum language probability");
+    }
+
+    #[test]
+    fn test_check_sample_rate_warns_on_mismatched_input() {
+        use std::sync::{Arc, Mutex};
+
+        // 收集日志输出
+        #[derive(Clone, Default)]
+        struct Captured(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Captured {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+
+        // 2 秒的 48kHz 音频未经重采样
+        let audio = vec![0.0; 2 * 48000];
+        let warning = tracing::subscriber::with_default(subscriber, || check_sample_rate(&audio, 2.0));
+        assert!(warning.is_some());
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("WARN"), "{}", logs);
+        assert!(logs.contains("probably not 16000 Hz"), "{}", logs);
+
+        // 16kHz 的音频以及被 VAD 裁掉部分静音的音频不会警告
+        assert!(check_sample_rate(&vec![0.0; 2 * 16000], 2.0).is_none());
+        assert!(check_sample_rate(&vec![0.0; 16000], 2.0).is_none());
+        assert!(check_sample_rate(&audio, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_resolve_language() {
+        let mut params = AsrParams::new();
+        // 未配置回退语言时总是使用检测结果
+        assert_eq!(params.resolve_language("cy", 0.1), "cy");
+
+        params.set_language_fallback(Some("en".to_string()), 0.5);
+        assert_eq!(params.resolve_language("cy", 0.2), "en");
+        assert_eq!(params.resolve_language("fr", 0.9), "fr");
+    }
+}
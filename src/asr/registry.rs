@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::asr::whisper::{WhisperAsr, WhisperConfig};
+use crate::asr::{AsrEngine, AsrParams, TranscribeResult};
+
+/// 根据模型文件路径加载识别引擎
+pub type ModelLoader = Arc<dyn Fn(&Path) -> Result<Arc<dyn AsrEngine>> + Send + Sync>;
+
+/// 模型注册表
+///
+/// 以模型 id 为键登记模型文件，按需加载并缓存。已加载的模型数量不超过 `max_loaded`，
+/// 超出时淘汰最久未使用的模型；正在使用被淘汰模型的任务会持有引用直到完成
+pub struct ModelRegistry {
+    paths: HashMap<String, PathBuf>,
+    default_model: String,
+    max_loaded: usize,
+    loader: ModelLoader,
+    // 已加载的模型，按使用时间排序，末尾为最近使用
+    loaded: Mutex<Vec<(String, Arc<dyn AsrEngine>)>>,
+}
+
+impl ModelRegistry {
+    /// 创建注册表并登记默认模型，使用默认的 `WhisperConfig` 加载 whisper 模型
+    pub fn new(default_model: impl Into<String>, default_path: impl Into<PathBuf>, max_loaded: usize) -> Self {
+        let default_model = default_model.into();
+        let mut paths = HashMap::new();
+        paths.insert(default_model.clone(), default_path.into());
+        Self {
+            paths,
+            default_model,
+            max_loaded: max_loaded.max(1),
+            loader: Self::whisper_loader(WhisperConfig::default()),
+            loaded: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 使用指定的引擎配置加载 whisper 模型
+    pub fn with_config(self, config: WhisperConfig) -> Self {
+        self.with_loader(Self::whisper_loader(config))
+    }
+
+    /// 替换模型加载方式
+    pub fn with_loader(mut self, loader: ModelLoader) -> Self {
+        self.loader = loader;
+        self
+    }
+
+    /// 登记一个模型，模型文件在首次使用时才加载
+    pub fn register(mut self, model: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.paths.insert(model.into(), path.into());
+        self
+    }
+
+    pub fn default_model(&self) -> &str {
+        &self.default_model
+    }
+
+    pub fn contains(&self, model: &str) -> bool {
+        self.paths.contains_key(model)
+    }
+
+    /// 当前已加载的模型 id，按使用时间排序
+    pub async fn loaded_models(&self) -> Vec<String> {
+        self.loaded.lock().await.iter().map(|(model, _)| model.clone()).collect()
+    }
+
+    /// 获取模型对应的引擎，未指定时使用默认模型
+    pub async fn get(&self, model: Option<&str>) -> Result<Arc<dyn AsrEngine>> {
+        let model = model.unwrap_or(&self.default_model);
+        let path = self.paths.get(model)
+            .ok_or_else(|| anyhow::anyhow!("unknown model: {}", model))?
+            .clone();
+
+        let mut loaded = self.loaded.lock().await;
+        if let Some(index) = loaded.iter().position(|(id, _)| id == model) {
+            let entry = loaded.remove(index);
+            let engine = entry.1.clone();
+            loaded.push(entry);
+            return Ok(engine);
+        }
+
+        // 持有锁加载，避免同一模型被并发加载多次
+        info!("Loading model {} from {}", model, path.display());
+        let loader = self.loader.clone();
+        let engine = tokio::task::spawn_blocking(move || loader(&path)).await??;
+
+        while loaded.len() >= self.max_loaded {
+            let (evicted, _) = loaded.remove(0);
+            info!("Unloading model {}", evicted);
+        }
+        loaded.push((model.to_string(), engine.clone()));
+
+        Ok(engine)
+    }
+
+    fn whisper_loader(config: WhisperConfig) -> ModelLoader {
+        Arc::new(move |path: &Path| {
+            let asr = WhisperAsr::new_with_config(path.to_string_lossy().to_string(), config.clone())?;
+            Ok(Arc::new(asr) as Arc<dyn AsrEngine>)
+        })
+    }
+}
+
+#[async_trait]
+impl AsrEngine for ModelRegistry {
+    async fn transcribe(&self, audio: Vec<f32>, params: AsrParams) -> Result<TranscribeResult> {
+        let engine = self.get(params.model.as_deref()).await?;
+        engine.transcribe(audio, params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // 返回模型文件名作为识别文本
+    struct NamedEngine(String);
+
+    #[async_trait]
+    impl AsrEngine for NamedEngine {
+        async fn transcribe(&self, _audio: Vec<f32>, _params: AsrParams) -> Result<TranscribeResult> {
+            Ok(TranscribeResult {
+                segments: Vec::new(),
+                full_text: self.0.clone(),
+                detected_language: "en".to_string(),
+                language: "en".to_string(),
+                language_probability: 1.0,
+            })
+        }
+    }
+
+    fn registry(loads: Arc<AtomicUsize>) -> ModelRegistry {
+        ModelRegistry::new("large-v3", "large-v3.bin", 2)
+            .register("base", "base.bin")
+            .register("small", "small.bin")
+            .with_loader(Arc::new(move |path: &Path| {
+                loads.fetch_add(1, Ordering::SeqCst);
+                Ok(Arc::new(NamedEngine(path.display().to_string())) as Arc<dyn AsrEngine>)
+            }))
+    }
+
+    #[tokio::test]
+    async fn test_select_model_per_task() -> Result<()> {
+        let loads = Arc::new(AtomicUsize::new(0));
+        let registry = registry(loads.clone());
+
+        let mut params = AsrParams::new();
+        assert_eq!(registry.transcribe(vec![0.0], params.clone()).await?.full_text, "large-v3.bin");
+
+        params.set_model(Some("base".to_string()));
+        assert_eq!(registry.transcribe(vec![0.0], params.clone()).await?.full_text, "base.bin");
+        assert_eq!(registry.transcribe(vec![0.0], params.clone()).await?.full_text, "base.bin");
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+
+        params.set_model(Some("tiny".to_string()));
+        assert!(registry.transcribe(vec![0.0], params).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used_model() -> Result<()> {
+        let loads = Arc::new(AtomicUsize::new(0));
+        let registry = registry(loads.clone());
+
+        registry.get(Some("large-v3")).await?;
+        registry.get(Some("base")).await?;
+        registry.get(Some("large-v3")).await?;
+        registry.get(Some("small")).await?;
+        assert_eq!(registry.loaded_models().await, vec!["large-v3", "small"]);
+
+        // base was evicted and has to be loaded again
+        registry.get(Some("base")).await?;
+        assert_eq!(loads.load(Ordering::SeqCst), 4);
+        assert_eq!(registry.loaded_models().await, vec!["small", "base"]);
+        Ok(())
+    }
+}
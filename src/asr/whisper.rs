@@ -1,60 +1,107 @@
-use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+use std::ffi::{c_int, c_void, CStr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use whisper_rs::{
+    whisper_rs_sys, FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState,
+    WhisperSysContext, WhisperSysState,
+};
 use anyhow::Result;
-use crate::asr::{AsrEngine, AsrParams, TranscribeResult, TranscribeSegment};
+use tracing::info;
+use crate::asr::{
+    check_sample_rate, AbortOnDrop, AsrEngine, AsrParams, ProgressSender, SamplingMode, TranscribeProgress, TranscribeResult, TranscribeSegment,
+    WordTiming,
+};
+
+/// 引擎级的 whisper 默认参数，在 `WhisperAsr::new_with_config` 时确定
+///
+/// 请求级的 `AsrParams::n_threads`、`AsrParams::temperature`、`AsrParams::translate`
+/// 若设置则覆盖对应的默认值，其中线程数不超过 `threads`；`suppress_blank` 只能在引擎级配置
+#[derive(Debug, Clone)]
+pub struct WhisperConfig {
+    /// 推理线程数，默认为可用的 CPU 核数，同时是请求可使用的线程数上限
+    pub threads: i32,
+    /// 采样温度。较低的值会使输出更加确定，较高的值会增加随机性
+    pub temperature: f32,
+    /// 是否将识别结果翻译为英语
+    pub translate: bool,
+    /// 是否抑制空白输出
+    pub suppress_blank: bool,
+}
+
+impl Default for WhisperConfig {
+    fn default() -> Self {
+        Self {
+            threads: std::thread::available_parallelism().map(|n| n.get() as i32).unwrap_or(4),
+            temperature: 0.3,
+            translate: false,
+            suppress_blank: true,
+        }
+    }
+}
 
+#[derive(Clone)]
 pub struct WhisperAsr {
-    whisper_ctx: WhisperContext,
+    whisper_ctx: Arc<WhisperContext>,
+    config: WhisperConfig,
 }
 
 impl WhisperAsr {
     pub fn new(model_path: String) -> Result<Self> {
+        Self::new_with_config(model_path, WhisperConfig::default())
+    }
+
+    pub fn new_with_config(model_path: String, config: WhisperConfig) -> Result<Self> {
         match WhisperContext::new_with_params(&model_path, WhisperContextParameters::default()) {
-            Ok(whisper_ctx) => Ok(Self { whisper_ctx }),
+            Ok(whisper_ctx) => Ok(Self { whisper_ctx: Arc::new(whisper_ctx), config }),
             Err(e) => Err(anyhow::anyhow!("failed to open whisper model: {}", e)),
         }
     }
 
+    /// 请求使用的推理线程数，不超过引擎配置的线程数
+    fn threads(&self, ap: &AsrParams) -> i32 {
+        ap.n_threads.map_or(self.config.threads, |n| n.min(self.config.threads))
+    }
+
     fn build_params(&self, ap: AsrParams) -> FullParams {
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        // 设置解码采样策略，默认贪心解码
+        let strategy = match ap.sampling {
+            SamplingMode::Greedy { best_of } => SamplingStrategy::Greedy { best_of },
+            SamplingMode::BeamSearch { beam_size, patience } => SamplingStrategy::BeamSearch { beam_size, patience },
+        };
+        let mut params = FullParams::new(strategy);
 
         // 启用说话人分离
         params.set_tdrz_enable(ap.speaker_diarization);
 
-        // 设置单段模式。如果设为true，会将音频分成多个段落进行识别
+        // 设置单段模式。设为 true 时整段音频只输出一个分段，适合短指令等不需要分段的场景
         params.set_single_segment(ap.single_segment);
 
         // 设置采样温度。较低的值会使输出更加确定，较高的值会增加随机性
-        params.set_temperature(0.3);
+        params.set_temperature(ap.temperature.unwrap_or(self.config.temperature));
 
         // 设置使用的线程数，提高并行处理能力
-        params.set_n_threads(8);
-
-        // 设置打印进度
-        params.set_print_progress(true);
+        params.set_n_threads(self.threads(&ap));
 
         // 设置音频上下文大小，提高识别准确度
         // params.set_audio_ctx(600);
 
-        // 禁用翻译功能。如果设为true，会将识别结果翻译为英语
-        params.set_translate(false);
+        // 翻译功能。如果设为true，会将识别结果翻译为英语
+        params.set_translate(ap.translate.unwrap_or(self.config.translate));
 
         // 启用打印特殊标记。这可能包括非语音声音、停顿等
         params.set_print_special(false);
 
-        // 启用打印进度。在处理过程中会显示进度信息
-        params.set_print_progress(true);
+        // 启用打印进度。在处理过程中会显示进度信息，有进度订阅时改为发送给订阅者
+        params.set_print_progress(ap.progress.is_none());
 
         // 启用实时打印。识别结果会实时输出，而不是等待全部处理完成
-        params.set_print_realtime(true);
+        params.set_print_realtime(ap.progress.is_none());
 
         // 禁用无上下文模式。启用上下文可以提高长音频的识别准确度
         params.set_no_context(false);
 
-        // 禁用单段模式。允许将音频分成多个段落进行识别
-        params.set_single_segment(false);
-
-        // 启用制空白。这可以减少输出中的无意义空白
-        params.set_suppress_blank(true);
+        // 抑制空白。这可以减少输出中的无意义空白
+        params.set_suppress_blank(self.config.suppress_blank);
 
         // 启用抑制非语音标记。这可以过滤掉一些非语音的声音
         params.set_suppress_non_speech_tokens(true);
@@ -64,27 +111,223 @@ impl WhisperAsr {
 
         // 设置初始时间戳的最大值。这可以影响分段的起始时间
         params.set_max_initial_ts(1.0);
+
+        // 启用 token 级时间戳，用于生成逐词时间
+        params.set_token_timestamps(ap.token_timestamps);
+
+        // 设置无语音阈值，whisper.cpp 解码时也会参考该值
+        params.set_no_speech_thold(ap.no_speech_threshold);
+
+        // 设置初始提示词，引导模型识别领域词汇（如品牌名、专业术语）
+        if let Some(prompt) = &ap.initial_prompt {
+            params.set_initial_prompt(prompt);
+        }
        
         params
     }
+
+    /// 将分段内的 token 聚合为单词
+    ///
+    /// 以空格开头的 token 视为新单词的开始，特殊 token（如 `[_BEG_]`）会被跳过
+    fn segment_words(state: &WhisperState, segment: i32) -> Result<Vec<WordTiming>> {
+        let num_tokens = state.full_n_tokens(segment)?;
+        let mut words: Vec<WordTiming> = Vec::new();
+        let mut probabilities: Vec<f32> = Vec::new();
+
+        for i in 0..num_tokens {
+            let text = match state.full_get_token_text(segment, i) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            if text.starts_with("[_") || text.starts_with("<|") {
+                continue;
+            }
+            let data = state.full_get_token_data(segment, i)?;
+
+            match words.last_mut() {
+                Some(word) if !text.starts_with(' ') => {
+                    word.text.push_str(&text);
+                    word.end = data.t1 as f64;
+                    probabilities.push(data.p);
+                }
+                _ => {
+                    if let Some(word) = words.last_mut() {
+                        word.probability = probabilities.iter().sum::<f32>() / probabilities.len() as f32;
+                    }
+                    probabilities.clear();
+                    probabilities.push(data.p);
+                    words.push(WordTiming {
+                        text: text.trim_start().to_string(),
+                        start: data.t0 as f64,
+                        end: data.t1 as f64,
+                        probability: data.p,
+                    });
+                }
+            }
+        }
+
+        if let Some(word) = words.last_mut() {
+            word.probability = probabilities.iter().sum::<f32>() / probabilities.len() as f32;
+        }
+
+        Ok(words)
+    }
+
+    /// 分段的置信度，即文本 token 的平均概率，没有文本 token 的分段为 0
+    ///
+    /// whisper-rs 0.11 未暴露 whisper.cpp 的分段 no_speech 概率，以 1 减去置信度近似
+    fn segment_confidence(state: &WhisperState, segment: i32) -> Result<f32> {
+        let num_tokens = state.full_n_tokens(segment)?;
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        for i in 0..num_tokens {
+            let text = match state.full_get_token_text(segment, i) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            if text.starts_with("[_") || text.starts_with("<|") {
+                continue;
+            }
+            sum += state.full_get_token_prob(segment, i)?;
+            count += 1;
+        }
+
+        if count == 0 {
+            return Ok(0.0);
+        }
+        Ok(sum / count as f32)
+    }
+
+    /// 自动检测音频语言
+    ///
+    /// 返回概率最高的语言代码及其概率
+    fn detect_language(state: &mut WhisperState, audio: &[f32], threads: usize) -> Result<(String, f32)> {
+        state.pcm_to_mel(audio, threads)?;
+        let probs = state.lang_detect(0, threads)?;
+
+        let (lang_id, probability) = probs.iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .ok_or_else(|| anyhow::anyhow!("language detection returned no probabilities"))?;
+
+        let lang = whisper_rs::get_lang_str(lang_id as i32)
+            .ok_or_else(|| anyhow::anyhow!("unknown language id: {}", lang_id))?;
+
+        Ok((lang.to_string(), probability))
+    }
 }
 
-#[async_trait::async_trait]
-impl AsrEngine for WhisperAsr {
-    async fn transcribe(&self, audio: Vec<f32>, user_params: AsrParams) -> Result<TranscribeResult> {
+/// whisper.cpp 的进度回调，`user_data` 指向 `ProgressSender`
+unsafe extern "C" fn progress_trampoline(
+    _: *mut WhisperSysContext,
+    _: *mut WhisperSysState,
+    progress: c_int,
+    user_data: *mut c_void,
+) {
+    let sender = &*(user_data as *const ProgressSender);
+    let _ = sender.send(TranscribeProgress::Progress(progress));
+}
+
+/// whisper.cpp 的中止回调，`user_data` 指向中止标记，返回 true 时中止解码
+unsafe extern "C" fn abort_trampoline(user_data: *mut c_void) -> bool {
+    let flag = &*(user_data as *const AtomicBool);
+    flag.load(Ordering::SeqCst)
+}
+
+/// whisper.cpp 的新分段回调，`n_new` 为本次新增的分段数
+unsafe extern "C" fn new_segment_trampoline(
+    _: *mut WhisperSysContext,
+    state: *mut WhisperSysState,
+    n_new: c_int,
+    user_data: *mut c_void,
+) {
+    let sender = &*(user_data as *const ProgressSender);
+    let num_segments = whisper_rs_sys::whisper_full_n_segments_from_state(state);
+    for i in (num_segments - n_new).max(0)..num_segments {
+        let text = whisper_rs_sys::whisper_full_get_segment_text_from_state(state, i);
+        if text.is_null() {
+            continue;
+        }
+        let _ = sender.send(TranscribeProgress::Segment {
+            text: CStr::from_ptr(text).to_string_lossy().into_owned(),
+            start: whisper_rs_sys::whisper_full_get_segment_t0_from_state(state, i) as f64,
+            end: whisper_rs_sys::whisper_full_get_segment_t1_from_state(state, i) as f64,
+        });
+    }
+}
+
+impl WhisperAsr {
+    /// 在当前线程上同步执行一次转写，`AsrParams::abort` 置位后 whisper 中止解码
+    fn transcribe_blocking(&self, audio: Vec<f32>, user_params: AsrParams) -> Result<TranscribeResult> {
+        if let Some(expected) = user_params.expected_duration {
+            check_sample_rate(&audio, expected);
+        }
         let mut state = self.whisper_ctx.create_state()?;
-        let lan = user_params.language.clone().unwrap_or("zh".to_string());
+        // 未指定语言时自动检测，指定语言时直接回显，概率为 1.0
+        // 检测置信度过低时使用回退语言解码，结果中同时保留检测到的语言
+        let (detected_language, language_probability, lan) = match user_params.language.clone() {
+            Some(lan) => (lan.clone(), 1.0, lan),
+            None => {
+                let threads = self.threads(&user_params).max(1) as usize;
+                let (detected, probability) = Self::detect_language(&mut state, &audio, threads)?;
+                let lan = user_params.resolve_language(&detected, probability);
+                if lan != detected {
+                    info!("Detected language {} with probability {:.2}, falling back to {}", detected, probability, lan);
+                }
+                (detected, probability, lan)
+            }
+        };
+        let token_timestamps = user_params.token_timestamps;
+        let no_speech_threshold = user_params.no_speech_threshold;
+        let progress = user_params.progress.clone();
+        let abort = user_params.abort.clone();
         let mut params = self.build_params(user_params);
         params.set_language(Some(lan.as_str()));
 
-        state.full(params, &audio)?;
+        // 将进度和新分段转发给订阅者
+        if let Some(sender) = &progress {
+            let user_data = sender as *const ProgressSender as *mut c_void;
+            // SAFETY: sender 在 state.full 返回之前一直有效，回调只读取 state，不修改上下文
+            unsafe {
+                params.set_progress_callback(Some(progress_trampoline));
+                params.set_progress_callback_user_data(user_data);
+                params.set_new_segment_callback(Some(new_segment_trampoline));
+                params.set_new_segment_callback_user_data(user_data);
+            }
+        }
+
+        // 每个解码步骤之间检查中止标记
+        if let Some(flag) = &abort {
+            let user_data = Arc::as_ptr(flag) as *mut c_void;
+            // SAFETY: flag 在 state.full 返回之前一直有效，回调只读取该标记
+            unsafe {
+                params.set_abort_callback(Some(abort_trampoline));
+                params.set_abort_callback_user_data(user_data);
+            }
+        }
+
+        let full = state.full(params, &audio);
+        if abort.as_ref().is_some_and(|flag| flag.load(Ordering::SeqCst)) {
+            return Err(anyhow::anyhow!("transcription aborted"));
+        }
+        full?;
         let num_segments = state.full_n_segments()?;
 
         let mut segments = Vec::new();
         let mut full_text = String::new();
         let mut current_speaker = 0;
+        let mut dropped = 0;
 
         for i in 0..num_segments {
+            // 丢弃无语音概率过高的分段，通常是静音或音乐段的幻觉输出
+            let confidence = Self::segment_confidence(&state, i)?;
+            if no_speech_threshold < 1.0 && 1.0 - confidence > no_speech_threshold {
+                dropped += 1;
+                continue;
+            }
+
             let text = state.full_get_segment_text(i)?;
             let start = state.full_get_segment_t0(i)?;
             let end = state.full_get_segment_t1(i)?;
@@ -93,22 +336,49 @@ impl AsrEngine for WhisperAsr {
                 current_speaker += 1;
             }
 
+            let words = if token_timestamps {
+                Self::segment_words(&state, i)?
+            } else {
+                Vec::new()
+            };
+
             segments.push(TranscribeSegment {
                 text: text.clone(),
                 speaker_id: current_speaker,
                 start: start as f64,
                 end: end as f64,
+                words,
+                confidence,
             });
 
             full_text.push_str(&text);
         }
 
+        if dropped > 0 {
+            info!("Dropped {} of {} segments above no speech threshold {}", dropped, num_segments, no_speech_threshold);
+        }
+
         Ok(TranscribeResult {
             segments,
             full_text,
+            detected_language,
+            language: lan,
+            language_probability,
         })
     }
+}
 
+#[async_trait::async_trait]
+impl AsrEngine for WhisperAsr {
+    async fn transcribe(&self, audio: Vec<f32>, mut user_params: AsrParams) -> Result<TranscribeResult> {
+        user_params.validate()?;
+        // 推理是 CPU 密集的同步调用，放到阻塞线程上执行以免占用异步运行时的工作线程
+        let abort = user_params.abort.clone().unwrap_or_default();
+        user_params.set_abort(Some(abort.clone()));
+        let _guard = AbortOnDrop(abort);
+        let engine = self.clone();
+        tokio::task::spawn_blocking(move || engine.transcribe_blocking(audio, user_params)).await?
+    }
 }
 
 
@@ -154,4 +424,33 @@ mod tests {
     
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    #[ignore = "needs the whisper model at ./models/ggml-large-v3.bin"]
+    async fn test_transcribe_with_initial_prompt() -> Result<()> {
+        let audio_path = Path::new("./test/2.wav");
+        let whisper_path = Path::new("./models/ggml-large-v3.bin");
+
+        if !audio_path.exists() {
+            panic!("audio file doesn't exist");
+        }
+        if !whisper_path.exists() {
+            panic!("whisper file doesn't exist");
+        }
+
+        let processed_audio = parse_audio_file(&audio_path, false, 0.0)?;
+        let asr = WhisperAsr::new(whisper_path.to_string_lossy().to_string())?;
+
+        let mut params = AsrParams::new();
+        params.set_language(Some("zh".to_string()));
+        let plain = asr.transcribe(processed_audio.clone(), params.clone()).await?;
+
+        // 提示词会引导模型偏向指定的词汇，这里引导输出繁体字
+        params.set_initial_prompt(Some("以下是繁體中文的句子。".to_string()));
+        let prompted = asr.transcribe(processed_audio, params).await?;
+
+        assert_ne!(plain.full_text, prompted.full_text);
+
+        Ok(())
+    }
+}
@@ -7,8 +7,14 @@ use std::fs;
 use rustfft::{FftPlanner, num_complex::Complex};
 use std::sync::Arc;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use tracing::{info, error};
 
+mod pipeline;
+pub mod voiceprint;
+
+pub use pipeline::{PreprocessPipeline, PreprocessStage};
+
 pub enum AudioFormat {
     Wav,
     Aac,
@@ -21,6 +27,54 @@ pub enum AudioFormat {
     Flac,
 }
 
+// RMS 目标增益的最大提升（dB），避免将静音段放大成噪声
+const MAX_RMS_GAIN_DB: f32 = 30.0;
+// 估计噪声功率谱使用的开头帧数
+const NOISE_ESTIMATE_FRAMES: usize = 20;
+// 降噪时一批并行处理的帧数
+const FRAMES_PER_BATCH: usize = 64;
+
+/// 音量增益方式
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Gain {
+    /// 固定增益（dB）
+    FixedDb(f32),
+    /// 将整体 RMS 提升到目标值（线性幅度，0 到 1 之间）
+    TargetRms(f32),
+}
+
+/// 音频预处理配置
+#[derive(Debug, Clone)]
+pub struct PreprocessConfig {
+    pub enable_noise_reduction: bool,
+    pub noise_reduction_strength: f32,
+    /// 归一化之后应用的增益，默认不启用
+    pub gain: Option<Gain>,
+    /// 增益之后依次执行的预处理阶段
+    pub pipeline: PreprocessPipeline,
+}
+
+impl PreprocessConfig {
+    pub fn new(enable_noise_reduction: bool, noise_reduction_strength: f32) -> Self {
+        Self {
+            enable_noise_reduction,
+            noise_reduction_strength,
+            gain: None,
+            pipeline: PreprocessPipeline::default_with(enable_noise_reduction, noise_reduction_strength),
+        }
+    }
+
+    pub fn with_gain(mut self, gain: Option<Gain>) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    pub fn with_pipeline(mut self, pipeline: PreprocessPipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+}
+
 /// 解析音频文件并进行预处理
 /// 
 /// 该函数读取音频文件，将其转换为WAV格式（如果需要），然后将其转换为单声道、归一化，并进行一系列预处理步骤
@@ -41,6 +95,13 @@ pub enum AudioFormat {
 /// 7. 应用噪声门限
 /// 8. 如果需要，重采样到16kHz
 pub fn parse_audio_file(path: &Path, enable_noise_reduction: bool, noise_reduction_strength: f32) -> Result<Vec<f32>> {
+    parse_audio_file_with_config(path, &PreprocessConfig::new(enable_noise_reduction, noise_reduction_strength))
+}
+
+/// 按预处理配置解析音频文件
+///
+/// 与 `parse_audio_file` 相同，额外支持在归一化后应用增益，并按 `config.pipeline` 的顺序执行预处理阶段
+pub fn parse_audio_file_with_config(path: &Path, config: &PreprocessConfig) -> Result<Vec<f32>> {
     let wav_path = ensure_wav_format(path)?;
     let (samples, num_channels, sample_rate) = read_wav_file(&wav_path)?;
     
@@ -56,23 +117,119 @@ pub fn parse_audio_file(path: &Path, enable_noise_reduction: bool, noise_reducti
 
     let mono_samples = convert_to_mono(&samples, num_channels);
     let normalized_samples = normalize_audio(&mono_samples);
-    let processed_samples = if enable_noise_reduction {
-        spectral_noise_reduction(&normalized_samples, 2048, 0.75, noise_reduction_strength)
-    } else {
-        normalized_samples
+    let normalized_samples = match &config.gain {
+        Some(gain) => apply_gain(&normalized_samples, gain),
+        None => normalized_samples,
     };
-    let vad_samples = voice_activity_detection(&processed_samples, 1024, 0.005);
-    let emphasized_samples = apply_pre_emphasis(&vad_samples, 0.97);
-    let gated_samples = apply_noise_gate(&emphasized_samples, 0.01);
+    let processed_samples = config.pipeline.run(normalized_samples);
     
     if sample_rate != 16000 {
-        Ok(resample_audio(&gated_samples, sample_rate))
+        Ok(resample_audio(&processed_samples, sample_rate))
     } else {
         info!("Sample rate is already 16000 Hz, no resampling needed.");
-        Ok(gated_samples)
+        Ok(processed_samples)
     }
 }
 
+/// 音频源文件信息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioInfo {
+    /// 容器格式，如 `wav`、`mp3`
+    pub format: String,
+    /// 编码格式，如 `pcm_s16le`，无法识别时为空
+    pub codec: Option<String>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// 时长（秒）
+    pub duration_secs: f64,
+}
+
+/// 探测音频文件的格式、采样率、声道数和时长
+///
+/// WAV 文件直接读取文件头，其他格式依赖系统中安装的 ffprobe。
+/// 读取文件头在阻塞线程池中进行，ffprobe 以异步子进程运行，都不会阻塞运行时
+pub async fn probe(path: &Path) -> Result<AudioInfo> {
+    let is_wav = path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("wav"));
+    if is_wav {
+        let path = path.to_path_buf();
+        return tokio::task::spawn_blocking(move || probe_wav(&path)).await?;
+    }
+
+    let output = tokio::process::Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "a:0"])
+        .args(["-show_entries", "stream=codec_name,sample_rate,channels:format=format_name,duration"])
+        .args(["-of", "json"])
+        .arg(path)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to execute ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("ffprobe failed with status: {}", output.status));
+    }
+
+    let probed: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    let stream = probed["streams"].get(0)
+        .ok_or_else(|| anyhow::anyhow!("No audio stream found"))?;
+    // ffprobe 以字符串形式输出采样率和时长
+    let number = |value: &serde_json::Value| value.as_str().and_then(|s| s.parse::<f64>().ok());
+    Ok(AudioInfo {
+        format: probed["format"]["format_name"].as_str().unwrap_or("unknown").to_string(),
+        codec: stream["codec_name"].as_str().map(str::to_string),
+        sample_rate: number(&stream["sample_rate"]).unwrap_or(0.0) as u32,
+        channels: stream["channels"].as_u64().unwrap_or(0) as u16,
+        duration_secs: number(&probed["format"]["duration"]).unwrap_or(0.0),
+    })
+}
+
+fn probe_wav(path: &Path) -> Result<AudioInfo> {
+    let reader = WavReader::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read WAV file: {}", e))?;
+    let spec = reader.spec();
+    let codec = match spec.sample_format {
+        SampleFormat::Int => format!("pcm_s{}le", spec.bits_per_sample),
+        SampleFormat::Float => format!("pcm_f{}le", spec.bits_per_sample),
+    };
+    Ok(AudioInfo {
+        format: "wav".to_string(),
+        codec: Some(codec),
+        sample_rate: spec.sample_rate,
+        channels: spec.channels,
+        duration_secs: reader.duration() as f64 / spec.sample_rate as f64,
+    })
+}
+
+/// 音频时长低于下限
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioTooShort {
+    pub duration_secs: f64,
+    pub min_secs: f64,
+}
+
+impl std::fmt::Display for AudioTooShort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "audio is {:.2}s long, shorter than the minimum of {:.2}s", self.duration_secs, self.min_secs)
+    }
+}
+
+impl std::error::Error for AudioTooShort {}
+
+/// 检查音频时长不低于 `min_secs`，过短时返回 `AudioTooShort`
+///
+/// `min_secs` 不大于 0 时不做检查，也不会探测文件
+pub async fn check_min_duration(path: &Path, min_secs: f64) -> Result<()> {
+    if min_secs <= 0.0 {
+        return Ok(());
+    }
+    let info = probe(path).await?;
+    if info.duration_secs < min_secs {
+        return Err(AudioTooShort { duration_secs: info.duration_secs, min_secs }.into());
+    }
+    Ok(())
+}
+
 /// 确保音频文件为WAV格式
 /// 
 /// 如果输入文件不是WAV格式，使用FFmpeg将其转换为WAV格式
@@ -183,6 +340,35 @@ fn normalize_audio(samples: &[f32]) -> Vec<f32> {
     samples.par_iter().map(|&s| s / max_abs).collect()
 }
 
+/// 应用增益
+///
+/// 按固定 dB 或目标 RMS 放大音频，结果限制在[-1, 1]范围内以防止削波溢出
+///
+/// # 参数
+/// * `samples` - 输入的音频样本（已归一化）
+/// * `gain` - 增益方式
+///
+/// # 返回值
+/// * `Vec<f32>` - 应用增益后的音频样本
+pub fn apply_gain(samples: &[f32], gain: &Gain) -> Vec<f32> {
+    let factor = match gain {
+        Gain::FixedDb(db) => 10f32.powf(db / 20.0),
+        Gain::TargetRms(target) => {
+            let rms = (samples.par_iter().map(|&s| s * s).sum::<f32>() / samples.len().max(1) as f32).sqrt();
+            if rms <= f32::EPSILON {
+                1.0
+            } else {
+                (target / rms).clamp(1.0, 10f32.powf(MAX_RMS_GAIN_DB / 20.0))
+            }
+        }
+    };
+    info!("Applying gain factor {:.2}", factor);
+
+    samples.par_iter()
+        .map(|&s| (s * factor).clamp(-1.0, 1.0))
+        .collect()
+}
+
 /// 应用预加重
 /// 
 /// 对音频样本应用预加滤波器，以增强高频成分
@@ -294,35 +480,44 @@ pub fn spectral_noise_reduction(samples: &[f32], frame_size: usize, overlap: f32
     let fft = planner.plan_fft_forward(frame_size);
     let ifft = planner.plan_fft_inverse(frame_size);
 
-    let frames = samples.windows(frame_size).step_by(step_size).collect::<Vec<_>>();
-    let noise_power = estimate_noise_power(&frames, &fft);
+    // 帧按需从输入中切出，不保存全部帧和频谱
+    let frames = || samples.windows(frame_size).step_by(step_size);
+    let noise_power = estimate_noise_power(frames().take(NOISE_ESTIMATE_FRAMES), &fft);
 
-    let processed_frames: Vec<Vec<Complex<f32>>> = frames.par_iter().map(|frame| {
-        let mut fft_input: Vec<Complex<f32>> = frame.iter()
-            .enumerate()
-            .map(|(i, &s)| Complex::new(s * hann_window(i, frame_size), 0.0))
-            .collect();
+    // 每批并行处理固定数量的帧后按顺序叠加，内存只与帧大小有关，与音频长度无关
+    let mut output = vec![0.0; samples.len()];
+    let mut batch: Vec<(usize, &[f32])> = Vec::with_capacity(FRAMES_PER_BATCH);
+    let mut spectra: Vec<Vec<Complex<f32>>> = Vec::with_capacity(FRAMES_PER_BATCH);
+    let mut remaining = frames().enumerate().peekable();
+    while remaining.peek().is_some() {
+        batch.clear();
+        batch.extend(remaining.by_ref().take(FRAMES_PER_BATCH));
+        // 复用上一批的缓冲区
+        spectra.resize_with(batch.len(), || vec![Complex::new(0.0, 0.0); frame_size]);
+        batch.par_iter().zip(spectra.par_iter_mut()).for_each(|(&(_, frame), spectrum)| {
+            for (i, (bin, &s)) in spectrum.iter_mut().zip(frame).enumerate() {
+                *bin = Complex::new(s * hann_window(i, frame_size), 0.0);
+            }
 
-        fft.process(&mut fft_input);
+            fft.process(spectrum);
 
-        for (i, complex) in fft_input.iter_mut().enumerate() {
-            let power = complex.norm_sqr();
-            let noise = noise_power[i];
-            let snr = power / (noise + 1e-10);
-            let gain = 1.0 - (strength / (snr + 1.0)).min(1.0);  // 更温和的降噪
-            *complex *= gain.sqrt();
-        }
+            for (i, complex) in spectrum.iter_mut().enumerate() {
+                let power = complex.norm_sqr();
+                let noise = noise_power[i];
+                let snr = power / (noise + 1e-10);
+                let gain = 1.0 - (strength / (snr + 1.0)).min(1.0);  // 更温和的降噪
+                *complex *= gain.sqrt();
+            }
 
-        ifft.process(&mut fft_input);
-        fft_input
-    }).collect();
+            ifft.process(spectrum);
+        });
 
-    let mut output = vec![0.0; samples.len()];
-    for (i, frame) in processed_frames.iter().enumerate() {
-        let start = i * step_size;
-        for (j, &complex) in frame.iter().enumerate() {
-            if start + j < output.len() {
-                output[start + j] += complex.re / (frame_size as f32);
+        for (&(index, _), spectrum) in batch.iter().zip(&spectra) {
+            let start = index * step_size;
+            for (j, &complex) in spectrum.iter().enumerate() {
+                if start + j < output.len() {
+                    output[start + j] += complex.re / (frame_size as f32);
+                }
             }
         }
     }
@@ -343,12 +538,57 @@ pub fn spectral_noise_reduction(samples: &[f32], frame_size: usize, overlap: f32
     output
 }
 
-fn estimate_noise_power(frames: &[&[f32]], fft: &Arc<dyn rustfft::Fft<f32>>) -> Vec<f32> {
+/// 对音频文件降噪并保存为 WAV 文件
+///
+/// 输入文件转换为单声道后按原采样率执行 `spectral_noise_reduction`，
+/// 结果以 16 位单声道 WAV 写入 `output`
+///
+/// # 参数
+/// * `input` - 输入音频文件的路径，非 WAV 格式时需要 FFmpeg
+/// * `output` - 输出 WAV 文件的路径
+/// * `frame_size` - FFT帧大小
+/// * `overlap` - 帧重叠率
+/// * `strength` - 降噪强度（0.0-1.0）
+pub fn denoise_file(input: &Path, output: &Path, frame_size: usize, overlap: f32, strength: f32) -> Result<()> {
+    let wav_path = ensure_wav_format(input)?;
+    let (samples, num_channels, sample_rate) = read_wav_file(&wav_path)?;
+    if wav_path != input {
+        if let Err(e) = fs::remove_file(&wav_path) {
+            error!("Failed to remove temporary WAV file: {}", e);
+        }
+    }
+
+    let mono_samples = convert_to_mono(&samples, num_channels);
+    if mono_samples.len() < frame_size {
+        return Err(anyhow::anyhow!(
+            "Audio is shorter than one frame: {} < {} samples", mono_samples.len(), frame_size
+        ));
+    }
+    let denoised = spectral_noise_reduction(&mono_samples, frame_size, overlap, strength);
+
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(output, spec)
+        .map_err(|e| anyhow::anyhow!("Failed to create WAV file: {}", e))?;
+    for sample in denoised {
+        // 均衡化后可能超出 16 位范围
+        writer.write_sample(sample.clamp(i16::MIN as f32, i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+
+    Ok(())
+}
+
+fn estimate_noise_power<'a>(frames: impl Iterator<Item = &'a [f32]> + Clone, fft: &Arc<dyn rustfft::Fft<f32>>) -> Vec<f32> {
     let frame_size = fft.len();
     let mut noise_power = vec![0.0; frame_size];
-    let num_frames = frames.len().min(20);  // 使用前20帧或所有帧（如果少于20帧）
+    let num_frames = frames.clone().count();  // 使用前20帧或所有帧（如果少于20帧）
 
-    for frame in frames.iter().take(num_frames) {
+    for frame in frames {
         let mut fft_input: Vec<Complex<f32>> = frame.iter()
             .enumerate()
             .map(|(i, &s)| Complex::new(s * hann_window(i, frame_size), 0.0))
@@ -424,6 +664,100 @@ mod tests {
     use hound::{WavSpec, WavWriter};
     use std::fs;
 
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|&s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_apply_gain() {
+        // quiet speech with a single loud click that sets the peak
+        let mut samples: Vec<f32> = (0..16000)
+            .map(|i| 0.02 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin())
+            .collect();
+        samples[8000] = 1.0;
+        let original_rms = rms(&samples);
+
+        for gain in [Gain::FixedDb(12.0), Gain::TargetRms(0.1)] {
+            let boosted = apply_gain(&samples, &gain);
+            assert!(rms(&boosted) > original_rms);
+
+            // the click is limited instead of overflowing
+            let peak = boosted.iter().fold(0.0f32, |a, &b| a.max(b.abs()));
+            assert!(peak <= 1.0 + 1e-6);
+        }
+    }
+
+    // the implementation before frames were streamed: every frame and spectrum is kept until overlap-add
+    fn collected_noise_reduction(samples: &[f32], frame_size: usize, overlap: f32, strength: f32) -> Vec<f32> {
+        let step_size = (frame_size as f32 * (1.0 - overlap)) as usize;
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(frame_size);
+        let ifft = planner.plan_fft_inverse(frame_size);
+
+        let frames = samples.windows(frame_size).step_by(step_size).collect::<Vec<_>>();
+        let noise_power = estimate_noise_power(frames.iter().copied().take(NOISE_ESTIMATE_FRAMES), &fft);
+
+        let processed_frames: Vec<Vec<Complex<f32>>> = frames.par_iter().map(|frame| {
+            let mut fft_input: Vec<Complex<f32>> = frame.iter()
+                .enumerate()
+                .map(|(i, &s)| Complex::new(s * hann_window(i, frame_size), 0.0))
+                .collect();
+            fft.process(&mut fft_input);
+            for (i, complex) in fft_input.iter_mut().enumerate() {
+                let snr = complex.norm_sqr() / (noise_power[i] + 1e-10);
+                let gain = 1.0 - (strength / (snr + 1.0)).min(1.0);
+                *complex *= gain.sqrt();
+            }
+            ifft.process(&mut fft_input);
+            fft_input
+        }).collect();
+
+        let mut output = vec![0.0; samples.len()];
+        for (i, frame) in processed_frames.iter().enumerate() {
+            let start = i * step_size;
+            for (j, &complex) in frame.iter().enumerate() {
+                if start + j < output.len() {
+                    output[start + j] += complex.re / (frame_size as f32);
+                }
+            }
+        }
+
+        output = smooth_signal(&output, 5);
+        remove_dc_offset(&mut output);
+        let max_abs = output.iter().fold(0.0f32, |a, &b| a.max(b.abs()));
+        let gain_factor = samples.iter().fold(0.0f32, |a, &b| a.max(b.abs())) / max_abs;
+        output.iter_mut().for_each(|x| *x *= gain_factor);
+        apply_equalization(&mut output);
+        output
+    }
+
+    #[test]
+    fn test_streaming_noise_reduction_matches_collected() {
+        // three seconds of a tone in noise, enough frames for several batches
+        let mut state = 12345u32;
+        let samples: Vec<f32> = (0..48000)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let noise = (state as f32 / u32::MAX as f32 - 0.5) * 2000.0;
+                let tone = if i > 16000 { 8000.0 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin() } else { 0.0 };
+                tone + noise
+            })
+            .collect();
+
+        for (frame_size, overlap) in [(512, 0.5), (1024, 0.75), (2048, 0.75)] {
+            assert!(samples.len() / ((frame_size as f32 * (1.0 - overlap)) as usize) > FRAMES_PER_BATCH);
+            let streamed = spectral_noise_reduction(&samples, frame_size, overlap, 0.6);
+            let collected = collected_noise_reduction(&samples, frame_size, overlap, 0.6);
+            assert_eq!(streamed.len(), collected.len());
+
+            let peak = collected.iter().fold(0.0f32, |a, &b| a.max(b.abs()));
+            let max_diff = streamed.iter().zip(&collected).fold(0.0f32, |a, (x, y)| a.max((x - y).abs()));
+            assert!(max_diff <= peak * 1e-5, "frame size {}: {} vs peak {}", frame_size, max_diff, peak);
+        }
+    }
+
     #[test]
     fn test_spectral_noise_reduction() -> Result<()> {
         let input_path = Path::new("./test/1.wav");
@@ -470,4 +804,36 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_check_min_duration() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let write = |name: &str, samples: usize| -> Result<std::path::PathBuf> {
+            let path = dir.path().join(name);
+            let mut writer = WavWriter::create(&path, spec)?;
+            for i in 0..samples {
+                writer.write_sample(((i as f32 * 0.05).sin() * 8000.0) as i16)?;
+            }
+            writer.finalize()?;
+            Ok(path)
+        };
+        let short = write("short.wav", 3200)?;
+        let long = write("long.wav", 32000)?;
+
+        let err = check_min_duration(&short, 1.0).await.unwrap_err();
+        let too_short = err.downcast_ref::<AudioTooShort>().expect("too short error");
+        assert!((too_short.duration_secs - 0.2).abs() < 1e-6);
+        assert_eq!(too_short.min_secs, 1.0);
+
+        check_min_duration(&long, 1.0).await?;
+        // no minimum configured
+        check_min_duration(&short, 0.0).await?;
+        Ok(())
+    }
 }
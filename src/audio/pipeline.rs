@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+use super::{apply_noise_gate, apply_pre_emphasis, spectral_noise_reduction, voice_activity_detection};
+
+/// 预处理阶段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum PreprocessStage {
+    /// 频谱降噪
+    NoiseReduction { strength: f32 },
+    /// 语音活动检测
+    Vad { frame_size: usize, threshold: f32 },
+    /// 预加重
+    PreEmphasis { coefficient: f32 },
+    /// 噪声门限
+    NoiseGate { threshold: f32 },
+}
+
+impl PreprocessStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PreprocessStage::NoiseReduction { .. } => "noise_reduction",
+            PreprocessStage::Vad { .. } => "vad",
+            PreprocessStage::PreEmphasis { .. } => "pre_emphasis",
+            PreprocessStage::NoiseGate { .. } => "noise_gate",
+        }
+    }
+
+    pub fn apply(&self, samples: &[f32]) -> Vec<f32> {
+        match self {
+            PreprocessStage::NoiseReduction { strength } => {
+                spectral_noise_reduction(samples, 2048, 0.75, *strength)
+            }
+            PreprocessStage::Vad { frame_size, threshold } => {
+                voice_activity_detection(samples, *frame_size, *threshold)
+            }
+            PreprocessStage::PreEmphasis { coefficient } => apply_pre_emphasis(samples, *coefficient),
+            PreprocessStage::NoiseGate { threshold } => apply_noise_gate(samples, *threshold),
+        }
+    }
+}
+
+/// 有序的预处理流水线
+///
+/// 阶段按列表顺序执行，可以调整顺序、省略或重复某个阶段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreprocessPipeline {
+    pub stages: Vec<PreprocessStage>,
+}
+
+impl PreprocessPipeline {
+    pub fn new(stages: Vec<PreprocessStage>) -> Self {
+        Self { stages }
+    }
+
+    /// 默认顺序：降噪（可选） → 语音活动检测 → 预加重 → 噪声门限
+    pub fn default_with(enable_noise_reduction: bool, noise_reduction_strength: f32) -> Self {
+        let mut stages = Vec::with_capacity(4);
+        if enable_noise_reduction {
+            stages.push(PreprocessStage::NoiseReduction { strength: noise_reduction_strength });
+        }
+        stages.push(PreprocessStage::Vad { frame_size: 1024, threshold: 0.005 });
+        stages.push(PreprocessStage::PreEmphasis { coefficient: 0.97 });
+        stages.push(PreprocessStage::NoiseGate { threshold: 0.01 });
+        Self { stages }
+    }
+
+    pub fn run(&self, samples: Vec<f32>) -> Vec<f32> {
+        self.run_inspect(samples, |_, _| {})
+    }
+
+    /// 执行流水线，每个阶段完成后调用 `inspect`
+    pub fn run_inspect<F>(&self, samples: Vec<f32>, mut inspect: F) -> Vec<f32>
+    where
+        F: FnMut(&PreprocessStage, &[f32]),
+    {
+        self.stages.iter().fold(samples, |samples, stage| {
+            let output = stage.apply(&samples);
+            inspect(stage, &output);
+            output
+        })
+    }
+}
+
+impl Default for PreprocessPipeline {
+    fn default() -> Self {
+        Self::default_with(true, 0.75)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_stage_order() {
+        // silence followed by a tone, so VAD and gate both have work to do
+        let samples: Vec<f32> = (0..8192)
+            .map(|i| if i < 4096 { 0.001 } else { 0.5 * (i as f32 * 0.05).sin() })
+            .collect();
+
+        let pipeline = PreprocessPipeline::new(vec![
+            PreprocessStage::NoiseGate { threshold: 0.01 },
+            PreprocessStage::Vad { frame_size: 1024, threshold: 0.005 },
+            PreprocessStage::NoiseGate { threshold: 0.01 },
+        ]);
+
+        let mut order = Vec::new();
+        let output = pipeline.run_inspect(samples.clone(), |stage, _| order.push(stage.name()));
+        assert_eq!(order, vec!["noise_gate", "vad", "noise_gate"]);
+
+        let expected = apply_noise_gate(&samples, 0.01);
+        let expected = voice_activity_detection(&expected, 1024, 0.005);
+        let expected = apply_noise_gate(&expected, 0.01);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_default_order() {
+        let names: Vec<_> = PreprocessPipeline::default_with(false, 0.75)
+            .stages
+            .iter()
+            .map(|stage| stage.name())
+            .collect();
+        assert_eq!(names, vec!["vad", "pre_emphasis", "noise_gate"]);
+    }
+}
@@ -0,0 +1,236 @@
+use rustfft::{FftPlanner, num_complex::Complex};
+use anyhow::Result;
+
+const SAMPLE_RATE: f32 = 16000.0;
+// 25ms 的帧，10ms 的步长
+const FRAME_SIZE: usize = 400;
+const HOP_SIZE: usize = 160;
+const FFT_SIZE: usize = 512;
+const MEL_FILTERS: usize = 26;
+const MFCC_COEFFICIENTS: usize = 13;
+// 能量低于平均帧能量这一比例的帧视为静音，不参与声纹计算
+const SILENCE_RATIO: f32 = 0.1;
+// 计算声纹至少需要的有声帧数，约 0.5 秒
+const MIN_VOICED_FRAMES: usize = 50;
+
+/// 声纹向量的维度：除 c0 外每个 MFCC 系数的均值和标准差
+pub const EMBEDDING_DIM: usize = 2 * (MFCC_COEFFICIENTS - 1);
+
+/// 有声部分太短，无法提取声纹
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotEnoughSpeech {
+    pub voiced_frames: usize,
+}
+
+impl std::fmt::Display for NotEnoughSpeech {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "not enough speech for a voiceprint: {} voiced frames, at least {} needed",
+            self.voiced_frames, MIN_VOICED_FRAMES
+        )
+    }
+}
+
+impl std::error::Error for NotEnoughSpeech {}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+/// 三角 mel 滤波器组，每个滤波器覆盖 `FFT_SIZE / 2 + 1` 个频点
+fn mel_filterbank() -> Vec<Vec<f32>> {
+    let bins = FFT_SIZE / 2 + 1;
+    let max_mel = hz_to_mel(SAMPLE_RATE / 2.0);
+    // 滤波器边界对应的频点，两端各多一个
+    let points: Vec<f32> = (0..MEL_FILTERS + 2)
+        .map(|i| mel_to_hz(max_mel * i as f32 / (MEL_FILTERS + 1) as f32) * FFT_SIZE as f32 / SAMPLE_RATE)
+        .collect();
+
+    (0..MEL_FILTERS)
+        .map(|m| {
+            let (left, center, right) = (points[m], points[m + 1], points[m + 2]);
+            (0..bins)
+                .map(|bin| {
+                    let bin = bin as f32;
+                    if bin > left && bin <= center {
+                        (bin - left) / (center - left)
+                    } else if bin > center && bin < right {
+                        (right - bin) / (right - center)
+                    } else {
+                        0.0
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// 计算 16kHz 单声道音频的 MFCC
+///
+/// # 返回值
+/// * 每帧的 MFCC 系数及该帧的能量，c0 为对数能量
+pub fn mfcc(samples: &[f32]) -> Vec<([f32; MFCC_COEFFICIENTS], f32)> {
+    if samples.len() < FRAME_SIZE {
+        return Vec::new();
+    }
+
+    let mut planner = FftPlanner::new();
+    let fft = planner.plan_fft_forward(FFT_SIZE);
+    let filterbank = mel_filterbank();
+    let window: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| 0.54 - 0.46 * (2.0 * std::f32::consts::PI * i as f32 / (FRAME_SIZE - 1) as f32).cos())
+        .collect();
+
+    samples
+        .windows(FRAME_SIZE)
+        .step_by(HOP_SIZE)
+        .map(|frame| {
+            let energy = frame.iter().map(|&s| s * s).sum::<f32>() / FRAME_SIZE as f32;
+
+            let mut spectrum: Vec<Complex<f32>> = frame
+                .iter()
+                .zip(&window)
+                .map(|(&s, &w)| Complex::new(s * w, 0.0))
+                .chain(std::iter::repeat(Complex::new(0.0, 0.0)))
+                .take(FFT_SIZE)
+                .collect();
+            fft.process(&mut spectrum);
+            let power: Vec<f32> = spectrum[..FFT_SIZE / 2 + 1]
+                .iter()
+                .map(|c| c.norm_sqr() / FFT_SIZE as f32)
+                .collect();
+
+            let log_energies: Vec<f32> = filterbank
+                .iter()
+                .map(|filter| {
+                    let energy: f32 = filter.iter().zip(&power).map(|(w, p)| w * p).sum();
+                    energy.max(1e-10).ln()
+                })
+                .collect();
+
+            // DCT-II
+            let mut coefficients = [0.0; MFCC_COEFFICIENTS];
+            for (k, coefficient) in coefficients.iter_mut().enumerate() {
+                *coefficient = log_energies
+                    .iter()
+                    .enumerate()
+                    .map(|(m, &e)| e * (std::f32::consts::PI * k as f32 * (m as f32 + 0.5) / MEL_FILTERS as f32).cos())
+                    .sum();
+            }
+            (coefficients, energy)
+        })
+        .collect()
+}
+
+/// 从 16kHz 单声道音频提取声纹
+///
+/// 跳过静音帧，对其余帧的 MFCC（不含 c0，使声纹与音量无关）取均值和标准差，
+/// 结果归一化为单位向量
+///
+/// # 错误
+/// * `NotEnoughSpeech` - 有声帧不足
+pub fn extract_embedding(samples: &[f32]) -> Result<Vec<f32>> {
+    let frames = mfcc(samples);
+    let mean_energy = frames.iter().map(|(_, energy)| energy).sum::<f32>() / frames.len().max(1) as f32;
+    let voiced: Vec<&[f32; MFCC_COEFFICIENTS]> = frames
+        .iter()
+        .filter(|(_, energy)| *energy > 0.0 && *energy >= mean_energy * SILENCE_RATIO)
+        .map(|(coefficients, _)| coefficients)
+        .collect();
+    if voiced.len() < MIN_VOICED_FRAMES {
+        return Err(NotEnoughSpeech { voiced_frames: voiced.len() }.into());
+    }
+
+    let count = voiced.len() as f32;
+    let mut embedding = Vec::with_capacity(EMBEDDING_DIM);
+    let means: Vec<f32> = (1..MFCC_COEFFICIENTS)
+        .map(|k| voiced.iter().map(|c| c[k]).sum::<f32>() / count)
+        .collect();
+    let deviations: Vec<f32> = (1..MFCC_COEFFICIENTS)
+        .map(|k| {
+            let mean = means[k - 1];
+            (voiced.iter().map(|c| (c[k] - mean).powi(2)).sum::<f32>() / count).sqrt()
+        })
+        .collect();
+    embedding.extend(means);
+    embedding.extend(deviations);
+
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|x| *x /= norm);
+    }
+    Ok(embedding)
+}
+
+/// 两个声纹的余弦相似度，-1 到 1 之间，维度不同时为 0
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 合成一段"语音"：基频的谐波，幅度按以 `formant` 为中心的共振峰衰减
+    fn synthetic_voice(fundamental: f32, formant: f32, seconds: f32, seed: u32) -> Vec<f32> {
+        let mut state = seed.wrapping_mul(2654435761).max(1);
+        let len = (SAMPLE_RATE * seconds) as usize;
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE;
+                let mut sample = 0.0;
+                let mut harmonic = fundamental;
+                while harmonic < SAMPLE_RATE / 2.0 {
+                    let distance = (harmonic - formant) / 400.0;
+                    sample += (-distance * distance).exp() * (2.0 * std::f32::consts::PI * harmonic * t).sin();
+                    harmonic += fundamental;
+                }
+                // 少量噪声，使不同片段不完全相同
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let noise = (state as f32 / u32::MAX as f32 - 0.5) * 0.02;
+                0.3 * sample + noise
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_embeddings_separate_speakers() {
+        let alice = extract_embedding(&synthetic_voice(120.0, 700.0, 2.0, 1)).unwrap();
+        let alice_again = extract_embedding(&synthetic_voice(120.0, 700.0, 1.5, 2)).unwrap();
+        let bob = extract_embedding(&synthetic_voice(220.0, 2200.0, 2.0, 3)).unwrap();
+        assert_eq!(alice.len(), EMBEDDING_DIM);
+
+        let same = cosine_similarity(&alice, &alice_again);
+        let different = cosine_similarity(&alice, &bob);
+        assert!(same > 0.9, "{}", same);
+        assert!(same > different, "{} vs {}", same, different);
+
+        // 音量不影响声纹
+        let quiet: Vec<f32> = synthetic_voice(120.0, 700.0, 2.0, 1).iter().map(|s| s * 0.25).collect();
+        let quiet = extract_embedding(&quiet).unwrap();
+        assert!(cosine_similarity(&alice, &quiet) > 0.99);
+    }
+
+    #[test]
+    fn test_silence_has_no_embedding() {
+        let error = extract_embedding(&vec![0.0; 16000]).unwrap_err();
+        assert!(error.downcast_ref::<NotEnoughSpeech>().is_some());
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0]), 0.0);
+    }
+}
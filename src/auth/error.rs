@@ -8,17 +8,33 @@ pub enum AuthError {
     KeySuspended,
     InsufficientPermissions,
     RateLimitExceeded,
+    // the key a management call refers to does not exist
+    KeyNotFound,
+    // a management call with an invalid value, e.g. a negative minimum audio duration
+    InvalidInput(String),
     StorageError(String),
 }
 
 impl Display for AuthError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            AuthError::InvalidApiKey => write!(f, "Invalid API key"),
+            AuthError::MissingApiKey => write!(f, "Missing API key"),
+            AuthError::KeyExpired => write!(f, "API key has expired"),
+            AuthError::KeySuspended => write!(f, "API key is suspended"),
+            AuthError::InsufficientPermissions => write!(f, "Insufficient permissions"),
+            AuthError::RateLimitExceeded => write!(f, "Rate limit exceeded"),
+            AuthError::KeyNotFound => write!(f, "API key not found"),
+            AuthError::InvalidInput(message) => write!(f, "{}", message),
+            AuthError::StorageError(error) => write!(f, "API key storage error: {}", error),
+        }
     }
 }
 
+impl std::error::Error for AuthError {}
+
 impl From<String> for AuthError {
     fn from(error: String) -> Self {
         AuthError::StorageError(error)
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,163 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use governor::{
+    Quota, RateLimiter,
+    clock::DefaultClock,
+    state::{InMemoryState, NotKeyed},
+};
+
+use super::types::{RateCategory, RateLimit};
+
+type DirectRateLimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock>;
+
+const SHARDS: usize = 16;
+
+/// a limiter idle for the longest window has fully replenished, so evicting it
+/// after that long does not change what the key is allowed to do
+pub const DEFAULT_LIMITER_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_MAX_LIMITERS: usize = 10_000;
+
+/// per key limiter enforcing the minute, hour and day windows of a `RateLimit`.
+/// a window configured as 0 is unlimited.
+pub struct KeyRateLimiter {
+    windows: Vec<DirectRateLimiter>,
+}
+
+impl KeyRateLimiter {
+    pub fn new(rate_limit: &RateLimit) -> Self {
+        let windows = [
+            (rate_limit.requests_per_minute, 60),
+            (rate_limit.requests_per_hour, 60 * 60),
+            (rate_limit.requests_per_day, 24 * 60 * 60),
+        ]
+        .into_iter()
+        .filter_map(|(requests, seconds)| {
+            let cells = NonZeroU32::new(requests.saturating_mul(RateCategory::Submit.cost()))?;
+            let quota = Quota::with_period(Duration::from_secs(seconds) / cells.get())?
+                .allow_burst(cells);
+            Some(RateLimiter::direct(quota))
+        })
+        .collect();
+        Self { windows }
+    }
+
+    /// windows are checked from the shortest to the longest and the check stops
+    /// at the first exhausted one, so a request rejected by the minute window
+    /// does not eat into the hourly or daily budget.
+    pub fn check(&self, cost: NonZeroU32) -> bool {
+        self.windows
+            .iter()
+            .all(|limiter| matches!(limiter.check_n(cost), Ok(Ok(()))))
+    }
+}
+
+struct Entry {
+    limiter: Arc<KeyRateLimiter>,
+    last_seen: Instant,
+}
+
+
+type Shard = HashMap<String, Entry>;
+
+/// limiters keyed by api key, split over a few mutex protected shards so
+/// concurrent requests for different keys rarely contend on the same lock.
+/// entries not seen within `ttl` are dropped lazily by a sweep on access,
+/// and each shard is capped at its share of `max_entries`, evicting the least
+/// recently seen key first.
+pub struct RateLimiterCache {
+    shards: Vec<Mutex<Shard>>,
+    ttl: Duration,
+    max_per_shard: usize,
+    last_sweep: Mutex<Instant>,
+}
+
+impl RateLimiterCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard::default())).collect(),
+            ttl,
+            max_per_shard: max_entries.div_ceil(SHARDS).max(1),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// get the limiter for a key, creating it from `rate_limit` when missing
+    pub fn get_or_insert(&self, api_key: &str, rate_limit: &RateLimit) -> Arc<KeyRateLimiter> {
+        let now = Instant::now();
+        self.sweep_if_due(now);
+
+        let mut shard = self.shard(api_key).lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = shard.get_mut(api_key) {
+            entry.last_seen = now;
+            return entry.limiter.clone();
+        }
+
+        while shard.len() >= self.max_per_shard {
+            let oldest = shard.iter()
+                .min_by_key(|(_, entry)| entry.last_seen)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(key) => shard.remove(&key),
+                None => break,
+            };
+        }
+
+        let limiter = Arc::new(KeyRateLimiter::new(rate_limit));
+        shard.insert(api_key.to_string(), Entry {
+            limiter: limiter.clone(),
+            last_seen: now,
+        });
+        limiter
+    }
+
+    /// drop the limiter of a key, the next request starts from a fresh budget
+    pub fn remove(&self, api_key: &str) {
+        self.shard(api_key)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(api_key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter()
+            .map(|shard| shard.lock().unwrap_or_else(|e| e.into_inner()).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // sweep at most a few times per ttl so the fast path stays a single lookup,
+    // a concurrent caller that finds the sweep in progress just skips it
+    fn sweep_if_due(&self, now: Instant) {
+        let Ok(mut last_sweep) = self.last_sweep.try_lock() else {
+            return;
+        };
+        if now.duration_since(*last_sweep) < self.ttl / 4 {
+            return;
+        }
+        *last_sweep = now;
+        for shard in &self.shards {
+            shard.lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .retain(|_, entry| now.duration_since(entry.last_seen) < self.ttl);
+        }
+    }
+
+    fn shard(&self, api_key: &str) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        api_key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+impl Default for RateLimiterCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_LIMITER_TTL, DEFAULT_MAX_LIMITERS)
+    }
+}
@@ -1,50 +1,222 @@
+use std::sync::Arc;
 use axum::{
-    http::Request,
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap, HeaderName, Method},
     middleware::Next,
     response::Response,
-    http::StatusCode,
 };
-use crate::utils::http::HttpResponse;
-use super::{Auth, Permission};
-
-pub async fn auth_middleware<B>(
-    req: Request<B>,
-    next: Next<B>,
-    auth: Auth,
-    required_permission: Permission,
-) -> Result<Response, (StatusCode, axum::Json<HttpResponse<String>>)> {
-    let api_key = req
-        .headers()
-        .get("X-API-Key")
-        .and_then(|value| value.to_str().ok());
-
-    if let Err(e) = auth.verify_api_key(api_key, required_permission).await {
-        let (status, message) = match e {
-            super::AuthError::MissingApiKey => 
-                (StatusCode::UNAUTHORIZED, "Missing API key"),
-            super::AuthError::InvalidApiKey => 
-                (StatusCode::UNAUTHORIZED, "Invalid API key"),
-            super::AuthError::KeyExpired => 
-                (StatusCode::FORBIDDEN, "API key has expired"),
-            super::AuthError::KeySuspended => 
-                (StatusCode::FORBIDDEN, "API key is suspended"),
-            super::AuthError::InsufficientPermissions => 
-                (StatusCode::FORBIDDEN, "Insufficient permissions"),
-            super::AuthError::RateLimitExceeded => 
-                (StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded"),
-            super::AuthError::StorageError(_) => 
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
-        };
-
-        return Err((
-            status,
-            axum::Json(HttpResponse::<String>::new(
-                status.as_u16() as i32,
-                message.to_string(),
-                "".to_string(),
-            )),
-        ));
+use crate::web::ApiError;
+use super::{parse_authorization, Auth, AuthError, Permission, RateCategory};
+
+/// header carrying the bare api key, an alternative to `Authorization: Bearer <key>`
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// the api key of a request, from `Authorization: Bearer <key>` or `X-API-Key: <key>`.
+/// `Authorization` wins when both are sent, None when neither is.
+/// a malformed `Authorization` value is rejected as an invalid key
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKeyHeader(pub Option<String>);
+
+impl ApiKeyHeader {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, AuthError> {
+        if let Some(value) = headers.get(AUTHORIZATION) {
+            let value = value.to_str().map_err(|_| AuthError::InvalidApiKey)?;
+            return Ok(Self(Some(parse_authorization(value)?.to_string())));
+        }
+        match headers.get(API_KEY_HEADER) {
+            Some(value) => {
+                let key = value.to_str().map_err(|_| AuthError::InvalidApiKey)?.trim();
+                if key.is_empty() || key.contains(char::is_whitespace) {
+                    return Err(AuthError::InvalidApiKey);
+                }
+                Ok(Self(Some(key.to_string())))
+            }
+            None => Ok(Self(None)),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ApiKeyHeader {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers)?)
+    }
+}
+
+/// state of `auth_middleware`: the auth service and the permission the routes require
+#[derive(Clone)]
+pub struct RequireAuth {
+    pub auth: Arc<Auth>,
+    pub permission: Permission,
+}
+
+impl RequireAuth {
+    pub fn new(auth: Arc<Auth>, permission: Permission) -> Self {
+        Self { auth, permission }
+    }
+}
+
+/// verify the api key of `ApiKeyHeader` before the handler runs.
+/// GET requests are charged as `RateCategory::Read`, everything else as `Submit`.
+/// the verified `ApiKeyInfo` is put in the request extensions for handlers to extract.
+///
+/// mount with `axum::middleware::from_fn_with_state(RequireAuth::new(..), auth_middleware)`
+pub async fn auth_middleware(
+    State(state): State<RequireAuth>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let ApiKeyHeader(api_key) = ApiKeyHeader::from_headers(req.headers())?;
+
+    let category = if req.method() == Method::GET {
+        RateCategory::Read
+    } else {
+        RateCategory::Submit
+    };
+
+    match state.auth.verify_api_key_for(api_key.as_deref(), state.permission.clone(), category).await {
+        Ok(key_info) => {
+            req.extensions_mut().insert(key_info);
+            Ok(next.run(req).await)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Extension, http::StatusCode, middleware::from_fn_with_state, routing::get, Router};
+    use crate::auth::{ApiKeyInfo, RateLimit};
+
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_auth_middleware() {
+        let auth = Arc::new(Auth::new_with_memory_storage());
+        let key_info = auth.create_api_key(
+            "Middleware Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit {
+                requests_per_minute: 60,
+                requests_per_hour: 1000,
+                requests_per_day: 10000,
+            },
+            None,
+        ).await.unwrap();
+
+        let whoami = get(|Extension(key_info): Extension<ApiKeyInfo>| async move { key_info.name });
+        let router = Router::new()
+            .route("/transcribe", whoami.clone())
+            .route_layer(from_fn_with_state(
+                RequireAuth::new(auth.clone(), Permission::Transcribe),
+                auth_middleware,
+            ))
+            .merge(Router::new()
+                .route("/admin", whoami)
+                .route_layer(from_fn_with_state(
+                    RequireAuth::new(auth.clone(), Permission::Admin),
+                    auth_middleware,
+                )));
+        let base = serve(router).await;
+        let client = reqwest::Client::new();
+
+        // missing key
+        let response = client.get(format!("{}/transcribe", base)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the verified key reaches the handler
+        let response = client.get(format!("{}/transcribe", base))
+            .bearer_auth(&key_info.key)
+            .send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "Middleware Key");
+
+        // X-API-Key is accepted too
+        let response = client.get(format!("{}/transcribe", base))
+            .header("X-API-Key", &key_info.key)
+            .send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // not an admin key
+        let response = client.get(format!("{}/admin", base))
+            .bearer_auth(&key_info.key)
+            .send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
     }
 
-    Ok(next.run(req).await)
-} 
\ No newline at end of file
+    #[tokio::test]
+    async fn test_header_forms_authorize_identically() {
+        let auth = Arc::new(Auth::new_with_memory_storage());
+        let key_info = auth.create_api_key(
+            "Header Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit {
+                requests_per_minute: 600,
+                requests_per_hour: 10000,
+                requests_per_day: 100000,
+            },
+            None,
+        ).await.unwrap();
+
+        let whoami = get(|Extension(key_info): Extension<ApiKeyInfo>| async move { key_info.name });
+        let router = Router::new()
+            .route("/transcribe", whoami.clone())
+            .route_layer(from_fn_with_state(
+                RequireAuth::new(auth.clone(), Permission::Transcribe),
+                auth_middleware,
+            ))
+            .merge(Router::new()
+                .route("/admin", whoami)
+                .route_layer(from_fn_with_state(
+                    RequireAuth::new(auth.clone(), Permission::Admin),
+                    auth_middleware,
+                )))
+            // the extractor on its own, without the middleware
+            .route("/key", get(|ApiKeyHeader(key): ApiKeyHeader| async move { key.unwrap_or_default() }));
+        let base = serve(router).await;
+        let client = reqwest::Client::new();
+
+        let forms = |key: &str| vec![
+            ("Authorization", format!("Bearer {}", key)),
+            ("Authorization", format!("bearer {}", key)),
+            ("X-API-Key", key.to_string()),
+        ];
+        for (path, key, status, body) in [
+            ("transcribe", key_info.key.as_str(), StatusCode::OK, Some("Header Key")),
+            ("transcribe", "key-unknown", StatusCode::UNAUTHORIZED, None),
+            ("admin", key_info.key.as_str(), StatusCode::FORBIDDEN, None),
+        ] {
+            for (header, value) in forms(key) {
+                let response = client.get(format!("{}/{}", base, path))
+                    .header(header, &value)
+                    .send().await.unwrap();
+                assert_eq!(response.status(), status, "{} {}: {}", path, header, value);
+                if let Some(body) = body {
+                    assert_eq!(response.text().await.unwrap(), body);
+                }
+            }
+        }
+
+        // every form yields the bare key
+        for (header, value) in forms(&key_info.key) {
+            let response = client.get(format!("{}/key", base)).header(header, &value).send().await.unwrap();
+            assert_eq!(response.text().await.unwrap(), key_info.key);
+        }
+
+        // Authorization takes precedence, a malformed one is not skipped for X-API-Key
+        let response = client.get(format!("{}/transcribe", base))
+            .header(AUTHORIZATION, format!("Basic {}", key_info.key))
+            .header(API_KEY_HEADER, &key_info.key)
+            .send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
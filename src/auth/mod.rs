@@ -1,11 +1,16 @@
 pub mod error;
+pub mod middleware;
+mod limiter;
 pub mod stats;
 pub mod storage;
+pub mod sqlite;
 pub mod service;
 pub mod types;
 
 pub use error::AuthError;
 pub use stats::{ApiKeyStats, ApiKeyUsageReport, UsageSummary};
 pub use storage::{ApiKeyStorage, ApiKeyStatsStorage, InMemoryApiKeyStorage, InMemoryApiKeyStatsStorage};
-pub use service::Auth;
-pub use types::{ApiKeyInfo, Permission, RateLimit, KeyStatus};
+pub use sqlite::{SqliteApiKeyStorage, SqliteApiKeyStatsStorage};
+pub use middleware::{auth_middleware, ApiKeyHeader, RequireAuth};
+pub use service::{Auth, parse_authorization};
+pub use types::{key_id, ApiKeyInfo, ApiKeyFilter, Permission, RateLimit, RateCategory, KeyStatus};
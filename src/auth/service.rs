@@ -1,27 +1,20 @@
 use std::sync::Arc;
 use uuid::Uuid;
 use chrono::{Duration, Utc};
-use governor::{
-    Quota, RateLimiter, 
-    clock::DefaultClock,
-    state::{InMemoryState, NotKeyed},
-};
 use std::num::NonZeroU32;
-use std::collections::HashMap;
-use tokio::sync::Mutex;
+use std::time::Duration as StdDuration;
 
 use super::error::AuthError;
+use super::limiter::RateLimiterCache;
 use super::stats::{ApiKeyStats, ApiKeyUsageReport, UsageSummary};
 use super::storage::{ApiKeyStorage, ApiKeyStatsStorage};
-use super::types::{ApiKeyInfo, Permission, RateLimit, KeyStatus};
+use super::types::{key_id, ApiKeyInfo, ApiKeyFilter, Permission, RateLimit, RateCategory, KeyStatus};
 use tracing::info;
 
-type DirectRateLimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock>;
-
 pub struct Auth {
     key_storage: Arc<dyn ApiKeyStorage>,
     stats_storage: Arc<dyn ApiKeyStatsStorage>,
-    rate_limiters: Arc<Mutex<HashMap<String, Arc<DirectRateLimiter>>>>,
+    rate_limiters: RateLimiterCache,
 }
 
 impl Auth {
@@ -32,7 +25,7 @@ impl Auth {
         Self {
             key_storage,
             stats_storage,
-            rate_limiters: Arc::new(Mutex::new(HashMap::new())),
+            rate_limiters: RateLimiterCache::default(),
         }
     }
 
@@ -41,21 +34,39 @@ impl Auth {
         Self {
             key_storage: Arc::new(InMemoryApiKeyStorage::new()),
             stats_storage: Arc::new(InMemoryApiKeyStatsStorage::new()),
-            rate_limiters: Arc::new(Mutex::new(HashMap::new())),
+            rate_limiters: RateLimiterCache::default(),
         }
     }
 
-    pub async fn verify_api_key(&self, api_key: Option<&str>, required_permission: Permission) -> Result<(), AuthError> {
+    /// evict per key rate limiters idle for longer than `ttl` and keep at most
+    /// about `max_entries` of them. evicting a key resets its rate limit budget,
+    /// so `ttl` should not be shorter than the longest limit window (a day).
+    pub fn with_rate_limiter_eviction(mut self, ttl: StdDuration, max_entries: usize) -> Self {
+        self.rate_limiters = RateLimiterCache::new(ttl, max_entries);
+        self
+    }
+
+    pub async fn verify_api_key(&self, api_key: Option<&str>, required_permission: Permission) -> Result<ApiKeyInfo, AuthError> {
+        self.verify_api_key_for(api_key, required_permission, RateCategory::Submit).await
+    }
+
+    /// verify an api key and consume rate limit budget weighted by the call category.
+    /// `requests_per_minute`, `requests_per_hour` and `requests_per_day` are the number
+    /// of `Submit` calls allowed in each window, cheaper categories get proportionally
+    /// more calls out of the same budget. exhausting any window rejects the call.
+    pub async fn verify_api_key_for(
+        &self,
+        api_key: Option<&str>,
+        required_permission: Permission,
+        category: RateCategory,
+    ) -> Result<ApiKeyInfo, AuthError> {
         info!("Verifying API key: {:?}", api_key);
-        let api_key = api_key.ok_or(AuthError::MissingApiKey)?;
-        let api_key = match api_key.split(" ").last() {
-            Some(key) => key,
-            None => return Err(AuthError::InvalidApiKey),
-        };
+        let api_key = parse_authorization(api_key.ok_or(AuthError::MissingApiKey)?)?;
 
         let key_info = self.key_storage
-            .get_key_info(api_key)?
-            .ok_or(AuthError::InvalidApiKey)?;
+            .get_key_info(api_key).await?
+            .ok_or(AuthError::InvalidApiKey)?
+            .with_id();
 
         // check key status
         match key_info.status {
@@ -72,40 +83,65 @@ impl Auth {
         }
 
         // check permissions
-        if !key_info.permissions.contains(&required_permission) {
+        if !key_info.permissions.iter().any(|p| p.implies(&required_permission)) {
             return Err(AuthError::InsufficientPermissions);
         }
 
-        // check rate limit
-        let mut limiters = self.rate_limiters.lock().await;
-        let limiter = limiters.entry(api_key.to_string())
-            .or_insert_with(|| {
-                Arc::new(RateLimiter::direct(
-                    Quota::per_minute(NonZeroU32::new(key_info.rate_limit.requests_per_minute).unwrap())
-                ))
-            });
+        // check rate limit, limiters only exist for keys that passed the checks above
+        let limiter = self.rate_limiters.get_or_insert(api_key, &key_info.rate_limit);
 
-        if let Err(_) = limiter.check() {
+        let cost = NonZeroU32::new(category.cost()).unwrap();
+        if !limiter.check(cost) {
             return Err(AuthError::RateLimitExceeded);
         }
 
         // update stats
         self.update_key_stats(api_key).await?;
 
-        Ok(())
+        Ok(key_info)
     }
 
-    pub fn create_api_key(
+    pub async fn create_api_key(
         &self,
         name: String,
         permissions: Vec<Permission>,
         rate_limit: RateLimit,
         expires_in_days: Option<i64>,
-    ) -> Result<ApiKeyInfo, String> {
+    ) -> Result<ApiKeyInfo, AuthError> {
         let key = format!("key-{}", Uuid::new_v4());
+        self.insert_api_key(key, name, permissions, rate_limit, expires_in_days).await
+    }
+
+    /// create an unlimited admin key when the storage has no keys at all, so a fresh
+    /// deployment can create the others. `key` is used as the secret when given,
+    /// otherwise one is generated. returns None when keys already exist.
+    pub async fn bootstrap_admin_key(&self, key: Option<String>) -> Result<Option<ApiKeyInfo>, AuthError> {
+        if !self.key_storage.list_keys().await?.is_empty() {
+            return Ok(None);
+        }
+        let key = match key {
+            Some(key) if !key.trim().is_empty() => key.trim().to_string(),
+            Some(_) => return Err(AuthError::InvalidInput("Invalid admin key".to_string())),
+            None => format!("key-{}", Uuid::new_v4()),
+        };
+        let key_info = self
+            .insert_api_key(key, "Bootstrap Admin".to_string(), vec![Permission::Admin], RateLimit::default(), None)
+            .await?;
+        Ok(Some(key_info))
+    }
+
+    async fn insert_api_key(
+        &self,
+        key: String,
+        name: String,
+        permissions: Vec<Permission>,
+        rate_limit: RateLimit,
+        expires_in_days: Option<i64>,
+    ) -> Result<ApiKeyInfo, AuthError> {
         let expires_at = expires_in_days.map(|days| Utc::now() + Duration::days(days));
 
         let key_info = ApiKeyInfo {
+            id: key_id(&key),
             key: key.clone(),
             name,
             created_at: Utc::now(),
@@ -113,42 +149,136 @@ impl Auth {
             permissions,
             rate_limit,
             status: KeyStatus::Active,
+            min_audio_seconds: None,
+            allowed_callback_hosts: Vec::new(),
         };
 
-        self.key_storage.set_key_info(key, key_info.clone())?;
+        self.key_storage.set_key_info(key, key_info.clone()).await?;
         Ok(key_info)
     }
 
-    pub fn revoke_api_key(&self, api_key: &str) -> Result<(), String> {
-        self.key_storage.update_key_status(api_key, KeyStatus::Suspended)
+    pub async fn revoke_api_key(&self, api_key: &str) -> Result<(), AuthError> {
+        if self.key_storage.get_key_info(api_key).await?.is_none() {
+            return Err(AuthError::KeyNotFound);
+        }
+        Ok(self.key_storage.update_key_status(api_key, KeyStatus::Suspended).await?)
+    }
+
+    pub async fn list_api_keys(&self) -> Result<Vec<ApiKeyInfo>, AuthError> {
+        Ok(self.key_storage.list_keys().await?.into_iter().map(ApiKeyInfo::with_id).collect())
+    }
+
+    /// the key with the given id (`ApiKeyInfo::id`), for management calls made from a listing
+    pub async fn find_api_key(&self, key_id: &str) -> Result<ApiKeyInfo, AuthError> {
+        self.list_api_keys()
+            .await?
+            .into_iter()
+            .find(|key_info| key_info.id == key_id)
+            .ok_or(AuthError::KeyNotFound)
+    }
+
+    /// keys matching the filter, e.g. the suspended ones or those expiring soon
+    pub async fn list_api_keys_filtered(&self, filter: &ApiKeyFilter) -> Result<Vec<ApiKeyInfo>, AuthError> {
+        let now = Utc::now();
+        Ok(self.key_storage
+            .list_keys().await?
+            .into_iter()
+            .map(ApiKeyInfo::with_id)
+            .filter(|key_info| filter.matches(key_info, now))
+            .collect())
+    }
+
+    /// update the status, rate limit and/or permissions of an existing key.
+    /// a new rate limit takes effect on the next request with a fresh budget.
+    pub async fn update_api_key(
+        &self,
+        api_key: &str,
+        status: Option<KeyStatus>,
+        rate_limit: Option<RateLimit>,
+        permissions: Option<Vec<Permission>>,
+    ) -> Result<ApiKeyInfo, AuthError> {
+        let mut key_info = self.key_storage
+            .get_key_info(api_key).await?
+            .ok_or(AuthError::KeyNotFound)?
+            .with_id();
+
+        if let Some(status) = status {
+            key_info.status = status;
+        }
+        let rate_limit_changed = rate_limit.is_some();
+        if let Some(rate_limit) = rate_limit {
+            key_info.rate_limit = rate_limit;
+        }
+        if let Some(permissions) = permissions {
+            key_info.permissions = permissions;
+        }
+
+        self.key_storage.set_key_info(api_key.to_string(), key_info.clone()).await?;
+        if rate_limit_changed {
+            self.rate_limiters.remove(api_key);
+        }
+        Ok(key_info)
+    }
+
+    // override the minimum audio duration of a key, None falls back to the global default
+    pub async fn set_min_audio_seconds(&self, api_key: &str, min_audio_seconds: Option<f64>) -> Result<ApiKeyInfo, AuthError> {
+        if min_audio_seconds.is_some_and(|seconds| !seconds.is_finite() || seconds < 0.0) {
+            return Err(AuthError::InvalidInput("Invalid minimum audio duration".to_string()));
+        }
+        let mut key_info = self.key_storage
+            .get_key_info(api_key).await?
+            .ok_or(AuthError::KeyNotFound)?
+            .with_id();
+        key_info.min_audio_seconds = min_audio_seconds;
+        self.key_storage.set_key_info(api_key.to_string(), key_info.clone()).await?;
+        Ok(key_info)
+    }
+
+    // restrict the callback urls of a key to the given hosts, empty lifts the restriction
+    pub async fn set_allowed_callback_hosts(&self, api_key: &str, hosts: Vec<String>) -> Result<ApiKeyInfo, AuthError> {
+        let hosts: Vec<String> = hosts
+            .iter()
+            .map(|host| host.trim().trim_end_matches('.').to_ascii_lowercase())
+            .collect();
+        if hosts.iter().any(|host| host.is_empty() || host.contains('/') || host.contains(':')) {
+            return Err(AuthError::InvalidInput("Invalid callback host".to_string()));
+        }
+        let mut key_info = self.key_storage
+            .get_key_info(api_key).await?
+            .ok_or(AuthError::KeyNotFound)?
+            .with_id();
+        key_info.allowed_callback_hosts = hosts;
+        self.key_storage.set_key_info(api_key.to_string(), key_info.clone()).await?;
+        Ok(key_info)
     }
 
     async fn update_key_stats(&self, api_key: &str) -> Result<(), String> {
         let mut stats = self.stats_storage
-            .get_stats(api_key)?
+            .get_stats(api_key).await?
             .unwrap_or_else(ApiKeyStats::new);
         
         stats.update();
-        self.stats_storage.update_stats(api_key, stats)
+        self.stats_storage.update_stats(api_key, stats).await
     }
 
-    pub fn get_key_stats(&self, api_key: &str) -> Result<ApiKeyStats, String> {
+    pub async fn get_key_stats(&self, api_key: &str) -> Result<ApiKeyStats, AuthError> {
         // check if api key exists
-        if self.key_storage.get_key_info(api_key)?.is_none() {
-            return Err("API key not found".to_string());
+        if self.key_storage.get_key_info(api_key).await?.is_none() {
+            return Err(AuthError::KeyNotFound);
         }
 
         // get stats
         Ok(self.stats_storage
-            .get_stats(api_key)?
+            .get_stats(api_key).await?
             .unwrap_or_else(ApiKeyStats::new))
     }
 
-    pub fn get_key_usage_report(&self, api_key: &str) -> Result<ApiKeyUsageReport, String> {
-        let stats = self.get_key_stats(api_key)?;
+    pub async fn get_key_usage_report(&self, api_key: &str) -> Result<ApiKeyUsageReport, AuthError> {
+        let stats = self.get_key_stats(api_key).await?;
         let key_info = self.key_storage
-            .get_key_info(api_key)?
-            .ok_or_else(|| "API key not found".to_string())?;
+            .get_key_info(api_key).await?
+            .ok_or(AuthError::KeyNotFound)?
+            .with_id();
 
         Ok(ApiKeyUsageReport {
             key_info: key_info.clone(),
@@ -164,6 +294,21 @@ impl Auth {
     }
 }
 
+/// the key in an `Authorization` value: `Bearer <key>` (scheme case insensitive) or the bare key.
+/// any other scheme, or tokens after the key, is an invalid key
+pub fn parse_authorization(value: &str) -> Result<&str, AuthError> {
+    let mut tokens = value.split_whitespace();
+    let key = match (tokens.next(), tokens.next()) {
+        (Some(scheme), Some(key)) if scheme.eq_ignore_ascii_case("Bearer") => key,
+        (Some(key), None) => key,
+        _ => return Err(AuthError::InvalidApiKey),
+    };
+    if tokens.next().is_some() {
+        return Err(AuthError::InvalidApiKey);
+    }
+    Ok(key)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,7 +333,7 @@ mod tests {
                 requests_per_day: 10000,
             },
             Some(30),
-        ).unwrap();
+        ).await.unwrap();
 
         // 2. validate basic info
         assert_eq!(key_info.name, "Test Key");
@@ -200,10 +345,27 @@ mod tests {
         assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
 
         // 4. revoke api key
-        auth.revoke_api_key(&key_info.key).unwrap();
+        auth.revoke_api_key(&key_info.key).await.unwrap();
         assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_err());
     }
 
+    #[tokio::test]
+    async fn test_bootstrap_admin_key() {
+        let auth = setup_test_auth().await;
+        // the default test key exists, nothing to bootstrap
+        assert!(auth.bootstrap_admin_key(None).await.unwrap().is_none());
+
+        auth.key_storage.remove_key("test-key-123").await.unwrap();
+        assert!(auth.bootstrap_admin_key(Some(" ".to_string())).await.is_err());
+        let key_info = auth.bootstrap_admin_key(Some("admin-secret".to_string())).await.unwrap().unwrap();
+        assert_eq!(key_info.key, "admin-secret");
+        assert!(auth.verify_api_key(Some("admin-secret"), Permission::Admin).await.is_ok());
+
+        // only an empty storage is seeded
+        assert!(auth.bootstrap_admin_key(None).await.unwrap().is_none());
+        assert_eq!(auth.list_api_keys().await.unwrap().len(), 1);
+    }
+
     #[tokio::test]
     async fn test_api_key_permissions() {
         let auth = setup_test_auth().await;
@@ -218,7 +380,7 @@ mod tests {
                 requests_per_day: 10000,
             },
             None,
-        ).unwrap();
+        ).await.unwrap();
 
         // test allowed permissions
         assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
@@ -231,6 +393,63 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_admin_implies_all_permissions() {
+        let auth = setup_test_auth().await;
+
+        let key_info = auth.create_api_key(
+            "Admin Key".to_string(),
+            vec![Permission::Admin],
+            RateLimit {
+                requests_per_minute: 60,
+                requests_per_hour: 1000,
+                requests_per_day: 10000,
+            },
+            None,
+        ).await.unwrap();
+
+        // an admin only key can use transcribe gated routes
+        for permission in [
+            Permission::Transcribe,
+            Permission::SpeakerDiarization,
+            Permission::EmotionRecognition,
+            Permission::Admin,
+        ] {
+            assert!(auth.verify_api_key(Some(&key_info.key), permission).await.is_ok());
+        }
+
+        // feature permissions do not imply each other
+        assert!(!Permission::SpeakerDiarization.implies(&Permission::Transcribe));
+        assert!(!Permission::Transcribe.implies(&Permission::EmotionRecognition));
+        assert!(!Permission::Transcribe.implies(&Permission::Admin));
+    }
+
+    #[tokio::test]
+    async fn test_key_without_status_is_active() {
+        let auth = setup_test_auth().await;
+
+        let key_info: ApiKeyInfo = serde_json::from_str(r#"{
+            "key": "key-legacy",
+            "name": "Legacy Key",
+            "created_at": "2024-01-01T00:00:00Z",
+            "expires_at": null,
+            "permissions": ["Transcribe"],
+            "rate_limit": {
+                "requests_per_minute": 60,
+                "requests_per_hour": 1000,
+                "requests_per_day": 10000
+            }
+        }"#).unwrap();
+        assert_eq!(key_info.status, KeyStatus::Active);
+        assert_eq!(ApiKeyInfo::default().status, KeyStatus::Active);
+
+        auth.key_storage.set_key_info(key_info.key.clone(), key_info).await.unwrap();
+        // stored without an id, it gets the one derived from the key
+        let verified = auth.verify_api_key(Some("key-legacy"), Permission::Transcribe).await.unwrap();
+        assert_eq!(verified.id, key_id("key-legacy"));
+        assert!(!verified.id.contains("legacy"));
+    }
+
     #[tokio::test]
     async fn test_api_key_expiration() {
         let auth = setup_test_auth().await;
@@ -245,7 +464,7 @@ mod tests {
                 requests_per_day: 10000,
             },
             Some(0), // 0 days expiration, expires immediately
-        ).unwrap();
+        ).await.unwrap();
 
         // validate key has expired
         sleep(Duration::from_secs(1)).await;
@@ -264,7 +483,7 @@ mod tests {
                 requests_per_day: 10000,
             },
             Some(30), // 30 days expiration
-        ).unwrap();
+        ).await.unwrap();
 
         // validate key is available
         assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
@@ -284,7 +503,7 @@ mod tests {
                 requests_per_day: 10000,
             },
             None,
-        ).unwrap();
+        ).await.unwrap();
 
         // first request should succeed
         assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
@@ -309,6 +528,294 @@ mod tests {
         assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_rate_limit_categories() {
+        let auth = setup_test_auth().await;
+
+        let key_info = auth.create_api_key(
+            "Weighted Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit {
+                requests_per_minute: 2,
+                requests_per_hour: 1000,
+                requests_per_day: 10000,
+            },
+            None,
+        ).await.unwrap();
+
+        // many status polls should not exhaust the submission budget
+        for _ in 0..10 {
+            assert!(auth.verify_api_key_for(Some(&key_info.key), Permission::Transcribe, RateCategory::Read).await.is_ok());
+        }
+        assert!(auth.verify_api_key_for(Some(&key_info.key), Permission::Transcribe, RateCategory::Submit).await.is_ok());
+
+        // while a couple of submissions do
+        let key_info = auth.create_api_key(
+            "Submit Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit {
+                requests_per_minute: 2,
+                requests_per_hour: 1000,
+                requests_per_day: 10000,
+            },
+            None,
+        ).await.unwrap();
+
+        assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+        assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+        assert!(matches!(
+            auth.verify_api_key_for(Some(&key_info.key), Permission::Transcribe, RateCategory::Read).await,
+            Err(AuthError::RateLimitExceeded)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_hourly_and_daily_rate_limits() {
+        let auth = setup_test_auth().await;
+
+        // the daily cap is reached long before the minute limit
+        let key_info = auth.create_api_key(
+            "Daily Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit {
+                requests_per_minute: 60,
+                requests_per_hour: 1000,
+                requests_per_day: 3,
+            },
+            None,
+        ).await.unwrap();
+
+        for _ in 0..3 {
+            assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+        }
+        assert!(matches!(
+            auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await,
+            Err(AuthError::RateLimitExceeded)
+        ));
+
+        // same for the hourly cap
+        let key_info = auth.create_api_key(
+            "Hourly Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit {
+                requests_per_minute: 60,
+                requests_per_hour: 2,
+                requests_per_day: 10000,
+            },
+            None,
+        ).await.unwrap();
+
+        for _ in 0..2 {
+            assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+        }
+        assert!(matches!(
+            auth.verify_api_key_for(Some(&key_info.key), Permission::Transcribe, RateCategory::Read).await,
+            Err(AuthError::RateLimitExceeded)
+        ));
+
+        // zero means the window is not limited
+        let key_info = auth.create_api_key(
+            "Unlimited Day Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit {
+                requests_per_minute: 60,
+                requests_per_hour: 0,
+                requests_per_day: 0,
+            },
+            None,
+        ).await.unwrap();
+
+        for _ in 0..10 {
+            assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_eviction() {
+        async fn create_keys(auth: &Auth, count: usize) -> Vec<ApiKeyInfo> {
+            let mut keys = Vec::with_capacity(count);
+            for i in 0..count {
+                keys.push(auth.create_api_key(
+                    format!("Key {}", i),
+                    vec![Permission::Transcribe],
+                    RateLimit {
+                        requests_per_minute: 60,
+                        requests_per_hour: 1000,
+                        requests_per_day: 10000,
+                    },
+                    None,
+                ).await.unwrap());
+            }
+            keys
+        }
+
+        // invalid keys never get a limiter
+        let auth = setup_test_auth().await;
+        for _ in 0..10 {
+            assert!(auth.verify_api_key(Some("invalid-key"), Permission::Transcribe).await.is_err());
+        }
+        assert!(auth.rate_limiters.is_empty());
+
+        // the map is capped
+        let auth = setup_test_auth().await
+            .with_rate_limiter_eviction(Duration::from_secs(3600), 16);
+        for key_info in create_keys(&auth, 40).await {
+            assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+        }
+        assert!(auth.rate_limiters.len() <= 16);
+
+        // idle limiters are dropped once the ttl has passed
+        let auth = setup_test_auth().await
+            .with_rate_limiter_eviction(Duration::from_millis(50), 1000);
+        let keys = create_keys(&auth, 10).await;
+        for key_info in &keys {
+            assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+        }
+        assert_eq!(auth.rate_limiters.len(), 10);
+
+        sleep(Duration::from_millis(100)).await;
+        assert!(auth.verify_api_key(Some(&keys[0].key), Permission::Transcribe).await.is_ok());
+        assert_eq!(auth.rate_limiters.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_api_key() {
+        let auth = setup_test_auth().await;
+
+        let key_info = auth.create_api_key(
+            "Update Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit {
+                requests_per_minute: 1,
+                requests_per_hour: 1000,
+                requests_per_day: 10000,
+            },
+            None,
+        ).await.unwrap();
+        assert!(auth.list_api_keys().await.unwrap().iter().any(|k| k.key == key_info.key));
+        // the listing's id leads back to the key
+        assert_eq!(auth.find_api_key(&key_info.id).await.unwrap().key, key_info.key);
+        assert!(matches!(auth.find_api_key(&key_info.key).await, Err(AuthError::KeyNotFound)));
+
+        // exhaust the minute budget, then raise the limit
+        assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+        assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_err());
+        let updated = auth.update_api_key(
+            &key_info.key,
+            None,
+            Some(RateLimit {
+                requests_per_minute: 60,
+                requests_per_hour: 1000,
+                requests_per_day: 10000,
+            }),
+            Some(vec![Permission::Transcribe, Permission::SpeakerDiarization]),
+        ).await.unwrap();
+        assert_eq!(updated.rate_limit.requests_per_minute, 60);
+        assert!(auth.verify_api_key(Some(&key_info.key), Permission::SpeakerDiarization).await.is_ok());
+
+        // a suspended key can be re-activated
+        auth.revoke_api_key(&key_info.key).await.unwrap();
+        assert!(matches!(
+            auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await,
+            Err(AuthError::KeySuspended)
+        ));
+        auth.update_api_key(&key_info.key, Some(KeyStatus::Active), None, None).await.unwrap();
+        assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+
+        assert!(auth.update_api_key("missing-key", Some(KeyStatus::Active), None, None).await.is_err());
+
+        let updated = auth.set_min_audio_seconds(&key_info.key, Some(0.5)).await.unwrap();
+        assert_eq!(updated.min_audio_seconds, Some(0.5));
+        assert!(auth.set_min_audio_seconds(&key_info.key, Some(-1.0)).await.is_err());
+        assert!(auth.set_min_audio_seconds("missing-key", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_allowed_callback_hosts() {
+        let auth = setup_test_auth().await;
+        let key_info = auth.create_api_key(
+            "Tenant Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit::default(),
+            None,
+        ).await.unwrap();
+
+        // keys without a list may call back anywhere
+        assert!(key_info.check_callback_url("https://anywhere.example.org/callback").is_ok());
+
+        let key_info = auth.set_allowed_callback_hosts(
+            &key_info.key,
+            vec!["Hooks.Tenant-A.com".to_string(), "*.tenant-a.io".to_string()],
+        ).await.unwrap();
+        assert_eq!(key_info.allowed_callback_hosts, vec!["hooks.tenant-a.com", "*.tenant-a.io"]);
+
+        assert!(key_info.check_callback_url("https://hooks.tenant-a.com/callback").is_ok());
+        assert!(key_info.check_callback_url("http://HOOKS.tenant-a.com:8080/cb?x=1").is_ok());
+        assert!(key_info.check_callback_url("https://api.tenant-a.io/callback").is_ok());
+
+        // another tenant's endpoint, and look-alikes of the listed hosts
+        for url in [
+            "https://hooks.tenant-b.com/callback",
+            "https://tenant-a.io/callback",
+            "https://eviltenant-a.io/callback",
+            "https://hooks.tenant-a.com.evil.net/callback",
+            "not a url",
+        ] {
+            assert!(key_info.check_callback_url(url).is_err(), "{} should be rejected", url);
+        }
+
+        // the stored key carries the restriction
+        let stored = auth.list_api_keys().await.unwrap().into_iter().find(|k| k.key == key_info.key).unwrap();
+        assert!(stored.check_callback_url("https://hooks.tenant-b.com/callback").is_err());
+
+        assert!(auth.set_allowed_callback_hosts(&key_info.key, vec!["https://x.com".to_string()]).await.is_err());
+        assert!(auth.set_allowed_callback_hosts("missing-key", Vec::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_api_keys_filtered() {
+        let auth = setup_test_auth().await;
+        let create = |name: &str, expires_in_days: Option<i64>| auth.create_api_key(
+            name.to_string(),
+            vec![Permission::Transcribe],
+            RateLimit { requests_per_minute: 60, requests_per_hour: 1000, requests_per_day: 10000 },
+            expires_in_days,
+        );
+
+        create("soon", Some(3)).await.unwrap();
+        create("later", Some(30)).await.unwrap();
+        create("never", None).await.unwrap();
+        create("lapsed", Some(-1)).await.unwrap();
+        let suspended = create("suspended soon", Some(5)).await.unwrap();
+        auth.revoke_api_key(&suspended.key).await.unwrap();
+
+        async fn names(auth: &Auth, filter: ApiKeyFilter) -> Vec<String> {
+            let mut names: Vec<String> = auth.list_api_keys_filtered(&filter).await.unwrap()
+                .into_iter()
+                .map(|k| k.name)
+                .collect();
+            names.sort();
+            names
+        }
+
+        assert_eq!(names(&auth, ApiKeyFilter::default()).await.len(), 5);
+        assert_eq!(names(&auth, ApiKeyFilter { status: Some(KeyStatus::Suspended), ..Default::default() }).await, vec!["suspended soon"]);
+        // an active key past its expiry counts as expired
+        assert_eq!(names(&auth, ApiKeyFilter { status: Some(KeyStatus::Expired), ..Default::default() }).await, vec!["lapsed"]);
+        assert_eq!(names(&auth, ApiKeyFilter { status: Some(KeyStatus::Active), ..Default::default() }).await, vec!["later", "never", "soon"]);
+
+        // already expired keys and keys without expiry are not expiring
+        assert_eq!(
+            names(&auth, ApiKeyFilter { expiring_within_days: Some(7), ..Default::default() }).await,
+            vec!["soon", "suspended soon"],
+        );
+        assert_eq!(
+            names(&auth, ApiKeyFilter { status: Some(KeyStatus::Active), expiring_within_days: Some(7) }).await,
+            vec!["soon"],
+        );
+        assert_eq!(names(&auth, ApiKeyFilter { expiring_within_days: Some(60), ..Default::default() }).await.len(), 3);
+    }
+
     #[tokio::test]
     async fn test_stats_and_usage_report() {
         let auth = setup_test_auth().await;
@@ -323,7 +830,7 @@ mod tests {
                 requests_per_day: 10000,
             },
             Some(30),
-        ).unwrap();
+        ).await.unwrap();
 
         // simulate some requests
         for _ in 0..5 {
@@ -331,12 +838,12 @@ mod tests {
         }
 
         // validate stats
-        let stats = auth.get_key_stats(&key_info.key).unwrap();
+        let stats = auth.get_key_stats(&key_info.key).await.unwrap();
         assert_eq!(stats.total_requests, 5);
         assert_eq!(stats.requests_today, 5);
 
         // validate usage report
-        let report = auth.get_key_usage_report(&key_info.key).unwrap();
+        let report = auth.get_key_usage_report(&key_info.key).await.unwrap();
         assert_eq!(report.stats.total_requests, 5);
         assert!(report.usage_summary.average_daily_requests > 0.0);
         assert_eq!(report.usage_summary.peak_daily_requests, 5);
@@ -369,12 +876,49 @@ mod tests {
                 requests_per_day: 10000,
             },
             None,
-        ).unwrap();
+        ).await.unwrap();
 
-        auth.revoke_api_key(&key_info.key).unwrap();
+        auth.revoke_api_key(&key_info.key).await.unwrap();
         assert!(matches!(
             auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await,
             Err(AuthError::KeySuspended)
         ));
     }
+
+    #[tokio::test]
+    async fn test_authorization_schemes() {
+        let auth = setup_test_auth().await;
+
+        let key_info = auth.create_api_key(
+            "Scheme Key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit {
+                requests_per_minute: 60,
+                requests_per_hour: 1000,
+                requests_per_day: 10000,
+            },
+            None,
+        ).await.unwrap();
+
+        // bearer scheme, with surrounding whitespace
+        let bearer = format!("  Bearer   {}  ", key_info.key);
+        assert!(auth.verify_api_key(Some(&bearer), Permission::Transcribe).await.is_ok());
+
+        // raw key
+        assert!(auth.verify_api_key(Some(&key_info.key), Permission::Transcribe).await.is_ok());
+
+        // unknown scheme is rejected even if the last token is a valid key
+        let basic = format!("Basic {}", key_info.key);
+        assert!(matches!(
+            auth.verify_api_key(Some(&basic), Permission::Transcribe).await,
+            Err(AuthError::InvalidApiKey)
+        ));
+
+        // extra tokens after the key are rejected
+        let trailing = format!("Bearer {} extra", key_info.key);
+        assert!(matches!(
+            auth.verify_api_key(Some(&trailing), Permission::Transcribe).await,
+            Err(AuthError::InvalidApiKey)
+        ));
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, Statement};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::storage::task::sqlite::{retry_on_busy, SqliteOptions};
+use super::stats::ApiKeyStats;
+use super::storage::{ApiKeyStorage, ApiKeyStatsStorage};
+use super::types::{ApiKeyInfo, KeyStatus};
+
+const API_KEYS_TABLE: &str = "api_keys";
+const API_KEY_STATS_TABLE: &str = "api_key_stats";
+
+/// 以 api key 为主键、JSON 为值的 SQLite 表
+///
+/// 读请求直接走内存；写操作先写入数据库，成功后再更新内存，失败时返回错误且内存不变。
+/// 写操作逐个进行，保证数据库和内存中的修改顺序一致
+struct KeyValueTable<T> {
+    db: DatabaseConnection,
+    table: &'static str,
+    values: RwLock<HashMap<String, T>>,
+    writes: Mutex<()>,
+}
+
+impl<T> KeyValueTable<T>
+where
+    T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    async fn open(db: DatabaseConnection, table: &'static str) -> Result<Self> {
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            format!(
+                r#"CREATE TABLE IF NOT EXISTS {} (
+                    api_key TEXT PRIMARY KEY NOT NULL,
+                    data TEXT NOT NULL,
+                    updated_at TEXT NOT NULL
+                )"#,
+                table
+            ),
+        ))
+        .await?;
+
+        let rows = db
+            .query_all(Statement::from_string(
+                DbBackend::Sqlite,
+                format!("SELECT api_key, data FROM {}", table),
+            ))
+            .await?;
+        let mut values = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let key: String = row.try_get("", "api_key")?;
+            let data: String = row.try_get("", "data")?;
+            values.insert(key, serde_json::from_str(&data)?);
+        }
+        info!("Loaded {} rows from {}", values.len(), table);
+
+        Ok(Self {
+            db,
+            table,
+            values: RwLock::new(values),
+            writes: Mutex::new(()),
+        })
+    }
+
+    fn get(&self, key: &str) -> Result<Option<T>, String> {
+        let values = self.values.read().map_err(|e| e.to_string())?;
+        Ok(values.get(key).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<T>, String> {
+        let values = self.values.read().map_err(|e| e.to_string())?;
+        Ok(values.values().cloned().collect())
+    }
+
+    async fn set(&self, key: String, value: T) -> Result<(), String> {
+        let _writes = self.writes.lock().await;
+        self.upsert(&key, &value).await?;
+        let mut values = self.values.write().map_err(|e| e.to_string())?;
+        values.insert(key, value);
+        Ok(())
+    }
+
+    /// 修改已存在的值，key 不存在时返回 false
+    async fn modify(&self, key: &str, f: impl FnOnce(&mut T) + Send) -> Result<bool, String> {
+        let _writes = self.writes.lock().await;
+        let Some(mut value) = self.get(key)? else {
+            return Ok(false);
+        };
+        f(&mut value);
+        self.upsert(key, &value).await?;
+        let mut values = self.values.write().map_err(|e| e.to_string())?;
+        values.insert(key.to_string(), value);
+        Ok(true)
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), String> {
+        let _writes = self.writes.lock().await;
+        let (db, table) = (&self.db, self.table);
+        retry_on_busy(move || async move {
+            db.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                &format!("DELETE FROM {} WHERE api_key = ?", table),
+                [key.into()],
+            ))
+            .await?;
+            Ok(())
+        })
+        .await
+        .map_err(|e| format!("Failed to delete {} row: {}", table, e))?;
+        let mut values = self.values.write().map_err(|e| e.to_string())?;
+        values.remove(key);
+        Ok(())
+    }
+
+    async fn upsert(&self, key: &str, value: &T) -> Result<(), String> {
+        let data = serde_json::to_string(value).map_err(|e| e.to_string())?;
+        let (db, table, data) = (&self.db, self.table, &data);
+        retry_on_busy(move || async move {
+            db.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                &format!(
+                    "INSERT OR REPLACE INTO {} (api_key, data, updated_at) VALUES (?, ?, ?)",
+                    table
+                ),
+                [
+                    key.into(),
+                    data.as_str().into(),
+                    Utc::now().to_rfc3339().into(),
+                ],
+            ))
+            .await?;
+            Ok(())
+        })
+        .await
+        .map_err(|e| format!("Failed to persist {} row: {}", table, e))
+    }
+}
+
+/// 持久化到 SQLite 的 API key 存储，不会预置任何 key，空库的管理员 key 由 `Auth::bootstrap_admin_key` 创建
+pub struct SqliteApiKeyStorage {
+    keys: KeyValueTable<ApiKeyInfo>,
+}
+
+impl SqliteApiKeyStorage {
+    pub async fn new(database_url: &str) -> Result<Self> {
+        Self::new_with_options(database_url, SqliteOptions::from_env()).await
+    }
+
+    pub async fn new_with_options(database_url: &str, options: SqliteOptions) -> Result<Self> {
+        info!("Initializing SQLite api key storage at {}", database_url);
+        let db = options.connect(database_url).await?;
+        Ok(Self {
+            keys: KeyValueTable::open(db, API_KEYS_TABLE).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl ApiKeyStorage for SqliteApiKeyStorage {
+    async fn get_key_info(&self, api_key: &str) -> Result<Option<ApiKeyInfo>, String> {
+        self.keys.get(api_key)
+    }
+
+    async fn set_key_info(&self, api_key: String, info: ApiKeyInfo) -> Result<(), String> {
+        self.keys.set(api_key, info).await
+    }
+
+    async fn remove_key(&self, api_key: &str) -> Result<(), String> {
+        self.keys.remove(api_key).await
+    }
+
+    async fn list_keys(&self) -> Result<Vec<ApiKeyInfo>, String> {
+        self.keys.list()
+    }
+
+    async fn update_key_status(&self, api_key: &str, status: KeyStatus) -> Result<(), String> {
+        if self.keys.modify(api_key, |info| info.status = status).await? {
+            Ok(())
+        } else {
+            Err("API key not found".to_string())
+        }
+    }
+}
+
+/// 持久化到 SQLite 的 API key 使用统计
+pub struct SqliteApiKeyStatsStorage {
+    stats: KeyValueTable<ApiKeyStats>,
+}
+
+impl SqliteApiKeyStatsStorage {
+    pub async fn new(database_url: &str) -> Result<Self> {
+        Self::new_with_options(database_url, SqliteOptions::from_env()).await
+    }
+
+    pub async fn new_with_options(database_url: &str, options: SqliteOptions) -> Result<Self> {
+        info!("Initializing SQLite api key stats storage at {}", database_url);
+        let db = options.connect(database_url).await?;
+        Ok(Self {
+            stats: KeyValueTable::open(db, API_KEY_STATS_TABLE).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl ApiKeyStatsStorage for SqliteApiKeyStatsStorage {
+    async fn get_stats(&self, api_key: &str) -> Result<Option<ApiKeyStats>, String> {
+        self.stats.get(api_key)
+    }
+
+    async fn update_stats(&self, api_key: &str, stats: ApiKeyStats) -> Result<(), String> {
+        self.stats.set(api_key.to_string(), stats).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::types::{key_id, Permission, RateLimit};
+    use tempfile::TempDir;
+
+    fn database_url(dir: &TempDir) -> String {
+        format!("sqlite://{}?mode=rwc", dir.path().join("auth.db").display())
+    }
+
+    fn key_info(key: &str) -> ApiKeyInfo {
+        ApiKeyInfo {
+            id: key_id(key),
+            key: key.to_string(),
+            name: "Persistent Key".to_string(),
+            created_at: Utc::now(),
+            expires_at: None,
+            permissions: vec![Permission::Transcribe],
+            rate_limit: RateLimit::default(),
+            status: KeyStatus::Active,
+            min_audio_seconds: None,
+            allowed_callback_hosts: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_survive_restart() -> Result<()> {
+        let dir = TempDir::new()?;
+        let url = database_url(&dir);
+
+        let storage = SqliteApiKeyStorage::new(&url).await?;
+        assert!(storage.list_keys().await.unwrap().is_empty());
+        storage.set_key_info("key-a".to_string(), key_info("key-a")).await.unwrap();
+        storage.set_key_info("key-b".to_string(), key_info("key-b")).await.unwrap();
+        storage.update_key_status("key-a", KeyStatus::Suspended).await.unwrap();
+        storage.remove_key("key-b").await.unwrap();
+        assert!(storage.update_key_status("missing", KeyStatus::Active).await.is_err());
+        drop(storage);
+
+        let storage = SqliteApiKeyStorage::new(&url).await?;
+        let keys = storage.list_keys().await.unwrap();
+        assert_eq!(keys.len(), 1);
+        let info = storage.get_key_info("key-a").await.unwrap().unwrap();
+        assert_eq!(info.name, "Persistent Key");
+        assert_eq!(info.status, KeyStatus::Suspended);
+        assert!(storage.get_key_info("key-b").await.unwrap().is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_key_stats_survive_restart() -> Result<()> {
+        let dir = TempDir::new()?;
+        let url = database_url(&dir);
+
+        let storage = SqliteApiKeyStatsStorage::new(&url).await?;
+        let mut stats = ApiKeyStats::default();
+        stats.total_requests = 42;
+        storage.update_stats("key-a", stats).await.unwrap();
+        drop(storage);
+
+        let storage = SqliteApiKeyStatsStorage::new(&url).await?;
+        let stats = storage.get_stats("key-a").await.unwrap().unwrap();
+        assert_eq!(stats.total_requests, 42);
+        assert!(storage.get_stats("key-b").await.unwrap().is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_is_reported() -> Result<()> {
+        let dir = TempDir::new()?;
+        let url = database_url(&dir);
+
+        let storage = SqliteApiKeyStorage::new(&url).await?;
+        let db = SqliteOptions::from_env().connect(&url).await?;
+        db.execute(Statement::from_string(DbBackend::Sqlite, format!("DROP TABLE {}", API_KEYS_TABLE))).await?;
+
+        // the write fails and the key is not visible as if it had been stored
+        assert!(storage.set_key_info("key-a".to_string(), key_info("key-a")).await.is_err());
+        assert!(storage.get_key_info("key-a").await.unwrap().is_none());
+        Ok(())
+    }
+}
@@ -1,20 +1,23 @@
 use std::sync::RwLock;
 use std::collections::HashMap;
+use async_trait::async_trait;
 use chrono::Utc;
-use super::types::{ApiKeyInfo, Permission, RateLimit, KeyStatus};
+use super::types::{key_id, ApiKeyInfo, Permission, RateLimit, KeyStatus};
 use super::stats::ApiKeyStats;
 
+#[async_trait]
 pub trait ApiKeyStorage: Send + Sync + 'static {
-    fn get_key_info(&self, api_key: &str) -> Result<Option<ApiKeyInfo>, String>;
-    fn set_key_info(&self, api_key: String, info: ApiKeyInfo) -> Result<(), String>;
-    fn remove_key(&self, api_key: &str) -> Result<(), String>;
-    fn list_keys(&self) -> Result<Vec<ApiKeyInfo>, String>;
-    fn update_key_status(&self, api_key: &str, status: KeyStatus) -> Result<(), String>;
+    async fn get_key_info(&self, api_key: &str) -> Result<Option<ApiKeyInfo>, String>;
+    async fn set_key_info(&self, api_key: String, info: ApiKeyInfo) -> Result<(), String>;
+    async fn remove_key(&self, api_key: &str) -> Result<(), String>;
+    async fn list_keys(&self) -> Result<Vec<ApiKeyInfo>, String>;
+    async fn update_key_status(&self, api_key: &str, status: KeyStatus) -> Result<(), String>;
 }
 
+#[async_trait]
 pub trait ApiKeyStatsStorage: Send + Sync + 'static {
-    fn get_stats(&self, api_key: &str) -> Result<Option<ApiKeyStats>, String>;
-    fn update_stats(&self, api_key: &str, stats: ApiKeyStats) -> Result<(), String>;
+    async fn get_stats(&self, api_key: &str) -> Result<Option<ApiKeyStats>, String>;
+    async fn update_stats(&self, api_key: &str, stats: ApiKeyStats) -> Result<(), String>;
 }
 
 pub struct InMemoryApiKeyStorage {
@@ -28,6 +31,7 @@ impl InMemoryApiKeyStorage {
         keys.insert(
             "test-key-123".to_string(),
             ApiKeyInfo {
+                id: key_id("test-key-123"),
                 key: "test-key-123".to_string(),
                 name: "Test Key".to_string(),
                 created_at: Utc::now(),
@@ -39,6 +43,8 @@ impl InMemoryApiKeyStorage {
                     requests_per_day: 10000,
                 },
                 status: KeyStatus::Active,
+                min_audio_seconds: None,
+                allowed_callback_hosts: Vec::new(),
             },
         );
         Self {
@@ -47,30 +53,31 @@ impl InMemoryApiKeyStorage {
     }
 }
 
+#[async_trait]
 impl ApiKeyStorage for InMemoryApiKeyStorage {
-    fn get_key_info(&self, api_key: &str) -> Result<Option<ApiKeyInfo>, String> {
+    async fn get_key_info(&self, api_key: &str) -> Result<Option<ApiKeyInfo>, String> {
         let keys = self.keys.read().map_err(|e| e.to_string())?;
         Ok(keys.get(api_key).cloned())
     }
 
-    fn set_key_info(&self, api_key: String, info: ApiKeyInfo) -> Result<(), String> {
+    async fn set_key_info(&self, api_key: String, info: ApiKeyInfo) -> Result<(), String> {
         let mut keys = self.keys.write().map_err(|e| e.to_string())?;
         keys.insert(api_key, info);
         Ok(())
     }
 
-    fn remove_key(&self, api_key: &str) -> Result<(), String> {
+    async fn remove_key(&self, api_key: &str) -> Result<(), String> {
         let mut keys = self.keys.write().map_err(|e| e.to_string())?;
         keys.remove(api_key);
         Ok(())
     }
 
-    fn list_keys(&self) -> Result<Vec<ApiKeyInfo>, String> {
+    async fn list_keys(&self) -> Result<Vec<ApiKeyInfo>, String> {
         let keys = self.keys.read().map_err(|e| e.to_string())?;
         Ok(keys.values().cloned().collect())
     }
 
-    fn update_key_status(&self, api_key: &str, status: KeyStatus) -> Result<(), String> {
+    async fn update_key_status(&self, api_key: &str, status: KeyStatus) -> Result<(), String> {
         let mut keys = self.keys.write().map_err(|e| e.to_string())?;
         if let Some(info) = keys.get_mut(api_key) {
             info.status = status;
@@ -93,13 +100,14 @@ impl InMemoryApiKeyStatsStorage {
     }
 }
 
+#[async_trait]
 impl ApiKeyStatsStorage for InMemoryApiKeyStatsStorage {
-    fn get_stats(&self, api_key: &str) -> Result<Option<ApiKeyStats>, String> {
+    async fn get_stats(&self, api_key: &str) -> Result<Option<ApiKeyStats>, String> {
         let stats = self.stats.read().map_err(|e| e.to_string())?;
         Ok(stats.get(api_key).cloned())
     }
 
-    fn update_stats(&self, api_key: &str, stats: ApiKeyStats) -> Result<(), String> {
+    async fn update_stats(&self, api_key: &str, stats: ApiKeyStats) -> Result<(), String> {
         let mut storage = self.stats.write().map_err(|e| e.to_string())?;
         storage.insert(api_key.to_string(), stats);
         Ok(())
@@ -1,15 +1,105 @@
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct ApiKeyInfo {
+    /// public handle of the key, tasks and the admin routes refer to keys by it.
+    /// keys stored before it existed get it from `key_id` when read
+    #[serde(default)]
+    pub id: String,
     pub key: String,
     pub name: String,
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
     pub permissions: Vec<Permission>,
     pub rate_limit: RateLimit,
+    #[serde(default)]
     pub status: KeyStatus,
+    /// minimum audio duration for this key, overrides the global `MIN_AUDIO_SECONDS`
+    #[serde(default)]
+    pub min_audio_seconds: Option<f64>,
+    /// hosts the callback urls of this key may point at, empty allows any host.
+    /// `*.example.com` allows every subdomain of example.com
+    #[serde(default)]
+    pub allowed_callback_hosts: Vec<String>,
+}
+
+/// stable id derived from a key, the key can't be recovered from it
+pub fn key_id(key: &str) -> String {
+    let digest = Sha256::digest(key.as_bytes());
+    format!("kid-{}", hex::encode(&digest[..8]))
+}
+
+impl ApiKeyInfo {
+    /// fill in the id of a key stored without one
+    pub fn with_id(mut self) -> Self {
+        if self.id.is_empty() {
+            self.id = key_id(&self.key);
+        }
+        self
+    }
+
+    /// whether this key may send callbacks to `host`
+    pub fn allows_callback_host(&self, host: &str) -> bool {
+        if self.allowed_callback_hosts.is_empty() {
+            return true;
+        }
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.allowed_callback_hosts.iter().any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.') && sub.len() > 1),
+            None => *allowed == host,
+        })
+    }
+
+    /// check a callback url against the allowed hosts of this key
+    pub fn check_callback_url(&self, callback_url: &str) -> Result<(), String> {
+        if self.allowed_callback_hosts.is_empty() {
+            return Ok(());
+        }
+        let url = reqwest::Url::parse(callback_url)
+            .map_err(|e| format!("Invalid callback url: {}", e))?;
+        let host = url.host_str().ok_or_else(|| "Callback url has no host".to_string())?;
+        if self.allows_callback_host(host) {
+            Ok(())
+        } else {
+            Err(format!("Callback host {} is not allowed for this API key", host))
+        }
+    }
+
+    /// status as enforced on requests: an active key past `expires_at` is expired
+    pub fn effective_status(&self, now: DateTime<Utc>) -> KeyStatus {
+        match self.status {
+            KeyStatus::Active if self.expires_at.is_some_and(|expires_at| expires_at < now) => KeyStatus::Expired,
+            ref status => status.clone(),
+        }
+    }
+}
+
+/// filters of the key listing, unset fields match every key
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ApiKeyFilter {
+    /// compared against `ApiKeyInfo::effective_status`
+    pub status: Option<KeyStatus>,
+    /// keys that have not expired yet but will within this many days
+    pub expiring_within_days: Option<i64>,
+}
+
+impl ApiKeyFilter {
+    pub fn matches(&self, key_info: &ApiKeyInfo, now: DateTime<Utc>) -> bool {
+        if let Some(status) = &self.status {
+            if key_info.effective_status(now) != *status {
+                return false;
+            }
+        }
+        if let Some(days) = self.expiring_within_days {
+            let deadline = now + chrono::Duration::days(days);
+            if !key_info.expires_at.is_some_and(|expires_at| expires_at >= now && expires_at <= deadline) {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 
@@ -22,6 +112,34 @@ pub enum Permission {
     Admin,
 }
 
+impl Permission {
+    /// whether holding this permission grants `other`. Admin grants everything,
+    /// the feature permissions only grant themselves: speaker diarization and
+    /// emotion recognition are add-ons and do not give access to transcription.
+    pub fn implies(&self, other: &Permission) -> bool {
+        self == other || *self == Permission::Admin
+    }
+}
+
+/// rate limit category of an api call, used to weight limiter consumption
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum RateCategory {
+    // creating tasks / transcribing, the expensive path
+    Submit,
+    // polling status and other cheap reads
+    Read,
+}
+
+impl RateCategory {
+    /// number of limiter cells one call of this category consumes
+    pub fn cost(&self) -> u32 {
+        match self {
+            RateCategory::Submit => 10,
+            RateCategory::Read => 1,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct RateLimit {
     pub requests_per_minute: u32,
@@ -29,16 +147,13 @@ pub struct RateLimit {
     pub requests_per_day: u32,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+// keys missing a status (older records, `ApiKeyInfo::default()`) are active,
+// expiry is decided by `expires_at`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
 pub enum KeyStatus {
+    #[default]
     Active,
     Suspended,
     Expired,
-} 
-
-impl Default for KeyStatus {
-    fn default() -> Self {
-        KeyStatus::Expired
-    }
 }
 
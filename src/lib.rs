@@ -8,16 +8,39 @@ pub mod audio;
 
 use std::{env, sync::Arc};
 use auth::Auth;
-use schedule::TaskManager;
+use schedule::{TaskManager, TaskScheduler};
 use once_cell::sync::Lazy;
 
 pub struct AppContext {
     pub auth: Arc<Auth>,
     pub task_manager: Arc<TaskManager>,
+    pub scheduler: Arc<TaskScheduler>,
+    pub voiceprints: Arc<dyn storage::voiceprint::VoiceprintStorage>,
 }
 
 const ASR_SQLITE_PATH: &str = "sqlite://./asr_data/database/storage.db?mode=rwc";
 const ASR_AUDIO_PATH: &str = "./asr_data/audio/";
+const ASR_DIRTY_WORDS_PATH: &str = "./asr_data/dirty_words/";
+const ASR_MAX_CONCURRENT_TRANSCRIPTIONS: usize = 1;
+const ASR_MAX_LOADED_MODELS: usize = 2;
+const ASR_SQLITE_JOURNAL_MODE: &str = "WAL";
+const ASR_SQLITE_SYNCHRONOUS: &str = "NORMAL";
+const ASR_SQLITE_BUSY_TIMEOUT_MS: u64 = 5000;
+const ASR_MAX_DOWNLOAD_BYTES: u64 = 512 * 1024 * 1024;
+const ASR_API_KEY_STORAGE: &str = "memory";
+const ASR_WORKER_POLL_JITTER: f64 = 0.2;
+const ASR_MIN_LANGUAGE_PROBABILITY: f32 = 0.5;
+const ASR_CALLBACK_MAX_ATTEMPTS: u32 = 5;
+const ASR_MIN_AUDIO_SECONDS: f64 = 0.0;
+const ASR_CALLBACK_CONNECT_TIMEOUT_SECS: u64 = 10;
+const ASR_CALLBACK_TIMEOUT_SECS: u64 = 30;
+const ASR_SYNC_MAX_AUDIO_SECONDS: f64 = 30.0;
+const ASR_SYNC_TIMEOUT_SECS: u64 = 60;
+const ASR_TRANSCRIBE_MAX_ATTEMPTS: u32 = 2;
+const ASR_NOISE_REDUCTION_MAX_ATTEMPTS: u32 = 3;
+const ASR_TASK_RETRY_DELAY_SECS: u64 = 5;
+const ASR_TASK_RETENTION_DAYS: i64 = 30;
+const ASR_TASK_CLEANUP_INTERVAL_SECS: u64 = 3600;
 
 pub static SQLITE_PATH: Lazy<String> = Lazy::new(|| {
     match env::var("ASR_SQLITE_PATH") {
@@ -28,6 +51,46 @@ pub static SQLITE_PATH: Lazy<String> = Lazy::new(|| {
     }
 });
 
+/// 任务存储的连接串，`postgres://` 开头时使用 Postgres，默认与其他数据共用 SQLite
+pub static TASK_DATABASE_URL: Lazy<String> = Lazy::new(|| {
+    match env::var("ASR_TASK_DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            dotenv::var("ASR_TASK_DATABASE_URL").unwrap_or_else(|_| SQLITE_PATH.clone())
+        }
+    }
+});
+
+pub static SQLITE_JOURNAL_MODE: Lazy<String> = Lazy::new(|| {
+    match env::var("ASR_SQLITE_JOURNAL_MODE") {
+        Ok(mode) => mode,
+        Err(_) => {
+            dotenv::var("ASR_SQLITE_JOURNAL_MODE").unwrap_or_else(|_| ASR_SQLITE_JOURNAL_MODE.to_string())
+        }
+    }
+});
+
+pub static SQLITE_SYNCHRONOUS: Lazy<String> = Lazy::new(|| {
+    match env::var("ASR_SQLITE_SYNCHRONOUS") {
+        Ok(mode) => mode,
+        Err(_) => {
+            dotenv::var("ASR_SQLITE_SYNCHRONOUS").unwrap_or_else(|_| ASR_SQLITE_SYNCHRONOUS.to_string())
+        }
+    }
+});
+
+pub static SQLITE_BUSY_TIMEOUT_MS: Lazy<u64> = Lazy::new(|| {
+    match env::var("ASR_SQLITE_BUSY_TIMEOUT_MS") {
+        Ok(value) => value.parse().unwrap_or(ASR_SQLITE_BUSY_TIMEOUT_MS),
+        Err(_) => {
+            dotenv::var("ASR_SQLITE_BUSY_TIMEOUT_MS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_SQLITE_BUSY_TIMEOUT_MS)
+        }
+    }
+});
+
 pub static AUDIO_PATH: Lazy<String> = Lazy::new(|| {
     match env::var("ASR_AUDIO_PATH") {
         Ok(path) => path,
@@ -37,15 +100,332 @@ pub static AUDIO_PATH: Lazy<String> = Lazy::new(|| {
     }
 });
 
+pub static DIRTY_WORDS_PATH: Lazy<String> = Lazy::new(|| {
+    match env::var("ASR_DIRTY_WORDS_PATH") {
+        Ok(path) => path,
+        Err(_) => {
+            dotenv::var("ASR_DIRTY_WORDS_PATH").unwrap_or_else(|_| ASR_DIRTY_WORDS_PATH.to_string())
+        }
+    }
+});
+
+pub static MAX_CONCURRENT_TRANSCRIPTIONS: Lazy<usize> = Lazy::new(|| {
+    match env::var("ASR_MAX_CONCURRENT_TRANSCRIPTIONS") {
+        Ok(value) => value.parse().unwrap_or(ASR_MAX_CONCURRENT_TRANSCRIPTIONS),
+        Err(_) => {
+            dotenv::var("ASR_MAX_CONCURRENT_TRANSCRIPTIONS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_MAX_CONCURRENT_TRANSCRIPTIONS)
+        }
+    }
+});
+
+pub static MAX_LOADED_MODELS: Lazy<usize> = Lazy::new(|| {
+    match env::var("ASR_MAX_LOADED_MODELS") {
+        Ok(value) => value.parse().unwrap_or(ASR_MAX_LOADED_MODELS),
+        Err(_) => {
+            dotenv::var("ASR_MAX_LOADED_MODELS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_MAX_LOADED_MODELS)
+        }
+    }
+});
+
+pub static MAX_DOWNLOAD_BYTES: Lazy<u64> = Lazy::new(|| {
+    match env::var("ASR_MAX_DOWNLOAD_BYTES") {
+        Ok(value) => value.parse().unwrap_or(ASR_MAX_DOWNLOAD_BYTES),
+        Err(_) => {
+            dotenv::var("ASR_MAX_DOWNLOAD_BYTES")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_MAX_DOWNLOAD_BYTES)
+        }
+    }
+});
+
+/// API key 存储后端，`memory` 或 `sqlite`，其他值启动失败
+pub static API_KEY_STORAGE: Lazy<String> = Lazy::new(|| {
+    match env::var("ASR_API_KEY_STORAGE") {
+        Ok(storage) => storage,
+        Err(_) => {
+            dotenv::var("ASR_API_KEY_STORAGE").unwrap_or_else(|_| ASR_API_KEY_STORAGE.to_string())
+        }
+    }
+});
+
+/// worker 空闲轮询间隔的随机抖动比例，0 到 1 之间
+pub static WORKER_POLL_JITTER: Lazy<f64> = Lazy::new(|| {
+    match env::var("ASR_WORKER_POLL_JITTER") {
+        Ok(value) => value.parse().unwrap_or(ASR_WORKER_POLL_JITTER),
+        Err(_) => {
+            dotenv::var("ASR_WORKER_POLL_JITTER")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_WORKER_POLL_JITTER)
+        }
+    }
+});
+
+/// 提交的音频最短时长（秒），为 0 时不限制；API key 可单独设置
+pub static MIN_AUDIO_SECONDS: Lazy<f64> = Lazy::new(|| {
+    match env::var("ASR_MIN_AUDIO_SECONDS") {
+        Ok(value) => value.parse().unwrap_or(ASR_MIN_AUDIO_SECONDS),
+        Err(_) => {
+            dotenv::var("ASR_MIN_AUDIO_SECONDS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_MIN_AUDIO_SECONDS)
+        }
+    }
+});
+
+/// HTTP 回调的最大发送次数（含首次），全部失败的回调记录到死信表
+pub static CALLBACK_MAX_ATTEMPTS: Lazy<u32> = Lazy::new(|| {
+    match env::var("ASR_CALLBACK_MAX_ATTEMPTS") {
+        Ok(value) => value.parse().unwrap_or(ASR_CALLBACK_MAX_ATTEMPTS),
+        Err(_) => {
+            dotenv::var("ASR_CALLBACK_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_CALLBACK_MAX_ATTEMPTS)
+        }
+    }
+});
+
+/// HTTP 回调的连接超时（秒）
+pub static CALLBACK_CONNECT_TIMEOUT_SECS: Lazy<u64> = Lazy::new(|| {
+    match env::var("ASR_CALLBACK_CONNECT_TIMEOUT_SECS") {
+        Ok(value) => value.parse().unwrap_or(ASR_CALLBACK_CONNECT_TIMEOUT_SECS),
+        Err(_) => {
+            dotenv::var("ASR_CALLBACK_CONNECT_TIMEOUT_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_CALLBACK_CONNECT_TIMEOUT_SECS)
+        }
+    }
+});
+
+/// HTTP 回调的请求超时（秒），接收方无响应时不会一直阻塞 worker
+pub static CALLBACK_TIMEOUT_SECS: Lazy<u64> = Lazy::new(|| {
+    match env::var("ASR_CALLBACK_TIMEOUT_SECS") {
+        Ok(value) => value.parse().unwrap_or(ASR_CALLBACK_TIMEOUT_SECS),
+        Err(_) => {
+            dotenv::var("ASR_CALLBACK_TIMEOUT_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_CALLBACK_TIMEOUT_SECS)
+        }
+    }
+});
+
+/// 同步转写接受的音频最长时长（秒），更长的音频应提交异步任务
+pub static SYNC_MAX_AUDIO_SECONDS: Lazy<f64> = Lazy::new(|| {
+    match env::var("ASR_SYNC_MAX_AUDIO_SECONDS") {
+        Ok(value) => value.parse().unwrap_or(ASR_SYNC_MAX_AUDIO_SECONDS),
+        Err(_) => {
+            dotenv::var("ASR_SYNC_MAX_AUDIO_SECONDS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_SYNC_MAX_AUDIO_SECONDS)
+        }
+    }
+});
+
+/// 同步转写的超时（秒），包括等待空闲的转写名额
+pub static SYNC_TIMEOUT_SECS: Lazy<u64> = Lazy::new(|| {
+    match env::var("ASR_SYNC_TIMEOUT_SECS") {
+        Ok(value) => value.parse().unwrap_or(ASR_SYNC_TIMEOUT_SECS),
+        Err(_) => {
+            dotenv::var("ASR_SYNC_TIMEOUT_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_SYNC_TIMEOUT_SECS)
+        }
+    }
+});
+
+/// 转写任务的默认最大执行次数（含首次），创建任务时可单独指定 `max_retries`
+pub static TRANSCRIBE_MAX_ATTEMPTS: Lazy<u32> = Lazy::new(|| {
+    match env::var("ASR_TRANSCRIBE_MAX_ATTEMPTS") {
+        Ok(value) => value.parse().unwrap_or(ASR_TRANSCRIBE_MAX_ATTEMPTS),
+        Err(_) => {
+            dotenv::var("ASR_TRANSCRIBE_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_TRANSCRIBE_MAX_ATTEMPTS)
+        }
+    }
+});
+
+/// 降噪任务的默认最大执行次数（含首次）
+pub static NOISE_REDUCTION_MAX_ATTEMPTS: Lazy<u32> = Lazy::new(|| {
+    match env::var("ASR_NOISE_REDUCTION_MAX_ATTEMPTS") {
+        Ok(value) => value.parse().unwrap_or(ASR_NOISE_REDUCTION_MAX_ATTEMPTS),
+        Err(_) => {
+            dotenv::var("ASR_NOISE_REDUCTION_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_NOISE_REDUCTION_MAX_ATTEMPTS)
+        }
+    }
+});
+
+/// 失败任务首次重试前的等待（秒），之后每次翻倍
+pub static TASK_RETRY_DELAY_SECS: Lazy<u64> = Lazy::new(|| {
+    match env::var("ASR_TASK_RETRY_DELAY_SECS") {
+        Ok(value) => value.parse().unwrap_or(ASR_TASK_RETRY_DELAY_SECS),
+        Err(_) => {
+            dotenv::var("ASR_TASK_RETRY_DELAY_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_TASK_RETRY_DELAY_SECS)
+        }
+    }
+});
+
+/// 已完成和失败任务的保留天数，超过后连同结果被定期删除；为 0 时不清理
+pub static TASK_RETENTION_DAYS: Lazy<i64> = Lazy::new(|| {
+    match env::var("ASR_TASK_RETENTION_DAYS") {
+        Ok(value) => value.parse().unwrap_or(ASR_TASK_RETENTION_DAYS),
+        Err(_) => {
+            dotenv::var("ASR_TASK_RETENTION_DAYS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_TASK_RETENTION_DAYS)
+        }
+    }
+});
+
+/// 清理过期任务的间隔（秒）
+pub static TASK_CLEANUP_INTERVAL_SECS: Lazy<u64> = Lazy::new(|| {
+    match env::var("ASR_TASK_CLEANUP_INTERVAL_SECS") {
+        Ok(value) => value.parse().unwrap_or(ASR_TASK_CLEANUP_INTERVAL_SECS),
+        Err(_) => {
+            dotenv::var("ASR_TASK_CLEANUP_INTERVAL_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_TASK_CLEANUP_INTERVAL_SECS)
+        }
+    }
+});
+
+/// 调度器 worker 的总数上限，未设置时为 CPU 核心数
+pub static MAX_WORKERS: Lazy<Option<usize>> = Lazy::new(|| {
+    env::var("ASR_MAX_WORKERS")
+        .or_else(|_| dotenv::var("ASR_MAX_WORKERS"))
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|&workers| workers > 0)
+});
+
+/// 启动时每种任务类型的 worker 数量
+const ASR_TYPE_WORKERS: usize = 1;
+
+/// 无法解析的值记录警告后使用默认值
+fn type_workers(key: &str) -> usize {
+    match env::var(key).or_else(|_| dotenv::var(key)) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            tracing::warn!("Invalid {}: {:?}, starting {} workers", key, value, ASR_TYPE_WORKERS);
+            ASR_TYPE_WORKERS
+        }),
+        Err(_) => ASR_TYPE_WORKERS,
+    }
+}
+
+/// 转写任务的 worker 数量，设为 0 时本实例不处理该类任务
+pub static TRANSCRIBE_WORKERS: Lazy<usize> = Lazy::new(|| type_workers("ASR_TRANSCRIBE_WORKERS"));
+
+/// 降噪任务的 worker 数量，设为 0 时本实例不处理该类任务
+pub static NOISE_REDUCTION_WORKERS: Lazy<usize> = Lazy::new(|| type_workers("ASR_NOISE_REDUCTION_WORKERS"));
+
+/// 声纹识别任务的 worker 数量，设为 0 时本实例不处理该类任务
+pub static VOICEPRINT_WORKERS: Lazy<usize> = Lazy::new(|| type_workers("ASR_VOICEPRINT_WORKERS"));
+
+/// 自动检测语言置信度过低时使用的语言，未设置时总是使用检测结果
+pub static FALLBACK_LANGUAGE: Lazy<Option<String>> = Lazy::new(|| {
+    env::var("ASR_FALLBACK_LANGUAGE")
+        .or_else(|_| dotenv::var("ASR_FALLBACK_LANGUAGE"))
+        .ok()
+        .filter(|language| !language.is_empty())
+});
+
+/// 任务用完重试次数后发送死信通知的地址，未设置时不发送
+pub static DEAD_LETTER_URL: Lazy<Option<String>> = Lazy::new(|| {
+    env::var("ASR_DEAD_LETTER_URL")
+        .or_else(|_| dotenv::var("ASR_DEAD_LETTER_URL"))
+        .ok()
+        .filter(|url| !url.is_empty())
+});
+
+/// 没有任何 API key 时创建的管理员 key，未设置时随机生成并在启动日志中输出一次
+pub static BOOTSTRAP_ADMIN_KEY: Lazy<Option<String>> = Lazy::new(|| {
+    env::var("ASR_BOOTSTRAP_ADMIN_KEY")
+        .or_else(|_| dotenv::var("ASR_BOOTSTRAP_ADMIN_KEY"))
+        .ok()
+        .filter(|key| !key.is_empty())
+});
+
+/// rayon 预处理线程池的线程数，未设置时按核心数自动分配，见 `ThreadAllocation`
+pub static PREPROCESS_THREADS: Lazy<Option<usize>> = Lazy::new(|| {
+    env::var("ASR_PREPROCESS_THREADS")
+        .or_else(|_| dotenv::var("ASR_PREPROCESS_THREADS"))
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|threads| *threads > 0)
+});
+
+/// 每个转写任务的 whisper 推理线程数，未设置时按核心数自动分配
+pub static WHISPER_THREADS: Lazy<Option<usize>> = Lazy::new(|| {
+    env::var("ASR_WHISPER_THREADS")
+        .or_else(|_| dotenv::var("ASR_WHISPER_THREADS"))
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|threads| *threads > 0)
+});
+
+/// HTTP 回调请求体的最大字节数，超出时只发送结果地址，未设置或为 0 时不限制
+pub static CALLBACK_MAX_PAYLOAD_BYTES: Lazy<Option<usize>> = Lazy::new(|| {
+    env::var("ASR_CALLBACK_MAX_PAYLOAD_BYTES")
+        .or_else(|_| dotenv::var("ASR_CALLBACK_MAX_PAYLOAD_BYTES"))
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|bytes| *bytes > 0)
+});
+
+/// 服务对外的地址，如 `https://asr.example.com`，用于拼接回调中的结果地址
+pub static PUBLIC_URL: Lazy<Option<String>> = Lazy::new(|| {
+    env::var("ASR_PUBLIC_URL")
+        .or_else(|_| dotenv::var("ASR_PUBLIC_URL"))
+        .ok()
+        .filter(|url| !url.is_empty())
+});
+
+/// 语言检测概率低于该值时使用 `FALLBACK_LANGUAGE`
+pub static MIN_LANGUAGE_PROBABILITY: Lazy<f32> = Lazy::new(|| {
+    match env::var("ASR_MIN_LANGUAGE_PROBABILITY") {
+        Ok(value) => value.parse().unwrap_or(ASR_MIN_LANGUAGE_PROBABILITY),
+        Err(_) => {
+            dotenv::var("ASR_MIN_LANGUAGE_PROBABILITY")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(ASR_MIN_LANGUAGE_PROBABILITY)
+        }
+    }
+});
+
 pub fn init_env() {
     dotenv::dotenv().ok();
     
     // 确保数据目录存在
-    if let Some(db_path) = SQLITE_PATH.strip_prefix("sqlite://") {
-        if let Some(dir) = std::path::Path::new(db_path).parent() {
-            std::fs::create_dir_all(dir).unwrap_or_else(|e| {
-                eprintln!("Failed to create database directory: {}", e);
-            });
+    for url in [SQLITE_PATH.as_str(), TASK_DATABASE_URL.as_str()] {
+        if let Some(db_path) = url.strip_prefix("sqlite://") {
+            if let Some(dir) = std::path::Path::new(db_path).parent() {
+                std::fs::create_dir_all(dir).unwrap_or_else(|e| {
+                    eprintln!("Failed to create database directory: {}", e);
+                });
+            }
         }
     }
 }
\ No newline at end of file
@@ -1,75 +1,184 @@
 #![allow(clippy::uninlined_format_args)]
 
 use anyhow::Result;
-use tracing::info;
+use tracing::{info, warn};
 use std::sync::Arc;
 use std::net::SocketAddr;
+use std::time::Duration;
 use asr_rs::{
-    asr::whisper::WhisperAsr, auth::Auth, schedule::{TaskManager, TaskScheduler}, utils::logger, AppContext, init_env, SQLITE_PATH
+    asr::registry::ModelRegistry, auth::Auth, schedule::{TaskManager, TaskScheduler}, utils::{logger, metrics}, AppContext, init_env, SQLITE_PATH, TASK_DATABASE_URL, MAX_CONCURRENT_TRANSCRIPTIONS, MAX_LOADED_MODELS, API_KEY_STORAGE, WORKER_POLL_JITTER, FALLBACK_LANGUAGE, MIN_LANGUAGE_PROBABILITY, CALLBACK_MAX_ATTEMPTS,
+    CALLBACK_CONNECT_TIMEOUT_SECS, CALLBACK_TIMEOUT_SECS, CALLBACK_MAX_PAYLOAD_BYTES, PUBLIC_URL, DEAD_LETTER_URL, AUDIO_PATH,
+    PREPROCESS_THREADS, WHISPER_THREADS, MAX_WORKERS, TRANSCRIBE_MAX_ATTEMPTS, NOISE_REDUCTION_MAX_ATTEMPTS,
+    TASK_RETRY_DELAY_SECS, TASK_RETENTION_DAYS, TASK_CLEANUP_INTERVAL_SECS, TRANSCRIBE_WORKERS, NOISE_REDUCTION_WORKERS,
+    VOICEPRINT_WORKERS, BOOTSTRAP_ADMIN_KEY, DIRTY_WORDS_PATH
 };
-use asr_rs::storage::task::sqlite::SqliteTaskStorage;
-use asr_rs::auth::storage::{InMemoryApiKeyStorage, InMemoryApiKeyStatsStorage};
-use asr_rs::schedule::types::TaskType;
+use asr_rs::utils::http::RetryPolicy;
+use asr_rs::storage::task;
+use asr_rs::auth::storage::{ApiKeyStorage, ApiKeyStatsStorage, InMemoryApiKeyStorage, InMemoryApiKeyStatsStorage};
+use asr_rs::auth::sqlite::{SqliteApiKeyStorage, SqliteApiKeyStatsStorage};
+use asr_rs::schedule::types::{TaskRetryPolicy, TaskType};
 use std::fs;
-use asr_rs::schedule::processors::TranscribeProcessor;
+use asr_rs::schedule::processors::{
+    DirtyWordPostProcessor, NoiseReductionProcessor, TranscribeProcessor, VoiceprintProcessor, WhitespaceNormalizer,
+};
+use asr_rs::asr::filter::DirtyWordFilter;
+use asr_rs::storage::voiceprint;
+use asr_rs::asr::AsrParams;
+use asr_rs::asr::whisper::WhisperConfig;
+use asr_rs::utils::threads::ThreadAllocation;
+use asr_rs::asr::listen::{self, DEFAULT_LISTEN_CHUNK_SECONDS};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // 初始化环境
     init_env();
+
+    // `asr listen [language]`：从标准输入读取 PCM 并实时输出识别文本，不启动服务
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("listen") {
+        return run_listen(args.get(1).cloned()).await;
+    }
     
     // 初始化日志系统
     let _guard = logger::init("./logs".to_string())?;
+    // 在任务开始处理前安装指标 recorder
+    metrics::init();
     // 创建必要的目录
     fs::create_dir_all("./asr_data/database")?;
     fs::create_dir_all("./asr_data/data")?;
 
     info!("Starting ASR service...");
 
-    // 初始化 ASR 模型
+    // 预处理和推理分开分配线程，两者之和不超过核心数
+    let threads = ThreadAllocation::detect(*MAX_CONCURRENT_TRANSCRIPTIONS, *PREPROCESS_THREADS, *WHISPER_THREADS);
+    threads.init_preprocess_pool()?;
+
+    // 初始化 ASR 模型，默认模型启动时加载，其他模型按需加载
     info!("Initializing Whisper ASR model...");
-    let asr = WhisperAsr::new("./models/ggml-large-v3.bin".to_string())?;
+    let asr = ModelRegistry::new("large-v3", "./models/ggml-large-v3.bin", *MAX_LOADED_MODELS)
+        .with_config(WhisperConfig { threads: threads.whisper_threads as i32, ..WhisperConfig::default() })
+        .register("base", "./models/ggml-base.bin");
+    asr.get(None).await?;
 
     // 初始化 storage
     info!("Initializing Storage...");
-    let api_key_storage = InMemoryApiKeyStorage::new();
-    let api_key_stats_storage = InMemoryApiKeyStatsStorage::new();
-    let storage = SqliteTaskStorage::new(&SQLITE_PATH).await?;
+    let (api_key_storage, api_key_stats_storage): (Arc<dyn ApiKeyStorage>, Arc<dyn ApiKeyStatsStorage>) =
+        match API_KEY_STORAGE.as_str() {
+            "sqlite" => (
+                Arc::new(SqliteApiKeyStorage::new(&SQLITE_PATH).await?),
+                Arc::new(SqliteApiKeyStatsStorage::new(&SQLITE_PATH).await?),
+            ),
+            "memory" => (
+                Arc::new(InMemoryApiKeyStorage::new()),
+                Arc::new(InMemoryApiKeyStatsStorage::new()),
+            ),
+            other => anyhow::bail!("Unknown ASR_API_KEY_STORAGE {:?}, expected memory or sqlite", other),
+        };
+    let storage = task::connect(&TASK_DATABASE_URL).await?;
     
     // 初始化认证管理器
     info!("Initializing Auth Manager...");
-    let auth_manager = Auth::new(Arc::new(api_key_storage), Arc::new(api_key_stats_storage));
+    let auth_manager = Auth::new(api_key_storage, api_key_stats_storage);
+    // 空库时创建管理员 key，否则无法通过接口创建其他 key
+    if let Some(key_info) = auth_manager.bootstrap_admin_key(BOOTSTRAP_ADMIN_KEY.clone()).await? {
+        if BOOTSTRAP_ADMIN_KEY.is_some() {
+            info!("No api keys found, created admin key {} from ASR_BOOTSTRAP_ADMIN_KEY", key_info.id);
+        } else {
+            warn!("No api keys found, created admin key {} ({}), it is not shown again", key_info.key, key_info.id);
+        }
+    }
     
     // 初始化任务管理器
     info!("Initializing Task Manager...");
-    let mut task_manager = TaskManager::new(Arc::new(storage));
+    let mut task_manager = TaskManager::new(storage.clone()).with_callback_retry(RetryPolicy {
+        max_attempts: (*CALLBACK_MAX_ATTEMPTS).max(1),
+        ..RetryPolicy::default()
+    })
+    .with_callback_timeouts(
+        Duration::from_secs(*CALLBACK_CONNECT_TIMEOUT_SECS),
+        Duration::from_secs(*CALLBACK_TIMEOUT_SECS),
+    )
+    .with_callback_max_payload(*CALLBACK_MAX_PAYLOAD_BYTES, PUBLIC_URL.clone())
+    .with_dead_letter_url(DEAD_LETTER_URL.clone())
+    .with_retry_policy(TaskType::Transcribe, TaskRetryPolicy {
+        max_attempts: (*TRANSCRIBE_MAX_ATTEMPTS).max(1),
+        ..TaskRetryPolicy::for_task_type(&TaskType::Transcribe)
+    })
+    .with_retry_policy(TaskType::NoiseReduction, TaskRetryPolicy {
+        max_attempts: (*NOISE_REDUCTION_MAX_ATTEMPTS).max(1),
+        ..TaskRetryPolicy::for_task_type(&TaskType::NoiseReduction)
+    })
+    .with_retry_delay(Duration::from_secs(*TASK_RETRY_DELAY_SECS));
 
+    // 注册处理器，声纹与任务存放在同一个数据库
+    let voiceprints = voiceprint::connect(&TASK_DATABASE_URL).await?;
+    let events = task_manager.events();
+    // 转写结果先屏蔽敏感词，再统一空白
+    let dirty_words = DirtyWordFilter::load_from_dir(std::path::Path::new(DIRTY_WORDS_PATH.as_str()))?;
+    task_manager.register_processor(Box::new(
+        TranscribeProcessor::new(Arc::new(asr), *MAX_CONCURRENT_TRANSCRIPTIONS)
+            .with_checkpoints(storage.clone())
+            .with_input_tasks(storage)
+            .with_events(events)
+            .with_language_fallback(FALLBACK_LANGUAGE.clone(), *MIN_LANGUAGE_PROBABILITY)
+            .with_post_processor(Arc::new(DirtyWordPostProcessor::new(dirty_words)))
+            .with_post_processor(Arc::new(WhitespaceNormalizer)),
+    ));
+    task_manager.register_processor(Box::new(NoiseReductionProcessor::new(
+        std::path::Path::new(AUDIO_PATH.as_str()).join("denoised"),
+    )));
+    task_manager.register_processor(Box::new(VoiceprintProcessor::new(voiceprints.clone())));
 
-     // 注册处理器
-     task_manager.register_processor(Box::new(TranscribeProcessor::new(Arc::new(asr))));
+    // 初始化调度器
+    info!("Initializing Scheduler...");
+    let task_manager = Arc::new(task_manager);
+    let mut scheduler = TaskScheduler::new(task_manager.clone()).with_poll_jitter(*WORKER_POLL_JITTER);
+    if let Some(max_workers) = *MAX_WORKERS {
+        scheduler = scheduler.with_max_workers(max_workers);
+    }
+    if *TASK_RETENTION_DAYS > 0 {
+        scheduler = scheduler.with_cleanup(Duration::from_secs((*TASK_CLEANUP_INTERVAL_SECS).max(1)), *TASK_RETENTION_DAYS);
+    }
+    let scheduler = Arc::new(scheduler);
 
     // 创建应用上下文
     let ctx = Arc::new(AppContext {
         auth: Arc::new(auth_manager),
-        task_manager: Arc::new(task_manager),
+        task_manager,
+        scheduler: scheduler.clone(),
+        voiceprints,
     });
 
-   
-    // 初始化调度器并启动
-    info!("Initializing Scheduler...");
-    let scheduler = TaskScheduler::new(ctx.task_manager.clone());
-    scheduler.spawn_worker(TaskType::Transcribe).await;
+    // 启动调度器
+    for (task_type, count) in [
+        (TaskType::Transcribe, *TRANSCRIBE_WORKERS),
+        (TaskType::NoiseReduction, *NOISE_REDUCTION_WORKERS),
+        (TaskType::VoiceprintRecognition, *VOICEPRINT_WORKERS),
+    ] {
+        let started = scheduler.spawn_workers(task_type.clone(), count).await;
+        info!("Started {} {} workers", started, task_type);
+    }
 
+    let runner = scheduler.clone();
     tokio::spawn(async move {
-        let _ =scheduler.run().await;
+        if let Err(e) = runner.run().await {
+            tracing::error!("Scheduler error: {}", e);
+        }
     });
 
     // 配置服务器地址
     let addr = SocketAddr::from(([127, 0, 0, 1], 7200));
     info!("Starting HTTP server at http://{}", addr);
 
-    // 启动 HTTP 服务器
-    match asr_rs::web::start_server(ctx.clone(), addr).await {
+    // 启动 HTTP 服务器，收到 Ctrl-C 后停止接收新请求
+    let shutdown_signal = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for shutdown signal: {}", e);
+            std::future::pending::<()>().await;
+        }
+        info!("Received shutdown signal");
+    };
+    match asr_rs::web::start_server_with_shutdown(ctx.clone(), addr, shutdown_signal).await {
         Ok(_) => info!("Server stopped gracefully"),
         Err(e) => {
             tracing::error!("Server error: {}", e);
@@ -77,10 +186,25 @@ async fn main() -> Result<()> {
         }
     }
 
-    // 优雅关闭
+    // 优雅关闭：等待正在进行的转写完成
     info!("Shutting down...");
-    // ctx.task_manager.shutdown().await?;
+    scheduler.shutdown().await?;
     
     Ok(())
 }
 
+// 读取 16kHz 单声道 16 位小端 PCM，例如 `arecord -f S16_LE -r 16000 -c 1 | asr listen`
+async fn run_listen(language: Option<String>) -> Result<()> {
+    let asr = ModelRegistry::new("large-v3", "./models/ggml-large-v3.bin", 1);
+    let engine = asr.get(None).await?;
+
+    let mut params = AsrParams::new();
+    params.set_language(language);
+    // 进度不打印到标准输出，标准输出只保留识别文本
+    let (progress, _) = tokio::sync::mpsc::unbounded_channel();
+    params.set_progress(Some(progress));
+    params.validate()?;
+
+    listen::listen(engine.as_ref(), params, DEFAULT_LISTEN_CHUNK_SECONDS, tokio::io::stdin(), tokio::io::stdout()).await?;
+    Ok(())
+}
@@ -2,8 +2,25 @@
 
 use async_trait::async_trait;
 use anyhow::Result;
+use std::time::Duration;
+use chrono::Utc;
 use serde::Serialize;
-use crate::schedule::types::{Task, TaskStatus, TaskResult};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+use crate::schedule::types::{Task, TaskStatus, TaskResult, TranscribeSegment};
+use crate::utils::http::{retry_after, RetryPolicy};
+
+/// 回调签名头，值为 `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "X-ASR-Signature";
+/// 签名时使用的 unix 时间戳（秒），接收方应拒绝过旧的时间戳以防重放
+pub const TIMESTAMP_HEADER: &str = "X-ASR-Timestamp";
+
+/// 回调默认的连接超时
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// 回调默认的请求超时，包括等待接收方响应的时间
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[async_trait]
 pub trait TaskCallback: Send + Sync {
@@ -21,11 +38,31 @@ impl Clone for Box<dyn TaskCallback> {
 }
 
 // HTTP 回调实现
+#[derive(Clone)]
 pub struct HttpCallback {
     client: reqwest::Client,
     callback_url: String,
+    secret: Option<String>,
+    retry: RetryPolicy,
+    max_payload_size: Option<usize>,
+    public_url: Option<String>,
+}
+
+/// 重试后仍未送达的回调，保留请求体以便重放
+#[derive(Debug, Clone)]
+pub struct UndeliveredCallback {
+    pub payload: String,
+    pub error: String,
+}
+
+impl std::fmt::Display for UndeliveredCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "callback was not delivered: {}", self.error)
+    }
 }
 
+impl std::error::Error for UndeliveredCallback {}
+
 #[derive(Debug, Serialize)]
 struct CallbackPayload<T> {
     task_id: String,
@@ -36,25 +73,146 @@ struct CallbackPayload<T> {
 impl HttpCallback {
     pub fn new(callback_url: String) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            client: Self::build_client(DEFAULT_CONNECT_TIMEOUT, DEFAULT_REQUEST_TIMEOUT),
             callback_url,
+            secret: None,
+            retry: RetryPolicy::none(),
+            max_payload_size: None,
+            public_url: None,
         }
     }
 
+    /// 设置回调请求体的最大字节数，超出时改为发送不含结果的精简回调，
+    /// 其中的 `result_url` 指向获取完整结果的接口。
+    /// `public_url` 为服务对外的地址，未设置时 `result_url` 为相对路径
+    pub fn with_max_payload_size(mut self, max_payload_size: Option<usize>, public_url: Option<String>) -> Self {
+        self.max_payload_size = max_payload_size;
+        self.public_url = public_url;
+        self
+    }
+
+    /// 设置连接超时和请求超时，超时按连接错误处理，会触发重试
+    pub fn with_timeouts(mut self, connect_timeout: Duration, request_timeout: Duration) -> Self {
+        self.client = Self::build_client(connect_timeout, request_timeout);
+        self
+    }
+
+    fn build_client(connect_timeout: Duration, request_timeout: Duration) -> reqwest::Client {
+        reqwest::Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(request_timeout)
+            .build()
+            .unwrap_or_else(|e| {
+                warn!("Failed to build callback client, using defaults: {}", e);
+                reqwest::Client::new()
+            })
+    }
+
+    /// 设置重试策略，默认不重试
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// 设置签名密钥，设置后每个回调都带有 `X-ASR-Signature` 和 `X-ASR-Timestamp` 头
+    pub fn with_secret(mut self, secret: Option<String>) -> Self {
+        self.secret = secret;
+        self
+    }
+
+    /// 发送死信通知：任务用完重试次数后调用一次，`data` 中包含尝试次数和每次失败的错误
+    pub async fn on_dead_letter(&self, task: &Task, attempts: u32, errors: &[String]) -> Result<()> {
+        let payload = CallbackPayload {
+            task_id: task.id.clone(),
+            status: TaskStatus::Failed(errors.last().cloned().unwrap_or_default()),
+            data: serde_json::json!({
+                "dead_letter": true,
+                "attempts": attempts,
+                "errors": errors,
+            }),
+        };
+        self.send_callback(payload).await
+    }
+
+    /// 发送回调，按重试策略重试连接错误、超时以及 5xx/429 响应。
+    /// 最终失败时返回 `UndeliveredCallback`
     async fn send_callback<T: Serialize>(&self, payload: CallbackPayload<T>) -> Result<()> {
-        self.client
-            .post(&self.callback_url)
-            .json(&payload)
-            .send()
-            .await?;
-        Ok(())
+        let mut body = serde_json::to_vec(&payload)?;
+        if let Some(max_payload_size) = self.max_payload_size {
+            if body.len() > max_payload_size {
+                warn!(
+                    "Callback of task {} is {} bytes, over the {} byte limit, sending the result url instead",
+                    payload.task_id, body.len(), max_payload_size
+                );
+                body = serde_json::to_vec(&self.compact_payload(payload.task_id, payload.status, body.len()))?;
+            }
+        }
+        let mut attempt = 1;
+        loop {
+            let (error, delay) = match self.post(&body).await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    let retryable = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                    let delay = retryable.then(|| {
+                        retry_after(response.headers())
+                            .map(|delay| delay.min(self.retry.max_delay))
+                            .unwrap_or_else(|| self.retry.backoff(attempt))
+                    });
+                    (format!("callback failed with status: {}", status), delay)
+                }
+                Err(e) => {
+                    let retryable = e.is_connect() || e.is_timeout();
+                    (format!("callback failed: {}", e), retryable.then(|| self.retry.backoff(attempt)))
+                }
+            };
+
+            match delay {
+                Some(delay) if attempt < self.retry.max_attempts => {
+                    warn!("{} to {} (attempt {}/{})", error, self.callback_url, attempt, self.retry.max_attempts);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => {
+                    return Err(UndeliveredCallback {
+                        payload: String::from_utf8_lossy(&body).into_owned(),
+                        error,
+                    }.into());
+                }
+            }
+        }
     }
 
-    fn box_clone(&self) -> Box<dyn TaskCallback> {
-        Box::new(Self {
-            client: self.client.clone(),
-            callback_url: self.callback_url.clone(),
-        })
+    /// 超出大小限制时发送的精简回调，接收方通过 `result_url` 获取完整结果
+    fn compact_payload(&self, task_id: String, status: TaskStatus, payload_size: usize) -> CallbackPayload<serde_json::Value> {
+        let result_url = format!(
+            "{}/schedule/tasks/{}/result",
+            self.public_url.as_deref().unwrap_or("").trim_end_matches('/'),
+            task_id
+        );
+        CallbackPayload {
+            task_id,
+            status,
+            data: serde_json::json!({
+                "truncated": true,
+                "payload_size": payload_size,
+                "result_url": result_url,
+            }),
+        }
+    }
+
+    // each attempt is signed with a fresh timestamp, so a retry is not mistaken for a replay
+    async fn post(&self, body: &[u8]) -> reqwest::Result<reqwest::Response> {
+        let mut request = self.client
+            .post(&self.callback_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(secret) = &self.secret {
+            let timestamp = Utc::now().timestamp();
+            request = request
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(SIGNATURE_HEADER, sign_payload(secret, timestamp, body));
+        }
+        request.body(body.to_vec()).send().await
     }
 }
 
@@ -69,13 +227,6 @@ impl TaskCallback for HttpCallback {
         self.send_callback(payload).await
     }
 
-    fn box_clone(&self) -> Box<dyn TaskCallback> {
-        Box::new(Self {
-            client: self.client.clone(),
-            callback_url: self.callback_url.clone(),
-        })
-    }
-
     async fn on_complete(&self, task: &Task, result: &TaskResult) -> Result<()> {
         let payload = CallbackPayload {
             task_id: task.id.clone(),
@@ -93,6 +244,24 @@ impl TaskCallback for HttpCallback {
         };
         self.send_callback(payload).await
     }
+
+    fn box_clone(&self) -> Box<dyn TaskCallback> {
+        Box::new(self.clone())
+    }
+}
+
+/// 计算回调签名
+///
+/// 签名的原文为 `<timestamp>.<body>`：`X-ASR-Timestamp` 头的十进制值、一个 `.`，
+/// 再接上请求体的原始字节。结果为 `sha256=` 加上 HMAC-SHA256 的小写十六进制，
+/// 接收方用同样的方式计算并比较即可验证回调来源
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
 }
 
 // 函数回调实现
@@ -139,21 +308,106 @@ where
     }
 }
 
-// 内部事件回调实现
+// 内部事件回调实现，克隆出的回调共用同一个广播通道
+#[derive(Clone)]
 pub struct EventCallback {
-    pub sender: tokio::sync::broadcast::Sender<TaskEvent>,
+    pub sender: broadcast::Sender<TaskEvent>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
 pub enum TaskEvent {
     StatusChanged { task_id: String, status: TaskStatus },
     Completed { task_id: String, result: TaskResult },
     Failed { task_id: String, error: String },
+    /// 处理进度百分比，分块转写时为整个任务的进度
+    Progress { task_id: String, percent: i32 },
+    /// 刚解码出的分段，尚未经过过滤
+    Segment { task_id: String, segment: TranscribeSegment },
+}
+
+impl TaskEvent {
+    pub fn task_id(&self) -> &str {
+        match self {
+            TaskEvent::StatusChanged { task_id, .. }
+            | TaskEvent::Completed { task_id, .. }
+            | TaskEvent::Failed { task_id, .. }
+            | TaskEvent::Progress { task_id, .. }
+            | TaskEvent::Segment { task_id, .. } => task_id,
+        }
+    }
+
+    /// 任务的最后一个事件：完成、失败，或进入取消、超时等终止状态
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            TaskEvent::Completed { .. } | TaskEvent::Failed { .. } => true,
+            TaskEvent::StatusChanged { status, .. } => status.is_terminal(),
+            TaskEvent::Progress { .. } | TaskEvent::Segment { .. } => false,
+        }
+    }
+}
+
+/// 订阅者接收过慢，广播通道中有 `skipped` 个事件未被接收
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Lagged {
+    pub skipped: u64,
+}
+
+/// 任务事件的订阅，可以只接收某一个任务的事件
+pub struct TaskEventReceiver {
+    receiver: broadcast::Receiver<TaskEvent>,
+    task_id: Option<String>,
+}
+
+impl TaskEventReceiver {
+    pub fn new(receiver: broadcast::Receiver<TaskEvent>, task_id: String) -> Self {
+        Self { receiver, task_id: Some(task_id) }
+    }
+
+    /// 接收所有任务的事件
+    pub fn all(receiver: broadcast::Receiver<TaskEvent>) -> Self {
+        Self { receiver, task_id: None }
+    }
+
+    /// 订阅的任务，接收所有任务时为 None
+    pub fn task_id(&self) -> Option<&str> {
+        self.task_id.as_deref()
+    }
+
+    /// 等待下一个事件，广播关闭时返回 None。
+    /// 接收过慢时错过的事件会被跳过
+    pub async fn recv(&mut self) -> Option<TaskEvent> {
+        loop {
+            match self.recv_or_lagged().await? {
+                Ok(event) => return Some(event),
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// 等待下一个事件，广播关闭时返回 None。
+    /// 接收过慢时返回 `Lagged`，错过的事件可能包括任务的结果，调用方需要重新获取任务状态
+    pub async fn recv_or_lagged(&mut self) -> Option<Result<TaskEvent, Lagged>> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) if self.task_id.as_deref().map_or(true, |id| event.task_id() == id) => return Some(Ok(event)),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "Event subscriber of {} lagged, skipped {} events",
+                        self.task_id.as_deref().unwrap_or("all tasks"), skipped
+                    );
+                    return Some(Err(Lagged { skipped }));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
 }
 
 impl EventCallback {
-    pub fn new(capacity: usize) -> (Self, tokio::sync::broadcast::Receiver<TaskEvent>) {
-        let (sender, receiver) = tokio::sync::broadcast::channel(capacity);
+    pub fn new(capacity: usize) -> (Self, broadcast::Receiver<TaskEvent>) {
+        let (sender, receiver) = broadcast::channel(capacity);
         (Self { sender }, receiver)
     }
 
@@ -191,4 +445,179 @@ impl TaskCallback for EventCallback {
     fn box_clone(&self) -> Box<dyn TaskCallback> {
         Box::new(self.clone())
     }
-} 
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use axum::{body::Bytes, extract::State, http::HeaderMap, routing::post, Router};
+    use crate::schedule::test_support;
+    use crate::schedule::types::{CallbackType, TaskConfig};
+
+    #[test]
+    fn test_sign_payload() {
+        // HMAC-SHA256 of `<timestamp>.<body>`, what a receiver computes to verify the callback
+        assert_eq!(
+            sign_payload("Jefe", 1700000000, b"what do ya want for nothing?"),
+            "sha256=1cdd0650c8be1cb0974b1788d458b1e781206cfef59b85faafc582d2e182c57e"
+        );
+
+        // keys longer than a block are hashed first
+        assert_eq!(
+            sign_payload(&"k".repeat(131), 1700000000, br#"{"task_id":"task-1"}"#),
+            "sha256=1faf6ad9808452e05e56ea554c92d210f10da8f191c8d6ccb7189d7e7a636d8c"
+        );
+    }
+
+    fn test_task(url: &str) -> Task {
+        Task {
+            id: "task-callback".to_string(),
+            status: TaskStatus::Failed("decode failed".to_string()),
+            error: Some("decode failed".to_string()),
+            ..test_support::task(TaskConfig {
+                input_path: "callback.wav".into(),
+                callback_type: CallbackType::Http { url: url.to_string(), secret: None },
+                ..test_support::task_config()
+            })
+        }
+    }
+
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    type Received = Arc<Mutex<Option<(HeaderMap, Bytes)>>>;
+
+    #[tokio::test]
+    async fn test_signed_callback() -> Result<()> {
+        let received = Received::default();
+        let router = Router::new()
+            .route("/callback", post(|State(received): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                *received.lock().unwrap() = Some((headers, body));
+            }))
+            .with_state(received.clone());
+        let url = format!("{}/callback", serve(router).await);
+        let task = test_task(&url);
+
+        let callback = HttpCallback::new(url).with_secret(Some("s3cret".to_string()));
+        callback.on_error(&task, "decode failed").await?;
+
+        let (headers, body) = received.lock().unwrap().take().expect("callback received");
+        let timestamp: i64 = headers[TIMESTAMP_HEADER].to_str()?.parse()?;
+        assert!((Utc::now().timestamp() - timestamp).abs() < 60);
+        let signature = headers[SIGNATURE_HEADER].to_str()?;
+        assert_eq!(signature, sign_payload("s3cret", timestamp, &body));
+        assert_ne!(signature, sign_payload("wrong", timestamp, &body));
+        assert_ne!(signature, sign_payload("s3cret", timestamp + 1, &body));
+
+        let payload: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(payload["task_id"], task.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_error_is_retried() -> Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use axum::http::StatusCode;
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let base = serve(Router::new().route("/callback", post(move || async move {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::OK
+            }
+        }))).await;
+        let url = format!("{}/callback", base);
+        let task = test_task(&url);
+
+        // without retries the 500 is a failed delivery
+        let err = HttpCallback::new(url.clone()).on_error(&task, "boom").await.unwrap_err();
+        assert!(err.downcast_ref::<UndeliveredCallback>().unwrap().error.contains("500"));
+
+        hits.store(0, Ordering::SeqCst);
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            jitter: false,
+        };
+        HttpCallback::new(url).with_retry(retry).on_error(&task, "boom").await?;
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_payload_is_sent_compact() -> Result<()> {
+        use crate::schedule::types::TranscribeResult;
+
+        let received = Received::default();
+        let router = Router::new()
+            .route("/callback", post(|State(received): State<Received>, headers: HeaderMap, body: Bytes| async move {
+                *received.lock().unwrap() = Some((headers, body));
+            }))
+            .with_state(received.clone());
+        let url = format!("{}/callback", serve(router).await);
+        let task = test_task(&url);
+        let result = |segments: usize| TaskResult::Transcribe(TranscribeResult {
+            text: "hello ".repeat(segments),
+            segments: (0..segments).map(|i| TranscribeSegment {
+                text: "hello".to_string(),
+                speaker_id: None,
+                start_time: i as f64 * 100.0,
+                end_time: (i + 1) as f64 * 100.0,
+                words: Vec::new(),
+                confidence: None,
+            }).collect(),
+            detected_language: "en".to_string(),
+            language_probability: 1.0,
+            language: "en".to_string(),
+            ..Default::default()
+        });
+        let callback = HttpCallback::new(url)
+            .with_max_payload_size(Some(4096), Some("https://asr.example.com/".to_string()));
+
+        // a small result is still sent inline
+        callback.on_complete(&task, &result(2)).await?;
+        let (_, body) = received.lock().unwrap().take().expect("callback received");
+        let payload: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(payload["data"]["result"]["segments"].as_array().unwrap().len(), 2);
+
+        callback.on_complete(&task, &result(5000)).await?;
+        let (_, body) = received.lock().unwrap().take().expect("callback received");
+        assert!(body.len() <= 4096);
+        let payload: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(payload["task_id"], task.id);
+        assert_eq!(payload["status"], "Completed");
+        assert_eq!(payload["data"]["truncated"], true);
+        assert!(payload["data"]["payload_size"].as_u64().unwrap() > 4096);
+        assert_eq!(
+            payload["data"]["result_url"],
+            "https://asr.example.com/schedule/tasks/task-callback/result"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hung_receiver_times_out() {
+        let base = serve(Router::new().route("/callback", post(|| async {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        }))).await;
+        let url = format!("{}/callback", base);
+        let task = test_task(&url);
+
+        let started = std::time::Instant::now();
+        let err = HttpCallback::new(url)
+            .with_timeouts(Duration::from_secs(1), Duration::from_millis(100))
+            .on_error(&task, "boom")
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<UndeliveredCallback>().is_some());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -6,6 +6,8 @@ pub mod processors;
 pub mod scheduler;
 pub mod callback;
 // mod tests;
+#[cfg(test)]
+pub(crate) mod test_support;
 
 // 重导出主要类型
 pub use types::{
@@ -19,6 +21,7 @@ pub use crate::storage::task::TaskStorage;
 // 重导出处理器接口
 pub use processors::TaskProcessor;
 pub use processors::transcribe::TranscribeProcessor;
+pub use processors::noise_reduction::NoiseReductionProcessor;
 
 // 重导出调度器接口
 pub use scheduler::{TaskManager, TaskScheduler};
@@ -1,10 +1,20 @@
 pub mod transcribe;
+pub mod noise_reduction;
+pub mod post_process;
+pub mod voiceprint;
 
 use async_trait::async_trait;
 use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
 use crate::schedule::types::{Task, TaskResult, TaskType, TaskParams};
+use crate::utils::paths::file_within;
+use crate::AUDIO_PATH;
 
 pub use transcribe::TranscribeProcessor;
+pub use noise_reduction::NoiseReductionProcessor;
+pub use voiceprint::VoiceprintProcessor;
+pub use post_process::{ResultPostProcessor, DirtyWordPostProcessor, WhitespaceNormalizer};
 
 #[async_trait]
 pub trait TaskProcessor: Send + Sync {
@@ -13,4 +23,30 @@ pub trait TaskProcessor: Send + Sync {
     fn validate_params(&self, params: &TaskParams) -> Result<()>;
     async fn cancel(&self, task: &Task) -> Result<()>;
     async fn cleanup(&self, task: &Task) -> Result<()>;
-} 
\ No newline at end of file
+    // files the processor wrote for a task, removed together with the task
+    fn output_files(&self, _task: &Task) -> Vec<PathBuf> {
+        Vec::new()
+    }
+    // remove the records the processor stored for a purged task, returns how many there were
+    async fn purge(&self, _task: &Task) -> Result<u64> {
+        Ok(0)
+    }
+} 
+
+// remove the downloaded input of a finished task. only files inside the audio directory are
+// removed, an input path pointing anywhere else is left in place
+pub(crate) fn remove_input_file(task: &Task) {
+    let input_path = &task.config.input_path;
+    match file_within(input_path, Path::new(AUDIO_PATH.as_str())) {
+        Some(path) => {
+            info!("Cleaning up temporary file: {}", path.display());
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("Failed to remove temporary file: {}", e);
+            }
+        }
+        None if input_path.exists() => {
+            warn!("Not removing {} of task {}, it is outside the audio directory", input_path.display(), task.id);
+        }
+        None => {}
+    }
+}
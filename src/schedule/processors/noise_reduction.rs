@@ -0,0 +1,168 @@
+use async_trait::async_trait;
+use anyhow::Result;
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::schedule::types::{
+    NoiseReductionParams, NoiseReductionResult, Task, TaskParams, TaskResult, TaskType
+};
+use super::{remove_input_file, TaskProcessor};
+
+#[derive(Clone)]
+pub struct NoiseReductionProcessor {
+    // where the denoised files are written, one `<task id>.wav` per task
+    output_dir: PathBuf,
+}
+
+impl NoiseReductionProcessor {
+    pub fn new(output_dir: PathBuf) -> Self {
+        Self { output_dir }
+    }
+
+    fn output_path(&self, task: &Task) -> PathBuf {
+        self.output_dir.join(format!("{}.wav", task.id))
+    }
+
+    fn validate(params: &NoiseReductionParams) -> Result<()> {
+        if !(0.0..=1.0).contains(&params.strength) {
+            return Err(anyhow::anyhow!("Invalid strength: {}, expected 0..=1", params.strength));
+        }
+        if !params.frame_size.is_power_of_two() || params.frame_size < 64 {
+            return Err(anyhow::anyhow!("Invalid frame size: {}, expected a power of two of at least 64", params.frame_size));
+        }
+        // frames must advance by at least one sample
+        let step_size = (params.frame_size as f32 * (1.0 - params.overlap)) as usize;
+        if !(0.0..1.0).contains(&params.overlap) || step_size == 0 {
+            return Err(anyhow::anyhow!("Invalid overlap: {}, expected 0..1", params.overlap));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TaskProcessor for NoiseReductionProcessor {
+    fn task_type(&self) -> TaskType {
+        TaskType::NoiseReduction
+    }
+
+    async fn process(&self, task: &Task) -> Result<TaskResult> {
+        let params = match &task.config.params {
+            TaskParams::NoiseReduction(p) => p.clone(),
+            _ => return Err(anyhow::anyhow!("Invalid task params")),
+        };
+
+        info!("Processing noise reduction task {} with params: {:?}", task.id, params);
+
+        std::fs::create_dir_all(&self.output_dir)?;
+        let input_path = task.config.input_path.clone();
+        let output_path = self.output_path(task);
+
+        // the fft work is cpu bound, keep it off the async workers
+        let output = output_path.clone();
+        tokio::task::spawn_blocking(move || {
+            crate::audio::denoise_file(&input_path, &output, params.frame_size, params.overlap, params.strength)
+        }).await??;
+
+        info!("Denoised task {} to {}", task.id, output_path.display());
+        Ok(TaskResult::NoiseReduction(NoiseReductionResult { output_path }))
+    }
+
+    fn validate_params(&self, params: &TaskParams) -> Result<()> {
+        match params {
+            TaskParams::NoiseReduction(p) => Self::validate(p),
+            _ => Err(anyhow::anyhow!("Invalid task params")),
+        }
+    }
+
+    async fn cancel(&self, task: &Task) -> Result<()> {
+        // the blocking denoise can't be interrupted, drop whatever it managed to write
+        info!("Cancelled noise reduction of task {}", task.id);
+        let output_path = self.output_path(task);
+        if output_path.exists() {
+            std::fs::remove_file(&output_path)?;
+        }
+        Ok(())
+    }
+
+    fn output_files(&self, task: &Task) -> Vec<PathBuf> {
+        vec![self.output_path(task)]
+    }
+
+    async fn cleanup(&self, task: &Task) -> Result<()> {
+        // clean up temporary file
+        remove_input_file(task);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::test_support;
+    use crate::schedule::types::{TaskConfig, TaskStatus};
+
+    fn params(strength: f32, frame_size: usize, overlap: f32) -> NoiseReductionParams {
+        NoiseReductionParams { strength, frame_size, overlap }
+    }
+
+    fn noise_reduction_task(input_path: PathBuf, params: NoiseReductionParams) -> Task {
+        Task {
+            status: TaskStatus::Processing,
+            ..test_support::task(TaskConfig {
+                task_type: TaskType::NoiseReduction,
+                input_path,
+                params: TaskParams::NoiseReduction(params),
+                ..test_support::task_config()
+            })
+        }
+    }
+
+    #[test]
+    fn test_validate_params() {
+        let processor = NoiseReductionProcessor::new(PathBuf::from("/tmp"));
+        let valid = |p| processor.validate_params(&TaskParams::NoiseReduction(p)).is_ok();
+
+        assert!(valid(params(0.0, 2048, 0.75)));
+        assert!(valid(params(1.0, 1024, 0.5)));
+        assert!(!valid(params(1.5, 2048, 0.75)));
+        assert!(!valid(params(-0.1, 2048, 0.75)));
+        assert!(!valid(params(f32::NAN, 2048, 0.75)));
+        assert!(!valid(params(0.5, 1000, 0.75)));
+        assert!(!valid(params(0.5, 2048, 1.0)));
+        assert!(!valid(params(0.5, 2048, -0.5)));
+    }
+
+    #[tokio::test]
+    async fn test_denoise_writes_wav() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let input_path = dir.path().join("noisy.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&input_path, spec)?;
+        for i in 0..16000 {
+            let tone = (i as f32 * 0.05).sin() * 8000.0;
+            let noise = ((i * 7919) % 200) as f32 - 100.0;
+            for _ in 0..2 {
+                writer.write_sample((tone + noise) as i16)?;
+            }
+        }
+        writer.finalize()?;
+
+        let processor = NoiseReductionProcessor::new(dir.path().join("denoised"));
+        let task = noise_reduction_task(input_path, params(0.5, 1024, 0.5));
+        let result = processor.process(&task).await?;
+        let output_path = &result.as_noise_reduction().unwrap().output_path;
+        assert_eq!(output_path, &dir.path().join("denoised").join(format!("{}.wav", task.id)));
+
+        // one second of mono audio at the original rate
+        let reader = hound::WavReader::open(output_path)?;
+        assert_eq!(reader.spec().channels, 1);
+        assert_eq!(reader.spec().sample_rate, 16000);
+        assert_eq!(reader.len(), 16000);
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::Result;
+use crate::asr::filter::DirtyWordFilter;
+use crate::schedule::types::{TranscribeParams, TranscribeResult};
+
+// a transform of a finished transcript, e.g. redaction or custom replacements.
+// registered processors run in registration order, see `TranscribeProcessor::with_post_processor`
+pub trait ResultPostProcessor: Send + Sync {
+    // used in logs and errors
+    fn name(&self) -> &str;
+    // an error fails the task
+    fn process(&self, params: &TranscribeParams, result: &mut TranscribeResult) -> Result<()>;
+}
+
+// masks dirty words of the result language when the task asked for `filter_dirty_words`.
+// masking is idempotent, so text an engine already filtered is left as is
+pub struct DirtyWordPostProcessor {
+    filter: DirtyWordFilter,
+}
+
+impl DirtyWordPostProcessor {
+    pub fn new(filter: DirtyWordFilter) -> Self {
+        Self { filter }
+    }
+}
+
+impl ResultPostProcessor for DirtyWordPostProcessor {
+    fn name(&self) -> &str {
+        "dirty_words"
+    }
+
+    fn process(&self, params: &TranscribeParams, result: &mut TranscribeResult) -> Result<()> {
+        if !params.filter_dirty_words {
+            return Ok(());
+        }
+        for segment in result.segments.iter_mut() {
+            segment.text = self.filter.filter(&result.language, &segment.text);
+        }
+        result.text = self.filter.filter(&result.language, &result.text);
+        Ok(())
+    }
+}
+
+// collapses runs of whitespace into one space and trims the segment texts and the full text
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WhitespaceNormalizer;
+
+impl WhitespaceNormalizer {
+    fn normalize(text: &str) -> String {
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl ResultPostProcessor for WhitespaceNormalizer {
+    fn name(&self) -> &str {
+        "whitespace"
+    }
+
+    fn process(&self, _params: &TranscribeParams, result: &mut TranscribeResult) -> Result<()> {
+        for segment in result.segments.iter_mut() {
+            segment.text = Self::normalize(&segment.text);
+        }
+        result.text = Self::normalize(&result.text);
+        Ok(())
+    }
+}
@@ -1,49 +1,330 @@
 use async_trait::async_trait;
 use anyhow::Result;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{broadcast, mpsc, Semaphore};
 use tracing::{info, warn};
 
-use crate::asr::{whisper::WhisperAsr, AsrParams, AsrEngine};
+use crate::asr::{
+    AbortOnDrop, AsrParams, AsrEngine, ProgressSender, SamplingMode, TranscribeProgress,
+    TranscribeResult as AsrResult
+};
+use crate::schedule::callback::TaskEvent;
 use crate::schedule::types::{
-    Task, TaskType, TaskResult, TaskParams, TranscribeParams,
-    TranscribeResult, TranscribeSegment
+    PhaseTimings, Task, TaskStatus, TaskType, TaskResult, TaskParams, TranscribeParams,
+    TranscribeResult, TranscribeSegment, TaskError
 };
-use super::TaskProcessor;
+use crate::audio::{Gain, PreprocessConfig};
+use crate::storage::task::TaskStorage;
+use super::{remove_input_file, ResultPostProcessor, TaskProcessor};
+
+// sample rate of the audio fed into the asr engine
+const SAMPLE_RATE: usize = 16000;
 
 #[derive(Clone)]
 pub struct TranscribeProcessor {
-    asr: Arc<WhisperAsr>,
+    asr: Arc<dyn AsrEngine>,
+    // bounds how many transcriptions run on the engine at once, shared by all clones
+    permits: Arc<Semaphore>,
+    // where chunk checkpoints are persisted, chunked tasks restart from scratch without it
+    checkpoints: Option<Arc<dyn TaskStorage>>,
+    // where the tasks named by `TranscribeParams::input_task` are looked up
+    input_tasks: Option<Arc<dyn TaskStorage>>,
+    // language used when auto detection is less confident than `min_language_probability`
+    fallback_language: Option<String>,
+    min_language_probability: f32,
+    // where live progress and segments are published, see `TaskManager::subscribe_task`
+    events: Option<broadcast::Sender<TaskEvent>>,
+    // transforms applied to every transcript in order, shared by all clones
+    post_processors: Vec<Arc<dyn ResultPostProcessor>>,
 }
 
 impl TranscribeProcessor {
-    pub fn new(asr: Arc<WhisperAsr>) -> Self {
-        Self { asr }
+    pub fn new(asr: Arc<dyn AsrEngine>, max_concurrent_transcriptions: usize) -> Self {
+        Self {
+            asr,
+            permits: Arc::new(Semaphore::new(max_concurrent_transcriptions.max(1))),
+            checkpoints: None,
+            input_tasks: None,
+            fallback_language: None,
+            min_language_probability: 0.0,
+            events: None,
+            post_processors: Vec::new(),
+        }
     }
 
-    async fn process_audio(&self, task: &Task, params: &TranscribeParams) -> Result<TranscribeResult> {
-        info!("Processing audio file: {}", task.config.input_path.display());
+    // append a transform run on every transcript after the ones registered before it
+    pub fn with_post_processor(mut self, post_processor: Arc<dyn ResultPostProcessor>) -> Self {
+        self.post_processors.push(post_processor);
+        self
+    }
+
+    pub fn with_events(mut self, events: broadcast::Sender<TaskEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
 
-        // set asr params
+    pub fn with_language_fallback(mut self, fallback_language: Option<String>, min_language_probability: f32) -> Self {
+        self.fallback_language = fallback_language;
+        self.min_language_probability = min_language_probability;
+        self
+    }
+
+    pub fn with_checkpoints(mut self, storage: Arc<dyn TaskStorage>) -> Self {
+        self.checkpoints = Some(storage);
+        self
+    }
+
+    pub fn with_input_tasks(mut self, storage: Arc<dyn TaskStorage>) -> Self {
+        self.input_tasks = Some(storage);
+        self
+    }
+
+    // the denoised output of a completed noise reduction task submitted with the same key as
+    // `task`. the chained task is only claimed once its input task finished, so an input that
+    // is missing, belongs to another key or didn't complete fails it for good
+    async fn denoised_input(&self, task: &Task, input_id: &str) -> Result<PathBuf> {
+        let storage = self.input_tasks.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Input tasks are not available"))?;
+        let input: Task = match storage.get(input_id).await? {
+            Some(model) => model.into(),
+            None => return Err(TaskError::input(anyhow::anyhow!("Input task not found: {}", input_id))),
+        };
+        if input.config.api_key != task.config.api_key {
+            return Err(TaskError::input(anyhow::anyhow!("Input task not found: {}", input_id)));
+        }
+        match (&input.status, &input.result) {
+            (TaskStatus::Completed, Some(TaskResult::NoiseReduction(result))) => Ok(result.output_path.clone()),
+            (TaskStatus::Completed, _) => Err(TaskError::input(anyhow::anyhow!("Input task {} did not produce denoised audio", input_id))),
+            (status, _) => Err(TaskError::input(anyhow::anyhow!("Input task {} is not completed: {:?}", input_id, status))),
+        }
+    }
+
+    // forward the engine progress of one chunk to the event broadcast as events of the task.
+    // the percentage is scaled to the whole task and segment times are shifted by the chunk offset
+    fn relay_progress(&self, task_id: &str, chunk: u32, chunks: u32, offset: f64) -> Option<ProgressSender> {
+        let events = self.events.clone()?;
+        let task_id = task_id.to_string();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(progress) = receiver.recv().await {
+                let event = match progress {
+                    TranscribeProgress::Progress(percent) => TaskEvent::Progress {
+                        task_id: task_id.clone(),
+                        percent: (chunk as i32 * 100 + percent) / chunks.max(1) as i32,
+                    },
+                    TranscribeProgress::Segment { text, start, end } => TaskEvent::Segment {
+                        task_id: task_id.clone(),
+                        segment: TranscribeSegment {
+                            text,
+                            speaker_id: None,
+                            start_time: start + offset,
+                            end_time: end + offset,
+                            words: Vec::new(),
+                            confidence: None,
+                        },
+                    },
+                };
+                // it is fine that nobody is listening
+                let _ = events.send(event);
+            }
+        });
+        Some(sender)
+    }
+
+    // run one transcription on the engine, waiting for a free permit first. the engine call
+    // runs on its own task that owns the permit: dropping this future (cancel, timeout) only
+    // raises the abort flag, and the permit is given back once the engine has really stopped
+    async fn transcribe(&self, audio: Vec<f32>, mut params: AsrParams) -> Result<AsrResult> {
+        let permit = self.permits.clone().acquire_owned().await?;
+        let abort = Arc::new(AtomicBool::new(false));
+        params.set_abort(Some(abort.clone()));
+        let _abort = AbortOnDrop(abort);
+        let asr = self.asr.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            asr.transcribe(audio, params).await
+        })
+        .await?
+    }
+
+    // transcribe audio in fixed size chunks, persisting each finished chunk so that
+    // a restarted task resumes from the last completed chunk instead of chunk 0
+    async fn transcribe_chunked(&self, task_id: &str, audio: Vec<f32>, params: AsrParams, chunk_seconds: u32) -> Result<AsrResult> {
+        let mut completed: HashMap<u32, AsrResult> = HashMap::new();
+        if let Some(storage) = &self.checkpoints {
+            for (index, data) in storage.get_checkpoints(task_id).await? {
+                completed.insert(index, serde_json::from_str(&data)?);
+            }
+            if !completed.is_empty() {
+                info!("Resuming task {} from {} checkpointed chunks", task_id, completed.len());
+            }
+        }
+
+        let mut merged = AsrResult {
+            segments: Vec::new(),
+            full_text: String::new(),
+            detected_language: String::new(),
+            language: String::new(),
+            language_probability: 0.0,
+        };
+
+        let chunk_len = chunk_seconds as usize * SAMPLE_RATE;
+        let chunks = audio.len().div_ceil(chunk_len) as u32;
+        for (index, chunk) in audio.chunks(chunk_len).enumerate() {
+            let index = index as u32;
+            // whisper timestamps are in 10ms units, shift them by the chunk offset
+            let offset = (index * chunk_seconds) as f64 * 100.0;
+            let result = match completed.remove(&index) {
+                Some(result) => result,
+                None => {
+                    let mut params = params.clone();
+                    params.set_progress(self.relay_progress(task_id, index, chunks, offset));
+                    let result = self.transcribe(chunk.to_vec(), params).await?;
+                    if let Some(storage) = &self.checkpoints {
+                        storage.save_checkpoint(task_id, index, &serde_json::to_string(&result)?).await?;
+                    }
+                    result
+                }
+            };
+
+            if index == 0 {
+                merged.detected_language = result.detected_language;
+                merged.language = result.language;
+                merged.language_probability = result.language_probability;
+            }
+
+            for mut segment in result.segments {
+                segment.start += offset;
+                segment.end += offset;
+                for word in segment.words.iter_mut() {
+                    word.start += offset;
+                    word.end += offset;
+                }
+                merged.segments.push(segment);
+            }
+            merged.full_text.push_str(&result.full_text);
+        }
+
+        if let Some(storage) = &self.checkpoints {
+            storage.delete_checkpoints(task_id).await?;
+        }
+
+        Ok(merged)
+    }
+
+    // map task params onto the asr engine params
+    fn asr_params(params: &TranscribeParams) -> AsrParams {
         let mut asr_params = AsrParams::new();
         asr_params.set_language(params.language.clone());
         asr_params.set_speaker_diarization(params.speaker_diarization);
         asr_params.set_emotion_recognition(params.emotion_recognition);
         asr_params.set_filter_dirty_words(params.filter_dirty_words);
+        asr_params.set_token_timestamps(params.token_timestamps);
+        asr_params.set_single_segment(params.single_segment);
+        asr_params.set_initial_prompt(params.initial_prompt.clone());
+        asr_params.set_n_threads(params.n_threads);
+        asr_params.set_temperature(params.temperature);
+        asr_params.set_translate(params.translate);
+        asr_params.set_model(params.model.clone());
+        if let Some(threshold) = params.no_speech_threshold {
+            asr_params.set_no_speech_threshold(threshold);
+        }
+        if let Some(beam_size) = params.beam_size {
+            asr_params.set_sampling(SamplingMode::BeamSearch { beam_size, patience: -1.0 });
+        }
+        asr_params
+    }
+
+    // remove the segments below the confidence threshold and rebuild the text from the rest.
+    // segments without a confidence are kept
+    fn drop_low_confidence_segments(result: &mut TranscribeResult, min_confidence: f32) {
+        let before = result.segments.len();
+        result.segments.retain(|s| s.confidence.map_or(true, |confidence| confidence >= min_confidence));
+        let dropped = before - result.segments.len();
+        if dropped > 0 {
+            info!("Dropped {} of {} segments below confidence {}", dropped, before, min_confidence);
+            result.text = result.segments.iter().map(|s| s.text.as_str()).collect();
+        }
+        result.dropped_segments = Some(dropped);
+    }
+
+    async fn process_audio(&self, task: &Task, params: &TranscribeParams) -> Result<TranscribeResult> {
+        let started = Instant::now();
+        // a chained task reads the denoised output of its input task, which is not denoised again
+        let (input_path, noise_reduction) = match &params.input_task {
+            Some(input_task) => (self.denoised_input(task, input_task).await?, false),
+            None => (task.config.input_path.clone(), true),
+        };
+        info!("Processing audio file: {}", input_path.display());
+
+        let mut asr_params = Self::asr_params(params);
+        asr_params.set_language_fallback(self.fallback_language.clone(), self.min_language_probability);
+
+        // record the original format before the input is converted, a failed probe
+        // only loses the metadata
+        let source = match crate::audio::probe(&input_path).await {
+            Ok(info) => Some(info),
+            Err(e) => {
+                warn!("Failed to probe {}: {}", input_path.display(), e);
+                None
+            }
+        };
 
         // process audio file
-        let audio = crate::audio::parse_audio_file(&task.config.input_path, true, 0.75)?;
-        let asr_result = self.asr.transcribe(audio, asr_params).await?;
+        let preprocess = PreprocessConfig::new(noise_reduction, 0.75).with_gain(params.gain.clone());
+        // audio that can't be decoded fails the same way on every attempt
+        let audio = crate::audio::parse_audio_file_with_config(&input_path, &preprocess).map_err(TaskError::input)?;
+        let preprocessed = Instant::now();
+        let asr_result = match params.chunk_seconds {
+            Some(chunk_seconds) => self.transcribe_chunked(&task.id, audio, asr_params, chunk_seconds).await?,
+            None => {
+                let mut asr_params = asr_params;
+                asr_params.set_progress(self.relay_progress(&task.id, 0, 1, 0.0));
+                // chunks are shorter than the source, only the whole clip can be compared
+                asr_params.set_expected_duration(source.as_ref().map(|source| source.duration_secs));
+                self.transcribe(audio, asr_params).await?
+            }
+        };
+        let inferred = Instant::now();
 
         // convert result format
-        Ok(TranscribeResult {
+        let mut result = TranscribeResult {
             text: asr_result.full_text,
             segments: asr_result.segments.into_iter().map(|s| TranscribeSegment {
                 text: s.text,
                 speaker_id: Some(s.speaker_id),
                 start_time: s.start,
                 end_time: s.end,
+                words: s.words,
+                confidence: Some(s.confidence),
             }).collect(),
-        })
+            detected_language: asr_result.detected_language,
+            language: asr_result.language,
+            language_probability: asr_result.language_probability,
+            source,
+            timings_ms: None,
+            dropped_segments: None,
+        };
+        if let Some(min_confidence) = params.min_segment_confidence {
+            Self::drop_low_confidence_segments(&mut result, min_confidence);
+        }
+        for post_processor in &self.post_processors {
+            post_processor.process(params, &mut result)
+                .map_err(|e| anyhow::anyhow!("Post processor {} failed: {}", post_processor.name(), e))?;
+        }
+
+        // the download happened on submission, its time came along in the params
+        result.timings_ms = params.timings.clone().map(|timings| PhaseTimings {
+            preprocess: (preprocessed - started).as_millis() as u64,
+            inference: (inferred - preprocessed).as_millis() as u64,
+            total: timings.download + started.elapsed().as_millis() as u64,
+            ..timings
+        });
+        Ok(result)
     }
 }
 
@@ -83,6 +364,32 @@ impl TaskProcessor for TranscribeProcessor {
                     }
                 }
 
+                // validate the params passed on to the asr engine
+                Self::asr_params(p).validate()?;
+
+                // validate gain parameter
+                match p.gain {
+                    Some(Gain::FixedDb(db)) if !(-20.0..=40.0).contains(&db) => {
+                        return Err(anyhow::anyhow!("Invalid gain: {} dB", db));
+                    }
+                    Some(Gain::TargetRms(rms)) if !(rms > 0.0 && rms <= 1.0) => {
+                        return Err(anyhow::anyhow!("Invalid target rms: {}", rms));
+                    }
+                    _ => {}
+                }
+
+                // validate segment confidence threshold
+                if let Some(confidence) = p.min_segment_confidence {
+                    if !(0.0..=1.0).contains(&confidence) {
+                        return Err(anyhow::anyhow!("Invalid minimum segment confidence: {}, must be between 0 and 1", confidence));
+                    }
+                }
+
+                // validate chunk size parameter
+                if p.chunk_seconds == Some(0) {
+                    return Err(anyhow::anyhow!("Invalid chunk size: 0"));
+                }
+
                 // validate input file - get from TaskConfig
                 if let TaskParams::Transcribe(_) = params {
                     // note: validation should be done when creating task, because we cannot access TaskConfig here
@@ -97,19 +404,18 @@ impl TaskProcessor for TranscribeProcessor {
     }
 
     async fn cancel(&self, task: &Task) -> Result<()> {
-        // ASR does not support canceling ongoing tasks
-        warn!("Cancel operation is not supported for task {}", task.id);
+        // the worker already dropped the transcription, which aborted an engine call in
+        // flight. a cancelled task never resumes, so its checkpoints can go
+        info!("Cancelled transcription of task {}", task.id);
+        if let Some(storage) = &self.checkpoints {
+            storage.delete_checkpoints(&task.id).await?;
+        }
         Ok(())
     }
 
     async fn cleanup(&self, task: &Task) -> Result<()> {
         // clean up temporary file
-        if task.config.input_path.exists() {
-            info!("Cleaning up temporary file: {}", task.config.input_path.display());
-            if let Err(e) = std::fs::remove_file(&task.config.input_path) {
-                warn!("Failed to remove temporary file: {}", e);
-            }
-        }
+        remove_input_file(task);
         Ok(())
     }
 }
@@ -119,10 +425,12 @@ mod tests {
     use super::*;
     use crate::schedule::types::TaskConfig;
     use std::path::PathBuf;
-    use crate::schedule::types::{CallbackType, TaskParams, TaskPriority, TaskStatus};
+    use crate::schedule::types::{CallbackType, FailureKind, TaskParams, TaskPriority, TaskStatus};
     use chrono::Utc;
     use crate::schedule::types::TranscribeParams;
     use crate::asr::whisper::WhisperAsr;
+    use crate::asr::MAX_INITIAL_PROMPT_LEN;
+    use crate::schedule::test_support;
 
     #[tokio::test]
     async fn test_transcribe_processor() -> Result<()> {
@@ -130,7 +438,7 @@ mod tests {
 
         // create processor
         let asr = Arc::new(WhisperAsr::new("./models/ggml-large-v3.bin".to_string())?);
-        let processor = TranscribeProcessor::new(asr);
+        let processor = TranscribeProcessor::new(asr, 1);
 
         // create test task
         let task = Task {
@@ -139,17 +447,20 @@ mod tests {
             config: TaskConfig {
                 task_type: TaskType::Transcribe,
                 input_path: test_file.clone(),
-                callback_type: CallbackType::Http { url: "http://localhost:8000/callback".to_string() },
+                callback_type: CallbackType::Http { url: "http://localhost:8000/callback".to_string(), secret: None },
                 params: TaskParams::Transcribe(TranscribeParams {
                     language: Some("zh".to_string()),
                     speaker_diarization: true,
-                    emotion_recognition: false,
-                    filter_dirty_words: false,
+                    ..Default::default()
                 }),
                 priority: TaskPriority::Normal,
                 retry_count: 0,
                 max_retries: 3,
                 timeout: Some(300),
+                notify_on_retry: false,
+                label: None,
+                api_key: None,
+                request_id: None,
             },
             created_at: Utc::now(),
             updated_at: Utc::now(),
@@ -166,18 +477,580 @@ mod tests {
         let result = processor.process(&task).await?;
 
         // validate result
-        match result {
-            TaskResult::Transcribe(result) => {
-                assert!(!result.text.is_empty());
-                assert!(!result.segments.is_empty());
+        let result = result.as_transcribe().expect("transcribe result");
+        assert!(!result.text.is_empty());
+        assert!(!result.segments.is_empty());
+
+        // the test file is outside the audio directory, cleanup leaves it in place
+        processor.cleanup(&task).await?;
+        assert!(test_file.exists());
+
+        Ok(())
+    }
+
+    // stub engine that tags each chunk by its first sample and can crash on a given call
+    struct StubEngine {
+        seen: std::sync::Mutex<Vec<String>>,
+        fail_on_call: Option<usize>,
+    }
+
+    impl StubEngine {
+        fn new(fail_on_call: Option<usize>) -> Self {
+            Self { seen: std::sync::Mutex::new(Vec::new()), fail_on_call }
+        }
+    }
+
+    #[async_trait]
+    impl AsrEngine for StubEngine {
+        async fn transcribe(&self, audio: Vec<f32>, _params: AsrParams) -> Result<AsrResult> {
+            let mut seen = self.seen.lock().unwrap();
+            if self.fail_on_call == Some(seen.len()) {
+                return Err(anyhow::anyhow!("simulated crash"));
             }
-            _ => panic!("Unexpected result type"),
+            let text = format!("{}", audio[0]);
+            seen.push(text.clone());
+
+            Ok(AsrResult {
+                segments: vec![crate::asr::TranscribeSegment {
+                    text: text.clone(),
+                    speaker_id: 0,
+                    start: 0.0,
+                    end: 100.0,
+                    words: Vec::new(),
+                    confidence: 1.0,
+                }],
+                full_text: text,
+                detected_language: "zh".to_string(),
+                language: "zh".to_string(),
+                language_probability: 1.0,
+            })
         }
+    }
 
-        // clean up test file
-        processor.cleanup(&task).await?;
-        assert!(!test_file.exists());
+    #[tokio::test]
+    async fn test_chunked_transcription_resumes_from_checkpoint() -> Result<()> {
+        let storage: Arc<dyn TaskStorage> = Arc::new(crate::storage::task::sqlite::SqliteTaskStorage::new("sqlite::memory:").await?);
+
+        // four one second chunks, each filled with its own index
+        let audio: Vec<f32> = (0..4).flat_map(|i| vec![i as f32; SAMPLE_RATE]).collect();
+
+        // first run crashes while transcribing chunk 2
+        let engine = Arc::new(StubEngine::new(Some(2)));
+        let processor = TranscribeProcessor::new(engine.clone(), 1).with_checkpoints(storage.clone());
+        assert!(processor.transcribe_chunked("task-resume", audio.clone(), AsrParams::new(), 1).await.is_err());
+        assert_eq!(*engine.seen.lock().unwrap(), vec!["0", "1"]);
+        assert_eq!(storage.get_checkpoints("task-resume").await?.len(), 2);
+
+        // after a restart only the remaining chunks are transcribed
+        let engine = Arc::new(StubEngine::new(None));
+        let processor = TranscribeProcessor::new(engine.clone(), 1).with_checkpoints(storage.clone());
+        let result = processor.transcribe_chunked("task-resume", audio, AsrParams::new(), 1).await?;
+        assert_eq!(*engine.seen.lock().unwrap(), vec!["2", "3"]);
+
+        assert_eq!(result.full_text, "0123");
+        let starts: Vec<f64> = result.segments.iter().map(|s| s.start).collect();
+        assert_eq!(starts, vec![0.0, 100.0, 200.0, 300.0]);
+
+        // checkpoints are dropped once the task completes
+        assert!(storage.get_checkpoints("task-resume").await?.is_empty());
+
+        Ok(())
+    }
 
+    struct SlowEngine {
+        running: std::sync::atomic::AtomicUsize,
+        peak: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AsrEngine for SlowEngine {
+        async fn transcribe(&self, _audio: Vec<f32>, _params: AsrParams) -> Result<AsrResult> {
+            use std::sync::atomic::Ordering;
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+
+            Ok(AsrResult {
+                segments: Vec::new(),
+                full_text: String::new(),
+                detected_language: "zh".to_string(),
+                language: "zh".to_string(),
+                language_probability: 1.0,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_transcriptions() -> Result<()> {
+        let engine = Arc::new(SlowEngine {
+            running: std::sync::atomic::AtomicUsize::new(0),
+            peak: std::sync::atomic::AtomicUsize::new(0),
+        });
+        let processor = TranscribeProcessor::new(engine.clone(), 2);
+
+        let handles: Vec<_> = (0..6).map(|_| {
+            let processor = processor.clone();
+            tokio::spawn(async move { processor.transcribe(vec![0.0; 16], AsrParams::new()).await })
+        }).collect();
+        for handle in handles {
+            handle.await??;
+        }
+
+        assert_eq!(engine.peak.load(std::sync::atomic::Ordering::SeqCst), 2);
         Ok(())
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_validate_initial_prompt_length() {
+        let processor = TranscribeProcessor::new(Arc::new(StubEngine::new(None)), 1);
+        let params = |initial_prompt: Option<String>| TaskParams::Transcribe(TranscribeParams {
+            language: Some("zh".to_string()),
+            initial_prompt,
+            ..Default::default()
+        });
+
+        assert!(processor.validate_params(&params(Some("ACME Cloud, Widgetron".to_string()))).is_ok());
+        assert!(processor.validate_params(&params(Some("a".repeat(MAX_INITIAL_PROMPT_LEN + 1)))).is_err());
+    }
+
+    #[test]
+    fn test_initial_prompt_reaches_the_engine_params() {
+        let params = TranscribeParams {
+            initial_prompt: Some("ACME Cloud, Widgetron".to_string()),
+            ..Default::default()
+        };
+        let asr_params = TranscribeProcessor::asr_params(&params);
+        assert_eq!(asr_params.initial_prompt.as_deref(), Some("ACME Cloud, Widgetron"));
+
+        assert_eq!(TranscribeProcessor::asr_params(&TranscribeParams::default()).initial_prompt, None);
+    }
+
+    fn write_wav(path: &std::path::Path, channels: u16, sample_rate: u32, frames: usize) -> Result<()> {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for i in 0..frames {
+            let sample = ((i as f32 * 0.05).sin() * 8000.0) as i16;
+            for _ in 0..channels {
+                writer.write_sample(sample)?;
+            }
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    fn local_task(input_path: PathBuf, params: &TranscribeParams) -> Task {
+        Task {
+            id: "task-local".to_string(),
+            ..test_support::task(TaskConfig {
+                input_path,
+                params: TaskParams::Transcribe(params.clone()),
+                ..test_support::task_config()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_result_records_source_audio() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let input_path = dir.path().join("source.wav");
+
+        // one and a half seconds of stereo 22.05kHz audio
+        write_wav(&input_path, 2, 22050, 33075)?;
+
+        let processor = TranscribeProcessor::new(Arc::new(StubEngine::new(None)), 1);
+        let params = TranscribeParams::default();
+        let task = local_task(input_path, &params);
+
+        let result = processor.process_audio(&task, &params).await?;
+        let source = result.source.as_ref().expect("source audio info");
+        assert_eq!(source.format, "wav");
+        assert_eq!(source.codec.as_deref(), Some("pcm_s16le"));
+        assert_eq!(source.sample_rate, 22050);
+        assert_eq!(source.channels, 2);
+        assert!((source.duration_secs - 1.5).abs() < 1e-6);
+
+        // the metadata survives a round trip through the stored json
+        let json = serde_json::to_string(&result)?;
+        let restored: TranscribeResult = serde_json::from_str(&json)?;
+        assert_eq!(restored.source.unwrap().sample_rate, 22050);
+        Ok(())
+    }
+
+    // engine detecting welsh with low confidence, deciding the decode language like whisper does
+    struct UnsureEngine;
+
+    #[async_trait]
+    impl AsrEngine for UnsureEngine {
+        async fn transcribe(&self, _audio: Vec<f32>, params: AsrParams) -> Result<AsrResult> {
+            let language = params.resolve_language("cy", 0.2);
+            Ok(AsrResult {
+                segments: Vec::new(),
+                full_text: String::new(),
+                detected_language: "cy".to_string(),
+                language,
+                language_probability: 0.2,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_low_confidence_language_falls_back() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let input_path = dir.path().join("short.wav");
+        write_wav(&input_path, 1, 16000, 16000)?;
+
+        let params = TranscribeParams::default();
+        let task = local_task(input_path, &params);
+
+        let processor = TranscribeProcessor::new(Arc::new(UnsureEngine), 1)
+            .with_language_fallback(Some("en".to_string()), 0.5);
+        let result = processor.process_audio(&task, &params).await?;
+        assert_eq!(result.detected_language, "cy");
+        assert_eq!(result.language, "en");
+        assert_eq!(result.language_probability, 0.2);
+
+        // without a fallback the detection is trusted
+        let processor = TranscribeProcessor::new(Arc::new(UnsureEngine), 1);
+        let result = processor.process_audio(&task, &params).await?;
+        assert_eq!(result.language, "cy");
+        Ok(())
+    }
+
+    // engine keeping the audio and params it was called with
+    #[derive(Default)]
+    struct RecordingEngine {
+        audio: std::sync::Mutex<Vec<Vec<f32>>>,
+        params: std::sync::Mutex<Vec<AsrParams>>,
+    }
+
+    #[async_trait]
+    impl AsrEngine for RecordingEngine {
+        async fn transcribe(&self, audio: Vec<f32>, params: AsrParams) -> Result<AsrResult> {
+            self.audio.lock().unwrap().push(audio);
+            self.params.lock().unwrap().push(params);
+            Ok(AsrResult {
+                segments: Vec::new(),
+                full_text: String::new(),
+                detected_language: "en".to_string(),
+                language: "en".to_string(),
+                language_probability: 1.0,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_single_segment_reaches_the_engine() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let input_path = dir.path().join("short.wav");
+        write_wav(&input_path, 1, 16000, 16000)?;
+        let engine = Arc::new(RecordingEngine::default());
+        let processor = TranscribeProcessor::new(engine.clone(), 1);
+
+        for single_segment in [true, false] {
+            let mut params = TranscribeParams::default();
+            params.single_segment = single_segment;
+            processor.process_audio(&local_task(input_path.clone(), &params), &params).await?;
+        }
+
+        let seen: Vec<bool> = engine.params.lock().unwrap().iter().map(|p| p.single_segment).collect();
+        assert_eq!(seen, vec![true, false]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chained_task_transcribes_the_denoised_output() -> Result<()> {
+        use crate::schedule::processors::NoiseReductionProcessor;
+        use crate::schedule::types::NoiseReductionParams;
+        use crate::storage::task::entity::Model as TaskModel;
+
+        let storage: Arc<dyn TaskStorage> = Arc::new(crate::storage::task::sqlite::SqliteTaskStorage::new("sqlite::memory:").await?);
+        let dir = tempfile::TempDir::new()?;
+        let input_path = dir.path().join("noisy.wav");
+        write_wav(&input_path, 1, 16000, 16000)?;
+
+        // the denoise step, as its worker runs it
+        let mut denoise = local_task(input_path.clone(), &TranscribeParams::default());
+        denoise.id = "task-denoise".to_string();
+        denoise.config.task_type = TaskType::NoiseReduction;
+        denoise.config.params = TaskParams::NoiseReduction(NoiseReductionParams { strength: 0.5, frame_size: 2048, overlap: 0.75 });
+        let result = NoiseReductionProcessor::new(dir.path().join("denoised")).process(&denoise).await?;
+        denoise.status = TaskStatus::Completed;
+        denoise.result = Some(result);
+        storage.create(&TaskModel::from(denoise)).await?;
+
+        let engine = Arc::new(RecordingEngine::default());
+        let processor = TranscribeProcessor::new(engine.clone(), 1).with_input_tasks(storage.clone());
+        let mut params = TranscribeParams::default();
+        params.input_task = Some("task-denoise".to_string());
+        processor.process_audio(&local_task(input_path.clone(), &params), &params).await?;
+
+        // the denoised output only went through the stages after noise reduction
+        let denoised = dir.path().join("denoised").join("task-denoise.wav");
+        let expected = crate::audio::parse_audio_file_with_config(&denoised, &PreprocessConfig::new(false, 0.75))?;
+        let denoised_twice = crate::audio::parse_audio_file_with_config(&denoised, &PreprocessConfig::new(true, 0.75))?;
+        assert_ne!(expected, denoised_twice);
+        assert_eq!(engine.audio.lock().unwrap().as_slice(), &[expected]);
+
+        // the output of another key's task is not readable
+        let mut chained = local_task(input_path.clone(), &params);
+        chained.config.api_key = Some("kid-other".to_string());
+        let err = processor.process_audio(&chained, &params).await.unwrap_err();
+        assert_eq!(FailureKind::of(&err), FailureKind::Input);
+
+        // an input task that did not complete fails the step without a retry
+        let mut pending = local_task(input_path.clone(), &TranscribeParams::default());
+        pending.id = "task-pending".to_string();
+        pending.config.task_type = TaskType::NoiseReduction;
+        storage.create(&TaskModel::from(pending)).await?;
+        params.input_task = Some("task-pending".to_string());
+        let err = processor.process_audio(&local_task(input_path, &params), &params).await.unwrap_err();
+        assert_eq!(FailureKind::of(&err), FailureKind::Input);
+        Ok(())
+    }
+
+    // engine returning one confident and one unsure segment
+    struct MixedConfidenceEngine;
+
+    #[async_trait]
+    impl AsrEngine for MixedConfidenceEngine {
+        async fn transcribe(&self, _audio: Vec<f32>, _params: AsrParams) -> Result<AsrResult> {
+            let segment = |text: &str, start: f64, confidence: f32| crate::asr::TranscribeSegment {
+                text: text.to_string(),
+                speaker_id: 0,
+                start,
+                end: start + 50.0,
+                words: Vec::new(),
+                confidence,
+            };
+            Ok(AsrResult {
+                segments: vec![segment("hello", 0.0, 0.9), segment(" mumble", 50.0, 0.2)],
+                full_text: "hello mumble".to_string(),
+                detected_language: "en".to_string(),
+                language: "en".to_string(),
+                language_probability: 1.0,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_low_confidence_segments_are_dropped() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let input_path = dir.path().join("short.wav");
+        write_wav(&input_path, 1, 16000, 16000)?;
+        let processor = TranscribeProcessor::new(Arc::new(MixedConfidenceEngine), 1);
+
+        let mut params = TranscribeParams::default();
+        params.min_segment_confidence = Some(0.5);
+        let result = processor.process_audio(&local_task(input_path.clone(), &params), &params).await?;
+        assert_eq!(result.segments.len(), 1);
+        assert_eq!(result.segments[0].confidence, Some(0.9));
+        assert_eq!(result.text, "hello");
+        assert_eq!(result.dropped_segments, Some(1));
+
+        // without a threshold every segment is kept
+        let params = TranscribeParams::default();
+        let result = processor.process_audio(&local_task(input_path, &params), &params).await?;
+        assert_eq!(result.segments.len(), 2);
+        assert_eq!(result.text, "hello mumble");
+        assert_eq!(result.dropped_segments, None);
+
+        // thresholds outside 0..=1 are rejected
+        let mut params = TranscribeParams::default();
+        params.min_segment_confidence = Some(1.5);
+        assert!(processor.validate_params(&TaskParams::Transcribe(params)).is_err());
+        Ok(())
+    }
+
+    // engine transcribing a sentence with an email address and irregular spacing
+    struct EmailEngine;
+
+    #[async_trait]
+    impl AsrEngine for EmailEngine {
+        async fn transcribe(&self, _audio: Vec<f32>, _params: AsrParams) -> Result<AsrResult> {
+            let segment = |text: &str, start: f64| crate::asr::TranscribeSegment {
+                text: text.to_string(),
+                speaker_id: 0,
+                start,
+                end: start + 100.0,
+                words: Vec::new(),
+                confidence: 1.0,
+            };
+            Ok(AsrResult {
+                segments: vec![segment(" write to  alice@example.com", 0.0), segment(" today", 100.0)],
+                full_text: " write to  alice@example.com today".to_string(),
+                detected_language: "en".to_string(),
+                language: "en".to_string(),
+                language_probability: 1.0,
+            })
+        }
+    }
+
+    // custom redactor masking every word that looks like an email address
+    struct EmailRedactor;
+
+    impl ResultPostProcessor for EmailRedactor {
+        fn name(&self) -> &str {
+            "email"
+        }
+
+        fn process(&self, _params: &TranscribeParams, result: &mut TranscribeResult) -> Result<()> {
+            let redact = |text: &str| text.split(' ')
+                .map(|word| match word.split_once('@') {
+                    Some((user, domain)) if !user.is_empty() && domain.contains('.') => "[email]",
+                    _ => word,
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            for segment in result.segments.iter_mut() {
+                segment.text = redact(&segment.text);
+            }
+            result.text = redact(&result.text);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_processors_run_in_order() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let input_path = dir.path().join("short.wav");
+        write_wav(&input_path, 1, 16000, 16000)?;
+        let params = TranscribeParams::default();
+
+        let processor = TranscribeProcessor::new(Arc::new(EmailEngine), 1)
+            .with_post_processor(Arc::new(EmailRedactor))
+            .with_post_processor(Arc::new(crate::schedule::processors::WhitespaceNormalizer));
+        let result = processor.process_audio(&local_task(input_path.clone(), &params), &params).await?;
+        assert_eq!(result.text, "write to [email] today");
+        assert_eq!(result.segments[0].text, "write to [email]");
+        assert_eq!(result.segments[1].text, "today");
+        // timings are untouched
+        assert_eq!(result.segments[1].start_time, 100.0);
+
+        // without post processors the transcript is kept verbatim
+        let processor = TranscribeProcessor::new(Arc::new(EmailEngine), 1);
+        let result = processor.process_audio(&local_task(input_path, &params), &params).await?;
+        assert_eq!(result.text, " write to  alice@example.com today");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dirty_words_are_masked_by_the_post_processors() -> Result<()> {
+        use crate::asr::filter::DirtyWordFilter;
+        use crate::schedule::processors::{DirtyWordPostProcessor, WhitespaceNormalizer};
+
+        let dir = tempfile::TempDir::new()?;
+        let input_path = dir.path().join("short.wav");
+        write_wav(&input_path, 1, 16000, 16000)?;
+        let mut dirty_words = DirtyWordFilter::new();
+        dirty_words.add_words("en", ["today"]);
+        let processor = TranscribeProcessor::new(Arc::new(EmailEngine), 1)
+            .with_post_processor(Arc::new(DirtyWordPostProcessor::new(dirty_words)))
+            .with_post_processor(Arc::new(WhitespaceNormalizer));
+
+        let params = TranscribeParams { filter_dirty_words: true, ..Default::default() };
+        let result = processor.process_audio(&local_task(input_path.clone(), &params), &params).await?;
+        assert_eq!(result.text, "write to alice@example.com *****");
+        assert_eq!(result.segments[1].text, "*****");
+
+        // tasks that didn't ask for it keep the words
+        let params = TranscribeParams::default();
+        let result = processor.process_audio(&local_task(input_path, &params), &params).await?;
+        assert_eq!(result.text, "write to alice@example.com today");
+        Ok(())
+    }
+
+    // engine reporting progress and a segment through the params like the whisper callbacks do
+    struct ProgressEngine;
+
+    #[async_trait]
+    impl AsrEngine for ProgressEngine {
+        async fn transcribe(&self, _audio: Vec<f32>, params: AsrParams) -> Result<AsrResult> {
+            if let Some(progress) = &params.progress {
+                progress.send(TranscribeProgress::Progress(50))?;
+                progress.send(TranscribeProgress::Segment { text: "hello".to_string(), start: 0.0, end: 80.0 })?;
+            }
+            Ok(AsrResult {
+                segments: Vec::new(),
+                full_text: String::new(),
+                detected_language: "en".to_string(),
+                language: "en".to_string(),
+                language_probability: 1.0,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress_is_published_as_task_events() -> Result<()> {
+        use crate::schedule::callback::TaskEventReceiver;
+
+        let (events, _) = broadcast::channel(16);
+        let mut receiver = TaskEventReceiver::new(events.subscribe(), "task-progress".to_string());
+        let mut other = TaskEventReceiver::new(events.subscribe(), "task-other".to_string());
+        let processor = TranscribeProcessor::new(Arc::new(ProgressEngine), 1).with_events(events);
+
+        // two one second chunks, each reporting half of its own progress
+        processor.transcribe_chunked("task-progress", vec![0.0; 2 * SAMPLE_RATE], AsrParams::new(), 1).await?;
+        drop(processor);
+
+        let mut percents = Vec::new();
+        let mut starts = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            match event {
+                TaskEvent::Progress { percent, .. } => percents.push(percent),
+                TaskEvent::Segment { segment, .. } => starts.push(segment.start_time),
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+        // chunks relay independently, so only the content is deterministic, not the order
+        percents.sort();
+        starts.sort_by(f64::total_cmp);
+        assert_eq!(percents, vec![25, 75]);
+        assert_eq!(starts, vec![0.0, 100.0]);
+
+        assert!(other.recv().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timings_only_when_requested() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let input_path = dir.path().join("timed.wav");
+        write_wav(&input_path, 1, 16000, 16000)?;
+
+        let engine = Arc::new(SlowEngine {
+            running: std::sync::atomic::AtomicUsize::new(0),
+            peak: std::sync::atomic::AtomicUsize::new(0),
+        });
+        let processor = TranscribeProcessor::new(engine, 1);
+
+        let params = TranscribeParams::default();
+        let task = local_task(input_path.clone(), &params);
+        let result = processor.process_audio(&task, &params).await?;
+        assert!(result.timings_ms.is_none());
+        assert!(!serde_json::to_string(&result)?.contains("timings_ms"));
+
+        let mut params = TranscribeParams::default();
+        params.timings = Some(PhaseTimings { download: 120, ..Default::default() });
+        let task = local_task(input_path, &params);
+        let result = processor.process_audio(&task, &params).await?;
+        let timings = result.timings_ms.clone().expect("timings were requested");
+
+        assert_eq!(timings.download, 120);
+        // the slow engine sleeps 50ms per call
+        assert!(timings.inference >= 50);
+        // the total includes every phase
+        let phases = timings.download + timings.preprocess + timings.inference;
+        assert!(phases <= timings.total, "{:?}", timings);
+        let json = serde_json::to_value(&result)?;
+        for phase in ["download", "preprocess", "inference", "total"] {
+            assert!(json["timings_ms"][phase].is_u64(), "{} missing from {}", phase, json);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use async_trait::async_trait;
+use anyhow::Result;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::audio::PreprocessConfig;
+use crate::audio::voiceprint::{cosine_similarity, extract_embedding, NotEnoughSpeech};
+use crate::schedule::types::{
+    Task, TaskError, TaskParams, TaskResult, TaskType, VoiceprintMode, VoiceprintParams, VoiceprintResult
+};
+use crate::storage::voiceprint::VoiceprintStorage;
+use super::{remove_input_file, TaskProcessor};
+
+// longest accepted speaker id
+const MAX_SPEAKER_ID_LEN: usize = 128;
+// matches returned by an identification, best first
+const MAX_MATCHES: usize = 5;
+
+// voiceprints belong to the key that enrolled them, tasks created without a key share one scope
+fn owner(task: &Task) -> &str {
+    task.config.api_key.as_deref().unwrap_or_default()
+}
+
+pub struct VoiceprintProcessor {
+    storage: Arc<dyn VoiceprintStorage>,
+}
+
+impl VoiceprintProcessor {
+    pub fn new(storage: Arc<dyn VoiceprintStorage>) -> Self {
+        Self { storage }
+    }
+
+    fn validate(params: &VoiceprintParams) -> Result<()> {
+        if let VoiceprintMode::Enroll { speaker_id } = &params.mode {
+            if speaker_id.trim().is_empty() {
+                return Err(anyhow::anyhow!("Invalid speaker id: must not be empty"));
+            }
+            if speaker_id.len() > MAX_SPEAKER_ID_LEN {
+                return Err(anyhow::anyhow!("Invalid speaker id: longer than {} bytes", MAX_SPEAKER_ID_LEN));
+            }
+        }
+        Ok(())
+    }
+
+    // the speakers enrolled under the key by similarity to the embedding, best first
+    async fn identify(&self, owner: &str, embedding: &[f32]) -> Result<Vec<(String, f32)>> {
+        let mut matches: Vec<(String, f32)> = self.storage.list(owner).await?
+            .into_iter()
+            .map(|(speaker_id, enrolled)| {
+                let similarity = cosine_similarity(embedding, &enrolled);
+                (speaker_id, similarity)
+            })
+            .collect();
+        matches.sort_by(|a, b| b.1.total_cmp(&a.1));
+        matches.truncate(MAX_MATCHES);
+        Ok(matches)
+    }
+}
+
+#[async_trait]
+impl TaskProcessor for VoiceprintProcessor {
+    fn task_type(&self) -> TaskType {
+        TaskType::VoiceprintRecognition
+    }
+
+    async fn process(&self, task: &Task) -> Result<TaskResult> {
+        let params = match &task.config.params {
+            TaskParams::VoiceprintRecognition(p) => p.clone(),
+            _ => return Err(anyhow::anyhow!("Invalid task params")),
+        };
+
+        info!("Processing voiceprint task {} with params: {:?}", task.id, params);
+
+        // decoding and the fft work are cpu bound, keep them off the async workers.
+        // a clip that can't be decoded or holds too little speech fails the same way on every attempt
+        let input_path = task.config.input_path.clone();
+        let embedding = tokio::task::spawn_blocking(move || -> Result<Vec<f32>> {
+            let samples = crate::audio::parse_audio_file_with_config(&input_path, &PreprocessConfig::new(false, 0.0))
+                .map_err(TaskError::input)?;
+            extract_embedding(&samples).map_err(|e| match e.downcast::<NotEnoughSpeech>() {
+                Ok(not_enough) => TaskError::input(not_enough),
+                Err(e) => e,
+            })
+        }).await??;
+
+        let matches = match params.mode {
+            VoiceprintMode::Enroll { speaker_id } => {
+                self.storage.save(owner(task), &speaker_id, &embedding).await?;
+                info!("Enrolled speaker {} from task {}", speaker_id, task.id);
+                Vec::new()
+            }
+            VoiceprintMode::Identify => {
+                let matches = self.identify(owner(task), &embedding).await?;
+                match matches.first() {
+                    Some((speaker_id, similarity)) => {
+                        info!("Task {} best matches speaker {} ({:.3})", task.id, speaker_id, similarity)
+                    }
+                    None => warn!("No speakers enrolled, task {} has no match", task.id),
+                }
+                matches
+            }
+        };
+        Ok(TaskResult::VoiceprintRecognition(VoiceprintResult { matches }))
+    }
+
+    fn validate_params(&self, params: &TaskParams) -> Result<()> {
+        match params {
+            TaskParams::VoiceprintRecognition(p) => Self::validate(p),
+            _ => Err(anyhow::anyhow!("Invalid task params")),
+        }
+    }
+
+    async fn cancel(&self, task: &Task) -> Result<()> {
+        // the embedding is only stored once it is complete, there is nothing to undo
+        info!("Cancelled voiceprint task {}", task.id);
+        Ok(())
+    }
+
+    async fn purge(&self, task: &Task) -> Result<u64> {
+        // the voiceprint an enrollment stored goes with its task
+        let TaskParams::VoiceprintRecognition(VoiceprintParams { mode: VoiceprintMode::Enroll { speaker_id } }) = &task.config.params else {
+            return Ok(0);
+        };
+        Ok(self.storage.delete(owner(task), speaker_id).await? as u64)
+    }
+
+    async fn cleanup(&self, task: &Task) -> Result<()> {
+        // clean up temporary file
+        remove_input_file(task);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use crate::schedule::test_support;
+    use crate::schedule::types::{FailureKind, TaskConfig, TaskStatus};
+    use crate::storage::voiceprint::InMemoryVoiceprintStorage;
+
+    fn voiceprint_task(input_path: PathBuf, mode: VoiceprintMode) -> Task {
+        Task {
+            status: TaskStatus::Processing,
+            ..test_support::task(TaskConfig {
+                task_type: TaskType::VoiceprintRecognition,
+                input_path,
+                params: TaskParams::VoiceprintRecognition(VoiceprintParams { mode }),
+                api_key: Some("kid-a".to_string()),
+                ..test_support::task_config()
+            })
+        }
+    }
+
+    // two seconds of harmonics of `fundamental`, shaped by a formant around `formant`
+    fn write_voice(path: &Path, fundamental: f32, formant: f32) -> Result<()> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for i in 0..32000 {
+            let t = i as f32 / 16000.0;
+            let mut sample = 0.0;
+            let mut harmonic = fundamental;
+            while harmonic < 8000.0 {
+                let distance = (harmonic - formant) / 400.0;
+                sample += (-distance * distance).exp() * (2.0 * std::f32::consts::PI * harmonic * t).sin();
+                harmonic += fundamental;
+            }
+            writer.write_sample((sample * 3000.0) as i16)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    fn enroll(speaker_id: &str) -> VoiceprintMode {
+        VoiceprintMode::Enroll { speaker_id: speaker_id.to_string() }
+    }
+
+    #[test]
+    fn test_validate_params() {
+        let processor = VoiceprintProcessor::new(Arc::new(InMemoryVoiceprintStorage::new()));
+        let valid = |mode| processor.validate_params(&TaskParams::VoiceprintRecognition(VoiceprintParams { mode })).is_ok();
+
+        assert!(valid(enroll("alice")));
+        assert!(valid(VoiceprintMode::Identify));
+        assert!(!valid(enroll(" ")));
+        assert!(!valid(enroll(&"a".repeat(MAX_SPEAKER_ID_LEN + 1))));
+    }
+
+    #[tokio::test]
+    async fn test_enroll_and_identify() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let storage = Arc::new(InMemoryVoiceprintStorage::new());
+        let processor = VoiceprintProcessor::new(storage.clone());
+
+        // nobody enrolled yet
+        write_voice(&dir.path().join("unknown.wav"), 120.0, 700.0)?;
+        let task = voiceprint_task(dir.path().join("unknown.wav"), VoiceprintMode::Identify);
+        let result = processor.process(&task).await?;
+        assert!(result.as_voiceprint_recognition().unwrap().matches.is_empty());
+
+        for (speaker_id, fundamental, formant) in [("alice", 120.0, 700.0), ("bob", 220.0, 2200.0)] {
+            let path = dir.path().join(format!("{}.wav", speaker_id));
+            write_voice(&path, fundamental, formant)?;
+            let result = processor.process(&voiceprint_task(path, enroll(speaker_id))).await?;
+            assert!(result.as_voiceprint_recognition().unwrap().matches.is_empty());
+        }
+        assert_eq!(storage.list("kid-a").await?.len(), 2);
+
+        let path = dir.path().join("clip.wav");
+        write_voice(&path, 220.0, 2200.0)?;
+        let result = processor.process(&voiceprint_task(path, VoiceprintMode::Identify)).await?;
+        let matches = &result.as_voiceprint_recognition().unwrap().matches;
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].0, "bob");
+        assert!(matches[0].1 > matches[1].1);
+
+        // another key does not see the speakers
+        let mut task = voiceprint_task(dir.path().join("clip.wav"), VoiceprintMode::Identify);
+        task.config.api_key = Some("kid-b".to_string());
+        let result = processor.process(&task).await?;
+        assert!(result.as_voiceprint_recognition().unwrap().matches.is_empty());
+
+        // purging an enrollment removes the voiceprint it stored
+        assert_eq!(processor.purge(&voiceprint_task(dir.path().join("bob.wav"), enroll("bob"))).await?, 1);
+        assert_eq!(storage.list("kid-a").await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_silent_clip_is_a_permanent_failure() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("silence.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec)?;
+        for _ in 0..16000 {
+            writer.write_sample(0i16)?;
+        }
+        writer.finalize()?;
+
+        let processor = VoiceprintProcessor::new(Arc::new(InMemoryVoiceprintStorage::new()));
+        let error = processor.process(&voiceprint_task(path, enroll("alice"))).await.unwrap_err();
+        assert_eq!(FailureKind::of(&error), FailureKind::Input);
+        Ok(())
+    }
+}
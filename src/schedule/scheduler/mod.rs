@@ -3,16 +3,47 @@ mod worker;
 
 use std::sync::Arc;
 use tokio::task::JoinHandle;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use anyhow::Result;
 
 pub use task_manager::TaskManager;
 use worker::TaskWorker;
-use crate::schedule::types::TaskType;
+use crate::schedule::types::{Task, TaskType};
+
+// default fraction of the poll interval workers randomize their idle sleep by
+const DEFAULT_POLL_JITTER: f64 = 0.2;
+
+// how often `run` looks for exited workers
+const WORKER_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+// a running worker. the task type is kept so a panicked one can be replaced
+struct WorkerHandle {
+    task_type: TaskType,
+    // flipped to true to stop this worker alone, see `scale_workers`
+    stop: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+    // the task the worker is processing, see `TaskWorker::current_task`
+    current: Arc<std::sync::Mutex<Option<Task>>>,
+}
+
+impl WorkerHandle {
+    fn is_stopping(&self) -> bool {
+        *self.stop.borrow()
+    }
+}
 
 pub struct TaskScheduler {
     task_manager: Arc<TaskManager>,
-    workers: Mutex<Vec<JoinHandle<()>>>,
+    workers: Mutex<Vec<WorkerHandle>>,
+    poll_jitter: f64,
+    // upper bound on the workers of all types together, see `spawn_workers`
+    max_workers: usize,
+    // flipped to true by `shutdown`, the timeout sweep and the cleanup stop once they see it
+    shutdown: watch::Sender<bool>,
+    sweeper: Mutex<Option<JoinHandle<()>>>,
+    // interval and retention in days of the cleanup of finished tasks, off when unset
+    cleanup: Option<(std::time::Duration, i64)>,
+    cleaner: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl TaskScheduler {
@@ -20,35 +51,220 @@ impl TaskScheduler {
         Self {
             task_manager,
             workers: Mutex::new(Vec::new()),
+            poll_jitter: DEFAULT_POLL_JITTER,
+            max_workers: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            shutdown: watch::channel(false).0,
+            sweeper: Mutex::new(None),
+            cleanup: None,
+            cleaner: Mutex::new(None),
         }
     }
 
+    // fraction of the poll interval each worker randomizes its idle sleep by, 0 disables it
+    pub fn with_poll_jitter(mut self, poll_jitter: f64) -> Self {
+        self.poll_jitter = poll_jitter;
+        self
+    }
+
+    // defaults to the number of cores
+    pub fn with_max_workers(mut self, max_workers: usize) -> Self {
+        self.max_workers = max_workers.max(1);
+        self
+    }
+
+    // delete completed and failed tasks older than `retention_days` every `interval`
+    pub fn with_cleanup(mut self, interval: std::time::Duration, retention_days: i64) -> Self {
+        self.cleanup = Some((interval, retention_days));
+        self
+    }
+
     pub async fn spawn_worker(&self, task_type: TaskType) {
-        let worker = TaskWorker::new(self.task_manager.clone(), task_type);
+        self.spawn_workers(task_type, 1).await;
+    }
+
+    // start `count` workers of the type, as many as fit under `max_workers` together with the
+    // ones already running, and return how many were started. each worker processes one task
+    // at a time, but transcriptions also wait for a permit of `MAX_CONCURRENT_TRANSCRIPTIONS`,
+    // so transcribe workers beyond that number only claim tasks they can't start yet
+    pub async fn spawn_workers(&self, task_type: TaskType, count: usize) -> usize {
+        let mut workers = self.workers.lock().await;
+        self.spawn_workers_locked(&mut workers, task_type, count)
+    }
+
+    // `spawn_workers` for a caller already holding the worker list
+    fn spawn_workers_locked(&self, workers: &mut Vec<WorkerHandle>, task_type: TaskType, count: usize) -> usize {
+        let running = workers.iter().filter(|worker| !worker.is_stopping()).count();
+        let available = self.max_workers.saturating_sub(running);
+        if count > available {
+            tracing::warn!(
+                "Requested {} {} workers, starting {} to stay within the limit of {} workers",
+                count, task_type, available, self.max_workers
+            );
+        }
+        let started = count.min(available);
+        for _ in 0..started {
+            workers.push(self.start_worker(task_type.clone()));
+        }
+        started
+    }
+
+    // run `count` workers of the type, starting or stopping the difference. a stopped worker
+    // finishes its current task first. returns the number of workers of the type afterwards,
+    // fewer than `count` when `max_workers` doesn't allow more. the worker list stays locked
+    // throughout, so concurrent calls don't act on the same count
+    pub async fn scale_workers(&self, task_type: TaskType, count: usize) -> usize {
+        let mut workers = self.workers.lock().await;
+        let running = Self::running_workers(&workers, &task_type);
+        if count > running {
+            self.spawn_workers_locked(&mut workers, task_type.clone(), count - running);
+        } else if count < running {
+            for worker in workers.iter()
+                .filter(|worker| worker.task_type == task_type && !worker.is_stopping())
+                .take(running - count)
+            {
+                worker.stop.send_replace(true);
+            }
+            tracing::info!("Stopping {} {} workers", running - count, task_type);
+        }
+        Self::running_workers(&workers, &task_type)
+    }
+
+    // workers of the type that were not asked to stop
+    pub async fn worker_count(&self, task_type: &TaskType) -> usize {
+        Self::running_workers(&self.workers.lock().await, task_type)
+    }
+
+    fn running_workers(workers: &[WorkerHandle], task_type: &TaskType) -> usize {
+        workers.iter()
+            .filter(|worker| &worker.task_type == task_type && !worker.is_stopping())
+            .count()
+    }
+
+    fn start_worker(&self, task_type: TaskType) -> WorkerHandle {
+        let worker = TaskWorker::new(self.task_manager.clone(), task_type.clone())
+            .with_jitter(self.poll_jitter);
+        // a worker started during the shutdown stops right away
+        let (stop, receiver) = watch::channel(*self.shutdown.borrow());
+        let current = worker.current_task();
         let handle = tokio::spawn(async move {
-            worker.run().await;
+            worker.run(receiver).await;
         });
-        self.workers.lock().await.push(handle);
+        WorkerHandle { task_type, stop, handle, current }
     }
 
     pub async fn run(&self) -> Result<()> {
         // start task timeout check
         let tm = self.task_manager.clone();
-        tokio::spawn(async move {
-            loop {
+        let mut shutdown = self.shutdown.subscribe();
+        let sweeper = tokio::spawn(async move {
+            while !*shutdown.borrow() {
                 if let Err(e) = tm.handle_timed_out_tasks().await {
                     tracing::error!("Error handling timed out tasks: {}", e);
                 }
-                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(60)) => {}
+                    changed = shutdown.changed() => if changed.is_err() { break },
+                }
             }
         });
+        *self.sweeper.lock().await = Some(sweeper);
+
+        // start the cleanup of finished tasks, the first run waits a whole interval
+        if let Some((interval, retention_days)) = self.cleanup {
+            let tm = self.task_manager.clone();
+            let mut shutdown = self.shutdown.subscribe();
+            let cleaner = tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {}
+                        changed = shutdown.changed() => if changed.is_err() || *shutdown.borrow() { break },
+                    }
+                    match tm.cleanup_tasks(retention_days).await {
+                        Ok(stats) => tracing::info!(
+                            "Cleaned up tasks older than {} days: {} completed, {} failed",
+                            retention_days, stats.completed, stats.failed
+                        ),
+                        Err(e) => tracing::error!("Error cleaning up old tasks: {}", e),
+                    }
+                }
+            });
+            *self.cleaner.lock().await = Some(cleaner);
+        }
+
+        // supervise the workers until the shutdown, workers are started and stopped meanwhile
+        // by `scale_workers`. a worker that panicked is replaced by a new one of the same type,
+        // the others keep running meanwhile. the task it was processing is handled like a failed
+        // one, otherwise it would stay processing
+        let mut shutdown = self.shutdown.subscribe();
+        loop {
+            let mut workers = self.workers.lock().await;
+            let mut abandoned = Vec::new();
+            let mut index = 0;
+            while index < workers.len() {
+                if !workers[index].handle.is_finished() {
+                    index += 1;
+                    continue;
+                }
+                let mut worker = workers.swap_remove(index);
+                match (&mut worker.handle).await {
+                    Ok(()) => {}
+                    Err(e) if e.is_panic() => {
+                        abandoned.extend(worker.current.lock().unwrap_or_else(|e| e.into_inner()).take());
+                        if !worker.is_stopping() && !*self.shutdown.borrow() {
+                            tracing::error!("{} worker panicked, starting a new one: {}", worker.task_type, e);
+                            workers.push(self.start_worker(worker.task_type));
+                        } else {
+                            tracing::error!("{} worker stopped abnormally: {}", worker.task_type, e);
+                        }
+                    }
+                    Err(e) => tracing::error!("{} worker stopped abnormally: {}", worker.task_type, e),
+                }
+            }
+            let finished = *self.shutdown.borrow() && workers.is_empty();
+            drop(workers);
+
+            for task in abandoned {
+                if let Err(e) = self.task_manager.abandon_task(&task).await {
+                    tracing::error!("Failed to release task {} of a panicked worker: {}", task.id, e);
+                }
+            }
+            if finished {
+                break;
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(WORKER_CHECK_INTERVAL) => {}
+                changed = shutdown.changed() => if changed.is_err() { break },
+            }
+        }
+
+        Ok(())
+    }
+
+    // stop the workers once their current task is done, then the timeout sweep and the cleanup.
+    // returns after all of them have exited
+    pub async fn shutdown(&self) -> Result<()> {
+        tracing::info!("Stopping scheduler workers...");
+        self.shutdown.send_replace(true);
 
-        // wait for all workers to finish
         let mut workers = self.workers.lock().await;
+        for worker in workers.iter() {
+            worker.stop.send_replace(true);
+        }
         for worker in workers.drain(..) {
-            worker.await?;
+            if let Err(e) = worker.handle.await {
+                tracing::error!("{} worker stopped abnormally: {}", worker.task_type, e);
+            }
         }
+        drop(workers);
 
-        Ok(())
+        if let Some(sweeper) = self.sweeper.lock().await.take() {
+            sweeper.await?;
+        }
+        if let Some(cleaner) = self.cleaner.lock().await.take() {
+            cleaner.await?;
+        }
+
+        self.task_manager.shutdown().await
     }
-} 
\ No newline at end of file
+}
@@ -2,30 +2,62 @@
 
 use std::sync::Arc;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use tokio::sync::Mutex;
 use anyhow::Result;
+use futures::Stream;
+use futures::future::AbortHandle;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use tracing::{info, warn, error};
 use serde::{Serialize, Deserialize};
 
 use crate::schedule::types::{
-    Task, TaskConfig, TaskResult, TaskStatus, TaskType,
-    CallbackType, TaskPriority
+    Task, TaskConfig, TaskParams, TaskResult, TaskStatus, TaskType,
+    CallbackType, TaskPriority, InvalidTransition, FailureKind, TaskRetryPolicy, TaskManagerError
 };
-use crate::storage::task::TaskStorage;
+use crate::storage::task::{FailedCallback, TaskFilter, TaskStorage};
+use crate::storage::task::entity::Model as TaskModel;
 use crate::schedule::processors::TaskProcessor;
 use crate::schedule::callback::{
-    TaskCallback, HttpCallback, FunctionCallback, EventCallback,
+    TaskCallback, HttpCallback, FunctionCallback, EventCallback, TaskEvent, TaskEventReceiver,
+    UndeliveredCallback, DEFAULT_CONNECT_TIMEOUT, DEFAULT_REQUEST_TIMEOUT,
 };
+use crate::utils::http::RetryPolicy;
+use crate::utils::metrics;
+use crate::utils::paths::file_within;
+use crate::AUDIO_PATH;
 use crate::web::Pagination;
 
+// size of each read when streaming a stored result
+const RESULT_STREAM_CHUNK_SIZE: u64 = 64 * 1024;
+
+// events buffered per subscriber, segment events of a long task arrive in bursts
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
 pub struct TaskManager {
     pub storage: Arc<dyn TaskStorage>,
     processors: HashMap<TaskType, Box<dyn TaskProcessor>>,
     processing_tasks: Mutex<HashMap<String, ProcessingInfo>>,
     function_callbacks: HashMap<String, Box<dyn TaskCallback>>,
     event_callback: EventCallback,
+    // retries of http callbacks, undelivered ones end up in the failed callbacks table
+    callback_retry: RetryPolicy,
+    // connect and request timeout of http callbacks
+    callback_timeouts: (std::time::Duration, std::time::Duration),
+    // larger http callbacks only carry the url of the result, built from `public_url`
+    callback_max_payload: Option<usize>,
+    public_url: Option<String>,
+    // recorded on the tasks this manager claims
+    instance_id: String,
+    // abort handles of the tasks running in this process, see `cancel_task`
+    running: std::sync::Mutex<HashMap<String, AbortHandle>>,
+    // notified once when a task has used up its retries
+    dead_letter_url: Option<String>,
+    // how failed tasks of each type are retried, see `retry_policy`
+    retry_policies: HashMap<TaskType, TaskRetryPolicy>,
+    // a purge only deletes input files inside this directory
+    audio_dir: PathBuf,
 }
 
 #[derive(Debug)]
@@ -33,38 +65,221 @@ struct ProcessingInfo {
     status: TaskStatus,
     started_at: DateTime<Utc>,
     attempts: u32,
+    // error of every failed attempt, sent with the dead-letter notification
+    errors: Vec<String>,
 }
 
 impl TaskManager {
     pub fn new(storage: Arc<dyn TaskStorage>) -> Self {
-        let (event_callback, _) = EventCallback::new(10);
+        let (event_callback, _) = EventCallback::new(EVENT_CHANNEL_CAPACITY);
         Self {
             storage,
             processors: HashMap::new(),
             processing_tasks: Mutex::new(HashMap::new()),
             function_callbacks: HashMap::new(),
             event_callback,
+            callback_retry: RetryPolicy::default(),
+            callback_timeouts: (DEFAULT_CONNECT_TIMEOUT, DEFAULT_REQUEST_TIMEOUT),
+            callback_max_payload: None,
+            public_url: None,
+            instance_id: format!("scheduler-{}", Uuid::new_v4()),
+            running: std::sync::Mutex::new(HashMap::new()),
+            dead_letter_url: None,
+            retry_policies: HashMap::new(),
+            audio_dir: PathBuf::from(AUDIO_PATH.as_str()),
         }
     }
 
+    pub fn with_callback_timeouts(mut self, connect_timeout: std::time::Duration, request_timeout: std::time::Duration) -> Self {
+        self.callback_timeouts = (connect_timeout, request_timeout);
+        self
+    }
+
+    pub fn with_callback_retry(mut self, callback_retry: RetryPolicy) -> Self {
+        self.callback_retry = callback_retry;
+        self
+    }
+
+    pub fn with_callback_max_payload(mut self, max_payload: Option<usize>, public_url: Option<String>) -> Self {
+        self.callback_max_payload = max_payload;
+        self.public_url = public_url;
+        self
+    }
+
+    pub fn with_dead_letter_url(mut self, dead_letter_url: Option<String>) -> Self {
+        self.dead_letter_url = dead_letter_url;
+        self
+    }
+
+    pub fn with_audio_dir(mut self, audio_dir: PathBuf) -> Self {
+        self.audio_dir = audio_dir;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, task_type: TaskType, policy: TaskRetryPolicy) -> Self {
+        self.retry_policies.insert(task_type, policy);
+        self
+    }
+
+    // sets the base delay of the policy of every task type
+    pub fn with_retry_delay(mut self, retry_delay: std::time::Duration) -> Self {
+        for task_type in [TaskType::Transcribe, TaskType::VoiceprintRecognition, TaskType::NoiseReduction] {
+            let policy = self.retry_policies.entry(task_type.clone())
+                .or_insert_with(|| TaskRetryPolicy::for_task_type(&task_type));
+            policy.base_delay = retry_delay;
+        }
+        self
+    }
+
+    // the retry policy of a task type, the built-in default when none was configured
+    pub fn retry_policy(&self, task_type: &TaskType) -> TaskRetryPolicy {
+        self.retry_policies.get(task_type)
+            .cloned()
+            .unwrap_or_else(|| TaskRetryPolicy::for_task_type(task_type))
+    }
+
     pub fn storage(&self) -> &Arc<dyn TaskStorage> {
         &self.storage
     }
 
+    // sender of the task event broadcast, processors publish progress and segments on it
+    pub fn events(&self) -> tokio::sync::broadcast::Sender<TaskEvent> {
+        self.event_callback.sender.clone()
+    }
+
+    // subscribe to the events of a single task
+    pub fn subscribe_task(&self, task_id: &str) -> TaskEventReceiver {
+        TaskEventReceiver::new(self.event_callback.sender.subscribe(), task_id.to_string())
+    }
+
+    // subscribe to the events of every task
+    pub fn subscribe(&self) -> TaskEventReceiver {
+        TaskEventReceiver::all(self.event_callback.sender.subscribe())
+    }
+
     pub fn register_processor(&mut self, processor: Box<dyn TaskProcessor>) {
         let task_type = processor.task_type();
         info!("Registering processor for task type: {:?}", task_type);
         self.processors.insert(task_type, processor);
     }
 
+    pub fn has_processor(&self, task_type: &TaskType) -> bool {
+        self.processors.contains_key(task_type)
+    }
+
+    // round trip to the task storage, for readiness checks
+    pub async fn ping_storage(&self) -> Result<()> {
+        self.storage.ping().await
+    }
+
+    // check params against the processor of the task type, without creating a task
+    pub fn validate_params(&self, task_type: &TaskType, params: &TaskParams) -> Result<()> {
+        let Some(processor) = self.processors.get(task_type) else {
+            // the type is valid, the server just isn't configured to run it
+            error!("No processor registered for task type {}", task_type);
+            return Err(TaskManagerError::NoProcessor(task_type.clone()).into());
+        };
+        processor.validate_params(params)
+            .map_err(|e| TaskManagerError::InvalidParams(format!("{:#}", e)).into())
+    }
+
+    // run a task on its processor right away without storing it, for callers waiting on the
+    // result. the processor still bounds the concurrency, and the input is cleaned up either way.
+    // running out of time fails with `tokio::time::error::Elapsed`
+    pub async fn run_inline(&self, config: TaskConfig, timeout: std::time::Duration) -> Result<TaskResult> {
+        let processor = self.processors.get(&config.task_type)
+            .ok_or_else(|| TaskManagerError::NoProcessor(config.task_type.clone()))?;
+        processor.validate_params(&config.params)
+            .map_err(|e| TaskManagerError::InvalidParams(format!("{:#}", e)))?;
+
+        let task = Self::new_task(config);
+        info!("Running task {} inline", task.id);
+        let result = tokio::time::timeout(timeout, processor.process(&task)).await;
+        if let Err(e) = processor.cleanup(&task).await {
+            warn!("Failed to clean up inline task {}: {}", task.id, e);
+        }
+        result?
+    }
+
     pub async fn create_task(&self, config: TaskConfig) -> Result<Task> {
         // validate task params
-        let processor = self.processors.get(&config.task_type)
-            .ok_or_else(|| anyhow::anyhow!("No processor found for task type: {:?}", config.task_type))?;
-        
-        processor.validate_params(&config.params)?;
+        self.validate_params(&config.task_type, &config.params)?;
+        self.check_input_task(&config).await?;
+
+        let task = Self::new_task(config);
+        self.storage.create(&task.clone().into()).await?;
+        metrics::task_created(&task.config.task_type);
+        info!("Creating new task: {}", task.id);
+        Ok(task)
+    }
+
+    // create several tasks in one storage transaction. each config is validated on its own,
+    // an invalid one only fails its own entry. the results are in the order of `configs`
+    pub async fn create_tasks(&self, configs: Vec<TaskConfig>) -> Vec<Result<Task>> {
+        let mut results: Vec<Result<Task>> = Vec::with_capacity(configs.len());
+        for config in configs {
+            let checked = match self.validate_params(&config.task_type, &config.params) {
+                Ok(()) => self.check_input_task(&config).await,
+                Err(e) => Err(e),
+            };
+            results.push(checked.map(|()| Self::new_task(config)));
+        }
+
+        let models: Vec<TaskModel> = results.iter()
+            .filter_map(|result| result.as_ref().ok())
+            .map(|task| task.clone().into())
+            .collect();
+        if models.is_empty() {
+            return results;
+        }
+
+        match self.storage.create_many(&models).await {
+            Ok(()) => {
+                for task in results.iter().filter_map(|result| result.as_ref().ok()) {
+                    metrics::task_created(&task.config.task_type);
+                }
+                info!("Created a batch of {} tasks", models.len())
+            }
+            Err(e) => {
+                // the transaction rolled back, none of the valid tasks exists
+                error!("Failed to create a batch of {} tasks: {}", models.len(), e);
+                for result in results.iter_mut().filter(|result| result.is_ok()) {
+                    *result = Err(anyhow::anyhow!("Failed to store task: {}", e));
+                }
+            }
+        }
+        results
+    }
 
-        let task = Task {
+    // a chained task may only read the output of a noise reduction task submitted with the same
+    // key, tasks of other keys are reported as missing. the claim query keeps the chained task
+    // pending until its input task has finished
+    async fn check_input_task(&self, config: &TaskConfig) -> Result<()> {
+        let TaskParams::Transcribe(params) = &config.params else {
+            return Ok(());
+        };
+        let Some(input_id) = &params.input_task else {
+            return Ok(());
+        };
+
+        let input: Task = match self.storage.get(input_id).await? {
+            Some(model) => model.into(),
+            None => return Err(TaskManagerError::InvalidParams(format!("Input task not found: {}", input_id)).into()),
+        };
+        if input.config.api_key != config.api_key {
+            return Err(TaskManagerError::InvalidParams(format!("Input task not found: {}", input_id)).into());
+        }
+        if input.config.task_type != TaskType::NoiseReduction {
+            return Err(TaskManagerError::InvalidParams(format!("Input task {} is not a noise reduction task", input_id)).into());
+        }
+        if input.status.is_terminal() && input.status != TaskStatus::Completed {
+            return Err(TaskManagerError::InvalidParams(format!("Input task {} did not complete", input_id)).into());
+        }
+        Ok(())
+    }
+
+    fn new_task(config: TaskConfig) -> Task {
+        Task {
             id: format!("task-{}", Uuid::new_v4()),
             status: TaskStatus::Pending,
             config,
@@ -74,56 +289,52 @@ impl TaskManager {
             completed_at: None,
             result: None,
             error: None,
-        };
-
-        self.storage.create(&task.clone().into()).await?;
-        info!("Creating new task: {}", task.id);
-        Ok(task)
+        }
     }
 
-    pub async fn get_next_task(&self) -> Result<Option<Task>> {
-        let mut processing = self.processing_tasks.lock().await;
-        
+    pub async fn get_next_task(&self, task_type: &TaskType) -> Result<Option<Task>> {
         // clean up stale processing tasks
-        self.cleanup_stale_tasks(&mut processing).await?;
-        
-        // get pending tasks by priority
-        let pending_tasks = self.storage.get_pending_by_priority(10).await?;
-        
-        // process tasks by priority
-        for task in pending_tasks {
-            if !processing.contains_key(&task.id) {
-                info!("Starting task {}", task.id);
-                
-                // mark task as processing
-                processing.insert(task.id.clone(), ProcessingInfo {
-                    status: TaskStatus::Processing,
-                    started_at: Utc::now(),
-                    attempts: 1,
-                });
-                
-                // update task status in database
-                self.storage.update(&task.id, &TaskStatus::Processing.to_string()).await?;
-                
-                // record task start time
-                let mut task = task;
-                task.started_at = Some(Utc::now());
-                self.storage.create(&task.clone().into()).await?;
-                
-                return Ok(Some(task.into()));
-            }
-        }
+        self.cleanup_stale_tasks().await?;
+
+        let mut processing = self.processing_tasks.lock().await;
+        // claim the first pending task of the type by priority, or a retrying one whose retry is due.
+        // the claim is a single conditional update, so other schedulers sharing the database can't take the same task
+        let model = match self.storage.claim_next_pending(&self.instance_id, task_type).await? {
+            Some(model) => model,
+            None => return Ok(None),
+        };
+        // the attempt is stored with the task, a retry claimed after a restart keeps counting
+        let attempts = model.attempts.max(1) as u32;
+        let task: Task = model.into();
+        info!("Starting task {} (attempt {})", task.id, attempts);
+
+        // mark task as processing, a retried task keeps the errors of its earlier attempts
+        let info = processing.entry(task.id.clone()).or_insert_with(|| ProcessingInfo {
+            status: TaskStatus::Processing,
+            started_at: Utc::now(),
+            attempts,
+            errors: Vec::new(),
+        });
+        info.status = TaskStatus::Processing;
+        info.started_at = Utc::now();
+        info.attempts = info.attempts.max(attempts);
+        drop(processing);
 
-        Ok(None)
+        // the claim is the pending -> processing transition, notify like `transition` does
+        self.notify_status_change(&task).await;
+
+        Ok(Some(task))
     }
 
     pub async fn process_task(&self, task: &Task) -> Result<TaskResult> {
-        let processor = self.processors.get(&task.config.task_type)
-            .ok_or_else(|| anyhow::anyhow!("No processor found for task type"))?;
-
         info!("Processing task {} with processor {:?}", task.id, task.config.task_type);
-        
-        match processor.process(task).await {
+
+        let result = match self.processors.get(&task.config.task_type) {
+            Some(processor) => processor.process(task).await,
+            None => Err(anyhow::anyhow!("No processor found for task type")),
+        };
+
+        match result {
             Ok(result) => {
                 info!("Task {} completed successfully", task.id);
                 Ok(result)
@@ -136,70 +347,253 @@ impl TaskManager {
         }
     }
 
+    // the worker processing the task panicked. the task is retried or failed like any other
+    // failed task, and is no longer tracked as running
+    pub async fn abandon_task(&self, task: &Task) -> Result<()> {
+        self.untrack_running(&task.id);
+        self.handle_task_error(task, anyhow::anyhow!("Worker panicked while processing the task")).await?;
+        if let Some(task) = self.get_task(&task.id).await?.filter(|task| matches!(task.status, TaskStatus::Failed(_))) {
+            if let Err(e) = self.handle_callback(&task).await {
+                error!("Failed to handle callback for task {}: {}", task.id, e);
+            }
+        }
+        Ok(())
+    }
+
     async fn handle_task_error(&self, task: &Task, error: anyhow::Error) -> Result<()> {
-        let mut processing = self.processing_tasks.lock().await;
-        
-        if let Some(info) = processing.get_mut(&task.id) {
-            if info.attempts < task.config.max_retries {
-                info.attempts += 1;
-                warn!("Retrying task {} (attempt {}/{})", task.id, info.attempts, task.config.max_retries);
-                
-                self.storage.update(&task.id, &TaskStatus::Retrying.to_string()).await?;
-            } else {
-                error!("Task {} failed after {} attempts", task.id, info.attempts);
-                
-                self.storage.update(&task.id, &TaskStatus::Failed(error.to_string()).to_string()).await?;
-                
-                processing.remove(&task.id);
+        enum Outcome {
+            Retry { attempts: u32, retry_at: DateTime<Utc> },
+            Exhausted(ProcessingInfo),
+            Unclaimed,
+        }
+
+        let policy = self.retry_policy(&task.config.task_type);
+        let kind = FailureKind::of(&error);
+
+        // decided under the lock, the transitions below notify callbacks and run without it
+        let outcome = {
+            let mut processing = self.processing_tasks.lock().await;
+            match processing.get_mut(&task.id) {
+                Some(info) => {
+                    info.errors.push(error.to_string());
+                    if !policy.is_retryable(kind) {
+                        warn!("Not retrying task {}, {:?} failures are permanent", task.id, kind);
+                    }
+                    if policy.is_retryable(kind) && info.attempts < task.config.max_retries {
+                        info.attempts += 1;
+                        warn!("Retrying task {} (attempt {}/{})", task.id, info.attempts, task.config.max_retries);
+
+                        let delay = policy.delay(info.attempts);
+                        info.status = TaskStatus::Retrying;
+                        let retry_at = Utc::now() + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+                        Outcome::Retry { attempts: info.attempts, retry_at }
+                    } else {
+                        error!("Task {} failed after {} attempts", task.id, info.attempts);
+                        match processing.remove(&task.id) {
+                            Some(info) => Outcome::Exhausted(info),
+                            None => Outcome::Unclaimed,
+                        }
+                    }
+                }
+                None => Outcome::Unclaimed,
+            }
+        };
+
+        match outcome {
+            Outcome::Retry { attempts, retry_at } => {
+                // stored before the status changes, so no scheduler claims the retry early
+                if let Err(e) = self.storage.schedule_retry(&task.id, attempts, retry_at).await {
+                    warn!("Failed to store the retry of task {}: {}", task.id, e);
+                }
+                if let Err(e) = self.transition(&task.id, TaskStatus::Retrying).await {
+                    // cancelled or timed out while it ran, nothing left to retry
+                    warn!("Failed to mark task {} as retrying: {}", task.id, e);
+                    self.processing_tasks.lock().await.remove(&task.id);
+                }
+            }
+            Outcome::Exhausted(info) => {
+                if let Err(e) = self.transition(&task.id, TaskStatus::Failed(error.to_string())).await {
+                    warn!("Failed to mark task {} as failed: {}", task.id, e);
+                }
+                self.notify_dead_letter(task, info.attempts, &info.errors).await;
+            }
+            Outcome::Unclaimed => {
+                if let Err(e) = self.transition(&task.id, TaskStatus::Failed(error.to_string())).await {
+                    // not claimed through `get_next_task`, there is no attempt to retry
+                    warn!("Failed to mark task {} as failed: {}", task.id, e);
+                }
             }
         }
-        
+
         Ok(())
     }
 
-    async fn cleanup_stale_tasks(&self, processing: &mut HashMap<String, ProcessingInfo>) -> Result<()> {
+    // the dead-letter webhook is separate from the task's own callback, undelivered
+    // notifications are kept in the failed callbacks table like any other callback
+    async fn notify_dead_letter(&self, task: &Task, attempts: u32, errors: &[String]) {
+        let Some(url) = &self.dead_letter_url else {
+            return;
+        };
+        let result = self.http_callback(url, &None).on_dead_letter(task, attempts, errors).await;
+        if let Err(e) = self.dead_letter(task, url, result).await {
+            error!("Dead-letter notification of task {} failed: {}", task.id, e);
+        }
+    }
+
+    async fn cleanup_stale_tasks(&self) -> Result<()> {
         let now = Utc::now();
         let mut to_remove = Vec::new();
 
+        // the stale tasks leave the map under the lock, the transitions run without it
+        let mut processing = self.processing_tasks.lock().await;
         for (task_id, info) in processing.iter() {
+            // tasks waiting for a retry are not running
+            if info.status != TaskStatus::Processing {
+                continue;
+            }
             let duration = now - info.started_at;
             if duration.num_minutes() > 30 { // 设置30分钟超时
                 to_remove.push(task_id.clone());
                 warn!("Task {} timed out after {} minutes", task_id, duration.num_minutes());
             }
         }
+        for task_id in &to_remove {
+            processing.remove(task_id);
+        }
+        drop(processing);
 
         for task_id in to_remove {
-            processing.remove(&task_id);
-            self.storage.update(&task_id, &TaskStatus::TimedOut.to_string()).await?;
+            // a task that finished in the meantime keeps its final status
+            if let Err(e) = self.transition(&task_id, TaskStatus::TimedOut).await {
+                warn!("Not timing out task {}: {}", task_id, e);
+            }
         }
 
         Ok(())
     }
 
-    // task status query method
-    pub async fn get_task_status(&self, task_id: &str) -> Result<Option<TaskStatus>> {
-        Ok(self.storage.get(task_id).await?.map(|t| TaskStatus::try_from(t.status).unwrap()))
+    // move a task to a new status, rejecting transitions the status machine does not allow.
+    // every status change of a stored task goes through here
+    pub async fn transition(&self, task_id: &str, to: TaskStatus) -> Result<Task> {
+        self.transition_with(task_id, to, |_| {}).await
     }
 
-    // task stats method
-    pub async fn get_task_stats(&self, pagination: &Pagination) -> Result<TaskStats> {
-        let all_tasks = self.storage.list(pagination).await?;
-        let mut stats = TaskStats::default();
-
-        for model in all_tasks {
-            let task = Task::from(model);
-            match task.status {
-                TaskStatus::Pending => stats.pending += 1,
-                TaskStatus::Processing => stats.processing += 1,
-                TaskStatus::Completed => stats.completed += 1,
-                TaskStatus::Failed(_) => stats.failed += 1,
-                TaskStatus::Retrying => stats.retrying += 1,
-                TaskStatus::TimedOut => stats.timed_out += 1,
-            }
+    // mark a task completed and store its result
+    pub async fn complete_task(&self, task_id: &str, result: TaskResult) -> Result<Task> {
+        let task = self.transition_with(task_id, TaskStatus::Completed, |task| task.result = Some(result)).await?;
+        self.processing_tasks.lock().await.remove(task_id);
+        Ok(task)
+    }
+
+    async fn transition_with<F>(&self, task_id: &str, to: TaskStatus, update: F) -> Result<Task>
+    where
+        F: FnOnce(&mut Task),
+    {
+        let mut task: Task = self.storage.get(task_id).await?
+            .ok_or_else(|| TaskManagerError::NotFound(task_id.to_string()))?
+            .into();
+
+        if !task.status.can_transition_to(&to) {
+            return Err(InvalidTransition {
+                task_id: task_id.to_string(),
+                from: task.status,
+                to,
+            }.into());
         }
 
-        Ok(stats)
+        let now = Utc::now();
+        match &to {
+            TaskStatus::Processing => task.started_at = Some(now),
+            TaskStatus::Completed => task.completed_at = Some(now),
+            TaskStatus::Failed(error) => task.error = Some(error.clone()),
+            _ => {}
+        }
+        task.status = to;
+        task.updated_at = now;
+        update(&mut task);
+
+        // the status check is part of the update, so a concurrent transition (a cancel racing
+        // the worker's completion) can't be overwritten by this one
+        let allowed: Vec<&str> = TaskStatus::ALL.iter()
+            .filter(|from| from.can_transition_to(&task.status))
+            .map(TaskStatus::name)
+            .collect();
+        if !self.storage.update_if_status(&task.clone().into(), &allowed).await? {
+            let current: Task = self.storage.get(task_id).await?
+                .ok_or_else(|| TaskManagerError::NotFound(task_id.to_string()))?
+                .into();
+            return Err(InvalidTransition {
+                task_id: task_id.to_string(),
+                from: current.status,
+                to: task.status,
+            }.into());
+        }
+        match task.status {
+            TaskStatus::Completed => metrics::task_completed(&task.config.task_type),
+            TaskStatus::Failed(_) => metrics::task_failed(&task.config.task_type),
+            _ => {}
+        }
+        self.notify_status_change(&task).await;
+        Ok(task)
+    }
+
+    // completed and failed tasks are reported by `handle_callback` together with their result
+    // or error, retries only when the task asked for it. new tasks are stored as pending
+    // directly, so a pending task here is a retry going back to the queue
+    async fn notify_status_change(&self, task: &Task) {
+        let notify = match task.status {
+            TaskStatus::Completed | TaskStatus::Failed(_) => false,
+            TaskStatus::Retrying | TaskStatus::Pending => task.config.notify_on_retry,
+            _ => true,
+        };
+        if !notify {
+            return;
+        }
+        if let Err(e) = self.handle_status_change(task, task.status.clone()).await {
+            warn!("Failed to send status change of task {} to {}: {}", task.id, task.status, e);
+        }
+    }
+
+    // number of tasks waiting for a worker
+    pub async fn queue_depth(&self) -> Result<u64> {
+        self.storage.count_by_status(TaskStatus::Pending.name()).await
+    }
+
+    pub async fn get_task_status(&self, task_id: &str) -> Result<Option<TaskStatus>> {
+        // the stored status is only the variant name, the task mapping restores a failure's
+        // reason from the error column
+        Ok(self.get_task(task_id).await?.map(|task| task.status))
+    }
+
+    // one page of the tasks matching the filter, oldest first, with the number of matches across all pages
+    pub async fn list_tasks(&self, pagination: &Pagination, filter: &TaskFilter) -> Result<TaskPage> {
+        let pagination = pagination.check();
+        let models = self.storage.list_filtered(&pagination, filter).await?;
+        let total = self.storage.count_filtered(filter).await?;
+        Ok(TaskPage {
+            tasks: models.into_iter().map(Task::from).collect(),
+            total,
+            index: pagination.index,
+            size: pagination.size,
+        })
+    }
+
+    // task stats method
+    // number of tasks in each status matching `filter`, counted by the database
+    pub async fn get_task_stats(&self, filter: &TaskFilter) -> Result<TaskStats> {
+        let count = |status: TaskStatus| async move {
+            let filter = TaskFilter { status: Some(status.name().to_string()), ..filter.clone() };
+            self.storage.count_filtered(&filter).await.map(|count| count as usize)
+        };
+        Ok(TaskStats {
+            pending: count(TaskStatus::Pending).await?,
+            processing: count(TaskStatus::Processing).await?,
+            completed: count(TaskStatus::Completed).await?,
+            failed: count(TaskStatus::Failed(String::new())).await?,
+            retrying: count(TaskStatus::Retrying).await?,
+            timed_out: count(TaskStatus::TimedOut).await?,
+            cancelled: count(TaskStatus::Cancelled).await?,
+        })
     }
 
     // task cleanup method
@@ -207,11 +601,8 @@ impl TaskManager {
         let cutoff = Utc::now() - chrono::Duration::days(retention_days);
         let mut stats = CleanupStats::default();
 
-        // clean up completed tasks
-        stats.completed = self.storage.cleanup_old(cutoff).await?;
-
-        // clean up failed tasks
-        let failed_tasks = self.storage.get_by_status(&TaskStatus::Failed("".into()).to_string()).await?;
+        // clean up failed tasks first, `cleanup_old` removes both statuses
+        let failed_tasks = self.storage.get_by_status(TaskStatus::Failed(String::new()).name()).await?;
         for task in failed_tasks {
             if task.updated_at < cutoff {
                 self.storage.delete(&task.id).await?;
@@ -219,19 +610,39 @@ impl TaskManager {
             }
         }
 
+        // clean up completed tasks, their outputs first
+        for model in self.storage.get_by_status(TaskStatus::Completed.name()).await? {
+            if model.updated_at < cutoff {
+                let task = Task::from(model);
+                if let Err(e) = self.remove_output_files(&task) {
+                    warn!("Failed to remove the outputs of task {}: {}", task.id, e);
+                }
+            }
+        }
+        stats.completed = self.storage.cleanup_old(cutoff).await?;
+
         Ok(stats)
     }
 
     pub async fn handle_callback(&self, task: &Task) -> Result<()> {
+        // subscribers of the task events learn the outcome whatever the callback type,
+        // having no subscriber at all is fine
+        let _ = match task.status {
+            TaskStatus::Completed => self.event_callback.on_complete(task, &task.result.clone().unwrap()).await,
+            TaskStatus::Failed(ref error) => self.event_callback.on_error(task, error).await,
+            _ => Ok(()),
+        };
+
         // handle callback by callback type and complete status change
         match &task.config.callback_type {
-            CallbackType::Http { url } => {
-                let callback = HttpCallback::new(url.clone());
-                match task.status {
-                    TaskStatus::Completed => callback.on_complete(task, &task.result.clone().unwrap()).await?,
-                    TaskStatus::Failed(ref error) => callback.on_error(task, error).await?,
+            CallbackType::Http { url, secret } => {
+                let callback = self.http_callback(url, secret);
+                let result = match task.status {
+                    TaskStatus::Completed => callback.on_complete(task, &task.result.clone().unwrap()).await,
+                    TaskStatus::Failed(ref error) => callback.on_error(task, error).await,
                     _ => return Ok(()),
-                }
+                };
+                self.dead_letter(task, url, result).await?;
             }
             CallbackType::Function { name } => {
                 let callback = self.get_function_callback(name)?;
@@ -241,19 +652,58 @@ impl TaskManager {
                     _ => return Ok(()),
                 }
             }
-            CallbackType::Event => {
-                let callback = self.event_callback.clone();
-                match task.status {
-                    TaskStatus::Completed => callback.on_complete(task, &task.result.clone().unwrap()).await?,
-                    TaskStatus::Failed(ref error) => callback.on_error(task, error).await?,
-                    _ => return Ok(()),
-                }
-            }
-            CallbackType::None => return Ok(()),
+            // already published above
+            CallbackType::Event | CallbackType::None => return Ok(()),
         }
         Ok(())
     }
 
+    pub async fn handle_status_change(&self, task: &Task, status: TaskStatus) -> Result<()> {
+        // subscribers of the task events see every status change, like the outcome in `handle_callback`
+        if !matches!(task.config.callback_type, CallbackType::Event) {
+            let _ = self.event_callback.on_status_change(task, status.clone()).await;
+        }
+
+        // notify the configured callback about an intermediate status change
+        match &task.config.callback_type {
+            CallbackType::Http { url, secret } => {
+                let result = self.http_callback(url, secret).on_status_change(task, status).await;
+                self.dead_letter(task, url, result).await
+            }
+            CallbackType::Function { name } => {
+                self.get_function_callback(name)?.on_status_change(task, status).await
+            }
+            CallbackType::Event => self.event_callback.on_status_change(task, status).await,
+            CallbackType::None => Ok(()),
+        }
+    }
+
+    fn http_callback(&self, url: &str, secret: &Option<String>) -> HttpCallback {
+        HttpCallback::new(url.to_string())
+            .with_secret(secret.clone())
+            .with_retry(self.callback_retry.clone())
+            .with_timeouts(self.callback_timeouts.0, self.callback_timeouts.1)
+            .with_max_payload_size(self.callback_max_payload, self.public_url.clone())
+    }
+
+    // keep a callback that failed after all retries so an operator can replay it
+    async fn dead_letter(&self, task: &Task, url: &str, result: Result<()>) -> Result<()> {
+        metrics::callback_delivery(result.is_ok());
+        if let Err(e) = &result {
+            if let Some(undelivered) = e.downcast_ref::<UndeliveredCallback>() {
+                warn!("Callback of task {} to {} was not delivered: {}", task.id, url, undelivered.error);
+                self.storage
+                    .save_failed_callback(&task.id, url, &undelivered.payload, &undelivered.error)
+                    .await?;
+            }
+        }
+        result
+    }
+
+    pub async fn list_failed_callbacks(&self, pagination: &Pagination) -> Result<Vec<FailedCallback>> {
+        self.storage.list_failed_callbacks(pagination).await
+    }
+
     pub fn register_function_callback<F>(&mut self, name: &str, callback: F)
     where
         F: Fn(&Task, &str) -> Result<()> + Send + Sync + Clone + 'static,
@@ -276,27 +726,245 @@ impl TaskManager {
         
         for task in timed_out_tasks {
             info!("Handling timed out task: {}", task.id);
-            self.storage.update(&task.id, &TaskStatus::TimedOut.to_string()).await?;
+            // the worker enforces the timeout itself, whichever of the two comes first wins
+            match self.transition(&task.id, TaskStatus::TimedOut).await {
+                Ok(task) => {
+                    self.processing_tasks.lock().await.remove(&task.id);
+                    self.abort_running(&task).await;
+                }
+                Err(e) => warn!("Not timing out task {}: {}", task.id, e),
+            }
         }
         
         Ok(())
     }
 
+    // called by a worker whose task ran past its timeout, the task was already dropped
+    pub async fn time_out_task(&self, task: &Task) -> Result<()> {
+        let task = match self.transition(&task.id, TaskStatus::TimedOut).await {
+            Ok(task) => task,
+            // cancelled or timed out by the sweep in the meantime
+            Err(e) if e.is::<InvalidTransition>() => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        self.processing_tasks.lock().await.remove(&task.id);
+        self.cancel_in_processor(&task).await;
+        Ok(())
+    }
+
+    // cancel all outstanding tasks carrying the given label
+    pub async fn cancel_tasks_by_label(&self, label: &str) -> Result<u64> {
+        let models = self.storage.get_by_config_field("label", label).await?;
+        self.cancel_outstanding(models).await
+    }
+
+    // cancel all outstanding tasks submitted with the api key of the given id
+    pub async fn cancel_tasks_by_api_key(&self, key_id: &str) -> Result<u64> {
+        let models = self.storage.get_by_config_field("api_key", key_id).await?;
+        self.cancel_outstanding(models).await
+    }
+
+    async fn cancel_outstanding(&self, models: Vec<TaskModel>) -> Result<u64> {
+        let mut cancelled = 0;
+
+        for model in models {
+            let status = match model.task_status() {
+                Ok(status) => status,
+                Err(_) => continue,
+            };
+            if !status.can_transition_to(&TaskStatus::Cancelled) {
+                continue;
+            }
+
+            info!("Cancelling task {}", model.id);
+            let task = match self.transition(&model.id, TaskStatus::Cancelled).await {
+                Ok(task) => task,
+                // finished between the lookup and the update
+                Err(e) if e.is::<InvalidTransition>() => continue,
+                Err(e) => return Err(e),
+            };
+            self.processing_tasks.lock().await.remove(&model.id);
+            self.abort_running(&task).await;
+            cancelled += 1;
+        }
+
+        Ok(cancelled)
+    }
+
+    // cancel one task. a pending task is cancelled before it ever starts, a running one is
+    // aborted by its worker. returns None when the task does not exist and an
+    // `InvalidTransition` error when it already finished
+    pub async fn cancel_task(&self, task_id: &str) -> Result<Option<Task>> {
+        if self.storage.get(task_id).await?.is_none() {
+            return Ok(None);
+        }
+
+        info!("Cancelling task {}", task_id);
+        let task = self.transition(task_id, TaskStatus::Cancelled).await?;
+        self.processing_tasks.lock().await.remove(task_id);
+        self.abort_running(&task).await;
+        Ok(Some(task))
+    }
+
+    // delete a task together with everything stored for it: the audio file, its chunk
+    // checkpoints and undelivered callbacks. an outstanding task is cancelled first so its
+    // worker does not write it back. returns None when the task does not exist
+    pub async fn purge_task(&self, task_id: &str) -> Result<Option<PurgeSummary>> {
+        let task: Task = match self.storage.get(task_id).await? {
+            Some(model) => model.into(),
+            None => return Ok(None),
+        };
+
+        info!("Purging task {}", task_id);
+        if task.status.can_transition_to(&TaskStatus::Cancelled) {
+            match self.transition(task_id, TaskStatus::Cancelled).await {
+                Ok(task) => self.abort_running(&task).await,
+                // finished between the lookup and the update
+                Err(e) if e.is::<InvalidTransition>() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.processing_tasks.lock().await.remove(task_id);
+
+        // the row goes last, a purge that failed half way can be run again
+        let input_path = &task.config.input_path;
+        let (audio_file, skipped_audio_file) = match file_within(input_path, &self.audio_dir) {
+            Some(path) => {
+                let removed = remove_file_if_exists(&path)
+                    .map_err(|e| anyhow::anyhow!("Failed to remove audio file {}: {}", path.display(), e))?;
+                (removed, None)
+            }
+            None if input_path.exists() => {
+                warn!("Not removing audio file {} of task {}, it is outside {}", input_path.display(), task_id, self.audio_dir.display());
+                (false, Some(input_path.clone()))
+            }
+            None => (false, None),
+        };
+        let output_files = self.remove_output_files(&task)?;
+        let records = match self.processors.get(&task.config.task_type) {
+            Some(processor) => processor.purge(&task).await?,
+            None => 0,
+        };
+        let checkpoints = self.storage.get_checkpoints(task_id).await?.len() as u64;
+        self.storage.delete_checkpoints(task_id).await?;
+        let failed_callbacks = self.storage.delete_failed_callbacks(task_id).await?;
+        self.storage.delete(task_id).await?;
+
+        Ok(Some(PurgeSummary {
+            task_id: task_id.to_string(),
+            audio_file,
+            skipped_audio_file,
+            output_files,
+            records,
+            checkpoints,
+            failed_callbacks,
+        }))
+    }
+
+    // called once the workers have stopped. tasks waiting for a retry keep their status and
+    // `retry_at` in storage, the next start claims them once they are due
+    pub async fn shutdown(&self) -> Result<()> {
+        let mut processing = self.processing_tasks.lock().await;
+        let unfinished: Vec<&String> = processing.iter()
+            .filter(|(_, info)| info.status == TaskStatus::Processing)
+            .map(|(task_id, _)| task_id)
+            .collect();
+        if !unfinished.is_empty() {
+            warn!("Shutting down with tasks still processing: {:?}", unfinished);
+        }
+        processing.clear();
+
+        info!("TaskManager shut down");
+        Ok(())
+    }
+
+    // register the abort handle of a task a worker is about to run
+    pub fn track_running(&self, task_id: &str, handle: AbortHandle) {
+        self.running.lock().unwrap_or_else(|e| e.into_inner()).insert(task_id.to_string(), handle);
+    }
+
+    pub fn untrack_running(&self, task_id: &str) {
+        self.running.lock().unwrap_or_else(|e| e.into_inner()).remove(task_id);
+    }
+
+    // stop the worker running a cancelled task and let its processor clean up.
+    // tasks running in another process are only marked, their worker drops the result
+    async fn abort_running(&self, task: &Task) {
+        let handle = self.running.lock().unwrap_or_else(|e| e.into_inner()).remove(&task.id);
+        let Some(handle) = handle else { return };
+        handle.abort();
+        self.cancel_in_processor(task).await;
+    }
+
+    // remove what the processor wrote for a task, returns how many files were still on disk
+    fn remove_output_files(&self, task: &Task) -> Result<u64> {
+        let Some(processor) = self.processors.get(&task.config.task_type) else {
+            return Ok(0);
+        };
+        let mut removed = 0;
+        for path in processor.output_files(task) {
+            if remove_file_if_exists(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to remove output file {}: {}", path.display(), e))?
+            {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn cancel_in_processor(&self, task: &Task) {
+        if let Some(processor) = self.processors.get(&task.config.task_type) {
+            if let Err(e) = processor.cancel(task).await {
+                warn!("Failed to cancel task {} in its processor: {}", task.id, e);
+            }
+        }
+    }
+
     // get task method
     pub async fn get_task(&self, task_id: &str) -> Result<Option<Task>> {
         let model = self.storage.get(task_id).await?;
         Ok(model.map(|m| Task::from(m)))
     }
 
+    // stream the stored result json in chunks, without loading or deserializing it as a whole.
+    // returns None when the task does not exist or has no result yet
+    pub async fn get_task_result_stream(
+        &self,
+        task_id: &str,
+    ) -> Result<Option<impl Stream<Item = Result<Vec<u8>>> + Send + 'static>> {
+        let len = match self.storage.get_result_len(task_id).await? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        let storage = self.storage.clone();
+        let task_id = task_id.to_string();
+        Ok(Some(futures::stream::try_unfold(0u64, move |offset| {
+            let storage = storage.clone();
+            let task_id = task_id.clone();
+            async move {
+                if offset >= len {
+                    return Ok(None);
+                }
+                let chunk = storage.read_result_chunk(&task_id, offset, RESULT_STREAM_CHUNK_SIZE).await?;
+                if chunk.is_empty() {
+                    return Ok(None);
+                }
+                let next = offset + chunk.len() as u64;
+                Ok(Some((chunk, next)))
+            }
+        })))
+    }
+
     // update task priority method
     pub async fn update_task_priority(&self, task_id: &str, new_priority: TaskPriority) -> Result<()> {
         let model = self.storage.get(task_id).await?
-            .ok_or_else(|| anyhow::anyhow!("Task not found"))?;
+            .ok_or_else(|| TaskManagerError::NotFound(task_id.to_string()))?;
         let task = Task::from(model);
 
         // only allow to adjust priority of pending tasks
         if task.status != TaskStatus::Pending {
-            return Err(anyhow::anyhow!("Can only adjust priority of pending tasks"));
+            return Err(TaskManagerError::NotPending(task_id.to_string()).into());
         }
         
         let mut task = task.clone();
@@ -312,6 +980,15 @@ impl TaskManager {
     }
 }
 
+// false when the file was already gone
+fn remove_file_if_exists(path: &std::path::Path) -> std::io::Result<bool> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct TaskStats {
     pub pending: usize,
@@ -320,6 +997,16 @@ pub struct TaskStats {
     pub failed: usize,
     pub retrying: usize,
     pub timed_out: usize,
+    pub cancelled: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskPage {
+    pub tasks: Vec<Task>,
+    // matching tasks across all pages
+    pub total: u64,
+    pub index: u64,
+    pub size: u64,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -328,16 +1015,692 @@ pub struct CleanupStats {
     pub failed: u64,
 }
 
+// what `purge_task` deleted besides the task itself
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PurgeSummary {
+    pub task_id: String,
+    // whether the audio file was still on disk
+    pub audio_file: bool,
+    // an audio file outside the audio directory, left in place
+    pub skipped_audio_file: Option<PathBuf>,
+    // outputs the processor had written for the task, such as a denoised wav
+    pub output_files: u64,
+    // records the processor kept for the task, such as the voiceprint of an enrollment
+    pub records: u64,
+    pub checkpoints: u64,
+    pub failed_callbacks: u64,
+}
+
 // implement Drop trait for TaskManager to ensure resources are cleaned up correctly
 impl Drop for TaskManager {
     fn drop(&mut self) {
+        // the async cleanup happens in `shutdown`, which can't run from here
         info!("TaskManager is being dropped, cleaning up resources...");
     }
 }
 
-impl Clone for EventCallback {
-    fn clone(&self) -> Self {
-        let (sender, _) = tokio::sync::broadcast::channel(10);
-        Self { sender }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::test_support::{self, StubProcessor};
+    use crate::schedule::types::{TaskParams, TranscribeResult};
+    use crate::storage::task::sqlite::SqliteTaskStorage;
+
+    #[tokio::test]
+    async fn test_run_inline() {
+        let processor = StubProcessor::new(TaskType::Transcribe)
+            .with_delay(std::time::Duration::from_millis(50));
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        task_manager.register_processor(Box::new(processor.clone()));
+
+        let config = create_test_task(CallbackType::None, false).config;
+        let result = task_manager.run_inline(config.clone(), std::time::Duration::from_secs(2)).await.unwrap();
+        assert_eq!(result.as_transcribe().unwrap().text, "done");
+        // nothing was stored
+        assert_eq!(task_manager.storage.count_filtered(&TaskFilter::default()).await.unwrap(), 0);
+
+        let err = task_manager.run_inline(config, std::time::Duration::from_millis(10)).await.unwrap_err();
+        assert!(err.downcast_ref::<tokio::time::error::Elapsed>().is_some());
+
+        // the input is cleaned up whether the task finished or not
+        assert_eq!(processor.cleanups(), 2);
+    }
+
+    fn create_test_task(callback_type: CallbackType, notify_on_retry: bool) -> Task {
+        test_support::task(TaskConfig {
+            callback_type,
+            max_retries: 3,
+            notify_on_retry,
+            ..test_support::task_config()
+        })
+    }
+
+    async fn setup_task_manager(notifications: Arc<std::sync::Mutex<Vec<String>>>) -> TaskManager {
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        task_manager.register_processor(Box::new(StubProcessor::new(TaskType::Transcribe)
+            .failing(|| anyhow::anyhow!("processing failed"))));
+        task_manager.register_function_callback("record", move |_task: &Task, message: &str| {
+            notifications.lock().unwrap().push(message.to_string());
+            Ok(())
+        });
+        task_manager
+    }
+
+    async fn start_processing(task_manager: &TaskManager, task: &Task) {
+        task_manager.storage.create(&TaskModel::from(task.clone())).await.unwrap();
+        task_manager.transition(&task.id, TaskStatus::Processing).await.unwrap();
+        task_manager.processing_tasks.lock().await.insert(task.id.clone(), ProcessingInfo {
+            status: TaskStatus::Processing,
+            started_at: Utc::now(),
+            attempts: 1,
+            errors: Vec::new(),
+        });
+    }
+
+    #[tokio::test]
+    async fn test_notify_on_retry() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications.clone()).await;
+        let task = create_test_task(CallbackType::Function { name: "record".to_string() }, true);
+        start_processing(&task_manager, &task).await;
+
+        // two failures are retried, the third one is terminal
+        fail_attempts(&task_manager, &task, 3).await;
+
+        let notifications = notifications.lock().unwrap();
+        let retries = notifications.iter().filter(|n| n.contains("Retrying")).count();
+        assert_eq!(retries, 2);
+        assert!(!task_manager.processing_tasks.lock().await.contains_key(&task.id));
+    }
+
+    #[tokio::test]
+    async fn test_retry_notifications_off_by_default() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications.clone()).await;
+        let task = create_test_task(CallbackType::Function { name: "record".to_string() }, false);
+        start_processing(&task_manager, &task).await;
+
+        fail_attempts(&task_manager, &task, 3).await;
+
+        assert!(!notifications.lock().unwrap().iter().any(|n| n.contains("Retrying")));
+    }
+
+    #[tokio::test]
+    async fn test_abandon_task_of_a_panicked_worker() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+        let mut task = create_test_task(CallbackType::None, false);
+        task.config.max_retries = 0;
+        start_processing(&task_manager, &task).await;
+        let (abort, _registration) = AbortHandle::new_pair();
+        task_manager.track_running(&task.id, abort);
+
+        task_manager.abandon_task(&task).await.unwrap();
+
+        let status = task_manager.get_task_status(&task.id).await.unwrap().unwrap();
+        assert!(matches!(status, TaskStatus::Failed(_)));
+        assert!(!task_manager.processing_tasks.lock().await.contains_key(&task.id));
+        assert!(!task_manager.running.lock().unwrap().contains_key(&task.id));
+    }
+
+    async fn fail_attempts(task_manager: &TaskManager, task: &Task, attempts: u32) {
+        for attempt in 0..attempts {
+            // a retried task is picked up again before its next attempt
+            if attempt > 0 {
+                task_manager.transition(&task.id, TaskStatus::Processing).await.unwrap();
+            }
+            assert!(task_manager.process_task(task).await.is_err());
+        }
+        let status = task_manager.get_task_status(&task.id).await.unwrap().unwrap();
+        assert!(matches!(status, TaskStatus::Failed(_)));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_tasks_by_label() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+
+        let mut ids = Vec::new();
+        for label in ["project-a", "project-a", "project-a", "project-b"] {
+            let mut config = create_test_task(CallbackType::None, false).config;
+            config.label = Some(label.to_string());
+            ids.push(task_manager.create_task(config).await.unwrap().id);
+        }
+        // a finished task of the same project is left alone
+        task_manager.storage.update(&ids[2], TaskStatus::Completed.name()).await.unwrap();
+
+        let cancelled = task_manager.cancel_tasks_by_label("project-a").await.unwrap();
+        assert_eq!(cancelled, 2);
+
+        let statuses: Vec<TaskStatus> = task_statuses(&task_manager, &ids).await;
+        assert_eq!(statuses, vec![
+            TaskStatus::Cancelled,
+            TaskStatus::Cancelled,
+            TaskStatus::Completed,
+            TaskStatus::Pending,
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_task_stats_count_every_task() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+
+        let mut ids = Vec::new();
+        for _ in 0..15 {
+            let config = create_test_task(CallbackType::None, false).config;
+            ids.push(task_manager.create_task(config).await.unwrap().id);
+        }
+        task_manager.storage.update(&ids[0], TaskStatus::Completed.name()).await.unwrap();
+        task_manager.storage.update(&ids[1], TaskStatus::Failed(String::new()).name()).await.unwrap();
+
+        // more tasks than one default page
+        let stats = task_manager.get_task_stats(&TaskFilter::default()).await.unwrap();
+        assert_eq!(stats.pending, 13);
+        assert_eq!(stats.completed, 1);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.processing, 0);
+
+        // scoped to the tasks of one api key
+        let mut config = create_test_task(CallbackType::None, false).config;
+        config.api_key = Some("kid-a".to_string());
+        task_manager.create_task(config).await.unwrap();
+        let filter = TaskFilter { api_key: Some("kid-a".to_string()), ..Default::default() };
+        let stats = task_manager.get_task_stats(&filter).await.unwrap();
+        assert_eq!(stats.pending, 1);
+        assert_eq!(stats.completed, 0);
+        assert_eq!(stats.failed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_tasks_counts_each_status() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+        let old = Utc::now() - chrono::Duration::days(8);
+
+        let mut ids = Vec::new();
+        for (status, updated_at) in [
+            (TaskStatus::Completed, old),
+            // the error message mentions a status, only the status column counts
+            (TaskStatus::Failed("Completed 3 of 4 chunks".to_string()), old),
+            (TaskStatus::Failed("disk full".to_string()), Utc::now()),
+            (TaskStatus::Pending, old),
+        ] {
+            let mut task = create_test_task(CallbackType::None, false);
+            task.status = status;
+            task.updated_at = updated_at;
+            task_manager.storage.create(&TaskModel::from(task.clone())).await.unwrap();
+            ids.push(task.id);
+        }
+
+        let stats = task_manager.cleanup_tasks(7).await.unwrap();
+        assert_eq!(stats.completed, 1);
+        assert_eq!(stats.failed, 1);
+        assert!(task_manager.get_task(&ids[0]).await.unwrap().is_none());
+        assert!(task_manager.get_task(&ids[1]).await.unwrap().is_none());
+        assert!(task_manager.get_task(&ids[2]).await.unwrap().is_some());
+        assert!(task_manager.get_task(&ids[3]).await.unwrap().is_some());
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_create_tasks_batch() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+
+        // no processor is registered for noise reduction, so the second config is rejected
+        let mut invalid = create_test_task(CallbackType::None, false).config;
+        invalid.task_type = TaskType::NoiseReduction;
+        let configs = vec![
+            create_test_task(CallbackType::None, false).config,
+            invalid,
+            create_test_task(CallbackType::None, false).config,
+        ];
+
+        let results = task_manager.create_tasks(configs).await;
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_err());
+
+        let ids: Vec<String> = results.iter()
+            .filter_map(|result| result.as_ref().ok())
+            .map(|task| task.id.clone())
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(task_statuses(&task_manager, &ids).await, vec![TaskStatus::Pending, TaskStatus::Pending]);
+    }
+
+    async fn task_statuses(task_manager: &TaskManager, ids: &[String]) -> Vec<TaskStatus> {
+        let mut statuses = Vec::new();
+        for id in ids {
+            statuses.push(task_manager.get_task(id).await.unwrap().unwrap().status);
+        }
+        statuses
+    }
+
+    #[tokio::test]
+    async fn test_get_task_result_stream() {
+        use futures::TryStreamExt;
+        use crate::schedule::types::{TranscribeResult, TranscribeSegment};
+
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+
+        // a result spanning several stream chunks, with multi-byte characters
+        let mut task = create_test_task(CallbackType::None, false);
+        task.status = TaskStatus::Completed;
+        task.result = Some(TaskResult::Transcribe(TranscribeResult {
+            text: "语音识别 ".repeat(40_000),
+            segments: (0..2_000).map(|i| TranscribeSegment {
+                text: format!("segment {} 片段", i),
+                speaker_id: Some(0),
+                start_time: i as f64 * 100.0,
+                end_time: (i + 1) as f64 * 100.0,
+                words: Vec::new(),
+                confidence: None,
+            }).collect(),
+            detected_language: "zh".to_string(),
+            language: "zh".to_string(),
+            language_probability: 1.0,
+            ..Default::default()
+        }));
+        let model = TaskModel::from(task.clone());
+        let stored = model.result.clone().unwrap();
+        assert!(stored.len() as u64 > RESULT_STREAM_CHUNK_SIZE * 3);
+        task_manager.storage.create(&model).await.unwrap();
+
+        let stream = task_manager.get_task_result_stream(&task.id).await.unwrap().unwrap();
+        let chunks: Vec<Vec<u8>> = stream.try_collect().await.unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), stored.into_bytes());
+
+        // tasks without a result have nothing to stream
+        let pending = create_test_task(CallbackType::None, false);
+        task_manager.storage.create(&TaskModel::from(pending.clone())).await.unwrap();
+        assert!(task_manager.get_task_result_stream(&pending.id).await.unwrap().is_none());
+        assert!(task_manager.get_task_result_stream("missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_task_filters_events() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+
+        let mut receiver = task_manager.subscribe_task("task-a");
+        let events = task_manager.events();
+        for (task_id, percent) in [("task-b", 10), ("task-a", 20), ("task-b", 30), ("task-a", 40)] {
+            events.send(TaskEvent::Progress { task_id: task_id.to_string(), percent }).unwrap();
+        }
+        events.send(TaskEvent::Failed { task_id: "task-a".to_string(), error: "boom".to_string() }).unwrap();
+
+        let mut received = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            assert_eq!(event.task_id(), "task-a");
+            match event {
+                TaskEvent::Progress { percent, .. } => received.push(percent),
+                TaskEvent::Failed { .. } => break,
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+        assert_eq!(received, vec![20, 40]);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_receives_events_of_every_task() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+        let mut receiver = task_manager.subscribe();
+
+        // status changes are published whatever the callback type
+        let first = create_test_task(CallbackType::None, false);
+        let second = create_test_task(CallbackType::Function { name: "record".to_string() }, false);
+        for task in [&first, &second] {
+            task_manager.storage.create(&TaskModel::from(task.clone())).await.unwrap();
+            task_manager.transition(&task.id, TaskStatus::Processing).await.unwrap();
+        }
+
+        // the copy of the event callback the manager publishes through shares the channel
+        task_manager.event_callback.clone().on_error(&first, "boom").await.unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let event = tokio::time::timeout(std::time::Duration::from_secs(1), receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            received.push(serde_json::to_value(&event).unwrap());
+        }
+        assert_eq!(received, vec![
+            serde_json::json!({"event": "status_changed", "task_id": first.id, "status": "Processing"}),
+            serde_json::json!({"event": "status_changed", "task_id": second.id, "status": "Processing"}),
+            serde_json::json!({"event": "failed", "task_id": first.id, "error": "boom"}),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_event_callback_task_completion_reaches_subscribers() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+        let mut receiver = task_manager.subscribe_task("task-event");
+
+        let mut task = create_test_task(CallbackType::Event, false);
+        task.id = "task-event".to_string();
+        task_manager.storage.create(&TaskModel::from(task.clone())).await.unwrap();
+        task_manager.transition(&task.id, TaskStatus::Processing).await.unwrap();
+        let task = task_manager.complete_task(&task.id, TaskResult::Transcribe(TranscribeResult {
+            text: "done".to_string(),
+            detected_language: "en".to_string(),
+            language_probability: 1.0,
+            language: "en".to_string(),
+            ..Default::default()
+        })).await.unwrap();
+        // the worker hands the outcome to the callback like this
+        task_manager.handle_callback(&task).await.unwrap();
+
+        let mut completed = None;
+        while let Some(event) = tokio::time::timeout(std::time::Duration::from_secs(1), receiver.recv())
+            .await
+            .unwrap()
+        {
+            if let TaskEvent::Completed { result, .. } = event {
+                completed = Some(result);
+                break;
+            }
+        }
+        assert_eq!(completed.unwrap().as_transcribe().unwrap().text, "done");
+    }
+
+    fn fast_retry(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(10),
+            jitter: false,
+        }
+    }
+
+    async fn serve(router: axum::Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_callback_retried_until_delivered() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use axum::{http::StatusCode, routing::post};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let base = serve(axum::Router::new().route("/callback", post(move || async move {
+            // the receiver is briefly down
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            }
+        }))).await;
+
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await.with_callback_retry(fast_retry(3));
+        let mut task = create_test_task(
+            CallbackType::Http { url: format!("{}/callback", base), secret: None },
+            false,
+        );
+        task.status = TaskStatus::Failed("boom".to_string());
+
+        task_manager.handle_callback(&task).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        assert!(task_manager.list_failed_callbacks(&Pagination::default()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_undelivered_callback_is_dead_lettered() {
+        // nothing listens on the port once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/callback", listener.local_addr().unwrap());
+        drop(listener);
+
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await.with_callback_retry(fast_retry(2));
+        let mut task = create_test_task(CallbackType::Http { url: url.clone(), secret: None }, false);
+        task.status = TaskStatus::Failed("boom".to_string());
+
+        let err = task_manager.handle_callback(&task).await.unwrap_err();
+        assert!(err.downcast_ref::<UndeliveredCallback>().is_some());
+
+        let failed = task_manager.list_failed_callbacks(&Pagination::default()).await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].task_id, task.id);
+        assert_eq!(failed[0].url, url);
+        let payload: serde_json::Value = serde_json::from_str(&failed[0].payload).unwrap();
+        assert_eq!(payload["task_id"], task.id);
+        assert_eq!(payload["data"], "boom");
+    }
+
+    #[tokio::test]
+    async fn test_status_transitions() {
+        use crate::schedule::types::TranscribeResult;
+
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+        let task = create_test_task(CallbackType::None, false);
+        task_manager.storage.create(&TaskModel::from(task.clone())).await.unwrap();
+
+        // a pending task can't skip processing
+        let err = task_manager.transition(&task.id, TaskStatus::Completed).await.unwrap_err();
+        assert!(err.downcast_ref::<InvalidTransition>().is_some());
+
+        let processing = task_manager.transition(&task.id, TaskStatus::Processing).await.unwrap();
+        assert_eq!(processing.status, TaskStatus::Processing);
+        assert!(processing.started_at.is_some());
+
+        let completed = task_manager.complete_task(&task.id, TaskResult::Transcribe(TranscribeResult {
+            text: "done".to_string(),
+            detected_language: "en".to_string(),
+            language_probability: 1.0,
+            language: "en".to_string(),
+            ..Default::default()
+        })).await.unwrap();
+        assert_eq!(completed.status, TaskStatus::Completed);
+        assert!(completed.completed_at.is_some());
+
+        // a completed task stays completed
+        let err = task_manager.transition(&task.id, TaskStatus::Processing).await.unwrap_err();
+        let invalid = err.downcast_ref::<InvalidTransition>().unwrap();
+        assert_eq!(invalid.from, TaskStatus::Completed);
+        assert_eq!(invalid.to, TaskStatus::Processing);
+        assert!(task_manager.transition(&task.id, TaskStatus::TimedOut).await.is_err());
+
+        let stored = task_manager.get_task(&task.id).await.unwrap().unwrap();
+        assert_eq!(stored.status, TaskStatus::Completed);
+        assert_eq!(stored.result.unwrap().as_transcribe().unwrap().text, "done");
+
+        assert!(task_manager.transition("missing", TaskStatus::Processing).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_status_change_posted_when_processing_starts() {
+        use axum::{routing::post, Json};
+
+        let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let base = serve(axum::Router::new().route("/callback", post(move |Json(body): Json<serde_json::Value>| {
+            let sender = sender.clone();
+            async move { sender.send(body).unwrap(); }
+        }))).await;
+
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+        let config = create_test_task(
+            CallbackType::Http { url: format!("{}/callback", base), secret: None },
+            false,
+        ).config;
+        let task = task_manager.create_task(config).await.unwrap();
+
+        let next = task_manager.get_next_task(&TaskType::Transcribe).await.unwrap().unwrap();
+        assert_eq!(next.id, task.id);
+
+        let body = received.recv().await.unwrap();
+        assert_eq!(body["task_id"], task.id);
+        assert_eq!(body["status"], "Processing");
+        assert_eq!(body["data"], "Processing");
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_sent_once_retries_are_exhausted() {
+        use axum::{routing::post, Json};
+
+        let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let base = serve(axum::Router::new().route("/dead-letter", post(move |Json(body): Json<serde_json::Value>| {
+            let sender = sender.clone();
+            async move { sender.send(body).unwrap(); }
+        }))).await;
+
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await
+            .with_dead_letter_url(Some(format!("{}/dead-letter", base)));
+        let task = create_test_task(CallbackType::Function { name: "record".to_string() }, false);
+        start_processing(&task_manager, &task).await;
+
+        fail_attempts(&task_manager, &task, 3).await;
+
+        let body = received.recv().await.unwrap();
+        assert_eq!(body["task_id"], task.id);
+        assert_eq!(body["status"]["Failed"], "processing failed");
+        assert_eq!(body["data"]["attempts"], 3);
+        assert_eq!(body["data"]["errors"], serde_json::json!(["processing failed", "processing failed", "processing failed"]));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_task_removes_all_artifacts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await.with_audio_dir(dir.path().to_path_buf());
+
+        let mut config = create_test_task(CallbackType::None, false).config;
+        config.input_path = dir.path().join("audio.wav");
+        std::fs::write(&config.input_path, b"RIFF").unwrap();
+        let task = task_manager.create_task(config).await.unwrap();
+        for chunk in 0..2 {
+            task_manager.storage.save_checkpoint(&task.id, chunk, "[]").await.unwrap();
+        }
+        task_manager.storage
+            .save_failed_callback(&task.id, "http://localhost:9/callback", "{}", "connection refused")
+            .await
+            .unwrap();
+        // artifacts of other tasks are kept
+        task_manager.storage.save_checkpoint("task-other", 0, "[]").await.unwrap();
+        task_manager.storage
+            .save_failed_callback("task-other", "http://localhost:9/callback", "{}", "connection refused")
+            .await
+            .unwrap();
+
+        let summary = task_manager.purge_task(&task.id).await.unwrap().unwrap();
+        assert_eq!(summary.task_id, task.id);
+        assert!(summary.audio_file);
+        assert_eq!(summary.checkpoints, 2);
+        assert_eq!(summary.failed_callbacks, 1);
+
+        assert!(task_manager.get_task(&task.id).await.unwrap().is_none());
+        assert!(!task.config.input_path.exists());
+        assert!(task_manager.storage.get_checkpoints(&task.id).await.unwrap().is_empty());
+        let failed = task_manager.list_failed_callbacks(&Pagination::default()).await.unwrap();
+        assert_eq!(failed.iter().map(|c| c.task_id.as_str()).collect::<Vec<_>>(), vec!["task-other"]);
+        assert_eq!(task_manager.storage.get_checkpoints("task-other").await.unwrap().len(), 1);
+
+        assert!(task_manager.purge_task(&task.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_purge_keeps_files_outside_the_audio_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let audio_dir = dir.path().join("audio");
+        std::fs::create_dir(&audio_dir).unwrap();
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await.with_audio_dir(audio_dir.clone());
+
+        // reaches out of the audio directory through `..`
+        std::fs::write(dir.path().join("keep.wav"), b"RIFF").unwrap();
+        let mut config = create_test_task(CallbackType::None, false).config;
+        config.input_path = audio_dir.join("..").join("keep.wav");
+        let task = task_manager.create_task(config).await.unwrap();
+
+        let summary = task_manager.purge_task(&task.id).await.unwrap().unwrap();
+        assert!(!summary.audio_file);
+        assert_eq!(summary.skipped_audio_file, Some(task.config.input_path.clone()));
+        assert!(dir.path().join("keep.wav").exists());
+        assert!(task_manager.get_task(&task.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chained_task_needs_an_input_task_of_the_same_key() {
+        let notifications = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let task_manager = setup_task_manager(notifications).await;
+
+        let mut denoise = create_test_task(CallbackType::None, false);
+        denoise.config.task_type = TaskType::NoiseReduction;
+        denoise.config.api_key = Some("kid-a".to_string());
+        task_manager.storage.create(&TaskModel::from(denoise.clone())).await.unwrap();
+
+        let mut config = create_test_task(CallbackType::None, false).config;
+        if let TaskParams::Transcribe(params) = &mut config.params {
+            params.input_task = Some(denoise.id.clone());
+        }
+        config.api_key = Some("kid-b".to_string());
+        let err = task_manager.create_task(config.clone()).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<TaskManagerError>(), Some(TaskManagerError::InvalidParams(_))));
+        let results = task_manager.create_tasks(vec![config.clone()]).await;
+        assert!(results[0].is_err());
+
+        // the input task is still pending, the chained task waits for it in the queue
+        config.api_key = Some("kid-a".to_string());
+        let task = task_manager.create_task(config.clone()).await.unwrap();
+        assert_eq!(task.status, TaskStatus::Pending);
+        assert!(task_manager.get_next_task(&TaskType::Transcribe).await.unwrap().is_none());
+
+        // an input task that failed can't be chained anymore
+        task_manager.storage.update(&denoise.id, TaskStatus::Failed(String::new()).name()).await.unwrap();
+        assert!(task_manager.create_task(config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_and_cleanup_remove_outputs() {
+        use crate::schedule::processors::NoiseReductionProcessor;
+        use crate::schedule::types::NoiseReductionParams;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        task_manager.register_processor(Box::new(NoiseReductionProcessor::new(dir.path().to_path_buf())));
+
+        let mut outputs = Vec::new();
+        for _ in 0..2 {
+            let mut config = create_test_task(CallbackType::None, false).config;
+            config.task_type = TaskType::NoiseReduction;
+            config.params = TaskParams::NoiseReduction(NoiseReductionParams { strength: 0.5, frame_size: 2048, overlap: 0.75 });
+            config.input_path = dir.path().join(format!("{}.mp3", Uuid::new_v4()));
+            let task = task_manager.create_task(config).await.unwrap();
+            let output = dir.path().join(format!("{}.wav", task.id));
+            std::fs::write(&output, b"RIFF").unwrap();
+            outputs.push((task, output));
+        }
+
+        let (purged, output) = &outputs[0];
+        let summary = task_manager.purge_task(&purged.id).await.unwrap().unwrap();
+        assert_eq!(summary.output_files, 1);
+        assert!(!output.exists());
+
+        // a completed task past the retention takes its output along
+        let (completed, output) = &outputs[1];
+        task_manager.transition(&completed.id, TaskStatus::Processing).await.unwrap();
+        task_manager.complete_task(&completed.id, TaskResult::NoiseReduction(crate::schedule::types::NoiseReductionResult {
+            output_path: output.clone(),
+        })).await.unwrap();
+        let stats = task_manager.cleanup_tasks(-1).await.unwrap();
+        assert_eq!(stats.completed, 1);
+        assert!(!output.exists());
+    }
+}
@@ -1,10 +1,13 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::sync::watch;
 use tokio::time::{sleep, Duration};
-use tracing::{info, error};
+use tracing::{info, info_span, warn, error, Instrument};
 use anyhow::Result;
-use chrono::Utc;
+use futures::future::{AbortHandle, Abortable};
 
-use crate::schedule::types::{TaskType, TaskStatus};
+use crate::schedule::types::{InvalidTransition, Task, TaskType, TaskStatus};
+use crate::utils::metrics;
 use super::TaskManager;
 
 pub struct TaskWorker {
@@ -14,6 +17,12 @@ pub struct TaskWorker {
     task_type: TaskType,
     // interval for checking task status. e.g. 1 second
     interval: Duration,
+    // each idle sleep is randomized by up to this fraction of the interval,
+    // so several workers don't poll the storage in lockstep
+    jitter: f64,
+    rng: Mutex<StdRng>,
+    // the task being processed, the scheduler releases it when the worker panics
+    current: Arc<Mutex<Option<Task>>>,
 }
 
 impl TaskWorker {
@@ -22,6 +31,9 @@ impl TaskWorker {
             task_manager,
             task_type,
             interval: Duration::from_secs(1),
+            jitter: 0.0,
+            rng: Mutex::new(StdRng::from_entropy()),
+            current: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -30,54 +42,503 @@ impl TaskWorker {
         self
     }
 
-    pub async fn run(&self) {
-        loop {
-            match self.process_next_task().await {
+    // fraction of the interval, clamped to 0..=1
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+        self
+    }
+
+    pub fn current_task(&self) -> Arc<Mutex<Option<Task>>> {
+        self.current.clone()
+    }
+
+    // interval scaled by a random factor in [1 - jitter, 1 + jitter]
+    fn poll_delay(&self) -> Duration {
+        if self.jitter <= 0.0 {
+            return self.interval;
+        }
+        let factor = self.rng
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .gen_range(1.0 - self.jitter..=1.0 + self.jitter);
+        self.interval.mul_f64(factor)
+    }
+
+    // process tasks until the shutdown signal turns true. a task already started is
+    // finished first, only the waits between tasks are cut short
+    pub async fn run(&self, mut shutdown: watch::Receiver<bool>) {
+        while !*shutdown.borrow() {
+            let delay = match self.process_next_task().await {
                 Ok(true) => continue,  // continue to process next task
-                Ok(false) => sleep(self.interval).await, // no task, wait
+                Ok(false) => self.poll_delay(), // no task, wait
                 Err(e) => {
                     error!("Error processing task: {}", e);
-                    sleep(Duration::from_millis(100)).await;
+                    Duration::from_millis(100)
                 }
+            };
+            tokio::select! {
+                _ = sleep(delay) => {}
+                // the scheduler is gone, nobody is left to stop us
+                changed = shutdown.changed() => if changed.is_err() { break },
             }
         }
+        info!("{} worker stopped", self.task_type);
     }
 
     async fn process_next_task(&self) -> Result<bool> {
         // get next task to process
-        let task = match self.task_manager.get_next_task().await? {
-            Some(task) if task.config.task_type == self.task_type => task,
-            _ => return Ok(false),
+        let task = match self.task_manager.get_next_task(&self.task_type).await? {
+            Some(task) => task,
+            None => return Ok(false),
         };
 
+        // the task's log lines carry the id of the request that created it
+        let span = info_span!(
+            "task",
+            task_id = %task.id,
+            request_id = %task.config.request_id.as_deref().unwrap_or_default(),
+        );
+        self.run_task(task).instrument(span).await?;
+        Ok(true)
+    }
+
+    async fn run_task(&self, task: Task) -> Result<()> {
         info!("Processing {} task: {}", self.task_type, task.id);
 
-        // process task
-        match self.task_manager.process_task(&task).await {
+        // process task, `TaskManager::cancel_task` and the timeout sweep abort it through the handle
+        let (abort, registration) = AbortHandle::new_pair();
+        *self.current.lock().unwrap_or_else(|e| e.into_inner()) = Some(task.clone());
+        self.task_manager.track_running(&task.id, abort);
+        let processing = Abortable::new(self.task_manager.process_task(&task), registration);
+        let started = std::time::Instant::now();
+        let outcome = match task.config.timeout {
+            Some(seconds) => tokio::time::timeout(Duration::from_secs(seconds), processing).await.ok(),
+            None => Some(processing.await),
+        };
+        self.task_manager.untrack_running(&task.id);
+        self.current.lock().unwrap_or_else(|e| e.into_inner()).take();
+
+        let outcome = match outcome {
+            Some(Ok(outcome)) => outcome,
+            Some(Err(_)) => {
+                // the task is already marked cancelled or timed out
+                info!("Task {} was stopped", task.id);
+                return Ok(());
+            }
+            None => {
+                warn!("Task {} timed out after {}s", task.id, task.config.timeout.unwrap_or_default());
+                self.task_manager.time_out_task(&task).await?;
+                return Ok(());
+            }
+        };
+
+        match outcome {
             Ok(result) => {
-                // update task status and result
-                let mut task = task;
-                task.result = Some(result.clone());
-                task.status = TaskStatus::Completed;
-                task.completed_at = Some(Utc::now());
-                task.updated_at = Utc::now();
-                self.task_manager.storage().create(&task.clone().into()).await?;
-                
+                if self.task_type == TaskType::Transcribe {
+                    metrics::transcription_duration(started.elapsed());
+                }
+
+                // update task status and result. a task cancelled from another process
+                // while it ran stays cancelled
+                let task = match self.task_manager.complete_task(&task.id, result).await {
+                    Ok(task) => task,
+                    Err(e) if e.is::<InvalidTransition>() => {
+                        info!("Dropping the result of task {}: {}", task.id, e);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                };
+
                 // Let the task manager handle the callback
                 if let Err(e) = self.task_manager.handle_callback(&task).await {
                     error!("Failed to handle callback for task {}: {}", task.id, e);
                 }
-                
-                Ok(true)
+
+                Ok(())
             }
             Err(e) => {
                 error!("Failed to process task {}: {}", task.id, e);
-                let mut task = task;
-                task.status = TaskStatus::Failed(e.to_string());
-                task.updated_at = Utc::now();
-                self.task_manager.storage().create(&task.into()).await?;
-                Ok(true)
+                // the task manager has either queued the task for another attempt or
+                // failed it once its retries ran out
+                let task = self.task_manager.get_task(&task.id).await?;
+                if let Some(task) = task.filter(|task| matches!(task.status, TaskStatus::Failed(_))) {
+                    if let Err(e) = self.task_manager.handle_callback(&task).await {
+                        error!("Failed to handle callback for task {}: {}", task.id, e);
+                    }
+                }
+                Ok(())
             }
         }
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::test_support::{self, StubProcessor};
+    use crate::schedule::types::TaskConfig;
+    use crate::storage::task::sqlite::SqliteTaskStorage;
+
+    #[tokio::test]
+    async fn test_poll_jitter() -> Result<()> {
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await?;
+        let task_manager = Arc::new(TaskManager::new(Arc::new(storage)));
+        let worker = |seed: u64| TaskWorker::new(task_manager.clone(), TaskType::Transcribe)
+            .with_jitter(0.2)
+            .with_seed(seed);
+
+        // workers with different seeds spread their polls out
+        let (a, b) = (worker(1), worker(2));
+        let delays_a: Vec<Duration> = (0..5).map(|_| a.poll_delay()).collect();
+        let delays_b: Vec<Duration> = (0..5).map(|_| b.poll_delay()).collect();
+        assert_ne!(delays_a, delays_b);
+        for delay in delays_a.iter().chain(&delays_b) {
+            assert!(*delay >= Duration::from_millis(800) && *delay <= Duration::from_millis(1200));
+        }
+
+        // the same seed gives the same sequence
+        let c = worker(1);
+        let delays_c: Vec<Duration> = (0..5).map(|_| c.poll_delay()).collect();
+        assert_eq!(delays_a, delays_c);
+
+        // no jitter keeps the fixed interval
+        let fixed = TaskWorker::new(task_manager.clone(), TaskType::Transcribe);
+        assert_eq!(fixed.poll_delay(), Duration::from_secs(1));
+        Ok(())
+    }
+
+    fn task_config() -> TaskConfig {
+        TaskConfig { max_retries: 3, ..test_support::task_config() }
+    }
+
+    // task manager with a processor that never finishes on its own
+    async fn hanging() -> Result<(Arc<TaskManager>, StubProcessor)> {
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await?;
+        let processor = StubProcessor::new(TaskType::Transcribe).hanging();
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        task_manager.register_processor(Box::new(processor.clone()));
+        Ok((Arc::new(task_manager), processor))
+    }
+
+    #[tokio::test]
+    async fn test_cancel_running_task() -> Result<()> {
+        let (task_manager, processor) = hanging().await?;
+        let task = task_manager.create_task(task_config()).await?;
+
+        let worker = TaskWorker::new(task_manager.clone(), TaskType::Transcribe);
+        let running = tokio::spawn(async move { worker.process_next_task().await });
+
+        // wait for the processor to start working on the task
+        processor.started().notified().await;
+        assert_eq!(task_manager.get_task_status(&task.id).await?, Some(TaskStatus::Processing));
+
+        let cancelled_task = task_manager.cancel_task(&task.id).await?.unwrap();
+        assert_eq!(cancelled_task.status, TaskStatus::Cancelled);
+
+        // the worker stops instead of hanging on the processor
+        let processed = tokio::time::timeout(Duration::from_secs(5), running).await??;
+        assert!(processed?);
+        assert_eq!(task_manager.get_task_status(&task.id).await?, Some(TaskStatus::Cancelled));
+        assert_eq!(processor.cancels(), 1);
+
+        // a finished task can't be cancelled again
+        let err = task_manager.cancel_task(&task.id).await.unwrap_err();
+        assert!(err.is::<InvalidTransition>());
+        assert!(task_manager.cancel_task("missing").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pending_task() -> Result<()> {
+        let (task_manager, processor) = hanging().await?;
+        let task = task_manager.create_task(task_config()).await?;
+
+        task_manager.cancel_task(&task.id).await?.unwrap();
+        assert_eq!(task_manager.get_task_status(&task.id).await?, Some(TaskStatus::Cancelled));
+
+        // the task never starts and its processor is not involved
+        let worker = TaskWorker::new(task_manager.clone(), TaskType::Transcribe);
+        assert!(!worker.process_next_task().await?);
+        assert!(task_manager.get_task(&task.id).await?.unwrap().started_at.is_none());
+        assert_eq!(processor.cancels(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timeout_stops_running_task() -> Result<()> {
+        let (task_manager, processor) = hanging().await?;
+        let mut config = task_config();
+        config.timeout = Some(1);
+        let task = task_manager.create_task(config).await?;
+
+        // the worker gives up on its own, without waiting for the sweep
+        let worker = TaskWorker::new(task_manager.clone(), TaskType::Transcribe);
+        let processed = tokio::time::timeout(Duration::from_secs(3), worker.process_next_task()).await??;
+        assert!(processed);
+        assert_eq!(task_manager.get_task_status(&task.id).await?, Some(TaskStatus::TimedOut));
+        assert_eq!(processor.cancels(), 1);
+
+        // the sweep finds nothing left to time out
+        task_manager.handle_timed_out_tasks().await?;
+        assert_eq!(task_manager.get_task_status(&task.id).await?, Some(TaskStatus::TimedOut));
+        assert_eq!(processor.cancels(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_task_is_retried_until_it_completes() -> Result<()> {
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await?;
+        let mut task_manager = TaskManager::new(Arc::new(storage))
+            .with_retry_delay(Duration::from_millis(200));
+        task_manager.register_processor(Box::new(StubProcessor::new(TaskType::Transcribe)
+            .failing_first(2, || anyhow::anyhow!("temporary failure"))));
+        let task_manager = Arc::new(task_manager);
+        let task = task_manager.create_task(task_config()).await?;
+        let worker = TaskWorker::new(task_manager.clone(), TaskType::Transcribe);
+
+        // the first attempt fails and the task waits for its retry
+        assert!(worker.process_next_task().await?);
+        assert_eq!(task_manager.get_task_status(&task.id).await?, Some(TaskStatus::Retrying));
+        assert!(!worker.process_next_task().await?);
+
+        // the retries are picked up again once their delay passed, until one succeeds
+        let mut attempts = 1;
+        for _ in 0..100 {
+            if task_manager.get_task_status(&task.id).await? == Some(TaskStatus::Completed) {
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+            if worker.process_next_task().await? {
+                attempts += 1;
+            }
+        }
+        assert_eq!(task_manager.get_task_status(&task.id).await?, Some(TaskStatus::Completed));
+        assert_eq!(attempts, 3);
+
+        let completed = task_manager.get_task(&task.id).await?.unwrap();
+        assert_eq!(completed.result.unwrap().as_transcribe().unwrap().text, "done");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_permanent_failures_are_not_retried() -> Result<()> {
+        use crate::schedule::types::{FailureKind, TaskError, TaskRetryPolicy};
+
+        let input: fn() -> anyhow::Error = || TaskError::input(anyhow::anyhow!("Unsupported sample format"));
+        let network: fn() -> anyhow::Error = || TaskError::network(anyhow::anyhow!("download timed out"));
+        for (kind, error, expected_attempts) in [("input", input, 1), ("network", network, 3)] {
+            let storage = SqliteTaskStorage::new("sqlite::memory:").await?;
+            let mut task_manager = TaskManager::new(Arc::new(storage))
+                .with_retry_policy(TaskType::Transcribe, TaskRetryPolicy {
+                    max_attempts: 3,
+                    base_delay: Duration::ZERO,
+                    retry_on: vec![FailureKind::Network],
+                });
+            let processor = StubProcessor::new(TaskType::Transcribe).failing(error);
+            task_manager.register_processor(Box::new(processor.clone()));
+            let task_manager = Arc::new(task_manager);
+            let task = task_manager.create_task(task_config()).await?;
+            let worker = TaskWorker::new(task_manager.clone(), TaskType::Transcribe);
+
+            // an unsupported format fails right away, a download timeout is retried until the task runs out of attempts
+            while worker.process_next_task().await? {}
+            assert!(matches!(task_manager.get_task_status(&task.id).await?, Some(TaskStatus::Failed(_))), "{}", kind);
+            assert_eq!(processor.attempts(), expected_attempts, "{}", kind);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_finishes_running_task() -> Result<()> {
+        use crate::schedule::TaskScheduler;
+
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await?;
+        let processor = StubProcessor::new(TaskType::Transcribe).with_delay(Duration::from_millis(300));
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        task_manager.register_processor(Box::new(processor.clone()));
+        let task_manager = Arc::new(task_manager);
+        let running = task_manager.create_task(task_config()).await?;
+        let mut config = task_config();
+        config.priority = crate::schedule::types::TaskPriority::Low;
+        let waiting = task_manager.create_task(config).await?;
+
+        let scheduler = Arc::new(TaskScheduler::new(task_manager.clone()));
+        scheduler.spawn_worker(TaskType::Transcribe).await;
+        let runner = scheduler.clone();
+        let run = tokio::spawn(async move { runner.run().await });
+
+        processor.started().notified().await;
+        tokio::time::timeout(Duration::from_secs(5), scheduler.shutdown()).await??;
+        tokio::time::timeout(Duration::from_secs(5), run).await???;
+
+        // the task in flight is finished, the next one is left for the next start
+        assert_eq!(task_manager.get_task_status(&running.id).await?, Some(TaskStatus::Completed));
+        assert_eq!(task_manager.get_task_status(&waiting.id).await?, Some(TaskStatus::Pending));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_spawn_workers_stays_within_the_limit() -> Result<()> {
+        use crate::schedule::TaskScheduler;
+
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await?;
+        let task_manager = Arc::new(TaskManager::new(Arc::new(storage)));
+        let scheduler = TaskScheduler::new(task_manager).with_max_workers(3);
+
+        assert_eq!(scheduler.spawn_workers(TaskType::Transcribe, 2).await, 2);
+        // the limit covers the workers of every type
+        assert_eq!(scheduler.spawn_workers(TaskType::NoiseReduction, 5).await, 1);
+        assert!(logs_contain("Requested 5 NoiseReduction workers, starting 1 to stay within the limit of 3 workers"));
+        assert_eq!(scheduler.spawn_workers(TaskType::Transcribe, 1).await, 0);
+        scheduler.spawn_worker(TaskType::Transcribe).await;
+        assert_eq!(scheduler.workers.lock().await.len(), 3);
+
+        tokio::time::timeout(Duration::from_secs(5), scheduler.shutdown()).await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_scaling_reaches_the_requested_count() -> Result<()> {
+        use crate::schedule::TaskScheduler;
+
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await?;
+        let task_manager = Arc::new(TaskManager::new(Arc::new(storage)));
+        let scheduler = Arc::new(TaskScheduler::new(task_manager).with_max_workers(16));
+
+        for count in [3, 1] {
+            let handles: Vec<_> = (0..8).map(|_| {
+                let scheduler = scheduler.clone();
+                tokio::spawn(async move { scheduler.scale_workers(TaskType::Transcribe, count).await })
+            }).collect();
+            for handle in handles {
+                assert_eq!(handle.await?, count);
+            }
+            assert_eq!(scheduler.worker_count(&TaskType::Transcribe).await, count);
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), scheduler.shutdown()).await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_workers_of_a_type_process_tasks_concurrently() -> Result<()> {
+        use crate::schedule::TaskScheduler;
+
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await?;
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        task_manager.register_processor(Box::new(StubProcessor::new(TaskType::Transcribe)
+            .with_delay(Duration::from_millis(300))));
+        let task_manager = Arc::new(task_manager);
+
+        let mut ids = Vec::new();
+        for _ in 0..6 {
+            ids.push(task_manager.create_task(task_config()).await?.id);
+        }
+
+        let scheduler = Arc::new(TaskScheduler::new(task_manager.clone()).with_poll_jitter(0.0));
+        assert_eq!(scheduler.spawn_workers(TaskType::Transcribe, 3).await, 3);
+        let runner = scheduler.clone();
+        let run = tokio::spawn(async move { runner.run().await });
+
+        let mut tasks = Vec::new();
+        for _ in 0..100 {
+            tasks.clear();
+            for id in &ids {
+                tasks.push(task_manager.get_task(id).await?.unwrap());
+            }
+            if tasks.iter().all(|task| task.status == TaskStatus::Completed) {
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert!(tasks.iter().all(|task| task.status == TaskStatus::Completed));
+
+        // the workers run tasks side by side, never more than three at once, and each task ran once
+        let overlap = tasks.iter()
+            .filter_map(|task| task.started_at)
+            .map(|start| tasks.iter()
+                .filter(|task| task.started_at.zip(task.completed_at)
+                    .is_some_and(|(started, completed)| started <= start && start < completed))
+                .count())
+            .max()
+            .unwrap_or(0);
+        assert!((2..=3).contains(&overlap), "{} tasks ran at once", overlap);
+        assert!(tasks.iter().all(|task| task.config.retry_count == 0));
+
+        tokio::time::timeout(Duration::from_secs(5), scheduler.shutdown()).await??;
+        tokio::time::timeout(Duration::from_secs(5), run).await???;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_panicked_worker_is_replaced() -> Result<()> {
+        use crate::schedule::TaskScheduler;
+        use crate::schedule::types::TaskPriority;
+
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await?;
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        task_manager.register_processor(Box::new(StubProcessor::new(TaskType::Transcribe).panicking_on("panic")));
+        let task_manager = Arc::new(task_manager);
+
+        // both workers run into a panicking task before the regular ones
+        let mut panicking = Vec::new();
+        for _ in 0..2 {
+            let mut config = task_config();
+            config.priority = TaskPriority::High;
+            config.label = Some("panic".to_string());
+            config.max_retries = 0;
+            panicking.push(task_manager.create_task(config).await?);
+        }
+        let mut tasks = Vec::new();
+        for _ in 0..3 {
+            tasks.push(task_manager.create_task(task_config()).await?);
+        }
+
+        let scheduler = Arc::new(TaskScheduler::new(task_manager.clone()).with_poll_jitter(0.0));
+        scheduler.spawn_worker(TaskType::Transcribe).await;
+        scheduler.spawn_worker(TaskType::Transcribe).await;
+        let runner = scheduler.clone();
+        let run = tokio::spawn(async move { runner.run().await });
+
+        for task in &tasks {
+            let mut status = None;
+            for _ in 0..100 {
+                status = task_manager.get_task_status(&task.id).await?;
+                if status == Some(TaskStatus::Completed) {
+                    break;
+                }
+                sleep(Duration::from_millis(50)).await;
+            }
+            assert_eq!(status, Some(TaskStatus::Completed), "{}", task.id);
+        }
+        assert!(!run.is_finished());
+
+        // the tasks of the panicked workers don't stay processing
+        for task in &panicking {
+            let mut status = None;
+            for _ in 0..100 {
+                status = task_manager.get_task_status(&task.id).await?;
+                if matches!(status, Some(TaskStatus::Failed(_))) {
+                    break;
+                }
+                sleep(Duration::from_millis(50)).await;
+            }
+            assert_eq!(
+                status,
+                Some(TaskStatus::Failed("Worker panicked while processing the task".to_string())),
+                "{}",
+                task.id,
+            );
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), scheduler.shutdown()).await??;
+        tokio::time::timeout(Duration::from_secs(5), run).await???;
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+// builders and a stub processor shared by the tests of the scheduler, the processors and
+// the handlers
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use tokio::sync::Notify;
+
+use crate::schedule::processors::TaskProcessor;
+use crate::schedule::types::{
+    CallbackType, Task, TaskConfig, TaskParams, TaskPriority, TaskResult, TaskStatus, TaskType,
+    TranscribeParams, TranscribeResult,
+};
+
+// a transcription of `/path/to/input` with default params, no callback, retries or timeout
+pub(crate) fn task_config() -> TaskConfig {
+    TaskConfig {
+        task_type: TaskType::Transcribe,
+        input_path: PathBuf::from("/path/to/input"),
+        callback_type: CallbackType::None,
+        params: TaskParams::Transcribe(TranscribeParams::default()),
+        priority: TaskPriority::Normal,
+        retry_count: 0,
+        max_retries: 0,
+        timeout: None,
+        notify_on_retry: false,
+        label: None,
+        api_key: None,
+        request_id: None,
+    }
+}
+
+// a pending task with a fresh id, as `TaskManager::create_task` would store it
+pub(crate) fn task(config: TaskConfig) -> Task {
+    Task {
+        id: format!("task-{}", uuid::Uuid::new_v4()),
+        status: TaskStatus::Pending,
+        config,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+        started_at: None,
+        completed_at: None,
+        result: None,
+        error: None,
+    }
+}
+
+// the transcript `StubProcessor` completes its tasks with
+pub(crate) fn done() -> TaskResult {
+    TaskResult::Transcribe(TranscribeResult {
+        text: "done".to_string(),
+        detected_language: "en".to_string(),
+        language: "en".to_string(),
+        language_probability: 1.0,
+        ..Default::default()
+    })
+}
+
+// processor completing every task with `done()` right away, unless told otherwise. clones
+// share their counters, so a test can keep one and register the other
+#[derive(Clone)]
+pub(crate) struct StubProcessor {
+    task_type: TaskType,
+    delay: Duration,
+    // attempts left to fail, and the error they fail with
+    failures: Arc<AtomicU32>,
+    error: fn() -> anyhow::Error,
+    hang: bool,
+    // tasks with this label panic
+    panic_label: Option<String>,
+    started: Arc<Notify>,
+    attempts: Arc<AtomicU32>,
+    cancels: Arc<AtomicUsize>,
+    cleanups: Arc<AtomicUsize>,
+}
+
+impl StubProcessor {
+    pub(crate) fn new(task_type: TaskType) -> Self {
+        Self {
+            task_type,
+            delay: Duration::ZERO,
+            failures: Arc::new(AtomicU32::new(0)),
+            error: || anyhow::anyhow!("processing failed"),
+            hang: false,
+            panic_label: None,
+            started: Arc::new(Notify::new()),
+            attempts: Arc::new(AtomicU32::new(0)),
+            cancels: Arc::new(AtomicUsize::new(0)),
+            cleanups: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // take this long for every task
+    pub(crate) fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    // fail the first `attempts` attempts with the error, the later ones succeed
+    pub(crate) fn failing_first(mut self, attempts: u32, error: fn() -> anyhow::Error) -> Self {
+        self.failures = Arc::new(AtomicU32::new(attempts));
+        self.error = error;
+        self
+    }
+
+    // fail every attempt with the error
+    pub(crate) fn failing(self, error: fn() -> anyhow::Error) -> Self {
+        self.failing_first(u32::MAX, error)
+    }
+
+    // never finish a task on its own, only a cancel or a timeout ends it
+    pub(crate) fn hanging(mut self) -> Self {
+        self.hang = true;
+        self
+    }
+
+    pub(crate) fn panicking_on(mut self, label: &str) -> Self {
+        self.panic_label = Some(label.to_string());
+        self
+    }
+
+    // notified whenever a task starts
+    pub(crate) fn started(&self) -> Arc<Notify> {
+        self.started.clone()
+    }
+
+    pub(crate) fn attempts(&self) -> u32 {
+        self.attempts.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn cancels(&self) -> usize {
+        self.cancels.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn cleanups(&self) -> usize {
+        self.cleanups.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl TaskProcessor for StubProcessor {
+    fn task_type(&self) -> TaskType {
+        self.task_type.clone()
+    }
+
+    async fn process(&self, task: &Task) -> Result<TaskResult> {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        self.started.notify_one();
+        if self.panic_label.is_some() && task.config.label == self.panic_label {
+            panic!("processor bug");
+        }
+        if self.hang {
+            futures::future::pending::<()>().await;
+        }
+        tokio::time::sleep(self.delay).await;
+        if self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+            return Err((self.error)());
+        }
+        Ok(done())
+    }
+
+    fn validate_params(&self, _params: &TaskParams) -> Result<()> {
+        Ok(())
+    }
+
+    async fn cancel(&self, _task: &Task) -> Result<()> {
+        self.cancels.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn cleanup(&self, _task: &Task) -> Result<()> {
+        self.cleanups.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
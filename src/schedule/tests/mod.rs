@@ -20,7 +20,7 @@ async fn setup_test_environment() -> Result<(Arc<TaskScheduler>, Arc<TaskManager
     let asr = Arc::new(WhisperAsr::new("./models/ggml-large-v3.bin".to_string())?);
     
     // 创建处理器
-    let processor = Box::new(TranscribeProcessor::new(asr));
+    let processor = Box::new(TranscribeProcessor::new(asr, 1));
     
     // 创建任务管理器
     let mut task_manager = TaskManager::new(storage);
@@ -43,17 +43,21 @@ fn create_test_task_config(priority: TaskPriority, input_path: PathBuf) -> TaskC
         input_path,
         callback_type: CallbackType::Http {
             url: "http://localhost:8080/callback".to_string(),
+            secret: None,
         },
         params: TaskParams::Transcribe(TranscribeParams {
             language: Some("zh".to_string()),
             speaker_diarization: true,
-            emotion_recognition: false,
-            filter_dirty_words: false,
+            ..Default::default()
         }),
         priority,
         retry_count: 0,
         max_retries: 3,
         timeout: Some(300),
+        notify_on_retry: false,
+        label: None,
+        api_key: None,
+        request_id: None,
     }
 }
 
@@ -2,6 +2,8 @@ use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use std::fmt::Display;
+use crate::asr::WordTiming;
+use crate::audio::{AudioInfo, Gain};
 
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -18,12 +20,13 @@ impl Display for TaskType {
     }
 }
 
+// stored as its discriminant, tasks with the lower value run first
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TaskPriority {
-    Critical,
-    High,
-    Normal,
-    Low,
+    Critical = 0,
+    High = 1,
+    Normal = 2,
+    Low = 3,
 }
 
 impl Default for TaskPriority {
@@ -42,6 +45,19 @@ pub struct TaskConfig {
     pub retry_count: u32,
     pub max_retries: u32,
     pub timeout: Option<u64>,
+    // fire a status change callback each time a retry is scheduled
+    #[serde(default)]
+    pub notify_on_retry: bool,
+    // free form label grouping tasks, e.g. a project name
+    #[serde(default)]
+    pub label: Option<String>,
+    // id of the api key that submitted the task (`ApiKeyInfo::id`), never the key itself.
+    // set by the server from the authenticated key, whatever the request carried
+    #[serde(default)]
+    pub api_key: Option<String>,
+    // `X-Request-Id` of the request that created the task, the worker logs under it
+    #[serde(default)]
+    pub request_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,22 +68,86 @@ pub enum TaskParams {
     NoiseReduction(NoiseReductionParams),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TranscribeParams {
     pub language: Option<String>,
     pub speaker_diarization: bool,
     pub emotion_recognition: bool,
     pub filter_dirty_words: bool,
+    #[serde(default)]
+    pub token_timestamps: bool,
+    // decode the whole clip as one segment instead of splitting it at pauses
+    #[serde(default)]
+    pub single_segment: bool,
+    // use beam search with the given beam size instead of greedy decoding
+    #[serde(default)]
+    pub beam_size: Option<i32>,
+    // split long audio into chunks of this many seconds, checkpointing each chunk
+    #[serde(default)]
+    pub chunk_seconds: Option<u32>,
+    // initial prompt biasing whisper toward domain vocabulary
+    #[serde(default)]
+    pub initial_prompt: Option<String>,
+    // optional gain applied after normalization to boost quiet recordings
+    #[serde(default)]
+    pub gain: Option<Gain>,
+    // per request overrides of the whisper engine defaults
+    #[serde(default)]
+    pub n_threads: Option<i32>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub translate: Option<bool>,
+    // model id in the registry, the default model is used when unset
+    #[serde(default)]
+    pub model: Option<String>,
+    // segments more likely than this to be silence are dropped, keep all when unset
+    #[serde(default)]
+    pub no_speech_threshold: Option<f32>,
+    // segments whose confidence is below this are dropped from the result, keep all when unset
+    #[serde(default)]
+    pub min_segment_confidence: Option<f32>,
+    // id of a noise reduction task of the same api key whose denoised output is transcribed
+    // instead of the input, without denoising it again. the task stays pending until it finished
+    #[serde(default)]
+    pub input_task: Option<String>,
+    // attach phase timings to the result when set, carries the download time measured on submission
+    #[serde(default)]
+    pub timings: Option<PhaseTimings>,
+}
+
+// wall time of the phases of one transcription, in milliseconds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PhaseTimings {
+    pub download: u64,
+    // probing, decoding and resampling the input
+    pub preprocess: u64,
+    pub inference: u64,
+    // download plus the whole processing, converting the result included
+    pub total: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VoiceprintParams {
-    // future implementation
+    pub mode: VoiceprintMode,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum VoiceprintMode {
+    // store the voiceprint of the clip under the speaker id, replacing an earlier one
+    Enroll { speaker_id: String },
+    // compare the clip against the enrolled speakers
+    Identify,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NoiseReductionParams {
-    // future implementation
+    // 0 leaves the audio untouched, 1 removes as much of the estimated noise as possible
+    pub strength: f32,
+    // fft frame size in samples, a power of two such as 1024 or 2048
+    pub frame_size: usize,
+    // fraction of each frame shared with the next one, usually 0.5 or 0.75
+    pub overlap: f32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,6 +163,19 @@ pub struct Task {
     pub error: Option<String>,
 }
 
+// shown in place of a callback secret, responses only tell whether one is set
+pub const REDACTED_SECRET: &str = "********";
+
+impl Task {
+    // the task as returned by the api, without the callback secret
+    pub fn redacted(mut self) -> Self {
+        if let CallbackType::Http { secret: Some(secret), .. } = &mut self.config.callback_type {
+            *secret = REDACTED_SECRET.to_string();
+        }
+        self
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum TaskStatus {
     Pending,
@@ -91,6 +184,191 @@ pub enum TaskStatus {
     Failed(String),
     Retrying,
     TimedOut,
+    Cancelled,
+}
+
+impl TaskStatus {
+    // one of each variant, `Failed` without a reason
+    pub const ALL: [TaskStatus; 7] = [
+        TaskStatus::Pending,
+        TaskStatus::Processing,
+        TaskStatus::Completed,
+        TaskStatus::Failed(String::new()),
+        TaskStatus::Retrying,
+        TaskStatus::TimedOut,
+        TaskStatus::Cancelled,
+    ];
+
+    // the variant name without its data, as stored in the status column
+    pub fn name(&self) -> &'static str {
+        match self {
+            TaskStatus::Pending => "Pending",
+            TaskStatus::Processing => "Processing",
+            TaskStatus::Completed => "Completed",
+            TaskStatus::Failed(_) => "Failed",
+            TaskStatus::Retrying => "Retrying",
+            TaskStatus::TimedOut => "TimedOut",
+            TaskStatus::Cancelled => "Cancelled",
+        }
+    }
+
+    // a task in a terminal status never changes status again
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, TaskStatus::Completed | TaskStatus::Failed(_) | TaskStatus::TimedOut | TaskStatus::Cancelled)
+    }
+
+    // the allowed transitions table, everything not listed is rejected
+    pub fn can_transition_to(&self, to: &TaskStatus) -> bool {
+        use TaskStatus::*;
+        match self {
+            Pending => matches!(to, Processing | Cancelled | TimedOut),
+            Processing => matches!(to, Completed | Failed(_) | Retrying | TimedOut | Cancelled),
+            Retrying => matches!(to, Pending | Processing | Failed(_) | TimedOut | Cancelled),
+            Completed | Failed(_) | TimedOut | Cancelled => false,
+        }
+    }
+}
+
+// a status change rejected by `TaskStatus::can_transition_to`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidTransition {
+    pub task_id: String,
+    pub from: TaskStatus,
+    pub to: TaskStatus,
+}
+
+impl Display for InvalidTransition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid status transition of task {}: {} -> {}", self.task_id, self.from, self.to)
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+// a task manager request the caller got wrong, as opposed to a storage or processing failure
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskManagerError {
+    NotFound(String),
+    // the processor of the task type rejected the params
+    InvalidParams(String),
+    NoProcessor(TaskType),
+    // the operation only applies to pending tasks
+    NotPending(String),
+}
+
+impl Display for TaskManagerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskManagerError::NotFound(task_id) => write!(f, "Task not found: {}", task_id),
+            TaskManagerError::InvalidParams(message) => write!(f, "{}", message),
+            TaskManagerError::NoProcessor(task_type) => write!(f, "No processor found for task type: {}", task_type),
+            TaskManagerError::NotPending(task_id) => write!(f, "Task {} is not pending", task_id),
+        }
+    }
+}
+
+impl std::error::Error for TaskManagerError {}
+
+// what went wrong in a failed attempt, the retry policy of the task type decides which kinds are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailureKind {
+    // the input can't be processed, e.g. an unsupported audio format. retrying gives the same result
+    Input,
+    // a download or connection failed or timed out
+    Network,
+    // any other processor failure, e.g. an engine error
+    Processing,
+}
+
+impl FailureKind {
+    // the kind of a processor error. errors a processor didn't mark with `TaskError` are
+    // classified by their source, unknown ones count as processing failures
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(task_error) = cause.downcast_ref::<TaskError>() {
+                return task_error.kind;
+            }
+            if let Some(download_error) = cause.downcast_ref::<crate::utils::http::DownloadError>() {
+                return match download_error {
+                    crate::utils::http::DownloadError::Truncated { .. }
+                    | crate::utils::http::DownloadError::Unreachable(_) => FailureKind::Network,
+                    _ => FailureKind::Input,
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                if e.is_connect() || e.is_timeout() {
+                    return FailureKind::Network;
+                }
+            }
+        }
+        FailureKind::Processing
+    }
+}
+
+// a processor error marked with its failure kind
+#[derive(Debug)]
+pub struct TaskError {
+    pub kind: FailureKind,
+    error: anyhow::Error,
+}
+
+impl TaskError {
+    pub fn new(kind: FailureKind, error: impl Into<anyhow::Error>) -> Self {
+        Self { kind, error: error.into() }
+    }
+
+    // an input that will never be processed, the task fails without a retry by default
+    pub fn input(error: impl Into<anyhow::Error>) -> anyhow::Error {
+        Self::new(FailureKind::Input, error).into()
+    }
+
+    pub fn network(error: impl Into<anyhow::Error>) -> anyhow::Error {
+        Self::new(FailureKind::Network, error).into()
+    }
+}
+
+impl Display for TaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.error)
+    }
+}
+
+impl std::error::Error for TaskError {}
+
+// how the failed attempts of a task type are retried. `max_attempts` is the default
+// `max_retries` of new tasks, a task created with its own `max_retries` overrides it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskRetryPolicy {
+    // attempts including the first one
+    pub max_attempts: u32,
+    // wait before the first retry, doubled with every further attempt
+    pub base_delay: std::time::Duration,
+    // failure kinds worth another attempt
+    pub retry_on: Vec<FailureKind>,
+}
+
+impl TaskRetryPolicy {
+    // transcription failures are mostly bad audio, which fails the same way again
+    pub fn for_task_type(task_type: &TaskType) -> Self {
+        let max_attempts = match task_type {
+            TaskType::Transcribe => 2,
+            _ => 3,
+        };
+        Self {
+            max_attempts,
+            base_delay: std::time::Duration::ZERO,
+            retry_on: vec![FailureKind::Network, FailureKind::Processing],
+        }
+    }
+
+    pub fn is_retryable(&self, kind: FailureKind) -> bool {
+        self.retry_on.contains(&kind)
+    }
+
+    // wait before the given attempt, the second attempt waits `base_delay`
+    pub fn delay(&self, attempt: u32) -> std::time::Duration {
+        self.base_delay.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(2)))
+    }
 }
 
 impl TryFrom<String> for TaskStatus {
@@ -103,6 +381,7 @@ impl TryFrom<String> for TaskStatus {
             "Failed" => Ok(TaskStatus::Failed(String::new())),
             "Retrying" => Ok(TaskStatus::Retrying),
             "TimedOut" => Ok(TaskStatus::TimedOut),
+            "Cancelled" => Ok(TaskStatus::Cancelled),
             _ => Err(format!("Invalid task status: {}", status)),
         }
     }
@@ -122,10 +401,57 @@ pub enum TaskResult {
     NoiseReduction(NoiseReductionResult),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl TaskResult {
+    pub fn as_transcribe(&self) -> Option<&TranscribeResult> {
+        match self {
+            TaskResult::Transcribe(result) => Some(result),
+            _ => None,
+        }
+    }
+
+    pub fn into_transcribe(self) -> Option<TranscribeResult> {
+        match self {
+            TaskResult::Transcribe(result) => Some(result),
+            _ => None,
+        }
+    }
+
+    pub fn as_voiceprint_recognition(&self) -> Option<&VoiceprintResult> {
+        match self {
+            TaskResult::VoiceprintRecognition(result) => Some(result),
+            _ => None,
+        }
+    }
+
+    pub fn as_noise_reduction(&self) -> Option<&NoiseReductionResult> {
+        match self {
+            TaskResult::NoiseReduction(result) => Some(result),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TranscribeResult {
     pub text: String,
     pub segments: Vec<TranscribeSegment>,
+    #[serde(default)]
+    pub detected_language: String,
+    #[serde(default)]
+    pub language_probability: f32,
+    // language the audio was decoded in, differs from `detected_language` when
+    // a low confidence detection fell back to the configured language
+    #[serde(default)]
+    pub language: String,
+    // format of the original input audio, absent for results stored before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<AudioInfo>,
+    // only present when the request asked for timings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings_ms: Option<PhaseTimings>,
+    // number of segments below `min_segment_confidence`, only present when it was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dropped_segments: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,23 +460,151 @@ pub struct TranscribeSegment {
     pub speaker_id: Option<usize>,
     pub start_time: f64,
     pub end_time: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<WordTiming>,
+    // mean probability of the segment's tokens, absent for streamed segments and older results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VoiceprintResult {
-    // future implementation
+    // enrolled speakers with their cosine similarity to the clip, best match first.
+    // empty for an enrollment
+    pub matches: Vec<(String, f32)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NoiseReductionResult {
-    // future implementation
+    // denoised 16 bit mono wav
+    pub output_path: PathBuf,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "config")]
 pub enum CallbackType {
-    Http { url: String },
+    Http {
+        url: String,
+        // hmac key signing the callback body, see `callback::sign_payload`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        secret: Option<String>,
+    },
     Function { name: String },
     Event,
     None,
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::test_support;
+
+    #[test]
+    fn test_task_result_accessors() {
+        let transcribe = TaskResult::Transcribe(TranscribeResult {
+            text: "hello".to_string(),
+            detected_language: "en".to_string(),
+            language: "en".to_string(),
+            language_probability: 1.0,
+            ..Default::default()
+        });
+        assert_eq!(transcribe.as_transcribe().map(|r| r.text.as_str()), Some("hello"));
+        assert!(transcribe.as_voiceprint_recognition().is_none());
+        assert!(transcribe.as_noise_reduction().is_none());
+        assert_eq!(transcribe.into_transcribe().map(|r| r.text), Some("hello".to_string()));
+
+        let noise_reduction = TaskResult::NoiseReduction(NoiseReductionResult {
+            output_path: PathBuf::from("/path/to/output.wav"),
+        });
+        assert!(noise_reduction.as_transcribe().is_none());
+        assert!(noise_reduction.as_noise_reduction().is_some());
+        assert!(noise_reduction.into_transcribe().is_none());
+
+        let voiceprint = TaskResult::VoiceprintRecognition(VoiceprintResult { matches: Vec::new() });
+        assert!(voiceprint.as_voiceprint_recognition().is_some());
+        assert!(voiceprint.as_transcribe().is_none());
+    }
+
+    #[test]
+    fn test_redacted_task_hides_callback_secret() {
+        let task = test_support::task(TaskConfig {
+            task_type: TaskType::NoiseReduction,
+            input_path: PathBuf::from("/path/to/input.wav"),
+            callback_type: CallbackType::Http {
+                url: "https://hooks.example.com/callback".to_string(),
+                secret: Some("hmac-secret".to_string()),
+            },
+            params: TaskParams::NoiseReduction(NoiseReductionParams { strength: 0.5, frame_size: 2048, overlap: 0.75 }),
+            ..test_support::task_config()
+        });
+
+        let json = serde_json::to_string(&task.redacted()).unwrap();
+        assert!(!json.contains("hmac-secret"));
+        assert!(json.contains(REDACTED_SECRET));
+        assert!(json.contains("https://hooks.example.com/callback"));
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        use TaskStatus::*;
+        let failed = Failed("boom".to_string());
+
+        for (from, to) in [
+            (Pending, Processing),
+            (Pending, Cancelled),
+            (Processing, Completed),
+            (Processing, failed.clone()),
+            (Processing, Retrying),
+            (Retrying, Processing),
+            (Retrying, failed.clone()),
+        ] {
+            assert!(from.can_transition_to(&to), "{} -> {} should be allowed", from, to);
+        }
+
+        for (from, to) in [
+            (Pending, Completed),
+            (Processing, Pending),
+            (Completed, Processing),
+            (failed.clone(), Retrying),
+            (Cancelled, Pending),
+            (TimedOut, Completed),
+        ] {
+            assert!(!from.can_transition_to(&to), "{} -> {} should be rejected", from, to);
+        }
+
+        // every variant is listed once and its name parses back to it
+        for status in &TaskStatus::ALL {
+            assert_eq!(TaskStatus::try_from(status.name().to_string()).as_ref(), Ok(status));
+        }
+        let mut names: Vec<&str> = TaskStatus::ALL.iter().map(TaskStatus::name).collect();
+        names.dedup();
+        assert_eq!(names.len(), TaskStatus::ALL.len());
+
+        // nothing leaves a terminal status
+        for terminal in [Completed, failed.clone(), TimedOut, Cancelled] {
+            assert!(terminal.is_terminal());
+            for to in [Pending, Processing, Completed, failed.clone(), Retrying, TimedOut, Cancelled] {
+                assert!(!terminal.can_transition_to(&to));
+            }
+        }
+    }
+
+    #[test]
+    fn test_failure_kinds() {
+        let unsupported = TaskError::input(anyhow::anyhow!("Unsupported sample format"));
+        assert_eq!(FailureKind::of(&unsupported), FailureKind::Input);
+        // the kind survives added context
+        assert_eq!(FailureKind::of(&unsupported.context("Failed to parse audio")), FailureKind::Input);
+
+        let too_large = anyhow::Error::from(crate::utils::http::DownloadError::TooLarge { max_bytes: 1 });
+        assert_eq!(FailureKind::of(&too_large), FailureKind::Input);
+        let truncated = anyhow::Error::from(crate::utils::http::DownloadError::Truncated { expected: 2, actual: 1 });
+        assert_eq!(FailureKind::of(&truncated), FailureKind::Network);
+        assert_eq!(FailureKind::of(&anyhow::anyhow!("engine failed")), FailureKind::Processing);
+
+        let policy = TaskRetryPolicy::for_task_type(&TaskType::Transcribe);
+        assert!(!policy.is_retryable(FailureKind::Input));
+        assert!(policy.is_retryable(FailureKind::Network));
+        assert!(policy.max_attempts < TaskRetryPolicy::for_task_type(&TaskType::NoiseReduction).max_attempts);
+    }
+}
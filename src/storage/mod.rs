@@ -1,4 +1,5 @@
 pub mod task;
+pub mod voiceprint;
 
 // 重导出常用类型
 pub use task::{TaskStorage, sqlite::SqliteTaskStorage};
@@ -7,7 +7,7 @@ use chrono::{DateTime, Utc};
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: String,
-    pub status: String,  // 存储序列化后的 TaskStatus
+    pub status: String,  // TaskStatus 的变体名，Failed 的原因存于 error
     pub config: String,  // 存储序列化后的配置
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -19,6 +19,10 @@ pub struct Model {
     pub retry_count: i32,
     pub max_retries: i32,
     pub timeout: Option<i64>,
+    pub detected_language: Option<String>,  // 转写结果中检测到的语言，便于按语言统计
+    pub claimed_by: Option<String>,  // 领取该任务的调度实例
+    pub attempts: i32,  // 下一次执行是第几次尝试，新任务为 0
+    pub retry_at: Option<DateTime<Utc>>,  // Retrying 状态的任务在此时间之后才能被领取
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
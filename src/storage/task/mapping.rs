@@ -1,11 +1,23 @@
 use crate::storage::task::entity::Model as TaskModel;
-use crate::schedule::types::Task;
+use crate::schedule::types::{Task, TaskResult, TaskStatus};
+
+impl TaskModel {
+    /// 由 status 列的变体名和 error 列还原 `TaskStatus`
+    pub fn task_status(&self) -> Result<TaskStatus, String> {
+        match TaskStatus::try_from(self.status.clone())? {
+            TaskStatus::Failed(_) => Ok(TaskStatus::Failed(self.error.clone().unwrap_or_default())),
+            status => Ok(status),
+        }
+    }
+}
 
 impl From<TaskModel> for Task {
     fn from(model: TaskModel) -> Self {
+        // 无法识别的状态（如新版本写入的行）按失败处理，错误信息中保留原值
+        let status = model.task_status().unwrap_or_else(TaskStatus::Failed);
         Task {
             id: model.id,
-            status: serde_json::from_str(&model.status).unwrap(),
+            status,
             config: serde_json::from_str(&model.config).unwrap(),
             created_at: model.created_at,
             updated_at: model.updated_at,
@@ -19,20 +31,34 @@ impl From<TaskModel> for Task {
 
 impl From<Task> for TaskModel {
     fn from(task: Task) -> Self {
+        let detected_language = task.result.as_ref()
+            .and_then(TaskResult::as_transcribe)
+            .map(|result| result.detected_language.clone())
+            .filter(|language| !language.is_empty());
         TaskModel {
             id: task.id,
-            status: serde_json::to_string(&task.status).unwrap(),
+            status: task.status.name().to_string(),
             config: serde_json::to_string(&task.config).unwrap(),
             created_at: task.created_at,
             updated_at: task.updated_at,
             started_at: task.started_at,
             completed_at: task.completed_at,
             result: task.result.map(|r| serde_json::to_string(&r).unwrap()),
-            error: task.error,
+            // 失败原因只存一份，以状态中的为准
+            error: match &task.status {
+                TaskStatus::Failed(error) => Some(error.clone()),
+                _ => task.error,
+            },
             priority: task.config.priority as i32,
             retry_count: task.config.retry_count as i32,
             max_retries: task.config.max_retries as i32,
             timeout: task.config.timeout.map(|t| t as i64),
+            detected_language,
+            // 只由 claim_next_pending 写入，保存任务时不会覆盖
+            claimed_by: None,
+            // 只由 schedule_retry 写入，保存任务时不会覆盖
+            attempts: 0,
+            retry_at: None,
         }
     }
 }
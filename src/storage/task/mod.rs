@@ -1,23 +1,105 @@
 use async_trait::async_trait;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 use crate::storage::task::entity::Model as TaskModel;
+use crate::schedule::types::TaskType;
 use crate::web::Pagination;
+use std::sync::Arc;
 pub mod sqlite;
+pub mod postgres;
 pub mod entity;
+pub mod results;
 pub mod mapping;
+// queries shared by the sqlite and postgres storages
+mod query;
+
+pub use query::SchemaMismatch;
+
+/// 任务列表的过滤条件，字段为空时不过滤
+#[derive(Debug, Clone, Default)]
+pub struct TaskFilter {
+    pub detected_language: Option<String>,
+    /// 状态名，如 `Pending`、`Failed`，`Failed` 匹配任意失败原因
+    pub status: Option<String>,
+    /// `TaskPriority` 的数值
+    pub priority: Option<i32>,
+    /// 提交任务的 api key 的 id，即 config 中的 `api_key`
+    pub api_key: Option<String>,
+}
+
+/// 重试后仍未送达的回调，保存请求体以便运维重放
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedCallback {
+    pub id: i64,
+    pub task_id: String,
+    pub url: String,
+    /// 原始请求体 JSON
+    pub payload: String,
+    /// 最后一次失败的原因
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
 
 #[async_trait]
 pub trait TaskStorage: Send + Sync + 'static {
     async fn create(&self, model: &TaskModel) -> Result<()>;
+    // insert new tasks in one transaction, either all of them are stored or none
+    async fn create_many(&self, models: &[TaskModel]) -> Result<()>;
     async fn list(&self, pagination: &Pagination) -> Result<Vec<TaskModel>>;
+    async fn list_filtered(&self, pagination: &Pagination, filter: &TaskFilter) -> Result<Vec<TaskModel>>;
+    // number of tasks matching the filter, across all pages
+    async fn count_filtered(&self, filter: &TaskFilter) -> Result<u64>;
     async fn get_pending_by_priority(&self, limit: usize) -> Result<Vec<TaskModel>>;
+    // atomically move the first pending task of the type by priority to processing and record
+    // who claimed it, so schedulers sharing the database never take the same task. retrying
+    // tasks are claimed like pending ones once their `retry_at` has passed
+    async fn claim_next_pending(&self, worker_id: &str, task_type: &TaskType) -> Result<Option<TaskModel>>;
+    // results live in their own table: `get` loads the result, the listings leave it out
     async fn get(&self, task_id: &str) -> Result<Option<TaskModel>>;
+    // the serialized result alone, None when the task has none
+    async fn get_result(&self, task_id: &str) -> Result<Option<String>>;
+    // statuses are stored and matched by `TaskStatus::name`, a failure's message lives in `error`
     async fn update(&self, task_id: &str, status: &str) -> Result<()>;
+    // store a status change only while the stored status is still one of `from`, checked in the
+    // same statement. false when another caller changed the status first
+    async fn update_if_status(&self, model: &TaskModel, from: &[&str]) -> Result<bool>;
+    // record the attempt a failed task retries with and when it may be claimed again.
+    // kept on the row, so a restarted scheduler honours both
+    async fn schedule_retry(&self, task_id: &str, attempts: u32, retry_at: DateTime<Utc>) -> Result<()>;
     async fn delete(&self, task_id: &str) -> Result<()>;
     async fn get_timeouted(&self) -> Result<Vec<TaskModel>>;
     async fn cleanup_old(&self, before: DateTime<Utc>) -> Result<u64>;
     async fn get_by_status(&self, status: &str) -> Result<Vec<TaskModel>>;
+    async fn count_by_status(&self, status: &str) -> Result<u64>;
+    // tasks whose serialized config has `field` equal to `value`
+    async fn get_by_config_field(&self, field: &str, value: &str) -> Result<Vec<TaskModel>>;
+    // chunk checkpoints of long running tasks, keyed by task id and chunk index
+    async fn save_checkpoint(&self, task_id: &str, chunk_index: u32, data: &str) -> Result<()>;
+    async fn get_checkpoints(&self, task_id: &str) -> Result<Vec<(u32, String)>>;
+    async fn delete_checkpoints(&self, task_id: &str) -> Result<()>;
+
+    // dead letter records of callbacks that could not be delivered, newest first
+    async fn save_failed_callback(&self, task_id: &str, url: &str, payload: &str, error: &str) -> Result<()>;
+    async fn list_failed_callbacks(&self, pagination: &Pagination) -> Result<Vec<FailedCallback>>;
+    // returns how many records were removed
+    async fn delete_failed_callbacks(&self, task_id: &str) -> Result<u64>;
+
+    // raw access to the stored result json, used to stream large results without deserializing
+    async fn get_result_len(&self, task_id: &str) -> Result<Option<u64>>;
+    async fn read_result_chunk(&self, task_id: &str, offset: u64, len: u64) -> Result<Vec<u8>>;
+
+    // cheap round trip to the database, for readiness checks
+    async fn ping(&self) -> Result<()>;
+}
+
+/// 按连接串的协议选择任务存储，`postgres://` 和 `postgresql://` 使用 Postgres，其余使用 SQLite
+pub async fn connect(database_url: &str) -> Result<Arc<dyn TaskStorage>> {
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        Ok(Arc::new(postgres::PostgresTaskStorage::new(database_url).await?))
+    } else {
+        Ok(Arc::new(sqlite::SqliteTaskStorage::new(database_url).await?))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,416 @@
+use async_trait::async_trait;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbBackend, EntityTrait, Statement};
+use tracing::info;
+use crate::schedule::types::{TaskStatus, TaskType};
+use crate::storage::task::entity::{self, Model as TaskModel};
+use crate::web::Pagination;
+
+use super::{query, FailedCallback, TaskFilter, TaskStorage};
+
+/// 基于 Postgres 的任务存储，多个调度实例可共享同一数据库
+pub struct PostgresTaskStorage {
+    db: DatabaseConnection,
+}
+
+impl PostgresTaskStorage {
+    pub async fn new(database_url: &str) -> Result<Self> {
+        Self::new_with_schema(database_url, None).await
+    }
+
+    /// 在指定 schema 中建表，不存在时创建，为空时使用连接的默认 search_path
+    pub async fn new_with_schema(database_url: &str, schema: Option<&str>) -> Result<Self> {
+        info!("Initializing Postgres task storage with schema {:?}", schema);
+        if let Some(schema) = schema {
+            let db = Database::connect(database_url).await?;
+            db.execute(Statement::from_string(
+                DbBackend::Postgres,
+                format!(r#"CREATE SCHEMA IF NOT EXISTS "{}""#, schema),
+            ))
+            .await?;
+            db.close().await?;
+        }
+
+        let mut options = ConnectOptions::new(database_url);
+        if let Some(schema) = schema {
+            options.set_schema_search_path(schema);
+        }
+        let db = Database::connect(options).await?;
+
+        db.execute(Statement::from_string(
+            DbBackend::Postgres,
+            r#"
+            CREATE TABLE IF NOT EXISTS tasks (
+                id TEXT PRIMARY KEY NOT NULL,
+                status TEXT NOT NULL,
+                config TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL,
+                started_at TIMESTAMPTZ,
+                completed_at TIMESTAMPTZ,
+                result TEXT,
+                error TEXT,
+                priority INTEGER NOT NULL,
+                retry_count INTEGER NOT NULL,
+                max_retries INTEGER NOT NULL,
+                timeout BIGINT,
+                detected_language TEXT,
+                claimed_by TEXT,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                retry_at TIMESTAMPTZ
+            )
+            "#.to_owned(),
+        ))
+        .await?;
+
+        // 与 SQLite 的迁移保持一致，兼容早期手动创建的表
+        for (column, definition) in [
+            ("detected_language", "TEXT"),
+            ("claimed_by", "TEXT"),
+            ("attempts", "INTEGER NOT NULL DEFAULT 0"),
+            ("retry_at", "TIMESTAMPTZ"),
+        ] {
+            db.execute(Statement::from_string(
+                DbBackend::Postgres,
+                format!("ALTER TABLE tasks ADD COLUMN IF NOT EXISTS {} {}", column, definition),
+            ))
+            .await?;
+        }
+        // 其余的列无法自动补齐，表结构不符时直接报错，而不是在查询时失败
+        query::validate_schema::<query::Postgres>(&db, &["tasks"]).await?;
+        db.execute(Statement::from_string(
+            DbBackend::Postgres,
+            "CREATE INDEX IF NOT EXISTS idx_tasks_detected_language ON tasks (detected_language)".to_owned(),
+        ))
+        .await?;
+
+        // 调度每次轮询都按状态筛选并按优先级、创建时间排序
+        db.execute(Statement::from_string(
+            DbBackend::Postgres,
+            "CREATE INDEX IF NOT EXISTS idx_tasks_status_priority ON tasks (status, priority, created_at)".to_owned(),
+        ))
+        .await?;
+
+        // 分块转写的检查点表
+        db.execute(Statement::from_string(
+            DbBackend::Postgres,
+            r#"
+            CREATE TABLE IF NOT EXISTS task_checkpoints (
+                task_id TEXT NOT NULL,
+                chunk_index BIGINT NOT NULL,
+                data TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL,
+                PRIMARY KEY (task_id, chunk_index)
+            )
+            "#.to_owned(),
+        ))
+        .await?;
+
+        // 未送达回调的死信表
+        db.execute(Statement::from_string(
+            DbBackend::Postgres,
+            r#"
+            CREATE TABLE IF NOT EXISTS failed_callbacks (
+                id BIGSERIAL PRIMARY KEY,
+                task_id TEXT NOT NULL,
+                url TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                error TEXT NOT NULL,
+                failed_at TIMESTAMPTZ NOT NULL
+            )
+            "#.to_owned(),
+        ))
+        .await?;
+
+        // 任务结果表，结果较大，不放在 tasks 表的行中
+        db.execute(Statement::from_string(
+            DbBackend::Postgres,
+            r#"
+            CREATE TABLE IF NOT EXISTS task_results (
+                task_id TEXT PRIMARY KEY NOT NULL,
+                result TEXT NOT NULL
+            )
+            "#.to_owned(),
+        ))
+        .await?;
+        query::validate_schema::<query::Postgres>(&db, &["task_checkpoints", "failed_callbacks", "task_results"]).await?;
+        query::migrate_results::<query::Postgres>(&db).await?;
+
+        Ok(Self { db })
+    }
+}
+
+#[async_trait]
+impl TaskStorage for PostgresTaskStorage {
+    async fn create(&self, model: &TaskModel) -> Result<()> {
+        query::upsert(&self.db, model).await
+    }
+
+    async fn create_many(&self, models: &[TaskModel]) -> Result<()> {
+        query::insert_all(&self.db, models).await
+    }
+
+    async fn list(&self, pagination: &Pagination) -> Result<Vec<TaskModel>> {
+        self.list_filtered(pagination, &TaskFilter::default()).await
+    }
+
+    async fn list_filtered(&self, pagination: &Pagination, filter: &TaskFilter) -> Result<Vec<TaskModel>> {
+        query::list_filtered::<query::Postgres>(&self.db, pagination, filter).await
+    }
+
+    async fn count_filtered(&self, filter: &TaskFilter) -> Result<u64> {
+        query::count_filtered::<query::Postgres>(&self.db, filter).await
+    }
+
+    async fn get_pending_by_priority(&self, limit: usize) -> Result<Vec<TaskModel>> {
+        query::pending_by_priority(&self.db, limit).await
+    }
+
+    async fn claim_next_pending(&self, worker_id: &str, task_type: &TaskType) -> Result<Option<TaskModel>> {
+        let now = Utc::now();
+        // SKIP LOCKED 让并发的领取者跳过已被锁定的行，而不是等待后拿到同一行
+        // Retrying 状态的任务到了 retry_at 才能领取，没有 retry_at 的为旧版本留下的任务
+        // 输入任务（params.input_task）仍在排队或执行的任务继续等待，不会被领取
+        let statement = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+            UPDATE tasks
+            SET status = $1, started_at = $2, updated_at = $3, claimed_by = $4
+            WHERE id = (
+                SELECT id FROM tasks
+                WHERE (status = $5 OR (status = $6 AND (retry_at IS NULL OR retry_at <= $7)))
+                AND config::jsonb ->> 'task_type' = $8
+                AND NOT EXISTS (
+                    SELECT 1 FROM tasks AS input
+                    WHERE input.id = tasks.config::jsonb #>> '{params,params,input_task}'
+                    AND input.status IN ($9, $10, $11)
+                )
+                ORDER BY priority ASC, created_at ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+            [
+                TaskStatus::Processing.name().into(),
+                now.into(),
+                now.into(),
+                worker_id.into(),
+                TaskStatus::Pending.name().into(),
+                TaskStatus::Retrying.name().into(),
+                now.into(),
+                task_type.to_string().into(),
+                TaskStatus::Pending.name().into(),
+                TaskStatus::Processing.name().into(),
+                TaskStatus::Retrying.name().into(),
+            ],
+        );
+
+        Ok(entity::Entity::find()
+            .from_raw_sql(statement)
+            .one(&self.db)
+            .await?)
+    }
+
+    async fn get(&self, task_id: &str) -> Result<Option<TaskModel>> {
+        query::get(&self.db, task_id).await
+    }
+
+    async fn get_result(&self, task_id: &str) -> Result<Option<String>> {
+        query::get_result(&self.db, task_id).await
+    }
+
+    async fn update(&self, task_id: &str, status: &str) -> Result<()> {
+        query::update_status(&self.db, task_id, status).await
+    }
+
+    async fn update_if_status(&self, model: &TaskModel, from: &[&str]) -> Result<bool> {
+        query::update_if_status(&self.db, model, from).await
+    }
+
+    async fn schedule_retry(&self, task_id: &str, attempts: u32, retry_at: DateTime<Utc>) -> Result<()> {
+        query::schedule_retry(&self.db, task_id, attempts, retry_at).await
+    }
+
+    async fn delete(&self, task_id: &str) -> Result<()> {
+        query::delete(&self.db, task_id).await
+    }
+
+    async fn get_timeouted(&self) -> Result<Vec<TaskModel>> {
+        query::timed_out::<query::Postgres>(&self.db).await
+    }
+
+    async fn cleanup_old(&self, before: DateTime<Utc>) -> Result<u64> {
+        query::cleanup_old(&self.db, before).await
+    }
+
+    async fn get_by_status(&self, status: &str) -> Result<Vec<TaskModel>> {
+        query::by_status(&self.db, status).await
+    }
+
+    async fn count_by_status(&self, status: &str) -> Result<u64> {
+        query::count_by_status(&self.db, status).await
+    }
+
+    async fn get_by_config_field(&self, field: &str, value: &str) -> Result<Vec<TaskModel>> {
+        let statement = Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "SELECT * FROM tasks WHERE config::jsonb ->> $1 = $2 ORDER BY created_at",
+            [field.into(), value.into()],
+        );
+
+        Ok(entity::Entity::find()
+            .from_raw_sql(statement)
+            .all(&self.db)
+            .await?)
+    }
+
+    async fn save_checkpoint(&self, task_id: &str, chunk_index: u32, data: &str) -> Result<()> {
+        self.db.execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+            INSERT INTO task_checkpoints (task_id, chunk_index, data, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (task_id, chunk_index) DO UPDATE
+            SET data = EXCLUDED.data, created_at = EXCLUDED.created_at
+            "#,
+            [
+                task_id.into(),
+                (chunk_index as i64).into(),
+                data.into(),
+                Utc::now().into(),
+            ],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn get_checkpoints(&self, task_id: &str) -> Result<Vec<(u32, String)>> {
+        let rows = self.db.query_all(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "SELECT chunk_index, data FROM task_checkpoints WHERE task_id = $1 ORDER BY chunk_index",
+            [task_id.into()],
+        ))
+        .await?;
+
+        let mut checkpoints = Vec::with_capacity(rows.len());
+        for row in rows {
+            let chunk_index: i64 = row.try_get("", "chunk_index")?;
+            let data: String = row.try_get("", "data")?;
+            checkpoints.push((chunk_index as u32, data));
+        }
+        Ok(checkpoints)
+    }
+
+    async fn delete_checkpoints(&self, task_id: &str) -> Result<()> {
+        self.db.execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "DELETE FROM task_checkpoints WHERE task_id = $1",
+            [task_id.into()],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn save_failed_callback(&self, task_id: &str, url: &str, payload: &str, error: &str) -> Result<()> {
+        self.db.execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+            INSERT INTO failed_callbacks (task_id, url, payload, error, failed_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            [
+                task_id.into(),
+                url.into(),
+                payload.into(),
+                error.into(),
+                Utc::now().into(),
+            ],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn list_failed_callbacks(&self, pagination: &Pagination) -> Result<Vec<FailedCallback>> {
+        let pagination = pagination.check();
+        let rows = self.db.query_all(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+            SELECT id, task_id, url, payload, error, failed_at FROM failed_callbacks
+            ORDER BY id DESC LIMIT $1 OFFSET $2
+            "#,
+            [(pagination.limit() as i64).into(), (pagination.offset() as i64).into()],
+        ))
+        .await?;
+
+        let mut callbacks = Vec::with_capacity(rows.len());
+        for row in rows {
+            callbacks.push(FailedCallback {
+                id: row.try_get("", "id")?,
+                task_id: row.try_get("", "task_id")?,
+                url: row.try_get("", "url")?,
+                payload: row.try_get("", "payload")?,
+                error: row.try_get("", "error")?,
+                failed_at: row.try_get("", "failed_at")?,
+            });
+        }
+        Ok(callbacks)
+    }
+
+    async fn delete_failed_callbacks(&self, task_id: &str) -> Result<u64> {
+        let result = self.db.execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "DELETE FROM failed_callbacks WHERE task_id = $1",
+            [task_id.into()],
+        ))
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn get_result_len(&self, task_id: &str) -> Result<Option<u64>> {
+        // octet_length 按 UTF-8 字节计算，与 SQLite 的 BLOB 长度一致
+        let row = self.db.query_one(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+            SELECT octet_length(COALESCE(r.result, t.result))::BIGINT AS len
+            FROM tasks t LEFT JOIN task_results r ON r.task_id = t.id
+            WHERE t.id = $1
+            "#,
+            [task_id.into()],
+        ))
+        .await?;
+
+        match row {
+            Some(row) => {
+                let len: Option<i64> = row.try_get("", "len")?;
+                Ok(len.map(|len| len as u64))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn read_result_chunk(&self, task_id: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        // substring on bytea works on bytes and is 1-based
+        let row = self.db.query_one(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"
+            SELECT substring(convert_to(COALESCE(r.result, t.result), 'UTF8') FROM $1::INTEGER FOR $2::INTEGER) AS chunk
+            FROM tasks t LEFT JOIN task_results r ON r.task_id = t.id
+            WHERE t.id = $3
+            "#,
+            [(offset as i64 + 1).into(), (len as i64).into(), task_id.into()],
+        ))
+        .await?;
+
+        match row {
+            Some(row) => Ok(row.try_get::<Option<Vec<u8>>>("", "chunk")?.unwrap_or_default()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.db.query_one(Statement::from_string(DbBackend::Postgres, "SELECT 1".to_owned())).await?;
+        Ok(())
+    }
+}
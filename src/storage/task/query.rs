@@ -0,0 +1,407 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, QueryOrder, QuerySelect, Condition,
+    DbBackend, Statement, ActiveModelTrait, Set, IntoActiveModel, TransactionTrait, PaginatorTrait,
+    ConnectionTrait, Iterable, IdenStatic, sea_query::Expr,
+};
+use crate::schedule::types::TaskStatus;
+use crate::web::Pagination;
+
+use super::TaskFilter;
+use super::entity::{self, Model as TaskModel};
+use super::results;
+
+/// 各数据库不同的 SQL 写法
+pub(crate) trait Dialect {
+    const BACKEND: DbBackend;
+
+    /// 查询表中所有列名的 SQL，参数为表名，结果列为 name
+    const TABLE_COLUMNS: &'static str;
+
+    /// 时间戳列对应的 unix 秒数表达式
+    fn epoch_seconds(column: &str) -> String;
+
+    /// config 列中 json 字段的文本表达式
+    fn config_field(field: &str) -> String;
+}
+
+pub(crate) struct Sqlite;
+
+impl Dialect for Sqlite {
+    const BACKEND: DbBackend = DbBackend::Sqlite;
+    const TABLE_COLUMNS: &'static str = "SELECT name FROM pragma_table_info(?)";
+
+    fn epoch_seconds(column: &str) -> String {
+        format!("strftime('%s', {})", column)
+    }
+
+    fn config_field(field: &str) -> String {
+        format!("json_extract(config, '$.{}')", field)
+    }
+}
+
+pub(crate) struct Postgres;
+
+impl Dialect for Postgres {
+    const BACKEND: DbBackend = DbBackend::Postgres;
+    // column_name 是 sql_identifier 类型，转为 text 才能按字符串读取
+    const TABLE_COLUMNS: &'static str = "SELECT column_name::text AS name FROM information_schema.columns \
+        WHERE table_schema = current_schema() AND table_name = $1";
+
+    fn epoch_seconds(column: &str) -> String {
+        format!("EXTRACT(EPOCH FROM {})", column)
+    }
+
+    fn config_field(field: &str) -> String {
+        format!("(config::jsonb ->> '{}')", field)
+    }
+}
+
+/// 将 `TaskFilter` 转为查询条件
+fn filter_condition<D: Dialect>(filter: &TaskFilter) -> Condition {
+    let mut condition = Condition::all();
+    if let Some(language) = &filter.detected_language {
+        condition = condition.add(entity::Column::DetectedLanguage.eq(language.as_str()));
+    }
+    if let Some(status) = &filter.status {
+        condition = condition.add(entity::Column::Status.eq(status.as_str()));
+    }
+    if let Some(priority) = filter.priority {
+        condition = condition.add(entity::Column::Priority.eq(priority));
+    }
+    if let Some(api_key) = &filter.api_key {
+        let expr = format!("{} = ?", D::config_field("api_key"));
+        condition = condition.add(Expr::cust_with_values(expr, [api_key.as_str()]));
+    }
+    condition
+}
+
+/// 插入任务，已存在时更新可变的列
+pub(crate) async fn upsert(db: &DatabaseConnection, model: &TaskModel) -> Result<()> {
+    let txn = db.begin().await?;
+    let (model, result) = split_result(model);
+    entity::Entity::insert(model.into_active_model())
+        .on_conflict(
+            sea_query::OnConflict::column(entity::Column::Id)
+                .update_columns([
+                    entity::Column::UpdatedAt,
+                    entity::Column::Status,
+                    entity::Column::StartedAt,
+                    entity::Column::CompletedAt,
+                    entity::Column::Error,
+                    entity::Column::DetectedLanguage,
+                ])
+                .to_owned()
+        )
+        .exec(&txn)
+        .await?;
+    if let Some(result) = result {
+        save_result(&txn, result).await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// 仅当任务当前状态为 `from` 之一时更新可变的列和结果，返回是否更新。
+/// 条件在同一条 UPDATE 中判断，并发的状态变更不会被覆盖
+pub(crate) async fn update_if_status(db: &DatabaseConnection, model: &TaskModel, from: &[&str]) -> Result<bool> {
+    let txn = db.begin().await?;
+    let (model, result) = split_result(model);
+    let updated = entity::Entity::update_many()
+        .col_expr(entity::Column::UpdatedAt, Expr::value(model.updated_at))
+        .col_expr(entity::Column::Status, Expr::value(model.status.clone()))
+        .col_expr(entity::Column::StartedAt, Expr::value(model.started_at))
+        .col_expr(entity::Column::CompletedAt, Expr::value(model.completed_at))
+        .col_expr(entity::Column::Error, Expr::value(model.error.clone()))
+        .col_expr(entity::Column::DetectedLanguage, Expr::value(model.detected_language.clone()))
+        .filter(entity::Column::Id.eq(model.id.as_str()))
+        .filter(entity::Column::Status.is_in(from.iter().copied()))
+        .exec(&txn)
+        .await?;
+    // 状态已被其他调用者改变，事务随 txn 丢弃而回滚
+    if updated.rows_affected == 0 {
+        return Ok(false);
+    }
+    if let Some(result) = result {
+        save_result(&txn, result).await?;
+    }
+    txn.commit().await?;
+    Ok(true)
+}
+
+/// 记录重试的尝试次数和可以再次领取的时间
+pub(crate) async fn schedule_retry(db: &DatabaseConnection, task_id: &str, attempts: u32, retry_at: DateTime<Utc>) -> Result<()> {
+    entity::Entity::update_many()
+        .col_expr(entity::Column::Attempts, Expr::value(attempts as i32))
+        .col_expr(entity::Column::RetryAt, Expr::value(Some(retry_at)))
+        .filter(entity::Column::Id.eq(task_id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// 在一个事务中插入全部任务
+pub(crate) async fn insert_all(db: &DatabaseConnection, models: &[TaskModel]) -> Result<()> {
+    // 逐行插入，避免一条语句超出 SQLite 的参数数量限制
+    let txn = db.begin().await?;
+    for model in models {
+        let (model, result) = split_result(model);
+        entity::Entity::insert(model.into_active_model())
+            .exec(&txn)
+            .await?;
+        if let Some(result) = result {
+            save_result(&txn, result).await?;
+        }
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// 拆出结果单独保存，tasks 表中的 result 列只保留旧版本写入的数据
+fn split_result(model: &TaskModel) -> (TaskModel, Option<results::Model>) {
+    let mut model = model.clone();
+    let result = model.result.take().map(|result| results::Model {
+        task_id: model.id.clone(),
+        result,
+    });
+    (model, result)
+}
+
+/// 保存结果，没有结果的任务保存时不会清除已有的结果
+async fn save_result<C: ConnectionTrait>(db: &C, result: results::Model) -> Result<()> {
+    results::Entity::insert(result.into_active_model())
+        .on_conflict(
+            sea_query::OnConflict::column(results::Column::TaskId)
+                .update_column(results::Column::Result)
+                .to_owned()
+        )
+        .exec_without_returning(db)
+        .await?;
+    Ok(())
+}
+
+/// 单独存放的结果，旧版本的行结果仍在 tasks 表中
+pub(crate) async fn get_result(db: &DatabaseConnection, task_id: &str) -> Result<Option<String>> {
+    if let Some(result) = results::Entity::find_by_id(task_id).one(db).await? {
+        return Ok(Some(result.result));
+    }
+    Ok(entity::Entity::find_by_id(task_id).one(db).await?.and_then(|model| model.result))
+}
+
+/// 已有的表缺少代码依赖的列
+///
+/// `CREATE TABLE IF NOT EXISTS` 遇到旧版本创建的表时不会报错，
+/// 启动时检查表结构，避免运行中才因查询不存在的列而失败
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaMismatch {
+    pub table: String,
+    pub missing: Vec<String>,
+}
+
+impl std::fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "table `{}` is missing columns: {}; migrate or recreate the table",
+            self.table,
+            self.missing.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for SchemaMismatch {}
+
+/// 表中已有的列
+pub(crate) async fn table_columns<D: Dialect>(db: &DatabaseConnection, table: &str) -> Result<Vec<String>> {
+    let rows = db.query_all(Statement::from_sql_and_values(D::BACKEND, D::TABLE_COLUMNS, [table.into()])).await?;
+    rows.iter()
+        .map(|row| Ok(row.try_get::<String>("", "name")?))
+        .collect()
+}
+
+/// 各表应有的列，tasks 和 task_results 取自实体定义
+fn expected_columns() -> Vec<(&'static str, Vec<String>)> {
+    vec![
+        ("tasks", entity::Column::iter().map(|column| column.as_str().to_owned()).collect()),
+        ("task_results", results::Column::iter().map(|column| column.as_str().to_owned()).collect()),
+        ("task_checkpoints", ["task_id", "chunk_index", "data", "created_at"].map(str::to_owned).to_vec()),
+        ("failed_callbacks", ["id", "task_id", "url", "payload", "error", "failed_at"].map(str::to_owned).to_vec()),
+    ]
+}
+
+/// 检查给定的表包含全部应有的列，缺少时返回 `SchemaMismatch`
+///
+/// 可以补齐的列应在此之前迁移，这里只检查，不修改表结构
+pub(crate) async fn validate_schema<D: Dialect>(db: &DatabaseConnection, tables: &[&str]) -> Result<()> {
+    for (table, expected) in expected_columns() {
+        if !tables.contains(&table) {
+            continue;
+        }
+        let columns = table_columns::<D>(db, table).await?;
+        let missing: Vec<String> = expected
+            .into_iter()
+            .filter(|column| !columns.contains(column))
+            .collect();
+        if !missing.is_empty() {
+            return Err(SchemaMismatch { table: table.to_owned(), missing }.into());
+        }
+    }
+    Ok(())
+}
+
+/// 旧版本的行结果存于 tasks 表，移入结果表，重复执行无影响
+pub(crate) async fn migrate_results<D: Dialect>(db: &DatabaseConnection) -> Result<()> {
+    let txn = db.begin().await?;
+    txn.execute(Statement::from_string(
+        D::BACKEND,
+        r#"
+        INSERT INTO task_results (task_id, result)
+        SELECT id, result FROM tasks WHERE result IS NOT NULL
+        ON CONFLICT (task_id) DO NOTHING
+        "#.to_owned(),
+    ))
+    .await?;
+    txn.execute(Statement::from_string(
+        D::BACKEND,
+        "UPDATE tasks SET result = NULL WHERE result IS NOT NULL".to_owned(),
+    ))
+    .await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+pub(crate) async fn list_filtered<D: Dialect>(db: &DatabaseConnection, pagination: &Pagination, filter: &TaskFilter) -> Result<Vec<TaskModel>> {
+    let pagination = pagination.check();
+    Ok(entity::Entity::find()
+        .filter(filter_condition::<D>(filter))
+        .order_by_asc(entity::Column::CreatedAt)
+        .limit(pagination.limit())
+        .offset(pagination.offset())
+        .all(db)
+        .await?)
+}
+
+pub(crate) async fn count_filtered<D: Dialect>(db: &DatabaseConnection, filter: &TaskFilter) -> Result<u64> {
+    Ok(entity::Entity::find()
+        .filter(filter_condition::<D>(filter))
+        .count(db)
+        .await?)
+}
+
+pub(crate) async fn pending_by_priority(db: &DatabaseConnection, limit: usize) -> Result<Vec<TaskModel>> {
+    Ok(entity::Entity::find()
+        .filter(entity::Column::Status.eq(TaskStatus::Pending.name()))
+        .order_by_asc(entity::Column::Priority)
+        .order_by_asc(entity::Column::CreatedAt)
+        .limit(limit as u64)
+        .all(db)
+        .await?)
+}
+
+/// 单个任务，包含其结果。列表查询不加载结果
+pub(crate) async fn get(db: &DatabaseConnection, task_id: &str) -> Result<Option<TaskModel>> {
+    let mut model = match entity::Entity::find_by_id(task_id).one(db).await? {
+        Some(model) => model,
+        None => return Ok(None),
+    };
+    if model.result.is_none() {
+        model.result = results::Entity::find_by_id(task_id).one(db).await?.map(|result| result.result);
+    }
+    Ok(Some(model))
+}
+
+/// 更新状态，进入 Processing 和 Completed 时同时记录开始和完成时间
+pub(crate) async fn update_status(db: &DatabaseConnection, task_id: &str, status: &str) -> Result<()> {
+    let now = Utc::now();
+    if let Some(model) = entity::Entity::find_by_id(task_id).one(db).await? {
+        let mut active_model = model.into_active_model();
+        active_model.status = Set(status.to_string());
+        active_model.updated_at = Set(now);
+
+        if status == TaskStatus::Processing.name() {
+            active_model.started_at = Set(Some(now));
+        }
+        if status == TaskStatus::Completed.name() {
+            active_model.completed_at = Set(Some(now));
+        }
+
+        active_model.update(db).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn delete(db: &DatabaseConnection, task_id: &str) -> Result<()> {
+    let txn = db.begin().await?;
+    results::Entity::delete_by_id(task_id).exec(&txn).await?;
+    entity::Entity::delete_by_id(task_id).exec(&txn).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+/// 处理中且已超过各自超时时间的任务
+pub(crate) async fn timed_out<D: Dialect>(db: &DatabaseConnection) -> Result<Vec<TaskModel>> {
+    let now = Utc::now().timestamp();
+
+    // 使用原生 SQL 来处理时间比较
+    let statement = Statement::from_string(
+        D::BACKEND,
+        format!(
+            r#"
+            SELECT * FROM tasks
+            WHERE status = '{}'
+            AND started_at IS NOT NULL
+            AND timeout IS NOT NULL
+            AND ({} + timeout) < {}
+            "#,
+            TaskStatus::Processing.name(), D::epoch_seconds("started_at"), now
+        ),
+    );
+
+    Ok(entity::Entity::find()
+        .from_raw_sql(statement)
+        .all(db)
+        .await?)
+}
+
+/// 删除在 `before` 之前结束的已完成和失败任务
+pub(crate) async fn cleanup_old(db: &DatabaseConnection, before: DateTime<Utc>) -> Result<u64> {
+    let finished = [TaskStatus::Completed.name(), TaskStatus::Failed(String::new()).name()];
+    let condition = Condition::all()
+        .add(entity::Column::Status.is_in(finished))
+        .add(entity::Column::UpdatedAt.lt(before));
+
+    let txn = db.begin().await?;
+    results::Entity::delete_many()
+        .filter(results::Column::TaskId.in_subquery(
+            sea_query::Query::select()
+                .column(entity::Column::Id)
+                .from(entity::Entity)
+                .cond_where(condition.clone())
+                .to_owned()
+        ))
+        .exec(&txn)
+        .await?;
+    let result = entity::Entity::delete_many()
+        .filter(condition)
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+    Ok(result.rows_affected)
+}
+
+pub(crate) async fn by_status(db: &DatabaseConnection, status: &str) -> Result<Vec<TaskModel>> {
+    Ok(entity::Entity::find()
+        .filter(entity::Column::Status.eq(status))
+        .order_by_asc(entity::Column::Priority)
+        .order_by_asc(entity::Column::CreatedAt)
+        .all(db)
+        .await?)
+}
+
+pub(crate) async fn count_by_status(db: &DatabaseConnection, status: &str) -> Result<u64> {
+    Ok(entity::Entity::find()
+        .filter(entity::Column::Status.eq(status))
+        .count(db)
+        .await?)
+}
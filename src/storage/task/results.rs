@@ -0,0 +1,15 @@
+use sea_orm::entity::prelude::*;
+
+/// 任务结果单独存放，tasks 表的行保持精简，只有读取单个任务时才加载
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "task_results")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub task_id: String,
+    pub result: String,  // 存储序列化后的结果
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,35 +1,126 @@
 use async_trait::async_trait;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use sea_orm::{
-    DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, QueryOrder,
-    QuerySelect, Condition, ConnectionTrait, DbBackend, Statement,
-    ActiveModelTrait, Set, IntoActiveModel,
-};
+use sea_orm::{DatabaseConnection, EntityTrait, ConnectionTrait, DbBackend, Statement, TransactionTrait};
 use crate::web::Pagination;
-use tracing::info;
-use crate::schedule::types::TaskStatus;
-use sea_query;
+use std::future::Future;
+use tracing::{info, warn};
+use crate::schedule::types::{TaskStatus, TaskType};
 
-use super::TaskStorage;
+use super::{query, FailedCallback, TaskFilter, TaskStorage};
 use super::entity::{self, Model as TaskModel};
-use sea_orm::{ConnectOptions, Database};
+use sea_orm::SqlxSqliteConnector;
+use sqlx::ConnectOptions;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
+use std::str::FromStr;
+use std::time::Duration;
 
+/// SQLite 连接参数，应用到连接池中的每个连接
+#[derive(Debug, Clone)]
+pub struct SqliteOptions {
+    pub journal_mode: SqliteJournalMode,
+    pub synchronous: SqliteSynchronous,
+    /// 数据库被锁时的等待时间
+    pub busy_timeout: Duration,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        Self {
+            journal_mode: SqliteJournalMode::Wal,
+            synchronous: SqliteSynchronous::Normal,
+            busy_timeout: Duration::from_millis(5000),
+        }
+    }
+}
+
+impl SqliteOptions {
+    /// 从环境变量读取，未设置或无法解析的项使用默认值
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            journal_mode: SqliteJournalMode::from_str(&crate::SQLITE_JOURNAL_MODE)
+                .unwrap_or(default.journal_mode),
+            synchronous: SqliteSynchronous::from_str(&crate::SQLITE_SYNCHRONOUS)
+                .unwrap_or(default.synchronous),
+            busy_timeout: Duration::from_millis(*crate::SQLITE_BUSY_TIMEOUT_MS),
+        }
+    }
+
+    /// 按当前参数打开数据库连接
+    ///
+    /// pragma 通过 sqlx 的连接参数设置，保证连接池重建连接后仍然生效。
+    /// 文件数据库使用默认大小的连接池，WAL 模式下读操作可以并发执行；
+    /// 内存数据库的每个连接都是一个独立的库，只使用单个连接
+    pub async fn connect(&self, database_url: &str) -> Result<DatabaseConnection> {
+        let connect_options = SqliteConnectOptions::from_str(database_url)?
+            .journal_mode(self.journal_mode)
+            .synchronous(self.synchronous)
+            .busy_timeout(self.busy_timeout)
+            .disable_statement_logging();
+        let mut pool_options = SqlitePoolOptions::new();
+        if is_in_memory(database_url) {
+            pool_options = pool_options.max_connections(1);
+        }
+        let pool = pool_options.connect_with(connect_options).await?;
+        Ok(SqlxSqliteConnector::from_sqlx_sqlite_pool(pool))
+    }
+}
+
+/// 判断连接串是否指向内存数据库，如 `sqlite::memory:` 或带 `mode=memory` 参数
+fn is_in_memory(database_url: &str) -> bool {
+    database_url.contains(":memory:") || database_url.contains("mode=memory")
+}
+
+// 写操作遇到数据库锁时的重试次数及初始退避时间，每次重试退避时间翻倍
+const BUSY_RETRIES: u32 = 5;
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// 判断错误是否为 SQLite 的 busy/locked 错误
+fn is_busy_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let message = cause.to_string();
+        message.contains("database is locked")
+            || message.contains("database table is locked")
+            || message.contains("database is busy")
+    })
+}
+
+/// 执行写操作，遇到 busy/locked 错误时退避重试
+pub(crate) async fn retry_on_busy<T, F, Fut>(operation: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut delay = BUSY_RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Err(e) if attempt < BUSY_RETRIES && is_busy_error(&e) => {
+                attempt += 1;
+                warn!("SQLite busy, retrying in {:?} ({}/{})", delay, attempt, BUSY_RETRIES);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// 基于 SQLite 的任务存储
 pub struct SqliteTaskStorage {
     db: DatabaseConnection,
 }
 
 impl SqliteTaskStorage {
     pub async fn new(database_url: &str) -> Result<Self> {
-        info!("Initializing SQLite task storage at {}", database_url);
-
-        // 直接创建 ConnectOptions 并配置
-        let db = Database::connect(
-            ConnectOptions::new(database_url.to_owned())
-                .sqlx_logging(false)
-                .to_owned()
-        ).await?;
-        
+        Self::new_with_options(database_url, SqliteOptions::from_env()).await
+    }
+
+    pub async fn new_with_options(database_url: &str, options: SqliteOptions) -> Result<Self> {
+        info!("Initializing SQLite task storage at {} with {:?}", database_url, options);
+        let db = options.connect(database_url).await?;
+
         // 使用原生 SQL 创建表
         db.execute(Statement::from_string(
             DbBackend::Sqlite,
@@ -47,144 +138,416 @@ impl SqliteTaskStorage {
                 priority INTEGER NOT NULL,
                 retry_count INTEGER NOT NULL,
                 max_retries INTEGER NOT NULL,
-                timeout INTEGER
+                timeout INTEGER,
+                detected_language TEXT,
+                claimed_by TEXT,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                retry_at TEXT
+            )
+            "#.to_owned(),
+        ))
+        .await?;
+
+        // 旧版本创建的表没有 detected_language、claimed_by、attempts 和 retry_at 列
+        let columns = query::table_columns::<query::Sqlite>(&db, "tasks").await?;
+        for (column, definition) in [
+            ("detected_language", "TEXT"),
+            ("claimed_by", "TEXT"),
+            ("attempts", "INTEGER NOT NULL DEFAULT 0"),
+            ("retry_at", "TEXT"),
+        ] {
+            if !columns.iter().any(|c| c == column) {
+                info!("Adding {} column to tasks table", column);
+                db.execute(Statement::from_string(
+                    DbBackend::Sqlite,
+                    format!("ALTER TABLE tasks ADD COLUMN {} {}", column, definition),
+                ))
+                .await?;
+            }
+        }
+        // 其余的列无法自动补齐，表结构不符时直接报错，而不是在查询时失败
+        query::validate_schema::<query::Sqlite>(&db, &["tasks"]).await?;
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            "CREATE INDEX IF NOT EXISTS idx_tasks_detected_language ON tasks (detected_language)".to_owned(),
+        ))
+        .await?;
+
+        // 调度每次轮询都按状态筛选并按优先级、创建时间排序
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            "CREATE INDEX IF NOT EXISTS idx_tasks_status_priority ON tasks (status, priority, created_at)".to_owned(),
+        ))
+        .await?;
+
+        // 旧版本将状态存为 JSON，如 `"Pending"` 和 `{"Failed":"..."}`，转为变体名
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            r#"
+            UPDATE tasks
+            SET error = json_extract(status, '$.Failed'), status = 'Failed'
+            WHERE status LIKE '{"Failed":%'
+            "#.to_owned(),
+        ))
+        .await?;
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            r#"UPDATE tasks SET status = json_extract(status, '$') WHERE status LIKE '"%"'"#.to_owned(),
+        ))
+        .await?;
+
+        // 分块转写的检查点表
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            r#"
+            CREATE TABLE IF NOT EXISTS task_checkpoints (
+                task_id TEXT NOT NULL,
+                chunk_index INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (task_id, chunk_index)
+            )
+            "#.to_owned(),
+        ))
+        .await?;
+
+        // 未送达回调的死信表
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            r#"
+            CREATE TABLE IF NOT EXISTS failed_callbacks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                task_id TEXT NOT NULL,
+                url TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                error TEXT NOT NULL,
+                failed_at TEXT NOT NULL
             )
             "#.to_owned(),
         ))
         .await?;
 
+        // 任务结果表，结果较大，不放在 tasks 表的行中
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            r#"
+            CREATE TABLE IF NOT EXISTS task_results (
+                task_id TEXT PRIMARY KEY NOT NULL,
+                result TEXT NOT NULL
+            )
+            "#.to_owned(),
+        ))
+        .await?;
+        query::validate_schema::<query::Sqlite>(&db, &["task_checkpoints", "failed_callbacks", "task_results"]).await?;
+        query::migrate_results::<query::Sqlite>(&db).await?;
+
         Ok(Self { db })
     }
+
+    #[cfg(test)]
+    pub(crate) fn connection(&self) -> &DatabaseConnection {
+        &self.db
+    }
 }
 
 #[async_trait]
 impl TaskStorage for SqliteTaskStorage {
     async fn create(&self, model: &TaskModel) -> Result<()> {
-        let active_model = model.clone().into_active_model();
-        entity::Entity::insert(active_model)
-            .on_conflict(
-                sea_query::OnConflict::column(entity::Column::Id)
-                    .update_columns([
-                        entity::Column::UpdatedAt,
-                        entity::Column::Status,
-                        entity::Column::StartedAt,
-                        entity::Column::CompletedAt,
-                        entity::Column::Result,
-                        entity::Column::Error,
-                    ])
-                    .to_owned()
-            )
-            .exec(&self.db)
-            .await?;
-        Ok(())
+        let db = &self.db;
+        retry_on_busy(move || query::upsert(db, model)).await
     }
     
+    async fn create_many(&self, models: &[TaskModel]) -> Result<()> {
+        let db = &self.db;
+        retry_on_busy(move || query::insert_all(db, models)).await
+    }
+
     async fn list(&self, pagination: &Pagination) -> Result<Vec<TaskModel>> {
-        let pagination = pagination.check();
+        self.list_filtered(pagination, &TaskFilter::default()).await
+    }
 
-        let models = entity::Entity::find()
-            .order_by_asc(entity::Column::CreatedAt)
-            .limit(pagination.limit())
-            .offset(pagination.offset())
-            .all(&self.db)
-            .await?;
-        Ok(models)
+    async fn list_filtered(&self, pagination: &Pagination, filter: &TaskFilter) -> Result<Vec<TaskModel>> {
+        query::list_filtered::<query::Sqlite>(&self.db, pagination, filter).await
+    }
+
+    async fn count_filtered(&self, filter: &TaskFilter) -> Result<u64> {
+        query::count_filtered::<query::Sqlite>(&self.db, filter).await
     }
 
     async fn get_pending_by_priority(&self, limit: usize) -> Result<Vec<TaskModel>> {
-        let pending_status = serde_json::to_string(&TaskStatus::Pending)?;
-        let models = entity::Entity::find()
-            .filter(entity::Column::Status.eq(pending_status))
-            .order_by_asc(entity::Column::Priority)
-            .order_by_asc(entity::Column::CreatedAt)
-            .limit(limit as u64)
-            .all(&self.db)
-            .await?;
-        Ok(models)
+        query::pending_by_priority(&self.db, limit).await
+    }
+
+    async fn claim_next_pending(&self, worker_id: &str, task_type: &TaskType) -> Result<Option<TaskModel>> {
+        // 配置中的任务类型序列化为变体名
+        let task_type = task_type.to_string();
+
+        let db = &self.db;
+        let task_type = &task_type;
+        retry_on_busy(move || async move {
+            let now = Utc::now();
+            // 选取和更新在同一条语句中完成，并发的领取者不会拿到同一行
+            // Retrying 状态的任务到了 retry_at 才能领取，没有 retry_at 的为旧版本留下的任务
+            // 输入任务（params.input_task）仍在排队或执行的任务继续等待，不会被领取
+            let statement = Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"
+                UPDATE tasks
+                SET status = ?, started_at = ?, updated_at = ?, claimed_by = ?
+                WHERE id = (
+                    SELECT id FROM tasks
+                    WHERE (status = ? OR (status = ? AND (retry_at IS NULL OR retry_at <= ?)))
+                    AND json_extract(config, '$.task_type') = ?
+                    AND NOT EXISTS (
+                        SELECT 1 FROM tasks AS input
+                        WHERE input.id = json_extract(tasks.config, '$.params.params.input_task')
+                        AND input.status IN (?, ?, ?)
+                    )
+                    ORDER BY priority ASC, created_at ASC
+                    LIMIT 1
+                )
+                RETURNING *
+                "#,
+                [
+                    TaskStatus::Processing.name().into(),
+                    now.into(),
+                    now.into(),
+                    worker_id.into(),
+                    TaskStatus::Pending.name().into(),
+                    TaskStatus::Retrying.name().into(),
+                    now.into(),
+                    task_type.as_str().into(),
+                    TaskStatus::Pending.name().into(),
+                    TaskStatus::Processing.name().into(),
+                    TaskStatus::Retrying.name().into(),
+                ],
+            );
+
+            let txn = db.begin().await?;
+            let model = entity::Entity::find()
+                .from_raw_sql(statement)
+                .one(&txn)
+                .await?;
+            txn.commit().await?;
+            Ok(model)
+        }).await
     }
 
     async fn get(&self, task_id: &str) -> Result<Option<TaskModel>> {
-        Ok(entity::Entity::find_by_id(task_id)
-            .one(&self.db)
-            .await?)
+        query::get(&self.db, task_id).await
+    }
+
+    async fn get_result(&self, task_id: &str) -> Result<Option<String>> {
+        query::get_result(&self.db, task_id).await
     }
 
     async fn update(&self, task_id: &str, status: &str) -> Result<()> {
-        let now = Utc::now();
-        if let Some(model) = entity::Entity::find_by_id(task_id).one(&self.db).await? {
-            let mut active_model = model.into_active_model();
-            active_model.status = Set(status.to_string());
-            active_model.updated_at = Set(now);
-            
-            if status == serde_json::to_string(&TaskStatus::Processing)? {
-                active_model.started_at = Set(Some(now));
-            }
-            if status == serde_json::to_string(&TaskStatus::Completed)? {
-                active_model.completed_at = Set(Some(now));
-            }
-            
-            active_model.update(&self.db).await?;
-        }
-        Ok(())
+        let db = &self.db;
+        retry_on_busy(move || query::update_status(db, task_id, status)).await
+    }
+
+    async fn update_if_status(&self, model: &TaskModel, from: &[&str]) -> Result<bool> {
+        let db = &self.db;
+        retry_on_busy(move || query::update_if_status(db, model, from)).await
+    }
+
+    async fn schedule_retry(&self, task_id: &str, attempts: u32, retry_at: DateTime<Utc>) -> Result<()> {
+        let db = &self.db;
+        retry_on_busy(move || query::schedule_retry(db, task_id, attempts, retry_at)).await
     }
 
     async fn delete(&self, task_id: &str) -> Result<()> {
-        entity::Entity::delete_by_id(task_id)
-            .exec(&self.db)
-            .await?;
-        Ok(())
+        let db = &self.db;
+        retry_on_busy(move || query::delete(db, task_id)).await
     }
 
     async fn get_timeouted(&self) -> Result<Vec<TaskModel>> {
-        let processing_status = serde_json::to_string(&TaskStatus::Processing)?;
-        let now = Utc::now().timestamp();
-        
-        // 使用原生 SQL 来处理时间比较
-        let statement = Statement::from_string(
+        query::timed_out::<query::Sqlite>(&self.db).await
+    }
+
+    async fn cleanup_old(&self, before: DateTime<Utc>) -> Result<u64> {
+        let db = &self.db;
+        retry_on_busy(move || query::cleanup_old(db, before)).await
+    }
+
+    async fn get_by_status(&self, status: &str) -> Result<Vec<TaskModel>> {
+        query::by_status(&self.db, status).await
+    }
+
+    async fn count_by_status(&self, status: &str) -> Result<u64> {
+        query::count_by_status(&self.db, status).await
+    }
+
+    async fn get_by_config_field(&self, field: &str, value: &str) -> Result<Vec<TaskModel>> {
+        let statement = Statement::from_sql_and_values(
             DbBackend::Sqlite,
-            format!(
-                r#"
-                SELECT * FROM tasks 
-                WHERE status = '{}'
-                AND started_at IS NOT NULL 
-                AND timeout IS NOT NULL
-                AND (strftime('%s', started_at) + timeout) < {}
-                "#,
-                processing_status, now
-            ),
+            "SELECT * FROM tasks WHERE json_extract(config, ?) = ? ORDER BY created_at",
+            [format!("$.{}", field).into(), value.into()],
         );
 
         let models = entity::Entity::find()
             .from_raw_sql(statement)
             .all(&self.db)
             .await?;
-        
+
         Ok(models)
     }
 
-    async fn cleanup_old(&self, before: DateTime<Utc>) -> Result<u64> {
-        let condition = Condition::any()
-            .add(entity::Column::Status.contains("Completed"))
-            .add(entity::Column::Status.contains("Failed"));
-
-        let result = entity::Entity::delete_many()
-            .filter(condition)
-            .filter(entity::Column::UpdatedAt.lt(before))
-            .exec(&self.db)
+    async fn save_checkpoint(&self, task_id: &str, chunk_index: u32, data: &str) -> Result<()> {
+        let db = &self.db;
+        retry_on_busy(move || async move {
+            db.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"
+                INSERT OR REPLACE INTO task_checkpoints (task_id, chunk_index, data, created_at)
+                VALUES (?, ?, ?, ?)
+                "#,
+                [
+                    task_id.into(),
+                    chunk_index.into(),
+                    data.into(),
+                    Utc::now().to_rfc3339().into(),
+                ],
+            ))
             .await?;
+            Ok(())
+        }).await
+    }
+
+    async fn get_checkpoints(&self, task_id: &str) -> Result<Vec<(u32, String)>> {
+        let rows = self.db.query_all(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "SELECT chunk_index, data FROM task_checkpoints WHERE task_id = ? ORDER BY chunk_index",
+            [task_id.into()],
+        ))
+        .await?;
 
-        Ok(result.rows_affected)
+        let mut checkpoints = Vec::with_capacity(rows.len());
+        for row in rows {
+            let chunk_index: i64 = row.try_get("", "chunk_index")?;
+            let data: String = row.try_get("", "data")?;
+            checkpoints.push((chunk_index as u32, data));
+        }
+        Ok(checkpoints)
     }
 
-    async fn get_by_status(&self, status: &str) -> Result<Vec<TaskModel>> {
-        let models = entity::Entity::find()
-            .filter(entity::Column::Status.eq(status))
-            .order_by_desc(entity::Column::Priority)
-            .order_by_asc(entity::Column::CreatedAt)
-            .all(&self.db)
+    async fn delete_checkpoints(&self, task_id: &str) -> Result<()> {
+        let db = &self.db;
+        retry_on_busy(move || async move {
+            db.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "DELETE FROM task_checkpoints WHERE task_id = ?",
+                [task_id.into()],
+            ))
             .await?;
-        
-        Ok(models)
+            Ok(())
+        }).await
+    }
+
+    async fn save_failed_callback(&self, task_id: &str, url: &str, payload: &str, error: &str) -> Result<()> {
+        let db = &self.db;
+        retry_on_busy(move || async move {
+            db.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"
+                INSERT INTO failed_callbacks (task_id, url, payload, error, failed_at)
+                VALUES (?, ?, ?, ?, ?)
+                "#,
+                [
+                    task_id.into(),
+                    url.into(),
+                    payload.into(),
+                    error.into(),
+                    Utc::now().to_rfc3339().into(),
+                ],
+            ))
+            .await?;
+            Ok(())
+        }).await
     }
-}
 
+    async fn list_failed_callbacks(&self, pagination: &Pagination) -> Result<Vec<FailedCallback>> {
+        let pagination = pagination.check();
+        let rows = self.db.query_all(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"
+            SELECT id, task_id, url, payload, error, failed_at FROM failed_callbacks
+            ORDER BY id DESC LIMIT ? OFFSET ?
+            "#,
+            [(pagination.limit() as i64).into(), (pagination.offset() as i64).into()],
+        ))
+        .await?;
+
+        let mut callbacks = Vec::with_capacity(rows.len());
+        for row in rows {
+            let failed_at: String = row.try_get("", "failed_at")?;
+            callbacks.push(FailedCallback {
+                id: row.try_get("", "id")?,
+                task_id: row.try_get("", "task_id")?,
+                url: row.try_get("", "url")?,
+                payload: row.try_get("", "payload")?,
+                error: row.try_get("", "error")?,
+                failed_at: DateTime::parse_from_rfc3339(&failed_at)?.with_timezone(&Utc),
+            });
+        }
+        Ok(callbacks)
+    }
+
+    async fn delete_failed_callbacks(&self, task_id: &str) -> Result<u64> {
+        let db = &self.db;
+        retry_on_busy(move || async move {
+            let result = db.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "DELETE FROM failed_callbacks WHERE task_id = ?",
+                [task_id.into()],
+            ))
+            .await?;
+            Ok(result.rows_affected())
+        }).await
+    }
+
+    async fn get_result_len(&self, task_id: &str) -> Result<Option<u64>> {
+        let row = self.db.query_one(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"
+            SELECT length(CAST(COALESCE(r.result, t.result) AS BLOB)) AS len
+            FROM tasks t LEFT JOIN task_results r ON r.task_id = t.id
+            WHERE t.id = ?
+            "#,
+            [task_id.into()],
+        ))
+        .await?;
+
+        match row {
+            Some(row) => {
+                let len: Option<i64> = row.try_get("", "len")?;
+                Ok(len.map(|len| len as u64))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn read_result_chunk(&self, task_id: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        // substr on a blob works on bytes and is 1-based
+        let row = self.db.query_one(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"
+            SELECT substr(CAST(COALESCE(r.result, t.result) AS BLOB), ?, ?) AS chunk
+            FROM tasks t LEFT JOIN task_results r ON r.task_id = t.id
+            WHERE t.id = ?
+            "#,
+            [(offset as i64 + 1).into(), (len as i64).into(), task_id.into()],
+        ))
+        .await?;
+
+        match row {
+            Some(row) => Ok(row.try_get::<Option<Vec<u8>>>("", "chunk")?.unwrap_or_default()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.db.query_one(Statement::from_string(DbBackend::Sqlite, "SELECT 1".to_owned())).await?;
+        Ok(())
+    }
+}
@@ -1,41 +1,122 @@
 use super::*;
 use crate::schedule::types::{
     TaskType, CallbackType, TaskParams, TranscribeParams, 
-    TaskStatus, TaskConfig, TaskPriority
+    TaskStatus, TaskConfig, TaskPriority, TaskResult
 };
 use chrono::Duration;
-use tempfile::NamedTempFile;
+use std::sync::Arc;
+use tempfile::TempDir;
 use uuid::Uuid;
 use std::path::PathBuf;
 use crate::storage::task::sqlite::SqliteTaskStorage;
+use crate::storage::task::postgres::PostgresTaskStorage;
 use crate::schedule::types::Task;
 use crate::storage::task::entity::Model as TaskModel;
-use crate::SQLITE_PATH;
 
-async fn setup_storage() -> (SqliteTaskStorage, NamedTempFile) {
-    let temp_file = NamedTempFile::new().unwrap();
-    let storage = SqliteTaskStorage::new(&SQLITE_PATH).await.unwrap();
-    (storage, temp_file)
+// the database a storage test runs against, every test gets an empty one
+enum TestDatabase {
+    Sqlite(TempDir),
+    // a fresh schema in the database at `ASR_TEST_POSTGRES_URL`
+    Postgres { url: String, schema: String },
 }
 
+impl TestDatabase {
+    fn sqlite() -> Self {
+        TestDatabase::Sqlite(tempfile::tempdir().unwrap())
+    }
+
+    // None when no postgres database is configured for tests
+    fn postgres() -> Option<Self> {
+        let url = std::env::var("ASR_TEST_POSTGRES_URL").ok().filter(|url| !url.is_empty())?;
+        let schema = format!("asr_test_{}", Uuid::new_v4().simple());
+        Some(TestDatabase::Postgres { url, schema })
+    }
+
+    // every call opens a new storage on the same database, like separate schedulers would
+    async fn open(&self) -> Arc<dyn TaskStorage> {
+        match self {
+            TestDatabase::Sqlite(dir) => {
+                let url = format!("sqlite://{}?mode=rwc", dir.path().join("tasks.db").display());
+                Arc::new(SqliteTaskStorage::new(&url).await.unwrap())
+            }
+            TestDatabase::Postgres { url, schema } => {
+                Arc::new(PostgresTaskStorage::new_with_schema(url, Some(schema)).await.unwrap())
+            }
+        }
+    }
+}
+
+// runs each test against sqlite, and against postgres in an ignored suite
+macro_rules! storage_tests {
+    ($($name:ident),* $(,)?) => {
+        mod sqlite_suite {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    super::$name(&super::TestDatabase::sqlite()).await;
+                }
+            )*
+        }
+
+        // run with `cargo test -- --ignored` and `ASR_TEST_POSTGRES_URL` set
+        mod postgres_suite {
+            $(
+                #[tokio::test]
+                #[ignore = "needs a postgres database at ASR_TEST_POSTGRES_URL"]
+                async fn $name() {
+                    let db = super::TestDatabase::postgres().expect("ASR_TEST_POSTGRES_URL is not set");
+                    super::$name(&db).await;
+                }
+            )*
+        }
+    };
+}
+
+storage_tests!(
+    test_save_and_get_task,
+    test_get_pending_tasks_priority_order,
+    test_update_task_status,
+    test_update_if_status,
+    test_delete_task,
+    test_get_timed_out_tasks,
+    test_cleanup_old_tasks,
+    test_cleanup_old_matches_flat_statuses,
+    test_get_tasks_by_status,
+    test_list_tasks_by_detected_language,
+    test_list_tasks_by_status_and_priority,
+    test_list_tasks_by_api_key,
+    test_failed_callbacks,
+    test_checkpoints,
+    test_get_by_config_field,
+    test_read_result_chunks,
+    test_results_are_stored_apart,
+    test_claim_next_pending_is_exclusive,
+    test_claim_next_pending_by_priority,
+    test_claim_next_pending_by_task_type,
+    test_claim_next_pending_waits_for_input_task,
+    test_claim_next_pending_waits_for_retry_at,
+    test_priority_order_is_consistent,
+    test_count_by_status,
+    test_ping,
+);
+
 fn create_test_task(priority: TaskPriority) -> Task {
     Task {
         id: Uuid::new_v4().to_string(),
         status: TaskStatus::Pending,
         config: TaskConfig {
             task_type: TaskType::Transcribe,
-            callback_type: CallbackType::Http { url: "http://localhost:3000/callback".to_string() },
-            params: TaskParams::Transcribe(TranscribeParams {
-                language: None,
-                speaker_diarization: false,
-                emotion_recognition: false,
-                filter_dirty_words: false,
-            }),
+            callback_type: CallbackType::Http { url: "http://localhost:3000/callback".to_string(), secret: None },
+            params: TaskParams::Transcribe(TranscribeParams::default()),
             input_path: PathBuf::from("/path/to/input"),
             priority,
             retry_count: 0,
             max_retries: 3,
             timeout: Some(300),
+            notify_on_retry: false,
+            label: None,
+            api_key: None,
+            request_id: None,
         },
         created_at: Utc::now(),
         updated_at: Utc::now(),
@@ -46,9 +127,8 @@ fn create_test_task(priority: TaskPriority) -> Task {
     }
 }
 
-#[tokio::test]
-async fn test_save_and_get_task() {
-    let (storage, _temp_file) = setup_storage().await;
+async fn test_save_and_get_task(db: &TestDatabase) {
+    let storage = db.open().await;
     let task = create_test_task(TaskPriority::Normal);
     
     let model = TaskModel::from(task.clone());
@@ -60,9 +140,8 @@ async fn test_save_and_get_task() {
     assert_eq!(task.status, retrieved_task.status);
 }
 
-#[tokio::test]
-async fn test_get_pending_tasks_priority_order() {
-    let (storage, _temp_file) = setup_storage().await;
+async fn test_get_pending_tasks_priority_order(db: &TestDatabase) {
+    let storage = db.open().await;
     
     let task1 = create_test_task(TaskPriority::Low);
     let task2 = create_test_task(TaskPriority::High);
@@ -80,13 +159,12 @@ async fn test_get_pending_tasks_priority_order() {
     assert_eq!(pending_tasks[2].config.priority, TaskPriority::Low);
 }
 
-#[tokio::test]
-async fn test_update_task_status() {
-    let (storage, _temp_file) = setup_storage().await;
+async fn test_update_task_status(db: &TestDatabase) {
+    let storage = db.open().await;
     let task = create_test_task(TaskPriority::Normal);
     
     storage.create(&TaskModel::from(task.clone())).await.unwrap();
-    storage.update(&task.id, &serde_json::to_string(&TaskStatus::Processing).unwrap()).await.unwrap();
+    storage.update(&task.id, TaskStatus::Processing.name()).await.unwrap();
     
     let updated_model = storage.get(&task.id).await.unwrap().unwrap();
     let updated_task = Task::from(updated_model);
@@ -94,9 +172,35 @@ async fn test_update_task_status() {
     assert!(updated_task.started_at.is_some());
 }
 
-#[tokio::test]
-async fn test_delete_task() {
-    let (storage, _temp_file) = setup_storage().await;
+async fn test_update_if_status(db: &TestDatabase) {
+    let storage = db.open().await;
+    let mut task = create_test_task(TaskPriority::Normal);
+    storage.create(&TaskModel::from(task.clone())).await.unwrap();
+
+    // the stored status is not one of the expected ones, nothing is written
+    task.status = TaskStatus::Completed;
+    task.result = Some(TaskResult::Transcribe(crate::schedule::types::TranscribeResult {
+        text: "done".to_string(),
+        detected_language: "en".to_string(),
+        language_probability: 1.0,
+        language: "en".to_string(),
+        ..Default::default()
+    }));
+    let from = [TaskStatus::Processing.name()];
+    assert!(!storage.update_if_status(&TaskModel::from(task.clone()), &from).await.unwrap());
+    let stored = Task::from(storage.get(&task.id).await.unwrap().unwrap());
+    assert_eq!(stored.status, TaskStatus::Pending);
+    assert!(stored.result.is_none());
+
+    let from = [TaskStatus::Pending.name(), TaskStatus::Processing.name()];
+    assert!(storage.update_if_status(&TaskModel::from(task.clone()), &from).await.unwrap());
+    let stored = Task::from(storage.get(&task.id).await.unwrap().unwrap());
+    assert_eq!(stored.status, TaskStatus::Completed);
+    assert!(stored.result.is_some());
+}
+
+async fn test_delete_task(db: &TestDatabase) {
+    let storage = db.open().await;
     let task = create_test_task(TaskPriority::Normal);
     
     storage.create(&TaskModel::from(task.clone())).await.unwrap();
@@ -106,9 +210,8 @@ async fn test_delete_task() {
     assert!(result.is_none());
 }
 
-#[tokio::test]
-async fn test_get_timed_out_tasks() {
-    let (storage, _temp_file) = setup_storage().await;
+async fn test_get_timed_out_tasks(db: &TestDatabase) {
+    let storage = db.open().await;
     let mut task = create_test_task(TaskPriority::Normal);
     task.status = TaskStatus::Processing;
     task.started_at = Some(Utc::now() - Duration::seconds(301));
@@ -121,9 +224,8 @@ async fn test_get_timed_out_tasks() {
     assert_eq!(timed_out_tasks[0].id, task.id);
 }
 
-#[tokio::test]
-async fn test_cleanup_old_tasks() {
-    let (storage, _temp_file) = setup_storage().await;
+async fn test_cleanup_old_tasks(db: &TestDatabase) {
+    let storage = db.open().await;
     let mut task = create_test_task(TaskPriority::Normal);
     task.status = TaskStatus::Completed;
     task.updated_at = Utc::now() - Duration::hours(25);
@@ -137,18 +239,651 @@ async fn test_cleanup_old_tasks() {
     assert!(result.is_none());
 }
 
+async fn test_cleanup_old_matches_flat_statuses(db: &TestDatabase) {
+    let storage = db.open().await;
+    let old = Utc::now() - Duration::hours(25);
+
+    let mut ids = Vec::new();
+    for (status, updated_at) in [
+        (TaskStatus::Completed, old),
+        (TaskStatus::Failed("disk full".to_string()), old),
+        (TaskStatus::Pending, old),
+        (TaskStatus::Failed("recent".to_string()), Utc::now()),
+        // a status name in the error message doesn't make the task completed
+        (TaskStatus::Failed("Completed 3 of 4 chunks".to_string()), Utc::now()),
+    ] {
+        let mut task = create_test_task(TaskPriority::Normal);
+        task.status = status;
+        task.updated_at = updated_at;
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+        ids.push(task.id);
+    }
+
+    let cleaned = storage.cleanup_old(Utc::now() - Duration::hours(24)).await.unwrap();
+    assert_eq!(cleaned, 2);
+    assert!(storage.get(&ids[0]).await.unwrap().is_none());
+    assert!(storage.get(&ids[1]).await.unwrap().is_none());
+    assert!(storage.get(&ids[2]).await.unwrap().is_some());
+    assert!(storage.get(&ids[3]).await.unwrap().is_some());
+    assert!(storage.get(&ids[4]).await.unwrap().is_some());
+}
+
+#[test]
+fn test_unknown_status_maps_to_failed() {
+    let mut model = TaskModel::from(create_test_task(TaskPriority::Normal));
+    model.status = "Paused".to_string();
+
+    let task = Task::from(model);
+    assert_eq!(task.status, TaskStatus::Failed("Invalid task status: Paused".to_string()));
+}
+
 #[tokio::test]
-async fn test_get_tasks_by_status() {
-    let (storage, _temp_file) = setup_storage().await;
+async fn test_legacy_json_statuses_are_migrated() {
+    use sea_orm::{ConnectionTrait, DbBackend, Statement};
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}?mode=rwc", dir.path().join("legacy.db").display());
+    let storage = SqliteTaskStorage::new(&url).await.unwrap();
+
+    let pending = create_test_task(TaskPriority::Normal);
+    let failed = create_test_task(TaskPriority::Normal);
+    for (task, status) in [(&pending, r#""Pending""#), (&failed, r#"{"Failed":"boom"}"#)] {
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+        storage.connection().execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "UPDATE tasks SET status = ?, error = NULL WHERE id = ?",
+            [status.into(), task.id.as_str().into()],
+        )).await.unwrap();
+    }
+
+    // reopening the database converts the old rows
+    let storage = SqliteTaskStorage::new(&url).await.unwrap();
+    let pending_models = storage.get_by_status("Pending").await.unwrap();
+    assert_eq!(pending_models.len(), 1);
+    assert_eq!(pending_models[0].id, pending.id);
+
+    let failed_models = storage.get_by_status("Failed").await.unwrap();
+    assert_eq!(failed_models.len(), 1);
+    assert_eq!(Task::from(failed_models[0].clone()).status, TaskStatus::Failed("boom".to_string()));
+}
+
+#[tokio::test]
+async fn test_outdated_tasks_table_is_rejected() {
+    use sea_orm::{ConnectionTrait, Database, DbBackend, Statement};
+
+    let dir = tempfile::tempdir().unwrap();
+    let create = |path: &std::path::Path, columns: &str| {
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let sql = format!("CREATE TABLE tasks (id TEXT PRIMARY KEY NOT NULL, {})", columns);
+        async move {
+            let db = Database::connect(&url).await.unwrap();
+            db.execute(Statement::from_string(DbBackend::Sqlite, sql)).await.unwrap();
+            db.close().await.unwrap();
+            url
+        }
+    };
+
+    // an early table without the columns added since, detected_language and claimed_by can be added
+    let url = create(
+        &dir.path().join("outdated.db"),
+        "status TEXT NOT NULL, config TEXT NOT NULL, created_at TEXT NOT NULL, updated_at TEXT NOT NULL, priority INTEGER NOT NULL",
+    ).await;
+    let error = SqliteTaskStorage::new(&url).await.err().expect("outdated schema is rejected");
+    let mismatch = error.downcast_ref::<SchemaMismatch>().expect("a schema mismatch");
+    assert_eq!(mismatch.table, "tasks");
+    assert_eq!(mismatch.missing, [
+        "started_at", "completed_at", "result", "error", "retry_count", "max_retries", "timeout",
+    ]);
+    assert!(error.to_string().contains("started_at, completed_at"), "{}", error);
+
+    // only missing the columns that can be added, the table is migrated
+    let url = create(
+        &dir.path().join("migratable.db"),
+        "status TEXT NOT NULL, config TEXT NOT NULL, created_at TEXT NOT NULL, updated_at TEXT NOT NULL, \
+         started_at TEXT, completed_at TEXT, result TEXT, error TEXT, priority INTEGER NOT NULL, \
+         retry_count INTEGER NOT NULL, max_retries INTEGER NOT NULL, timeout INTEGER",
+    ).await;
+    let storage = SqliteTaskStorage::new(&url).await.unwrap();
+    let task = create_test_task(TaskPriority::Normal);
+    storage.create(&TaskModel::from(task.clone())).await.unwrap();
+    assert!(storage.get(&task.id).await.unwrap().is_some());
+}
+
+async fn test_get_tasks_by_status(db: &TestDatabase) {
+    let storage = db.open().await;
     let mut task = create_test_task(TaskPriority::Normal);
     let status = TaskStatus::Failed("Test failure".to_string());
     task.status = status.clone();
     
     storage.create(&TaskModel::from(task.clone())).await.unwrap();
     
-    let status_str = serde_json::to_string(&status).unwrap();
-    let failed_models = storage.get_by_status(&status_str).await.unwrap();
+    let failed_models = storage.get_by_status("Failed").await.unwrap();
+    assert_eq!(failed_models.len(), 1);
+    assert_eq!(failed_models[0].status, "Failed");
+    assert_eq!(failed_models[0].error.as_deref(), Some("Test failure"));
+
+    // the message comes back from the error column
     let failed_tasks: Vec<Task> = failed_models.into_iter().map(Task::from).collect();
-    assert_eq!(failed_tasks.len(), 1);
     assert_eq!(failed_tasks[0].id, task.id);
-} 
\ No newline at end of file
+    assert_eq!(failed_tasks[0].status, status);
+}
+
+#[tokio::test]
+async fn test_legacy_results_are_moved_out_of_tasks() {
+    use sea_orm::{ConnectionTrait, DbBackend, Statement};
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}?mode=rwc", dir.path().join("legacy.db").display());
+    let storage = SqliteTaskStorage::new(&url).await.unwrap();
+
+    // older versions kept the result in the tasks row
+    let task = completed_task_with_result("stored inline");
+    let model = TaskModel::from(task.clone());
+    storage.create(&model).await.unwrap();
+    storage.connection().execute(Statement::from_sql_and_values(
+        DbBackend::Sqlite,
+        "UPDATE tasks SET result = ? WHERE id = ?",
+        [model.result.clone().into(), task.id.as_str().into()],
+    )).await.unwrap();
+    storage.connection().execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "DELETE FROM task_results".to_owned(),
+    )).await.unwrap();
+
+    // readable before the migration ran
+    assert_eq!(storage.get_result(&task.id).await.unwrap(), model.result);
+    let len = storage.get_result_len(&task.id).await.unwrap();
+    assert_eq!(len, model.result.as_ref().map(|r| r.len() as u64));
+
+    // reopening the database moves it to the results table
+    let storage = SqliteTaskStorage::new(&url).await.unwrap();
+    let row = storage.connection().query_one(Statement::from_string(
+        DbBackend::Sqlite,
+        "SELECT COUNT(*) AS count FROM tasks WHERE result IS NOT NULL".to_owned(),
+    )).await.unwrap().unwrap();
+    assert_eq!(row.try_get::<i64>("", "count").unwrap(), 0);
+    assert_eq!(storage.get(&task.id).await.unwrap().unwrap().result, model.result);
+    assert_eq!(storage.get_result_len(&task.id).await.unwrap(), len);
+}
+
+#[tokio::test]
+async fn test_wal_mode_enabled() {
+    use sea_orm::{ConnectionTrait, DbBackend, Statement};
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}?mode=rwc", dir.path().join("wal.db").display());
+    let storage = SqliteTaskStorage::new(&url).await.unwrap();
+
+    let pragma = |name: &str| Statement::from_string(DbBackend::Sqlite, format!("PRAGMA {}", name));
+    let row = storage.connection().query_one(pragma("journal_mode")).await.unwrap().unwrap();
+    let journal_mode: String = row.try_get_by_index(0).unwrap();
+    assert_eq!(journal_mode.to_lowercase(), "wal");
+
+    // NORMAL is 1
+    let row = storage.connection().query_one(pragma("synchronous")).await.unwrap().unwrap();
+    let synchronous: i32 = row.try_get_by_index(0).unwrap();
+    assert_eq!(synchronous, 1);
+}
+
+#[tokio::test]
+async fn test_file_databases_use_a_connection_pool() {
+    use sea_orm::{ConnectionTrait, TransactionTrait};
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}?mode=rwc", dir.path().join("pool.db").display());
+    let storage = SqliteTaskStorage::new(&url).await.unwrap();
+    let max_connections = |storage: &SqliteTaskStorage| {
+        storage.connection().get_sqlite_connection_pool().options().get_max_connections()
+    };
+    assert!(max_connections(&storage) > 1);
+
+    // a reader isn't held up by a write transaction on another connection
+    let task = create_test_task(TaskPriority::Normal);
+    storage.create(&TaskModel::from(task.clone())).await.unwrap();
+    let txn = storage.connection().begin().await.unwrap();
+    txn.execute_unprepared("UPDATE tasks SET priority = 0").await.unwrap();
+    let read = tokio::time::timeout(std::time::Duration::from_secs(1), storage.get(&task.id)).await;
+    assert!(read.expect("read while a write is open").unwrap().is_some());
+    txn.rollback().await.unwrap();
+
+    // every connection to an in-memory database would open a database of its own
+    let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+    assert_eq!(max_connections(&storage), 1);
+}
+
+#[tokio::test]
+async fn test_pending_scan_uses_status_priority_index() {
+    use sea_orm::{ConnectionTrait, DbBackend, Statement};
+
+    let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+    let row = storage.connection().query_one(Statement::from_string(
+        DbBackend::Sqlite,
+        "SELECT sql FROM sqlite_master WHERE type = 'index' AND name = 'idx_tasks_status_priority'".to_owned(),
+    )).await.unwrap().expect("index exists");
+    let sql: String = row.try_get("", "sql").unwrap();
+    assert!(sql.contains("(status, priority, created_at)"), "{}", sql);
+
+    // the poll query is answered from the index, without a separate sort
+    let plan = storage.connection().query_all(Statement::from_string(
+        DbBackend::Sqlite,
+        "EXPLAIN QUERY PLAN SELECT * FROM tasks WHERE status = 'Pending' ORDER BY priority, created_at LIMIT 10".to_owned(),
+    )).await.unwrap();
+    let details: Vec<String> = plan.iter().map(|row| row.try_get("", "detail").unwrap()).collect();
+    assert!(details.iter().any(|d| d.contains("idx_tasks_status_priority")), "{:?}", details);
+    assert!(!details.iter().any(|d| d.contains("TEMP B-TREE")), "{:?}", details);
+}
+
+#[tokio::test]
+async fn test_concurrent_updates_retry_on_busy() {
+    use crate::storage::task::sqlite::SqliteOptions;
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}?mode=rwc", dir.path().join("busy.db").display());
+
+    // no busy_timeout, so lock contention between the two connections surfaces immediately
+    let options = SqliteOptions {
+        busy_timeout: std::time::Duration::ZERO,
+        ..SqliteOptions::default()
+    };
+    let storages = [
+        Arc::new(SqliteTaskStorage::new_with_options(&url, options.clone()).await.unwrap()),
+        Arc::new(SqliteTaskStorage::new_with_options(&url, options).await.unwrap()),
+    ];
+
+    let task = create_test_task(TaskPriority::Normal);
+    storages[0].create(&TaskModel::from(task.clone())).await.unwrap();
+
+    let statuses = [TaskStatus::Processing, TaskStatus::Retrying, TaskStatus::Pending];
+    let handles: Vec<_> = (0..50).map(|i| {
+        let storage = storages[i % 2].clone();
+        let task_id = task.id.clone();
+        let status = statuses[i % statuses.len()].name();
+        tokio::spawn(async move { storage.update(&task_id, status).await })
+    }).collect();
+
+    for handle in handles {
+        handle.await.unwrap().unwrap();
+    }
+}
+
+async fn test_list_tasks_by_detected_language(db: &TestDatabase) {
+    use crate::schedule::types::{TaskResult, TranscribeResult};
+
+    let storage = db.open().await;
+
+    let mut ids = Vec::new();
+    for language in ["fr", "en", "fr"] {
+        let mut task = create_test_task(TaskPriority::Normal);
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+
+        // the language is only known once the task completes
+        task.status = TaskStatus::Completed;
+        task.result = Some(TaskResult::Transcribe(TranscribeResult {
+            text: "bonjour".to_string(),
+            detected_language: language.to_string(),
+            language: language.to_string(),
+            language_probability: 0.9,
+            ..Default::default()
+        }));
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+        ids.push((task.id, language));
+    }
+    // a pending task has no language yet
+    storage.create(&TaskModel::from(create_test_task(TaskPriority::Normal))).await.unwrap();
+
+    let pagination = Pagination { index: 1, size: 10 };
+    let filter = |language: &str| TaskFilter { detected_language: Some(language.to_string()), ..Default::default() };
+
+    let french = storage.list_filtered(&pagination, &filter("fr")).await.unwrap();
+    let expected: Vec<&String> = ids.iter().filter(|(_, l)| *l == "fr").map(|(id, _)| id).collect();
+    assert_eq!(french.iter().map(|m| &m.id).collect::<Vec<_>>(), expected);
+    assert!(french.iter().all(|m| m.detected_language.as_deref() == Some("fr")));
+
+    assert_eq!(storage.list_filtered(&pagination, &filter("en")).await.unwrap().len(), 1);
+    assert!(storage.list_filtered(&pagination, &filter("de")).await.unwrap().is_empty());
+    assert_eq!(storage.list_filtered(&pagination, &TaskFilter::default()).await.unwrap().len(), 4);
+}
+
+async fn test_list_tasks_by_status_and_priority(db: &TestDatabase) {
+    let storage = db.open().await;
+
+    let statuses = [
+        (TaskStatus::Pending, TaskPriority::High),
+        (TaskStatus::Pending, TaskPriority::Normal),
+        (TaskStatus::Pending, TaskPriority::High),
+        (TaskStatus::Failed("disk full".to_string()), TaskPriority::High),
+        (TaskStatus::Failed("timeout".to_string()), TaskPriority::Low),
+        (TaskStatus::Completed, TaskPriority::High),
+    ];
+    for (status, priority) in statuses {
+        let mut task = create_test_task(priority);
+        task.status = status;
+        storage.create(&TaskModel::from(task)).await.unwrap();
+    }
+
+    let filter = |status: Option<&str>, priority: Option<TaskPriority>| TaskFilter {
+        status: status.map(str::to_string),
+        priority: priority.map(|p| p as i32),
+        ..Default::default()
+    };
+    let count = |filter: TaskFilter| {
+        let storage = &storage;
+        async move { storage.count_filtered(&filter).await.unwrap() }
+    };
+
+    assert_eq!(count(filter(Some("Pending"), None)).await, 3);
+    // any failure reason matches
+    assert_eq!(count(filter(Some("Failed"), None)).await, 2);
+    assert_eq!(count(filter(None, Some(TaskPriority::High))).await, 4);
+    assert_eq!(count(filter(Some("Pending"), Some(TaskPriority::High))).await, 2);
+    assert_eq!(count(filter(Some("Retrying"), None)).await, 0);
+    assert_eq!(count(TaskFilter::default()).await, 6);
+
+    // the count spans all pages, the list only one
+    let pagination = Pagination { index: 2, size: 2 };
+    let page = storage.list_filtered(&pagination, &filter(Some("Pending"), None)).await.unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].status, TaskStatus::Pending.name());
+}
+
+async fn test_list_tasks_by_api_key(db: &TestDatabase) {
+    let storage = db.open().await;
+
+    for api_key in [Some("kid-a"), Some("kid-b"), Some("kid-a"), None] {
+        let mut task = create_test_task(TaskPriority::Normal);
+        task.config.api_key = api_key.map(str::to_string);
+        storage.create(&TaskModel::from(task)).await.unwrap();
+    }
+
+    let filter = |api_key: &str| TaskFilter { api_key: Some(api_key.to_string()), ..Default::default() };
+    assert_eq!(storage.count_filtered(&filter("kid-a")).await.unwrap(), 2);
+    assert_eq!(storage.count_filtered(&filter("kid-b")).await.unwrap(), 1);
+    assert_eq!(storage.count_filtered(&filter("kid-c")).await.unwrap(), 0);
+
+    let page = storage.list_filtered(&Pagination::default(), &filter("kid-a")).await.unwrap();
+    assert_eq!(page.len(), 2);
+    assert!(page.iter().all(|m| Task::from(m.clone()).config.api_key.as_deref() == Some("kid-a")));
+}
+
+async fn test_failed_callbacks(db: &TestDatabase) {
+    let storage = db.open().await;
+    assert!(storage.list_failed_callbacks(&Pagination::default()).await.unwrap().is_empty());
+
+    for i in 0..3 {
+        storage.save_failed_callback(
+            &format!("task-{}", i),
+            "http://localhost:9/callback",
+            &format!(r#"{{"task_id":"task-{}"}}"#, i),
+            "connection refused",
+        ).await.unwrap();
+    }
+
+    let page = storage.list_failed_callbacks(&Pagination { index: 1, size: 2 }).await.unwrap();
+    assert_eq!(page.iter().map(|c| c.task_id.as_str()).collect::<Vec<_>>(), vec!["task-2", "task-1"]);
+    assert_eq!(page[0].payload, r#"{"task_id":"task-2"}"#);
+    assert_eq!(page[0].error, "connection refused");
+
+    let page = storage.list_failed_callbacks(&Pagination { index: 2, size: 2 }).await.unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].task_id, "task-0");
+}
+
+async fn test_checkpoints(db: &TestDatabase) {
+    let storage = db.open().await;
+    storage.save_checkpoint("task", 1, "second").await.unwrap();
+    storage.save_checkpoint("task", 0, "first").await.unwrap();
+    // saving a chunk again replaces it
+    storage.save_checkpoint("task", 1, "second again").await.unwrap();
+    storage.save_checkpoint("other", 0, "other").await.unwrap();
+
+    let checkpoints = storage.get_checkpoints("task").await.unwrap();
+    assert_eq!(checkpoints, vec![(0, "first".to_string()), (1, "second again".to_string())]);
+
+    storage.delete_checkpoints("task").await.unwrap();
+    assert!(storage.get_checkpoints("task").await.unwrap().is_empty());
+    assert_eq!(storage.get_checkpoints("other").await.unwrap().len(), 1);
+}
+
+async fn test_get_by_config_field(db: &TestDatabase) {
+    let storage = db.open().await;
+    let mut labelled = create_test_task(TaskPriority::Normal);
+    labelled.config.label = Some("batch-1".to_string());
+    for task in [&labelled, &create_test_task(TaskPriority::Normal)] {
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+    }
+
+    let models = storage.get_by_config_field("label", "batch-1").await.unwrap();
+    assert_eq!(models.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(), vec![labelled.id.as_str()]);
+    assert!(storage.get_by_config_field("label", "batch-2").await.unwrap().is_empty());
+}
+
+async fn test_read_result_chunks(db: &TestDatabase) {
+    use crate::schedule::types::{TaskResult, TranscribeResult};
+
+    let storage = db.open().await;
+    let mut task = create_test_task(TaskPriority::Normal);
+    task.status = TaskStatus::Completed;
+    task.result = Some(TaskResult::Transcribe(TranscribeResult {
+        text: "你好，世界".to_string(),
+        detected_language: "zh".to_string(),
+        language: "zh".to_string(),
+        language_probability: 0.9,
+        ..Default::default()
+    }));
+    let model = TaskModel::from(task.clone());
+    storage.create(&model).await.unwrap();
+
+    // lengths and offsets are in bytes, chunks may split a multi-byte character
+    let expected = model.result.unwrap().into_bytes();
+    assert_eq!(storage.get_result_len(&task.id).await.unwrap(), Some(expected.len() as u64));
+    let mut read = Vec::new();
+    let mut offset = 0;
+    while offset < expected.len() as u64 {
+        read.extend(storage.read_result_chunk(&task.id, offset, 7).await.unwrap());
+        offset += 7;
+    }
+    assert_eq!(read, expected);
+
+    assert_eq!(storage.get_result_len("missing").await.unwrap(), None);
+    assert!(storage.read_result_chunk("missing", 0, 7).await.unwrap().is_empty());
+}
+
+fn completed_task_with_result(text: &str) -> Task {
+    use crate::schedule::types::{TaskResult, TranscribeResult};
+
+    let mut task = create_test_task(TaskPriority::Normal);
+    task.status = TaskStatus::Completed;
+    task.result = Some(TaskResult::Transcribe(TranscribeResult {
+        text: text.to_string(),
+        detected_language: "en".to_string(),
+        language: "en".to_string(),
+        language_probability: 0.9,
+        ..Default::default()
+    }));
+    task
+}
+
+async fn test_results_are_stored_apart(db: &TestDatabase) {
+    let storage = db.open().await;
+    let task = completed_task_with_result("a long transcript");
+    let model = TaskModel::from(task.clone());
+    storage.create(&model).await.unwrap();
+
+    // listings stay lean, a single task comes with its result
+    let listed = storage.list(&Pagination::default()).await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].result, None);
+    assert_eq!(listed[0].detected_language.as_deref(), Some("en"));
+    assert_eq!(storage.get(&task.id).await.unwrap().unwrap().result, model.result);
+    assert_eq!(storage.get_result(&task.id).await.unwrap(), model.result);
+
+    // saving the task without its result keeps the stored one
+    storage.create(&listed[0]).await.unwrap();
+    assert_eq!(storage.get_result(&task.id).await.unwrap(), model.result);
+
+    storage.delete(&task.id).await.unwrap();
+    assert_eq!(storage.get_result(&task.id).await.unwrap(), None);
+}
+
+async fn test_claim_next_pending_is_exclusive(db: &TestDatabase) {
+    use std::collections::HashSet;
+
+    // two storages on one database behave like two schedulers sharing it
+    let storages = [db.open().await, db.open().await];
+
+    let mut ids = HashSet::new();
+    for priority in [TaskPriority::Low, TaskPriority::Critical, TaskPriority::Normal, TaskPriority::High] {
+        for _ in 0..5 {
+            let task = create_test_task(priority.clone());
+            ids.insert(task.id.clone());
+            storages[0].create(&TaskModel::from(task)).await.unwrap();
+        }
+    }
+
+    let handles: Vec<_> = (0..40).map(|i| {
+        let storage = storages[i % 2].clone();
+        let worker_id = format!("worker-{}", i);
+        tokio::spawn(async move {
+            let claimed = storage.claim_next_pending(&worker_id, &TaskType::Transcribe).await.unwrap();
+            claimed.map(|model| (model, worker_id))
+        })
+    }).collect();
+
+    let mut claimed = HashSet::new();
+    for handle in handles {
+        if let Some((model, worker_id)) = handle.await.unwrap() {
+            assert_eq!(model.claimed_by.as_deref(), Some(worker_id.as_str()));
+            assert_eq!(Task::from(model.clone()).status, TaskStatus::Processing);
+            assert!(model.started_at.is_some());
+            // no task is handed out twice
+            assert!(claimed.insert(model.id));
+        }
+    }
+    assert_eq!(claimed, ids);
+    assert!(storages[1].claim_next_pending("late", &TaskType::Transcribe).await.unwrap().is_none());
+}
+
+async fn test_claim_next_pending_by_priority(db: &TestDatabase) {
+    let storage = db.open().await;
+
+    for priority in [TaskPriority::Low, TaskPriority::High, TaskPriority::Critical] {
+        storage.create(&TaskModel::from(create_test_task(priority))).await.unwrap();
+    }
+
+    let mut order = Vec::new();
+    while let Some(model) = storage.claim_next_pending("worker", &TaskType::Transcribe).await.unwrap() {
+        order.push(Task::from(model).config.priority);
+    }
+    assert_eq!(order, vec![TaskPriority::Critical, TaskPriority::High, TaskPriority::Low]);
+
+    // a later save of the task keeps the claim
+    let model = storage.get_by_status(TaskStatus::Processing.name()).await.unwrap().remove(0);
+    let mut task = Task::from(model.clone());
+    task.status = TaskStatus::Completed;
+    storage.create(&TaskModel::from(task)).await.unwrap();
+    let stored = storage.get(&model.id).await.unwrap().unwrap();
+    assert_eq!(stored.claimed_by.as_deref(), Some("worker"));
+}
+
+async fn test_claim_next_pending_by_task_type(db: &TestDatabase) {
+    let storage = db.open().await;
+
+    let mut transcribe = create_test_task(TaskPriority::Low);
+    transcribe.config.task_type = TaskType::Transcribe;
+    let mut noise_reduction = create_test_task(TaskPriority::Critical);
+    noise_reduction.config.task_type = TaskType::NoiseReduction;
+    for task in [&transcribe, &noise_reduction] {
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+    }
+
+    // a worker only takes tasks of its own type, whatever their priority
+    let claimed = storage.claim_next_pending("worker", &TaskType::Transcribe).await.unwrap().unwrap();
+    assert_eq!(claimed.id, transcribe.id);
+    assert!(storage.claim_next_pending("worker", &TaskType::Transcribe).await.unwrap().is_none());
+    assert!(storage.claim_next_pending("worker", &TaskType::VoiceprintRecognition).await.unwrap().is_none());
+
+    let claimed = storage.claim_next_pending("worker", &TaskType::NoiseReduction).await.unwrap().unwrap();
+    assert_eq!(claimed.id, noise_reduction.id);
+}
+
+async fn test_claim_next_pending_waits_for_input_task(db: &TestDatabase) {
+    let storage = db.open().await;
+
+    let mut denoise = create_test_task(TaskPriority::Normal);
+    denoise.config.task_type = TaskType::NoiseReduction;
+    let mut transcribe = create_test_task(TaskPriority::Critical);
+    if let TaskParams::Transcribe(params) = &mut transcribe.config.params {
+        params.input_task = Some(denoise.id.clone());
+    }
+    for task in [&denoise, &transcribe] {
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+    }
+
+    // the chained task waits while its input task is queued or running
+    assert!(storage.claim_next_pending("worker", &TaskType::Transcribe).await.unwrap().is_none());
+    storage.claim_next_pending("worker", &TaskType::NoiseReduction).await.unwrap().unwrap();
+    assert!(storage.claim_next_pending("worker", &TaskType::Transcribe).await.unwrap().is_none());
+
+    storage.update(&denoise.id, TaskStatus::Completed.name()).await.unwrap();
+    let claimed = storage.claim_next_pending("worker", &TaskType::Transcribe).await.unwrap().unwrap();
+    assert_eq!(claimed.id, transcribe.id);
+}
+
+async fn test_claim_next_pending_waits_for_retry_at(db: &TestDatabase) {
+    let storage = db.open().await;
+    let task = create_test_task(TaskPriority::Normal);
+    storage.create(&TaskModel::from(task.clone())).await.unwrap();
+    storage.update(&task.id, TaskStatus::Retrying.name()).await.unwrap();
+
+    // a retry that is not due yet stays where it is
+    storage.schedule_retry(&task.id, 2, Utc::now() + Duration::minutes(5)).await.unwrap();
+    assert!(storage.claim_next_pending("worker", &TaskType::Transcribe).await.unwrap().is_none());
+
+    storage.schedule_retry(&task.id, 2, Utc::now() - Duration::seconds(1)).await.unwrap();
+    let claimed = storage.claim_next_pending("worker", &TaskType::Transcribe).await.unwrap().unwrap();
+    assert_eq!(claimed.id, task.id);
+    assert_eq!(claimed.status, TaskStatus::Processing.name());
+    assert_eq!(claimed.attempts, 2);
+
+    // saving the task again keeps the attempts
+    let mut task = Task::from(claimed);
+    task.status = TaskStatus::Completed;
+    storage.create(&TaskModel::from(task.clone())).await.unwrap();
+    assert_eq!(storage.get(&task.id).await.unwrap().unwrap().attempts, 2);
+}
+
+async fn test_priority_order_is_consistent(db: &TestDatabase) {
+    let storage = db.open().await;
+
+    for priority in [TaskPriority::Normal, TaskPriority::Low, TaskPriority::Critical, TaskPriority::High] {
+        storage.create(&TaskModel::from(create_test_task(priority))).await.unwrap();
+    }
+    let expected = vec![TaskPriority::Critical, TaskPriority::High, TaskPriority::Normal, TaskPriority::Low];
+    let priorities = |models: Vec<TaskModel>| -> Vec<TaskPriority> {
+        models.into_iter().map(|model| Task::from(model).config.priority).collect()
+    };
+
+    let pending = storage.get_pending_by_priority(10).await.unwrap();
+    assert_eq!(priorities(pending), expected);
+
+    let by_status = storage.get_by_status(TaskStatus::Pending.name()).await.unwrap();
+    assert_eq!(priorities(by_status), expected);
+}
+
+async fn test_count_by_status(db: &TestDatabase) {
+    let storage = db.open().await;
+    for status in [TaskStatus::Pending, TaskStatus::Pending, TaskStatus::Failed("boom".to_string())] {
+        let mut task = create_test_task(TaskPriority::Normal);
+        task.status = status;
+        storage.create(&TaskModel::from(task)).await.unwrap();
+    }
+
+    assert_eq!(storage.count_by_status(TaskStatus::Pending.name()).await.unwrap(), 2);
+    assert_eq!(storage.count_by_status("Failed").await.unwrap(), 1);
+    assert_eq!(storage.count_by_status(TaskStatus::Processing.name()).await.unwrap(), 0);
+}
+
+async fn test_ping(db: &TestDatabase) {
+    let storage = db.open().await;
+    storage.ping().await.unwrap();
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use async_trait::async_trait;
+use anyhow::Result;
+use chrono::Utc;
+use std::sync::Arc;
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbBackend, QueryResult, Statement, TransactionTrait};
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::storage::task::sqlite::{retry_on_busy, SqliteOptions};
+
+/// 已注册说话人的声纹，按所属的 api key（`ApiKeyInfo::id`）隔离，
+/// 同一个说话人 id 在不同 key 下互不影响
+#[async_trait]
+pub trait VoiceprintStorage: Send + Sync {
+    /// 保存说话人的声纹，已注册时覆盖
+    async fn save(&self, owner: &str, speaker_id: &str, embedding: &[f32]) -> Result<()>;
+    /// 某个 key 下所有已注册的说话人及其声纹
+    async fn list(&self, owner: &str) -> Result<Vec<(String, Vec<f32>)>>;
+    /// 删除说话人，返回其是否已注册
+    async fn delete(&self, owner: &str, speaker_id: &str) -> Result<bool>;
+}
+
+/// 按数据库地址选择存储，与任务存储使用同一个数据库
+pub async fn connect(database_url: &str) -> Result<Arc<dyn VoiceprintStorage>> {
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        Ok(Arc::new(PostgresVoiceprintStorage::new(database_url).await?))
+    } else {
+        Ok(Arc::new(SqliteVoiceprintStorage::new(database_url).await?))
+    }
+}
+
+/// 保存在内存中的声纹，重启后丢失
+#[derive(Default)]
+pub struct InMemoryVoiceprintStorage {
+    embeddings: RwLock<HashMap<(String, String), Vec<f32>>>,
+}
+
+impl InMemoryVoiceprintStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl VoiceprintStorage for InMemoryVoiceprintStorage {
+    async fn save(&self, owner: &str, speaker_id: &str, embedding: &[f32]) -> Result<()> {
+        self.embeddings.write().await.insert((owner.to_string(), speaker_id.to_string()), embedding.to_vec());
+        Ok(())
+    }
+
+    async fn list(&self, owner: &str) -> Result<Vec<(String, Vec<f32>)>> {
+        let mut embeddings: Vec<(String, Vec<f32>)> = self.embeddings.read().await
+            .iter()
+            .filter(|((o, _), _)| o == owner)
+            .map(|((_, id), e)| (id.clone(), e.clone()))
+            .collect();
+        embeddings.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(embeddings)
+    }
+
+    async fn delete(&self, owner: &str, speaker_id: &str) -> Result<bool> {
+        Ok(self.embeddings.write().await.remove(&(owner.to_string(), speaker_id.to_string())).is_some())
+    }
+}
+
+/// 保存在 SQLite `voiceprints` 表中的声纹，向量以 JSON 数组存储
+pub struct SqliteVoiceprintStorage {
+    db: DatabaseConnection,
+}
+
+impl SqliteVoiceprintStorage {
+    pub async fn new(database_url: &str) -> Result<Self> {
+        Self::new_with_options(database_url, SqliteOptions::from_env()).await
+    }
+
+    pub async fn new_with_options(database_url: &str, options: SqliteOptions) -> Result<Self> {
+        info!("Initializing SQLite voiceprint storage at {}", database_url);
+        let db = options.connect(database_url).await?;
+
+        // 早期的表没有 owner 列，主键也只有 speaker_id，只能重建。
+        // 原有的声纹不属于任何 key，保留下来由运维重新分配
+        let columns = db
+            .query_all(Statement::from_string(
+                DbBackend::Sqlite,
+                "SELECT name FROM pragma_table_info('voiceprints')".to_string(),
+            ))
+            .await?
+            .into_iter()
+            .map(|row| row.try_get::<String>("", "name"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let txn = db.begin().await?;
+        if !columns.is_empty() && !columns.iter().any(|column| column == "owner") {
+            info!("Migrating the voiceprints table to per key voiceprints");
+            txn.execute(Statement::from_string(
+                DbBackend::Sqlite,
+                "ALTER TABLE voiceprints RENAME TO voiceprints_unscoped".to_string(),
+            ))
+            .await?;
+        }
+        txn.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            r#"CREATE TABLE IF NOT EXISTS voiceprints (
+                owner TEXT NOT NULL,
+                speaker_id TEXT NOT NULL,
+                embedding TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (owner, speaker_id)
+            )"#.to_string(),
+        ))
+        .await?;
+        if !columns.is_empty() && !columns.iter().any(|column| column == "owner") {
+            txn.execute(Statement::from_string(
+                DbBackend::Sqlite,
+                r#"INSERT INTO voiceprints (owner, speaker_id, embedding, updated_at)
+                SELECT '', speaker_id, embedding, updated_at FROM voiceprints_unscoped"#.to_string(),
+            ))
+            .await?;
+            txn.execute(Statement::from_string(DbBackend::Sqlite, "DROP TABLE voiceprints_unscoped".to_string()))
+                .await?;
+        }
+        txn.commit().await?;
+        Ok(Self { db })
+    }
+}
+
+#[async_trait]
+impl VoiceprintStorage for SqliteVoiceprintStorage {
+    async fn save(&self, owner: &str, speaker_id: &str, embedding: &[f32]) -> Result<()> {
+        let embedding = &serde_json::to_string(embedding)?;
+        let db = &self.db;
+        retry_on_busy(move || async move {
+            db.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"INSERT INTO voiceprints (owner, speaker_id, embedding, updated_at) VALUES (?, ?, ?, ?)
+                ON CONFLICT (owner, speaker_id) DO UPDATE SET embedding = excluded.embedding, updated_at = excluded.updated_at"#,
+                [owner.into(), speaker_id.into(), embedding.as_str().into(), Utc::now().to_rfc3339().into()],
+            ))
+            .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(&self, owner: &str) -> Result<Vec<(String, Vec<f32>)>> {
+        let rows = self.db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "SELECT speaker_id, embedding FROM voiceprints WHERE owner = ? ORDER BY speaker_id",
+                [owner.into()],
+            ))
+            .await?;
+        parse_rows(rows)
+    }
+
+    async fn delete(&self, owner: &str, speaker_id: &str) -> Result<bool> {
+        let db = &self.db;
+        retry_on_busy(move || async move {
+            let result = db.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "DELETE FROM voiceprints WHERE owner = ? AND speaker_id = ?",
+                [owner.into(), speaker_id.into()],
+            ))
+            .await?;
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+}
+
+/// 保存在 Postgres `voiceprints` 表中的声纹，多个实例共享
+pub struct PostgresVoiceprintStorage {
+    db: DatabaseConnection,
+}
+
+impl PostgresVoiceprintStorage {
+    pub async fn new(database_url: &str) -> Result<Self> {
+        info!("Initializing Postgres voiceprint storage");
+        let db = Database::connect(database_url).await?;
+        db.execute(Statement::from_string(
+            DbBackend::Postgres,
+            r#"CREATE TABLE IF NOT EXISTS voiceprints (
+                owner TEXT NOT NULL,
+                speaker_id TEXT NOT NULL,
+                embedding TEXT NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL,
+                PRIMARY KEY (owner, speaker_id)
+            )"#.to_string(),
+        ))
+        .await?;
+        Ok(Self { db })
+    }
+}
+
+#[async_trait]
+impl VoiceprintStorage for PostgresVoiceprintStorage {
+    async fn save(&self, owner: &str, speaker_id: &str, embedding: &[f32]) -> Result<()> {
+        self.db.execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"INSERT INTO voiceprints (owner, speaker_id, embedding, updated_at) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (owner, speaker_id) DO UPDATE SET embedding = EXCLUDED.embedding, updated_at = EXCLUDED.updated_at"#,
+            [owner.into(), speaker_id.into(), serde_json::to_string(embedding)?.into(), Utc::now().into()],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn list(&self, owner: &str) -> Result<Vec<(String, Vec<f32>)>> {
+        let rows = self.db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT speaker_id, embedding FROM voiceprints WHERE owner = $1 ORDER BY speaker_id",
+                [owner.into()],
+            ))
+            .await?;
+        parse_rows(rows)
+    }
+
+    async fn delete(&self, owner: &str, speaker_id: &str) -> Result<bool> {
+        let result = self.db.execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "DELETE FROM voiceprints WHERE owner = $1 AND speaker_id = $2",
+            [owner.into(), speaker_id.into()],
+        ))
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+fn parse_rows(rows: Vec<QueryResult>) -> Result<Vec<(String, Vec<f32>)>> {
+    rows.into_iter()
+        .map(|row| {
+            let speaker_id: String = row.try_get("", "speaker_id")?;
+            let embedding: String = row.try_get("", "embedding")?;
+            Ok((speaker_id, serde_json::from_str(&embedding)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sqlite_voiceprints() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("voiceprints.db").display());
+        let storage = SqliteVoiceprintStorage::new(&url).await.unwrap();
+
+        storage.save("kid-a", "alice", &[0.5, 0.25]).await.unwrap();
+        storage.save("kid-a", "bob", &[1.0, 0.0]).await.unwrap();
+        // 再次注册覆盖原有声纹
+        storage.save("kid-a", "alice", &[0.0, 1.0]).await.unwrap();
+        // 其他 key 下的同名说话人互不影响
+        storage.save("kid-b", "alice", &[1.0, 1.0]).await.unwrap();
+
+        // 重新连接后仍然存在
+        let storage = SqliteVoiceprintStorage::new(&url).await.unwrap();
+        assert_eq!(storage.list("kid-a").await.unwrap(), vec![
+            ("alice".to_string(), vec![0.0, 1.0]),
+            ("bob".to_string(), vec![1.0, 0.0]),
+        ]);
+        assert_eq!(storage.list("kid-b").await.unwrap(), vec![("alice".to_string(), vec![1.0, 1.0])]);
+
+        assert!(!storage.delete("kid-b", "bob").await.unwrap());
+        assert!(storage.delete("kid-a", "bob").await.unwrap());
+        assert!(!storage.delete("kid-a", "bob").await.unwrap());
+        assert_eq!(storage.list("kid-a").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_migrates_unscoped_voiceprints() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("voiceprints.db").display());
+        let db = SqliteOptions::default().connect(&url).await.unwrap();
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            "CREATE TABLE voiceprints (speaker_id TEXT PRIMARY KEY NOT NULL, embedding TEXT NOT NULL, updated_at TEXT NOT NULL)".to_string(),
+        )).await.unwrap();
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            "INSERT INTO voiceprints VALUES ('alice', '[0.5,0.25]', '2024-01-01T00:00:00Z')".to_string(),
+        )).await.unwrap();
+        drop(db);
+
+        // 原有的声纹不属于任何 key
+        let storage = SqliteVoiceprintStorage::new(&url).await.unwrap();
+        assert_eq!(storage.list("").await.unwrap(), vec![("alice".to_string(), vec![0.5, 0.25])]);
+        assert!(storage.list("kid-a").await.unwrap().is_empty());
+    }
+}
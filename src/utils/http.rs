@@ -1,32 +1,597 @@
-use serde::{Deserialize, Serialize};
+use base64::Engine;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use futures::{Stream, StreamExt};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
 use std::path::PathBuf;
+use std::time::Duration;
 use anyhow::Result;
-use tracing::info;
+use rand::Rng;
+use tracing::{info, warn};
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct HttpResponse<T> {
-    pub code: u16,
-    pub message: String,
-    pub body: T,
+// 除 audio/* 之外允许下载的容器格式
+const ALLOWED_CONTENT_TYPES: &[&str] = &[
+    "video/mp4",
+    "video/webm",
+    "video/ogg",
+    "application/ogg",
+    "application/octet-stream",
+];
+
+/// HTTP 请求的重试策略，用于音频下载和任务回调
+///
+/// 只重试连接错误、超时以及 5xx/429 响应，第 n 次重试前等待 `base_delay * 2^(n-1)`，
+/// 不超过 `max_delay`；服务端返回 `Retry-After` 时以其为准
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// 最大请求次数（含首次），为 1 时不重试
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// 是否在退避时间上加入随机抖动
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// 不重试
+    pub fn none() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+
+    /// 第 `attempt` 次请求失败后的等待时间
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
 }
 
-impl<T> HttpResponse<T> {
-    pub fn new(code: u16, message: String, body: T) -> Self {
-        Self { code, message, body }
+/// 解析 `Retry-After`，支持秒数与 HTTP 日期
+pub(crate) fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
     }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
 }
 
+/// 发送 GET 请求，按重试策略重试临时性失败
+async fn get_with_retry(url: &reqwest::Url, policy: &RetryPolicy) -> Result<reqwest::Response> {
+    let mut attempt = 1;
+    loop {
+        let delay = match reqwest::get(url.clone()).await {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                let retryable = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                if !retryable || attempt >= policy.max_attempts {
+                    return Err(DownloadError::Unreachable(format!("HTTP request failed with status: {}", status)).into());
+                }
+                warn!("HTTP request to {} failed with status {} (attempt {}/{})", url, status, attempt, policy.max_attempts);
+                retry_after(response.headers())
+                    .map(|delay| delay.min(policy.max_delay))
+                    .unwrap_or_else(|| policy.backoff(attempt))
+            }
+            Err(e) => {
+                let retryable = e.is_connect() || e.is_timeout();
+                if !retryable || attempt >= policy.max_attempts {
+                    return Err(DownloadError::Unreachable(format!("HTTP request failed: {}", e)).into());
+                }
+                warn!("HTTP request to {} failed: {} (attempt {}/{})", url, e, attempt, policy.max_attempts);
+                policy.backoff(attempt)
+            }
+        };
+
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
 
-pub async fn download_audio(url: &str, dest_dir: &PathBuf) -> Result<PathBuf> {
+/// 下载参数
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// 允许下载的最大字节数
+    pub max_bytes: u64,
+    /// 期望的 SHA-256（十六进制），设置后下载完成时校验
+    pub expected_sha256: Option<String>,
+    pub retry: RetryPolicy,
+    /// 是否合并同一 URL 的并发下载，默认开启
+    pub single_flight: bool,
+    /// 下载前是否先探测大小和类型，默认关闭
+    pub probe: bool,
+}
+
+impl DownloadOptions {
+    pub fn new(max_bytes: u64) -> Self {
+        Self { max_bytes, expected_sha256: None, retry: RetryPolicy::default(), single_flight: true, probe: false }
+    }
+
+    /// 开启后下载前先用 HEAD（或只取首字节的 Range GET）检查大小和类型，
+    /// 超限的文件不会被下载
+    pub fn with_probe(mut self, probe: bool) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    pub fn with_single_flight(mut self, single_flight: bool) -> Self {
+        self.single_flight = single_flight;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_expected_sha256(mut self, expected_sha256: Option<String>) -> Self {
+        self.expected_sha256 = expected_sha256;
+        self
+    }
+}
+
+/// 下载音频时的请求和校验错误，web 层据此返回 413/415/422
+#[derive(Debug, Clone)]
+pub enum DownloadError {
+    /// URL 无效，或请求失败（重试后仍失败）
+    Unreachable(String),
+    TooLarge { max_bytes: u64 },
+    UnsupportedContentType(String),
+    Truncated { expected: u64, actual: u64 },
+    ChecksumMismatch { algorithm: &'static str, expected: String, actual: String },
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Unreachable(message) => write!(f, "{}", message),
+            DownloadError::TooLarge { max_bytes } => {
+                write!(f, "audio file exceeds the maximum size of {} bytes", max_bytes)
+            }
+            DownloadError::UnsupportedContentType(content_type) => {
+                write!(f, "unsupported content type: {}", content_type)
+            }
+            DownloadError::Truncated { expected, actual } => {
+                write!(f, "download truncated: expected {} bytes, got {}", expected, actual)
+            }
+            DownloadError::ChecksumMismatch { algorithm, expected, actual } => {
+                write!(f, "{} checksum mismatch: expected {}, got {}", algorithm, expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+fn is_allowed_content_type(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    mime.starts_with("audio/") || ALLOWED_CONTENT_TYPES.contains(&mime.as_str())
+}
+
+/// 服务端提供的 MD5，来自 `Content-MD5`（base64）或形如 MD5 的强 `ETag`，返回十六进制
+fn server_md5(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    if let Some(value) = headers.get("content-md5").and_then(|value| value.to_str().ok()) {
+        if let Ok(digest) = base64::engine::general_purpose::STANDARD.decode(value.trim()) {
+            if digest.len() == 16 {
+                return Some(hex::encode(digest));
+            }
+        }
+    }
+
+    // 分段上传等情况下 ETag 不是 MD5，只接受 32 位十六进制的强 ETag
+    let etag = headers.get(reqwest::header::ETAG).and_then(|value| value.to_str().ok())?;
+    let etag = etag.trim().strip_prefix('"')?.strip_suffix('"')?;
+    if etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Some(etag.to_ascii_lowercase());
+    }
+    None
+}
+
+/// 根据内容类型推断文件扩展名
+fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    let extension = match mime.as_str() {
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/wav" | "audio/x-wav" | "audio/wave" | "audio/vnd.wave" => "wav",
+        "audio/aac" | "audio/x-aac" => "aac",
+        "audio/amr" => "amr",
+        "audio/mp4" | "audio/x-m4a" | "audio/m4a" => "m4a",
+        "audio/ogg" | "application/ogg" | "video/ogg" => "ogg",
+        "audio/opus" => "opus",
+        "audio/flac" | "audio/x-flac" => "flac",
+        "audio/x-ms-wma" => "wma",
+        "audio/webm" | "video/webm" => "webm",
+        "video/mp4" => "mp4",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// 从 `Content-Disposition` 中取出文件名，支持 `filename*=UTF-8''name` 与 `filename="name"`
+fn content_disposition_filename(header: &str) -> Option<String> {
+    let mut filename = None;
+    for part in header.split(';').map(str::trim) {
+        let Some((key, value)) = part.split_once('=') else { continue };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                // RFC 5987 编码优先
+                let encoded = value.trim().splitn(3, '\'').nth(2)?;
+                return Some(percent_decode(encoded));
+            }
+            "filename" => filename = Some(value.trim().trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+    filename
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(byte) = hex {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// 只保留文件名本身，去掉路径及不安全的字符
+fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("").trim();
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    Some(name)
+}
+
+/// 确定下载文件的文件名
+///
+/// 优先使用 `Content-Disposition` 中的文件名，其次是 URL 路径（不含查询参数）的最后一段，
+/// 都没有时生成 UUID 文件名；缺少扩展名时根据内容类型补全
+fn resolve_filename(url: &reqwest::Url, content_disposition: Option<&str>, content_type: &str) -> String {
+    let name = content_disposition
+        .and_then(content_disposition_filename)
+        .and_then(|name| sanitize_filename(&name))
+        .or_else(|| {
+            url.path_segments()
+                .and_then(|segments| segments.filter(|s| !s.is_empty()).last())
+                .and_then(|segment| sanitize_filename(&percent_decode(segment)))
+        });
+    complete_filename(name, content_type)
+}
+
+/// 缺少扩展名时根据内容类型补全，没有文件名时生成 UUID 文件名
+fn complete_filename(name: Option<String>, content_type: &str) -> String {
+    let extension = extension_for_content_type(content_type);
+    match name {
+        Some(name) if std::path::Path::new(&name).extension().is_some() => name,
+        Some(name) => match extension {
+            Some(extension) => format!("{}.{}", name, extension),
+            None => name,
+        },
+        None => match extension {
+            Some(extension) => format!("{}.{}", uuid::Uuid::new_v4(), extension),
+            None => uuid::Uuid::new_v4().to_string(),
+        },
+    }
+}
+
+/// 下载音频文件到指定目录
+///
+/// 同一 URL 的并发下载合并为一次请求（single-flight），下载结果按内容的 SHA-256 存放，
+/// 每个调用方得到指向该内容的独立硬链接，互不影响各自的清理。
+/// 优先使用解析出的文件名，已被占用时在文件名后追加随机后缀
+pub async fn download_audio(url: &str, dest_dir: &PathBuf, options: &DownloadOptions) -> Result<PathBuf> {
+    if !options.single_flight {
+        let (content_path, filename) = fetch_audio(url, dest_dir, options).await?;
+        let path = link_download(&content_path, dest_dir, &filename).await;
+        remove_content_file(&content_path);
+        return path;
+    }
+
+    let key = format!("{}\n{}\n{}\n{:?}\n{}", url, dest_dir.display(), options.max_bytes, options.expected_sha256, options.probe);
+    let (flight, _guard) = {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        let entry = in_flight.entry(key.clone()).or_insert_with(|| {
+            let (url, dest_dir, options) = (url.to_string(), dest_dir.clone(), options.clone());
+            InFlight {
+                download: async move {
+                    fetch_audio(&url, &dest_dir, &options).await.map_err(Arc::new)
+                }.boxed().shared(),
+                waiters: 0,
+            }
+        });
+        entry.waiters += 1;
+        (entry.download.clone(), FlightGuard { key })
+    };
+
+    match flight.await {
+        Ok((content_path, filename)) => link_download(&content_path, dest_dir, &filename).await,
+        Err(e) => Err(clone_error(&e)),
+    }
+}
+
+type SharedDownload = Shared<BoxFuture<'static, std::result::Result<(PathBuf, String), Arc<anyhow::Error>>>>;
+
+struct InFlight {
+    download: SharedDownload,
+    waiters: usize,
+}
+
+// 进行中的下载，键包含 URL、目录及校验参数
+static IN_FLIGHT: Lazy<Mutex<HashMap<String, InFlight>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 调用方完成（或被取消）时减少计数，最后一个调用方移除记录并删除共享的内容文件。
+/// 删除在持锁时进行，之后同一 URL 的下载会重新发起，不会与这里的删除冲突
+struct FlightGuard {
+    key: String,
+}
+
+impl Drop for FlightGuard {
+    fn drop(&mut self) {
+        let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(entry) = in_flight.get_mut(&self.key) else { return };
+        entry.waiters -= 1;
+        if entry.waiters > 0 {
+            return;
+        }
+        if let Some(InFlight { download, .. }) = in_flight.remove(&self.key) {
+            if let Some(Ok((content_path, _))) = download.peek() {
+                remove_content_file(content_path);
+            }
+        }
+    }
+}
+
+// 按内容命名的文件被多少次下载引用。不同 URL 的相同内容落在同一个文件上，
+// 最后一个引用释放时才删除
+static CONTENT_REFS: Lazy<Mutex<HashMap<PathBuf, usize>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 将下载完成的临时文件移到按内容命名的位置，并增加该文件的引用计数。
+/// 移动和计数在同一把锁内完成，不会与 `remove_content_file` 的删除交错
+fn store_content(part_path: &std::path::Path, content_path: &std::path::Path) -> std::io::Result<()> {
+    let mut refs = CONTENT_REFS.lock().unwrap_or_else(|e| e.into_inner());
+    std::fs::rename(part_path, content_path)?;
+    *refs.entry(content_path.to_path_buf()).or_insert(0) += 1;
+    Ok(())
+}
+
+/// 释放一次对内容文件的引用，没有其他引用时删除文件
+fn remove_content_file(content_path: &std::path::Path) {
+    let mut refs = CONTENT_REFS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(count) = refs.get_mut(content_path) {
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        refs.remove(content_path);
+    }
+    if let Err(e) = std::fs::remove_file(content_path) {
+        warn!("Failed to remove downloaded content {:?}: {}", content_path, e);
+    }
+}
+
+/// 写入中的临时文件，丢弃时删除。出错或下载被取消（future 被丢弃）时都不会留下残片，
+/// 已被移走的文件删除时不存在，直接忽略
+struct PartFile {
+    path: PathBuf,
+}
+
+impl PartFile {
+    fn new(dir: &std::path::Path) -> Self {
+        Self { path: dir.join(format!(".{}.part", uuid::Uuid::new_v4())) }
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove partial file {:?}: {}", self.path, e),
+        }
+    }
+}
+
+/// 共享结果中的错误无法移动，复制一份，保留 `DownloadError` 以便 web 层映射状态码
+fn clone_error(error: &anyhow::Error) -> anyhow::Error {
+    match error.downcast_ref::<DownloadError>() {
+        Some(download_error) => download_error.clone().into(),
+        None => anyhow::anyhow!("{:#}", error),
+    }
+}
+
+/// 为下载内容创建调用方自己的硬链接，文件系统不支持时退回复制
+async fn link_download(content_path: &std::path::Path, dest_dir: &std::path::Path, filename: &str) -> Result<PathBuf> {
+    let preferred = dest_dir.join(filename);
+    let unique = {
+        let name = std::path::Path::new(filename);
+        let stem = name.file_stem().and_then(|s| s.to_str()).unwrap_or("audio");
+        match name.extension().and_then(|e| e.to_str()) {
+            Some(extension) => dest_dir.join(format!("{}-{}.{}", stem, uuid::Uuid::new_v4(), extension)),
+            None => dest_dir.join(format!("{}-{}", stem, uuid::Uuid::new_v4())),
+        }
+    };
+
+    for target in [preferred, unique] {
+        match fs::hard_link(content_path, &target).await {
+            Ok(()) => return Ok(target),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                warn!("Failed to link {:?}, copying instead: {}", target, e);
+                if fs::try_exists(&target).await.unwrap_or(true) {
+                    continue;
+                }
+                fs::copy(content_path, &target).await
+                    .map_err(|e| anyhow::anyhow!("Failed to copy downloaded file: {}", e))?;
+                return Ok(target);
+            }
+        }
+    }
+    Err(anyhow::anyhow!("Failed to create a file for the download of {}", filename))
+}
+
+/// 将上传的音频流保存到指定目录，返回文件路径和内容的 SHA-256（十六进制）
+///
+/// 大小限制与下载相同，超限时中止并删除已写入的部分。文件名只保留上传文件名本身，
+/// 缺少扩展名时根据内容类型补全；未声明内容类型的上传不校验类型
+pub async fn save_upload<S, B, E>(
+    chunks: S,
+    dest_dir: &PathBuf,
+    filename: Option<&str>,
+    content_type: &str,
+    max_bytes: u64,
+) -> Result<(PathBuf, String)>
+where
+    S: Stream<Item = std::result::Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    if !content_type.is_empty() && !is_allowed_content_type(content_type) {
+        return Err(DownloadError::UnsupportedContentType(content_type.to_string()).into());
+    }
+    fs::create_dir_all(dest_dir).await
+        .map_err(|e| anyhow::anyhow!("Failed to create directory: {}", e))?;
+
+    let filename = complete_filename(filename.and_then(sanitize_filename), content_type);
+    let part = PartFile::new(dest_dir);
+    let mut file = fs::File::create(&part.path).await
+        .map_err(|e| anyhow::anyhow!("Failed to create file: {}", e))?;
+
+    // 分块写入文件，同时计算摘要
+    let mut written: u64 = 0;
+    let mut sha256 = Sha256::new();
+    let result: Result<PathBuf> = async {
+        futures::pin_mut!(chunks);
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|e| anyhow::anyhow!("Failed to read upload: {}", e))?;
+            let chunk = chunk.as_ref();
+            written += chunk.len() as u64;
+            if written > max_bytes {
+                return Err(DownloadError::TooLarge { max_bytes }.into());
+            }
+            sha256.update(chunk);
+            file.write_all(chunk).await
+                .map_err(|e| anyhow::anyhow!("Failed to write file: {}", e))?;
+        }
+        file.flush().await
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}", e))?;
+        link_download(&part.path, dest_dir, &filename).await
+    }.await;
+
+    drop(file);
+    drop(part);
+    let path = result?;
+    info!("Upload saved to {:?} ({} bytes)", path, written);
+    Ok((path, hex::encode(sha256.finalize())))
+}
+
+/// 下载前探测音频的大小和类型
+///
+/// 先发送 HEAD 请求读取 `Content-Length` 和 `Content-Type`；服务端不支持 HEAD 时
+/// 退回只请求首字节的 Range GET，从 `Content-Range` 得到总长度。
+/// 探测本身失败时不阻止下载，大小和类型仍由下载过程校验
+async fn probe_audio(url: &reqwest::Url, max_bytes: u64) -> Result<()> {
+    let client = reqwest::Client::new();
+    let head = match client.head(url.clone()).send().await {
+        Ok(response) if response.status().is_success() => Some(response),
+        Ok(response) => {
+            info!("HEAD {} returned {}, probing with a ranged GET", url, response.status());
+            None
+        }
+        Err(e) => {
+            info!("HEAD {} failed, probing with a ranged GET: {}", url, e);
+            None
+        }
+    };
+
+    // HEAD 响应没有响应体，reqwest 的 content_length() 为 0，直接读取头部
+    let (length, headers) = match head {
+        Some(response) => (header_u64(response.headers(), reqwest::header::CONTENT_LENGTH), response.headers().clone()),
+        None => match client.get(url.clone()).header(reqwest::header::RANGE, "bytes=0-0").send().await {
+            Ok(response) if response.status() == reqwest::StatusCode::PARTIAL_CONTENT => {
+                (content_range_total(response.headers()), response.headers().clone())
+            }
+            // 忽略了 Range 的服务端返回完整内容，丢弃响应即中止传输
+            Ok(response) if response.status().is_success() => {
+                (header_u64(response.headers(), reqwest::header::CONTENT_LENGTH), response.headers().clone())
+            }
+            Ok(response) => {
+                info!("Probing {} returned {}, downloading without a probe", url, response.status());
+                return Ok(());
+            }
+            Err(e) => {
+                info!("Probing {} failed, downloading without a probe: {}", url, e);
+                return Ok(());
+            }
+        },
+    };
+
+    if matches!(length, Some(length) if length > max_bytes) {
+        return Err(DownloadError::TooLarge { max_bytes }.into());
+    }
+    if let Some(content_type) = headers.get(reqwest::header::CONTENT_TYPE).and_then(|value| value.to_str().ok()) {
+        if !is_allowed_content_type(content_type) {
+            return Err(DownloadError::UnsupportedContentType(content_type.to_string()).into());
+        }
+    }
+    Ok(())
+}
+
+fn header_u64(headers: &reqwest::header::HeaderMap, name: reqwest::header::HeaderName) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// `Content-Range: bytes 0-0/12345` 中的总长度，未知（`*`）时为 None
+fn content_range_total(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    let value = headers.get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    value.rsplit('/').next()?.trim().parse().ok()
+}
+
+/// 执行一次实际的下载
+///
+/// 响应体分块写入磁盘，超过 `max_bytes` 时中止并删除已写入的部分；
+/// `Content-Type` 必须为 audio/* 或已知的容器格式。
+/// 下载完成后校验长度，以及请求给出的 SHA-256 和服务端提供的 MD5，不一致时删除文件。
+/// 返回按 SHA-256 命名的内容文件及解析出的文件名
+async fn fetch_audio(url: &str, dest_dir: &PathBuf, options: &DownloadOptions) -> Result<(PathBuf, String)> {
+    let max_bytes = options.max_bytes;
     info!("Starting download from URL: {}", url);
-    
-    // 从 URL 中提取文件名
-    let filename = url.split('/').last()
-        .ok_or_else(|| anyhow::anyhow!("Invalid URL: no filename found"))?;
-    
-    let dest_path = dest_dir.join(filename);
-    info!("Destination path: {:?}", dest_path);
+    let parsed_url = reqwest::Url::parse(url)
+        .map_err(|e| DownloadError::Unreachable(format!("Invalid URL {}: {}", url, e)))?;
 
     // 创建目标目录（如果不存在）
     if !dest_dir.exists() {
@@ -34,38 +599,413 @@ pub async fn download_audio(url: &str, dest_dir: &PathBuf) -> Result<PathBuf> {
             .map_err(|e| anyhow::anyhow!("Failed to create directory: {}", e))?;
     }
 
-    // 发送 HTTP GET 请求
-    let response = reqwest::get(url).await
-        .map_err(|e| anyhow::anyhow!("HTTP request failed: {}", e))?;
+    // 先探测，超限或类型不符的文件不下载
+    if options.probe {
+        probe_audio(&parsed_url, max_bytes).await?;
+    }
+
+    // 发送 HTTP GET 请求，临时性失败按策略重试
+    let response = get_with_retry(&parsed_url, &options.retry).await?;
+
+    // 校验内容类型
+    let content_type = response.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    if !is_allowed_content_type(&content_type) {
+        return Err(DownloadError::UnsupportedContentType(content_type).into());
+    }
 
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "HTTP request failed with status: {}", 
-            response.status()
-        ));
+    // 确定文件名
+    let content_disposition = response.headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|value| value.to_str().ok());
+    let filename = resolve_filename(&parsed_url, content_disposition, &content_type);
+    // 声明的长度已超限时直接拒绝
+    if matches!(response.content_length(), Some(len) if len > max_bytes) {
+        return Err(DownloadError::TooLarge { max_bytes }.into());
     }
 
-    // 读取响应内容
-    let bytes = response.bytes().await
-        .map_err(|e| anyhow::anyhow!("Failed to read response: {}", e))?;
+    let content_length = response.content_length();
+    let expected_md5 = server_md5(response.headers());
 
-    // 写入文件
-    fs::write(&dest_path, bytes).await
-        .map_err(|e| anyhow::anyhow!("Failed to write file: {}", e))?;
+    // 分块写入文件，同时计算摘要
+    let mut response = response;
+    let part = PartFile::new(dest_dir);
+    let mut file = fs::File::create(&part.path).await
+        .map_err(|e| anyhow::anyhow!("Failed to create file: {}", e))?;
+    let mut written: u64 = 0;
+    let mut sha256 = Sha256::new();
+    let mut md5 = Md5::new();
+    let result: Result<PathBuf> = async {
+        while let Some(chunk) = response.chunk().await
+            .map_err(|e| anyhow::anyhow!("Failed to read response: {}", e))?
+        {
+            written += chunk.len() as u64;
+            if written > max_bytes {
+                return Err(DownloadError::TooLarge { max_bytes }.into());
+            }
+            sha256.update(&chunk);
+            md5.update(&chunk);
+            file.write_all(&chunk).await
+                .map_err(|e| anyhow::anyhow!("Failed to write file: {}", e))?;
+        }
+        file.flush().await
+            .map_err(|e| anyhow::anyhow!("Failed to write file: {}", e))?;
 
-    info!("Download completed successfully");
-    Ok(dest_path)
+        // 校验长度与摘要
+        if let Some(expected) = content_length {
+            if written != expected {
+                return Err(DownloadError::Truncated { expected, actual: written }.into());
+            }
+        }
+        let digest = hex::encode(sha256.finalize());
+        if let Some(expected) = &options.expected_sha256 {
+            if !digest.eq_ignore_ascii_case(expected.trim()) {
+                return Err(DownloadError::ChecksumMismatch {
+                    algorithm: "sha256",
+                    expected: expected.trim().to_ascii_lowercase(),
+                    actual: digest,
+                }.into());
+            }
+        }
+        if let Some(expected) = expected_md5 {
+            let actual = hex::encode(md5.finalize());
+            if actual != expected {
+                return Err(DownloadError::ChecksumMismatch { algorithm: "md5", expected, actual }.into());
+            }
+        }
+
+        // 按内容命名，相同内容的文件只保留一份
+        let content_path = match std::path::Path::new(&filename).extension().and_then(|e| e.to_str()) {
+            Some(extension) => dest_dir.join(format!("{}.{}", digest, extension)),
+            None => dest_dir.join(&digest),
+        };
+        store_content(&part.path, &content_path)
+            .map_err(|e| anyhow::anyhow!("Failed to move downloaded file: {}", e))?;
+        Ok(content_path)
+    }.await;
+
+    // 未移走的临时文件在 `part` 丢弃时删除
+    let content_path = result?;
+    info!("Download completed successfully: {:?}", content_path);
+    Ok((content_path, filename))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use axum::{routing::get, Router, http::header};
+
     #[tokio::test]
     async fn test_download_audio() {
         let url = "https://cdn.myshell.ai/audio/opensource/comparison-with-state-of-the-arts/20240201/comp-cn-1-xtts.mp3";
         let dest = PathBuf::from("./xtts.mp3");
-        let result = download_audio(url, &dest).await;
+        let result = download_audio(url, &dest, &DownloadOptions::new(100 * 1024 * 1024)).await;
         assert!(result.is_ok());
     }
+
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn test_resolve_filename() {
+        let url = |s: &str| reqwest::Url::parse(s).unwrap();
+
+        // query strings are not part of the filename
+        assert_eq!(resolve_filename(&url("https://host/audio/talk.mp3?token=abc"), None, "audio/mpeg"), "talk.mp3");
+        assert_eq!(resolve_filename(&url("https://host/files?id=123"), None, "audio/mpeg"), "files.mp3");
+
+        // no path segment falls back to a generated name
+        let name = resolve_filename(&url("https://host/?id=123"), None, "audio/wav");
+        assert!(name.ends_with(".wav"));
+        assert!(uuid::Uuid::parse_str(name.trim_end_matches(".wav")).is_ok());
+
+        // content disposition wins over the url, path components are dropped
+        assert_eq!(
+            resolve_filename(&url("https://host/download?id=1"), Some("attachment; filename=\"../episode 1.m4a\""), "audio/mp4"),
+            "episode 1.m4a"
+        );
+        assert_eq!(
+            resolve_filename(&url("https://host/download"), Some("attachment; filename=\"a.mp3\"; filename*=UTF-8''%E8%AF%AD%E9%9F%B3.mp3"), "audio/mpeg"),
+            "语音.mp3"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_audio_content_disposition() {
+        let base = serve(Router::new()
+            .route("/download", get(|| async {
+                ([(header::CONTENT_TYPE, "audio/mpeg"), (header::CONTENT_DISPOSITION, "attachment; filename=\"meeting.mp3\"")], vec![1u8; 16])
+            }))
+        ).await;
+        let dest_dir = tempfile::tempdir().unwrap().into_path();
+
+        let path = download_audio(&format!("{}/download?id=123", base), &dest_dir, &DownloadOptions::new(4096)).await.unwrap();
+        assert_eq!(path, dest_dir.join("meeting.mp3"));
+    }
+
+    #[tokio::test]
+    async fn test_download_audio_checksum() {
+        let body = vec![7u8; 2048];
+        let sha256 = hex::encode(Sha256::digest(&body));
+        let md5 = base64::engine::general_purpose::STANDARD.encode(Md5::digest(&body));
+        let base = serve(Router::new()
+            .route("/a.mp3", get(move || async move { ([(header::CONTENT_TYPE, "audio/mpeg")], body) }))
+            .route("/md5.mp3", get(move || async move {
+                ([(header::CONTENT_TYPE, "audio/mpeg".to_string()), (header::HeaderName::from_static("content-md5"), md5)], vec![7u8; 2048])
+            }))
+            .route("/bad-md5.mp3", get(|| async {
+                ([(header::CONTENT_TYPE, "audio/mpeg"), (header::ETAG, "\"00000000000000000000000000000000\"")], vec![7u8; 2048])
+            }))
+        ).await;
+        let dest_dir = tempfile::tempdir().unwrap().into_path();
+
+        let options = DownloadOptions::new(4096).with_expected_sha256(Some(sha256.to_uppercase()));
+        assert!(download_audio(&format!("{}/a.mp3", base), &dest_dir, &options).await.is_ok());
+        assert!(download_audio(&format!("{}/md5.mp3", base), &dest_dir, &DownloadOptions::new(4096)).await.is_ok());
+
+        // a mismatched checksum rejects the download and removes the file
+        let files = std::fs::read_dir(&dest_dir).unwrap().count();
+        let options = DownloadOptions::new(4096).with_expected_sha256(Some("0".repeat(64)));
+        let err = download_audio(&format!("{}/a.mp3", base), &dest_dir, &options).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::ChecksumMismatch { algorithm: "sha256", .. })));
+        assert!(err.to_string().contains("sha256 checksum mismatch"));
+        assert_eq!(std::fs::read_dir(&dest_dir).unwrap().count(), files);
+
+        let err = download_audio(&format!("{}/bad-md5.mp3", base), &dest_dir, &DownloadOptions::new(4096)).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::ChecksumMismatch { algorithm: "md5", .. })));
+    }
+
+    #[tokio::test]
+    async fn test_download_audio_retry() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use axum::http::StatusCode;
+
+        // fails twice with 503 before succeeding
+        let flaky_hits = Arc::new(AtomicUsize::new(0));
+        let missing_hits = Arc::new(AtomicUsize::new(0));
+        let flaky = flaky_hits.clone();
+        let missing = missing_hits.clone();
+        let base = serve(Router::new()
+            .route("/flaky.mp3", get(move || async move {
+                if flaky.fetch_add(1, Ordering::SeqCst) < 2 {
+                    (StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, "0")], Vec::new())
+                } else {
+                    (StatusCode::OK, [(header::CONTENT_TYPE, "audio/mpeg")], vec![1u8; 16])
+                }
+            }))
+            .route("/missing.mp3", get(move || async move {
+                missing.fetch_add(1, Ordering::SeqCst);
+                StatusCode::NOT_FOUND
+            }))
+        ).await;
+        let dest_dir = tempfile::tempdir().unwrap().into_path();
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            jitter: true,
+        };
+
+        let options = DownloadOptions::new(4096).with_retry(RetryPolicy::none());
+        assert!(download_audio(&format!("{}/flaky.mp3", base), &dest_dir, &options).await.is_err());
+        flaky_hits.store(0, Ordering::SeqCst);
+
+        let options = DownloadOptions::new(4096).with_retry(retry);
+        assert!(download_audio(&format!("{}/flaky.mp3", base), &dest_dir, &options).await.is_ok());
+        assert_eq!(flaky_hits.load(Ordering::SeqCst), 3);
+
+        // client errors are not retried
+        let err = download_audio(&format!("{}/missing.mp3", base), &dest_dir, &options).await.unwrap_err();
+        assert!(err.to_string().contains("404"));
+        assert_eq!(missing_hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_downloads_single_flight() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let base = serve(Router::new()
+            .route("/shared.mp3", get(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                ([(header::CONTENT_TYPE, "audio/mpeg")], vec![3u8; 1024])
+            }))
+        ).await;
+        let dest_dir = tempfile::tempdir().unwrap().into_path();
+        let url = format!("{}/shared.mp3", base);
+
+        let handles: Vec<_> = (0..10).map(|_| {
+            let (url, dest_dir) = (url.clone(), dest_dir.clone());
+            tokio::spawn(async move { download_audio(&url, &dest_dir, &DownloadOptions::new(4096)).await })
+        }).collect();
+        let mut paths = Vec::new();
+        for handle in handles {
+            paths.push(handle.await.unwrap().unwrap());
+        }
+
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // every caller owns a distinct file with the shared content
+        let unique: std::collections::HashSet<_> = paths.iter().collect();
+        assert_eq!(unique.len(), 10);
+        assert!(paths.contains(&dest_dir.join("shared.mp3")));
+        for path in &paths {
+            assert_eq!(std::fs::read(path).unwrap(), vec![3u8; 1024]);
+        }
+
+        // removing one caller's file leaves the others intact, and the shared content file is gone
+        std::fs::remove_file(&paths[0]).unwrap();
+        assert!(paths[1].exists());
+        assert_eq!(std::fs::read_dir(&dest_dir).unwrap().count(), 9);
+    }
+
+    #[tokio::test]
+    async fn test_same_content_from_different_urls() {
+        let base = serve(Router::new()
+            .route("/a.mp3", get(|| async { ([(header::CONTENT_TYPE, "audio/mpeg")], vec![5u8; 1024]) }))
+            .route("/b.mp3", get(|| async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                ([(header::CONTENT_TYPE, "audio/mpeg")], vec![5u8; 1024])
+            }))
+        ).await;
+        let dest_dir = tempfile::tempdir().unwrap().into_path();
+
+        // both downloads share one content file, the first to finish must not delete it under the other
+        let options = DownloadOptions::new(4096);
+        let (url_a, url_b) = (format!("{}/a.mp3", base), format!("{}/b.mp3", base));
+        let (a, b) = tokio::join!(
+            download_audio(&url_a, &dest_dir, &options),
+            download_audio(&url_b, &dest_dir, &options),
+        );
+        assert_eq!(std::fs::read(a.unwrap()).unwrap(), vec![5u8; 1024]);
+        assert_eq!(std::fs::read(b.unwrap()).unwrap(), vec![5u8; 1024]);
+
+        let mut names: Vec<_> = std::fs::read_dir(&dest_dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, vec![std::ffi::OsString::from("a.mp3"), std::ffi::OsString::from("b.mp3")]);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_download_leaves_no_partial_file() {
+        let base = serve(Router::new()
+            .route("/slow.mp3", get(|| async {
+                let chunks = futures::stream::iter(0..100).then(|_| async {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok::<_, std::io::Error>(vec![1u8; 64])
+                });
+                ([(header::CONTENT_TYPE, "audio/mpeg")], axum::body::Body::from_stream(chunks))
+            }))
+        ).await;
+        let dest_dir = tempfile::tempdir().unwrap().into_path();
+
+        let url = format!("{}/slow.mp3", base);
+        let options = DownloadOptions::new(1024 * 1024);
+        let download = download_audio(&url, &dest_dir, &options);
+        assert!(tokio::time::timeout(Duration::from_millis(200), download).await.is_err());
+
+        assert_eq!(std::fs::read_dir(&dest_dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_download_audio_limits() {
+        let base = serve(Router::new()
+            .route("/ok.mp3", get(|| async { ([(header::CONTENT_TYPE, "audio/mpeg")], vec![1u8; 1024]) }))
+            .route("/big.mp3", get(|| async { ([(header::CONTENT_TYPE, "audio/mpeg")], vec![1u8; 64 * 1024]) }))
+            .route("/error.mp3", get(|| async { ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], "<html></html>") }))
+        ).await;
+        let dest_dir = tempfile::tempdir().unwrap().into_path();
+
+        let path = download_audio(&format!("{}/ok.mp3", base), &dest_dir, &DownloadOptions::new(4096)).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap().len(), 1024);
+
+        let err = download_audio(&format!("{}/big.mp3", base), &dest_dir, &DownloadOptions::new(4096)).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::TooLarge { max_bytes: 4096 })));
+        assert!(!dest_dir.join("big.mp3").exists());
+
+        let err = download_audio(&format!("{}/error.mp3", base), &dest_dir, &DownloadOptions::new(4096)).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::UnsupportedContentType(_))));
+    }
+
+    #[tokio::test]
+    async fn test_save_upload() {
+        let dest_dir = tempfile::tempdir().unwrap().into_path();
+        let chunks = |sizes: &[usize]| futures::stream::iter(
+            sizes.iter().map(|&size| Ok::<_, std::io::Error>(vec![1u8; size])).collect::<Vec<_>>()
+        );
+
+        // the extension comes from the content type when the filename has none
+        let (path, digest) = save_upload(chunks(&[512, 512]), &dest_dir, Some("../recording"), "audio/wav", 4096).await.unwrap();
+        assert_eq!(path, dest_dir.join("recording.wav"));
+        assert_eq!(std::fs::read(&path).unwrap().len(), 1024);
+        assert_eq!(digest, hex::encode(Sha256::digest(vec![1u8; 1024])));
+
+        let err = save_upload(chunks(&[4096, 1]), &dest_dir, Some("big.mp3"), "", 4096).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::TooLarge { max_bytes: 4096 })));
+        assert!(!dest_dir.join("big.mp3").exists());
+
+        let err = save_upload(chunks(&[16]), &dest_dir, Some("page.html"), "text/html", 4096).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::UnsupportedContentType(_))));
+
+        // nothing but the saved upload is left behind
+        let names: Vec<_> = std::fs::read_dir(&dest_dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, vec![std::ffi::OsString::from("recording.wav")]);
+    }
+
+    #[tokio::test]
+    async fn test_probe_rejects_oversized_download() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use axum::http::{HeaderMap, StatusCode};
+
+        let gets = Arc::new(AtomicUsize::new(0));
+        let ranged_gets = Arc::new(AtomicUsize::new(0));
+        let (huge, legacy, legacy_ranged) = (gets.clone(), gets.clone(), ranged_gets.clone());
+        let base = serve(Router::new()
+            // HEAD reveals a size over the limit
+            .route("/huge.mp3", get(move || async move {
+                huge.fetch_add(1, Ordering::SeqCst);
+                ([(header::CONTENT_TYPE, "audio/mpeg")], vec![1u8; 64 * 1024])
+            }).head(|| async {
+                [(header::CONTENT_TYPE, "audio/mpeg"), (header::CONTENT_LENGTH, "10485760")]
+            }))
+            // a server without HEAD support answers the first byte of a ranged GET
+            .route("/legacy.mp3", get(move |headers: HeaderMap| async move {
+                if headers.contains_key(header::RANGE) {
+                    legacy_ranged.fetch_add(1, Ordering::SeqCst);
+                    (StatusCode::PARTIAL_CONTENT, [(header::CONTENT_TYPE, "audio/mpeg"), (header::CONTENT_RANGE, "bytes 0-0/10485760")], vec![1u8])
+                } else {
+                    legacy.fetch_add(1, Ordering::SeqCst);
+                    (StatusCode::OK, [(header::CONTENT_TYPE, "audio/mpeg"), (header::ACCEPT_RANGES, "bytes")], vec![1u8; 64 * 1024])
+                }
+            }).head(|| async { StatusCode::METHOD_NOT_ALLOWED }))
+            .route("/ok.mp3", get(|| async { ([(header::CONTENT_TYPE, "audio/mpeg")], vec![1u8; 1024]) }))
+        ).await;
+        let dest_dir = tempfile::tempdir().unwrap().into_path();
+        let options = DownloadOptions::new(4096).with_probe(true);
+
+        let err = download_audio(&format!("{}/huge.mp3", base), &dest_dir, &options).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::TooLarge { max_bytes: 4096 })));
+
+        let err = download_audio(&format!("{}/legacy.mp3", base), &dest_dir, &options).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::TooLarge { max_bytes: 4096 })));
+        assert_eq!(ranged_gets.load(Ordering::SeqCst), 1);
+
+        // the full body was never requested
+        assert_eq!(gets.load(Ordering::SeqCst), 0);
+        assert_eq!(std::fs::read_dir(&dest_dir).unwrap().count(), 0);
+
+        // files within the limit still download after the probe
+        let path = download_audio(&format!("{}/ok.mp3", base), &dest_dir, &options).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap().len(), 1024);
+    }
 }
@@ -0,0 +1,85 @@
+use std::time::Duration;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use once_cell::sync::Lazy;
+use crate::schedule::types::TaskType;
+
+pub const TASKS_CREATED: &str = "asr_tasks_created_total";
+pub const TASKS_COMPLETED: &str = "asr_tasks_completed_total";
+pub const TASKS_FAILED: &str = "asr_tasks_failed_total";
+pub const QUEUE_DEPTH: &str = "asr_queue_depth";
+pub const TRANSCRIPTION_DURATION: &str = "asr_transcription_duration_seconds";
+pub const CALLBACK_DELIVERIES: &str = "asr_callback_deliveries_total";
+pub const API_KEY_REQUESTS: &str = "asr_api_key_requests_total";
+
+/// 转写耗时直方图的桶，单位秒
+const TRANSCRIPTION_DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0];
+
+/// 全局的 Prometheus recorder，首次使用时安装，之前记录的指标会被丢弃
+static PROMETHEUS: Lazy<PrometheusHandle> = Lazy::new(|| {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Full(TRANSCRIPTION_DURATION.to_string()), TRANSCRIPTION_DURATION_BUCKETS)
+        .expect("transcription duration buckets are not empty")
+        .install_recorder()
+        .expect("no other metrics recorder is installed")
+});
+
+/// 安装 recorder，应在服务启动时调用
+pub fn init() {
+    Lazy::force(&PROMETHEUS);
+}
+
+/// 以 Prometheus 文本格式导出所有指标
+pub fn render() -> String {
+    PROMETHEUS.render()
+}
+
+pub fn task_created(task_type: &TaskType) {
+    ::metrics::counter!(TASKS_CREATED, "task_type" => task_type.to_string()).increment(1);
+}
+
+pub fn task_completed(task_type: &TaskType) {
+    ::metrics::counter!(TASKS_COMPLETED, "task_type" => task_type.to_string()).increment(1);
+}
+
+pub fn task_failed(task_type: &TaskType) {
+    ::metrics::counter!(TASKS_FAILED, "task_type" => task_type.to_string()).increment(1);
+}
+
+/// 等待处理的任务数
+pub fn set_queue_depth(pending: u64) {
+    ::metrics::gauge!(QUEUE_DEPTH).set(pending as f64);
+}
+
+pub fn transcription_duration(duration: Duration) {
+    ::metrics::histogram!(TRANSCRIPTION_DURATION).record(duration.as_secs_f64());
+}
+
+/// 一次 HTTP 回调的投递结果，重试全部失败才算失败
+pub fn callback_delivery(delivered: bool) {
+    let outcome = if delivered { "success" } else { "failure" };
+    ::metrics::counter!(CALLBACK_DELIVERIES, "outcome" => outcome).increment(1);
+}
+
+/// 由 `ApiKeyStats` 得到的请求总数，`key` 应为脱敏后的 API key
+pub fn set_api_key_requests(key: &str, name: &str, total_requests: u64) {
+    ::metrics::counter!(API_KEY_REQUESTS, "key" => key.to_string(), "name" => name.to_string())
+        .absolute(total_requests);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_exposition_format() {
+        init();
+        task_created(&TaskType::NoiseReduction);
+        transcription_duration(Duration::from_secs(3));
+        callback_delivery(false);
+
+        let rendered = render();
+        assert!(rendered.contains(r#"asr_tasks_created_total{task_type="NoiseReduction"}"#));
+        assert!(rendered.contains(r#"asr_transcription_duration_seconds_bucket{le="5"}"#));
+        assert!(rendered.contains(r#"asr_callback_deliveries_total{outcome="failure"}"#));
+    }
+}
@@ -1,2 +1,5 @@
 pub mod logger;
 pub mod http;
+pub mod threads;
+pub mod metrics;
+pub mod paths;
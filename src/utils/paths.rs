@@ -0,0 +1,33 @@
+use std::path::{Path, PathBuf};
+
+// the canonical path of `path` when it names an existing file inside `dir`. task inputs are
+// only deleted from the audio directory, a task naming any other file leaves it in place
+pub fn file_within(path: &Path, dir: &Path) -> Option<PathBuf> {
+    let path = path.canonicalize().ok()?;
+    let dir = dir.canonicalize().ok()?;
+    (path.is_file() && path.starts_with(&dir)).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_within() {
+        let dir = tempfile::tempdir().unwrap();
+        let audio_dir = dir.path().join("audio");
+        std::fs::create_dir(&audio_dir).unwrap();
+        let inside = audio_dir.join("a.wav");
+        let outside = dir.path().join("b.wav");
+        std::fs::write(&inside, b"RIFF").unwrap();
+        std::fs::write(&outside, b"RIFF").unwrap();
+
+        assert_eq!(file_within(&inside, &audio_dir), Some(inside.canonicalize().unwrap()));
+        assert_eq!(file_within(&outside, &audio_dir), None);
+        // `..` is resolved before the check
+        assert_eq!(file_within(&audio_dir.join("../b.wav"), &audio_dir), None);
+        assert_eq!(file_within(&audio_dir.join("missing.wav"), &audio_dir), None);
+        // the directory itself is not a file
+        assert_eq!(file_within(&audio_dir, &audio_dir), None);
+    }
+}
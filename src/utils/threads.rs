@@ -0,0 +1,111 @@
+use anyhow::Result;
+use tracing::info;
+
+/// 预处理和推理的线程分配
+///
+/// 预处理（重采样、FFT 降噪等）使用 rayon 全局线程池，whisper 推理使用自己的线程，
+/// 两者分开配置，避免在同一批核心上争抢
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadAllocation {
+    /// rayon 预处理线程池的线程数
+    pub preprocess_threads: usize,
+    /// 每个转写任务的 whisper 推理线程数
+    pub whisper_threads: usize,
+}
+
+impl ThreadAllocation {
+    /// 按核心数计算线程分配，使 `preprocess_threads + whisper_threads * concurrency` 不超过 `cores`
+    ///
+    /// 未配置时预处理分到四分之一的核心，其余核心平分给同时进行的转写；
+    /// 配置值超出可用核心时会被压低。每项至少 1 个线程，核心数少于 `concurrency + 1` 时无法避免超出
+    pub fn compute(
+        cores: usize,
+        concurrency: usize,
+        preprocess_threads: Option<usize>,
+        whisper_threads: Option<usize>,
+    ) -> Self {
+        let cores = cores.max(1);
+        let concurrency = concurrency.max(1);
+
+        // 至少给推理留出每个并发任务一个核心
+        let max_preprocess = cores.saturating_sub(concurrency).max(1);
+        let preprocess_threads = preprocess_threads
+            .unwrap_or(cores / 4)
+            .clamp(1, max_preprocess);
+
+        let max_whisper = (cores.saturating_sub(preprocess_threads) / concurrency).max(1);
+        let whisper_threads = whisper_threads
+            .unwrap_or(max_whisper)
+            .clamp(1, max_whisper);
+
+        Self { preprocess_threads, whisper_threads }
+    }
+
+    /// 按本机可用的核心数计算
+    pub fn detect(
+        concurrency: usize,
+        preprocess_threads: Option<usize>,
+        whisper_threads: Option<usize>,
+    ) -> Self {
+        let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        Self::compute(cores, concurrency, preprocess_threads, whisper_threads)
+    }
+
+    /// 按分配创建 rayon 全局线程池，须在首次预处理之前调用
+    pub fn init_preprocess_pool(&self) -> Result<()> {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.preprocess_threads)
+            .thread_name(|index| format!("preprocess-{}", index))
+            .build_global()?;
+        info!(
+            "Using {} preprocessing threads and {} whisper threads per transcription",
+            self.preprocess_threads, self.whisper_threads
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocation_fits_in_cores() {
+        for cores in 2..=64 {
+            for concurrency in 1..cores {
+                for configured in [None, Some(1), Some(4), Some(cores), Some(cores * 2)] {
+                    let allocation = ThreadAllocation::compute(cores, concurrency, configured, configured);
+                    assert!(allocation.preprocess_threads >= 1);
+                    assert!(allocation.whisper_threads >= 1);
+                    assert!(
+                        allocation.preprocess_threads + allocation.whisper_threads * concurrency <= cores,
+                        "{:?} exceeds {} cores with {} concurrent transcriptions",
+                        allocation, cores, concurrency
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_allocation() {
+        assert_eq!(
+            ThreadAllocation::compute(16, 1, None, None),
+            ThreadAllocation { preprocess_threads: 4, whisper_threads: 12 }
+        );
+        assert_eq!(
+            ThreadAllocation::compute(16, 2, None, None),
+            ThreadAllocation { preprocess_threads: 4, whisper_threads: 6 }
+        );
+        // configured values within the cores are kept
+        assert_eq!(
+            ThreadAllocation::compute(16, 1, Some(2), Some(8)),
+            ThreadAllocation { preprocess_threads: 2, whisper_threads: 8 }
+        );
+        // a single core can't be split
+        assert_eq!(
+            ThreadAllocation::compute(1, 1, None, None),
+            ThreadAllocation { preprocess_threads: 1, whisper_threads: 1 }
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::error;
+
+use crate::auth::AuthError;
+use crate::schedule::types::{InvalidTransition, TaskManagerError};
+use crate::utils::http::DownloadError;
+use super::ApiResponse;
+
+// error of a handler, answered with its status code and the usual `ApiResponse` envelope.
+// the message of an internal error stays in the log, clients only see that the server failed
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(String),
+    // the resource existed, e.g. a task removed by retention cleanup
+    Gone(String),
+    Validation(String),
+    // the request doesn't fit the current state, e.g. cancelling a finished task
+    Conflict(String),
+    Unauthorized(String),
+    Forbidden(String),
+    RateLimited(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    // well formed but unusable, e.g. audio that can't be fetched or fails its checksum
+    Unprocessable(String),
+    // the server gave up waiting, e.g. on a synchronous transcription
+    Timeout(String),
+    // the server can't handle the request as configured, e.g. no processor for a task type
+    NotImplemented(String),
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // the same category with a reworded message, internal errors keep theirs hidden
+    pub fn map_message(self, f: impl FnOnce(String) -> String) -> Self {
+        match self {
+            ApiError::NotFound(message) => ApiError::NotFound(f(message)),
+            ApiError::Gone(message) => ApiError::Gone(f(message)),
+            ApiError::Validation(message) => ApiError::Validation(f(message)),
+            ApiError::Conflict(message) => ApiError::Conflict(f(message)),
+            ApiError::Unauthorized(message) => ApiError::Unauthorized(f(message)),
+            ApiError::Forbidden(message) => ApiError::Forbidden(f(message)),
+            ApiError::RateLimited(message) => ApiError::RateLimited(f(message)),
+            ApiError::PayloadTooLarge(message) => ApiError::PayloadTooLarge(f(message)),
+            ApiError::UnsupportedMediaType(message) => ApiError::UnsupportedMediaType(f(message)),
+            ApiError::Unprocessable(message) => ApiError::Unprocessable(f(message)),
+            ApiError::Timeout(message) => ApiError::Timeout(f(message)),
+            ApiError::NotImplemented(message) => ApiError::NotImplemented(f(message)),
+            ApiError::Internal(e) => ApiError::Internal(e),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::Gone(message)
+            | ApiError::Validation(message)
+            | ApiError::Conflict(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::RateLimited(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::UnsupportedMediaType(message)
+            | ApiError::Unprocessable(message)
+            | ApiError::Timeout(message)
+            | ApiError::NotImplemented(message) => write!(f, "{}", message),
+            ApiError::Internal(_) => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Internal(e) = &self {
+            error!("{:#}", e);
+        }
+        (self.status(), Json(ApiResponse::<()>::error(self.to_string()))).into_response()
+    }
+}
+
+impl From<TaskManagerError> for ApiError {
+    fn from(error: TaskManagerError) -> Self {
+        match error {
+            TaskManagerError::NotFound(_) => ApiError::NotFound(error.to_string()),
+            TaskManagerError::InvalidParams(_)
+            | TaskManagerError::NotPending(_) => ApiError::Validation(error.to_string()),
+            // not the caller's fault, the task type is valid but this server doesn't run it
+            TaskManagerError::NoProcessor(_) => ApiError::NotImplemented(error.to_string()),
+        }
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(error: AuthError) -> Self {
+        match error {
+            AuthError::MissingApiKey | AuthError::InvalidApiKey => ApiError::Unauthorized(error.to_string()),
+            AuthError::KeyExpired | AuthError::KeySuspended | AuthError::InsufficientPermissions => {
+                ApiError::Forbidden(error.to_string())
+            }
+            AuthError::RateLimitExceeded => ApiError::RateLimited(error.to_string()),
+            AuthError::KeyNotFound => ApiError::NotFound(error.to_string()),
+            AuthError::InvalidInput(_) => ApiError::Validation(error.to_string()),
+            AuthError::StorageError(_) => ApiError::Internal(error.into()),
+        }
+    }
+}
+
+impl From<DownloadError> for ApiError {
+    fn from(error: DownloadError) -> Self {
+        match error {
+            DownloadError::TooLarge { .. } => ApiError::PayloadTooLarge(error.to_string()),
+            DownloadError::UnsupportedContentType(_) => ApiError::UnsupportedMediaType(error.to_string()),
+            DownloadError::Unreachable(_)
+            | DownloadError::Truncated { .. }
+            | DownloadError::ChecksumMismatch { .. } => ApiError::Unprocessable(error.to_string()),
+        }
+    }
+}
+
+// errors of the task manager come as `anyhow::Error`, the typed ones anywhere in the chain
+// decide the category, everything else is internal
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<TaskManagerError>() {
+                return e.clone().into();
+            }
+            if let Some(e) = cause.downcast_ref::<InvalidTransition>() {
+                return ApiError::Conflict(e.to_string());
+            }
+            if let Some(e) = cause.downcast_ref::<DownloadError>() {
+                return e.clone().into();
+            }
+        }
+        match error.downcast::<AuthError>() {
+            Ok(e) => e.into(),
+            Err(error) => ApiError::Internal(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+    use crate::schedule::types::{TaskStatus, TaskType};
+
+    #[test]
+    fn test_errors_are_categorized() {
+        let not_found: anyhow::Error = TaskManagerError::NotFound("task-1".to_string()).into();
+        let error = ApiError::from(not_found.context("Failed to update task priority"));
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error.to_string(), "Task not found: task-1");
+
+        let invalid: anyhow::Error = TaskManagerError::InvalidParams("Invalid language".to_string()).into();
+        assert_eq!(ApiError::from(invalid).status(), StatusCode::BAD_REQUEST);
+        let no_processor: anyhow::Error = TaskManagerError::NoProcessor(TaskType::Transcribe).into();
+        assert_eq!(ApiError::from(no_processor).status(), StatusCode::NOT_IMPLEMENTED);
+
+        let finished: anyhow::Error = InvalidTransition {
+            task_id: "task-1".to_string(),
+            from: TaskStatus::Completed,
+            to: TaskStatus::Cancelled,
+        }.into();
+        assert_eq!(ApiError::from(finished).status(), StatusCode::CONFLICT);
+
+        assert_eq!(ApiError::from(AuthError::MissingApiKey).status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(ApiError::from(AuthError::KeySuspended).status(), StatusCode::FORBIDDEN);
+        assert_eq!(ApiError::from(AuthError::RateLimitExceeded).status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(ApiError::from(AuthError::KeyNotFound).status(), StatusCode::NOT_FOUND);
+
+        let too_large: anyhow::Error = DownloadError::TooLarge { max_bytes: 1 }.into();
+        assert_eq!(ApiError::from(too_large.context("Failed to download audio")).status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let unreachable: anyhow::Error = DownloadError::Unreachable("HTTP request failed with status: 404".to_string()).into();
+        let error = ApiError::from(unreachable);
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let error = error.map_message(|message| format!("Failed to download audio: {}", message));
+        assert_eq!(error.to_string(), "Failed to download audio: HTTP request failed with status: 404");
+
+        // a database outage is not a missing task, and its details stay on the server
+        let outage = ApiError::from(anyhow::anyhow!("database is locked"));
+        assert_eq!(outage.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(outage.to_string(), "Internal server error");
+        let storage = ApiError::from(AuthError::StorageError("disk full".to_string()));
+        assert_eq!(storage.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_response_uses_the_envelope() {
+        let response = ApiError::Internal(anyhow::anyhow!("connection refused")).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, serde_json::json!({
+            "success": false,
+            "data": null,
+            "error": "Internal server error",
+        }));
+    }
+}
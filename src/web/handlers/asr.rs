@@ -1,127 +1,898 @@
 use axum::{
-    http::{StatusCode, HeaderMap},
+    async_trait,
+    body::Body,
+    http::{header, StatusCode},
     Json,
-    extract::State,
-    routing::post,
+    extract::{DefaultBodyLimit, Extension, FromRequest, Multipart, Path, Query, Request, State},
+    extract::multipart::MultipartError,
+    middleware::from_fn_with_state,
+    routing::{delete, post},
     Router,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
-use crate::utils::http::HttpResponse;
+use crate::web::{ApiError, ApiResponse, RequestId};
 use crate::AppContext;
-use tracing::{info, error};
-use crate::auth::Permission;
-use crate::utils::http::download_audio;
+use tracing::{info, warn, error};
+use crate::auth::{auth_middleware, ApiKeyInfo, Permission, RequireAuth};
+use crate::utils::http::{download_audio, save_upload, DownloadError, DownloadOptions};
+use std::convert::Infallible;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
+use futures::Stream;
 use crate::schedule::TaskConfig;
 use crate::schedule::TaskType;
 use crate::schedule::CallbackType;
 use crate::schedule::TaskPriority;
 use crate::schedule::TaskParams;
 use crate::schedule::TranscribeParams;
+use crate::schedule::types::{FailureKind, NoiseReductionParams, PhaseTimings, Task, TaskResult, TaskStatus, TranscribeSegment};
+use crate::schedule::callback::{TaskEvent, TaskEventReceiver};
 use serde::{Deserialize, Serialize};
-use crate::AUDIO_PATH;
+use crate::audio::{check_min_duration, AudioTooShort, Gain};
+use crate::{AUDIO_PATH, MAX_DOWNLOAD_BYTES, MIN_AUDIO_SECONDS, SYNC_MAX_AUDIO_SECONDS, SYNC_TIMEOUT_SECS};
 use std::fs;
 
 
 pub fn transcribe_router(ctx: Arc<AppContext>) -> Router {
     Router::new()
-        .route("/transcribe", post(transcribe))
+        .route("/transcribe", post(transcribe).layer(DefaultBodyLimit::max(upload_body_limit())))
+        .route("/transcribe/sync", post(transcribe_sync))
+        .route("/denoise", post(denoise))
+        .route("/voiceprints/:speaker_id", delete(delete_voiceprint))
+        .route_layer(from_fn_with_state(
+            RequireAuth::new(ctx.auth.clone(), Permission::Transcribe),
+            auth_middleware,
+        ))
         .with_state(ctx)
 }
 
 
+// room for the params next to the audio of a multipart transcription
+const UPLOAD_FIELDS_BYTES: usize = 1024 * 1024;
+
+// fields of a multipart transcription taken as text, the others are parsed as json
+const UPLOAD_TEXT_FIELDS: &[&str] = &[
+    "callback_url",
+    "callback_secret",
+    "language",
+    "initial_prompt",
+    "label",
+    "model",
+    "expected_sha256",
+];
+
+fn upload_body_limit() -> usize {
+    usize::try_from(*MAX_DOWNLOAD_BYTES).unwrap_or(usize::MAX).saturating_add(UPLOAD_FIELDS_BYTES)
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TranscribeRequest {
     pub audio_url: String,
     pub callback_url: String,
+    // signs the callbacks of this task with hmac-sha256 when set
+    pub callback_secret: Option<String>,
     pub language: Option<String>,
     // optional features
     pub speaker_diarization: bool,
     pub emotion_recognition: bool,
     pub filter_dirty_words: bool,
+    #[serde(default)]
+    pub token_timestamps: bool,
+    #[serde(default)]
+    pub single_segment: bool,
+    pub beam_size: Option<i32>,
+    pub chunk_seconds: Option<u32>,
+    pub initial_prompt: Option<String>,
+    pub gain: Option<Gain>,
+    pub label: Option<String>,
+    pub n_threads: Option<i32>,
+    pub temperature: Option<f32>,
+    pub translate: Option<bool>,
+    pub model: Option<String>,
+    pub no_speech_threshold: Option<f32>,
+    pub min_segment_confidence: Option<f32>,
+    // hex sha256 of the audio file, the download is rejected when it does not match
+    pub expected_sha256: Option<String>,
+    // keep the request open and stream the segments as ndjson, ending with a summary line
+    #[serde(default)]
+    pub stream: bool,
+}
+
+// a short clip transcribed while the request waits, there is no callback
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SyncTranscribeRequest {
+    pub audio_url: String,
+    pub language: Option<String>,
+    #[serde(default)]
+    pub filter_dirty_words: bool,
+    #[serde(default)]
+    pub token_timestamps: bool,
+    #[serde(default)]
+    pub single_segment: bool,
+    pub beam_size: Option<i32>,
+    pub initial_prompt: Option<String>,
+    pub gain: Option<Gain>,
+    pub temperature: Option<f32>,
+    pub translate: Option<bool>,
+    pub model: Option<String>,
+    pub no_speech_threshold: Option<f32>,
+    pub min_segment_confidence: Option<f32>,
+    pub expected_sha256: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DenoiseRequest {
+    pub audio_url: String,
+    // receives the task with the path of the denoised wav once it is done
+    pub callback_url: String,
+    pub callback_secret: Option<String>,
+    // 0..=1
+    pub strength: f32,
+    #[serde(default = "default_frame_size")]
+    pub frame_size: usize,
+    #[serde(default = "default_overlap")]
+    pub overlap: f32,
+    pub label: Option<String>,
+    pub expected_sha256: Option<String>,
+}
+
+fn default_frame_size() -> usize {
+    2048
+}
+
+fn default_overlap() -> f32 {
+    0.75
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TranscribeQuery {
+    // attach a `timings_ms` breakdown of download, preprocessing and inference to the result
+    #[serde(default)]
+    pub timings: bool,
+}
+
+// what the asynchronous endpoints return, the task itself is read from /schedule/tasks/:task_id
+#[derive(Debug, Serialize)]
+pub struct TaskCreated {
+    pub task_id: String,
+    pub status: TaskStatus,
+}
+
+impl From<Task> for TaskCreated {
+    fn from(task: Task) -> Self {
+        Self { task_id: task.id, status: task.status }
+    }
+}
+
+// one line of a streamed transcription
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TranscriptLine {
+    Segment(TranscribeSegment),
+    // always the last line of a finished transcription
+    Summary {
+        task_id: String,
+        full_text: String,
+        segments: usize,
+        duration: f64,
+        language: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timings_ms: Option<PhaseTimings>,
+    },
+    Error {
+        task_id: String,
+        error: String,
+    },
+}
+
+// a transcription either names an `audio_url` in json, or uploads the file as the `audio` part
+// of a multipart form with the other params as fields
+pub enum TranscribeBody {
+    Json(TranscribeRequest),
+    Multipart(Multipart),
+}
+
+#[async_trait]
+impl<S> FromRequest<S> for TranscribeBody
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_multipart = req.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.to_ascii_lowercase().starts_with("multipart/form-data"));
+        if is_multipart {
+            Multipart::from_request(req, state).await
+                .map(TranscribeBody::Multipart)
+                .map_err(IntoResponse::into_response)
+        } else {
+            Json::<TranscribeRequest>::from_request(req, state).await
+                .map(|Json(req)| TranscribeBody::Json(req))
+                .map_err(IntoResponse::into_response)
+        }
+    }
 }
 
 pub async fn transcribe(
     State(ctx): State<Arc<AppContext>>,
-    headers: HeaderMap,
-    Json(req): Json<TranscribeRequest>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    RequestId(request_id): RequestId,
+    Query(query): Query<TranscribeQuery>,
+    body: TranscribeBody,
 ) -> impl IntoResponse {
-    // validate api key
-    let api_key = headers.get("Authorization")
-        .and_then(|value| value.to_str().ok());
-
-    if let Err(e) = ctx.auth.verify_api_key(api_key, Permission::Transcribe).await {
-        let response = HttpResponse::new(
-            401,
-            "Authentication failed".to_string(),
-            e.to_string()
-        );
-        return (StatusCode::UNAUTHORIZED, Json(response)).into_response();
+    let download_started = Instant::now();
+    let (req, dest, mut params) = match body {
+        TranscribeBody::Json(req) => {
+            // checked before anything is downloaded
+            if let Err(e) = check_callback_url(&key_info, &req.callback_url) {
+                return e.into_response();
+            }
+            let params = transcribe_params(&req, query.timings);
+            if let Err(e) = ctx.task_manager.validate_params(&TaskType::Transcribe, &params) {
+                return ApiError::from(e).into_response();
+            }
+            match download_request_audio(&req.audio_url, req.expected_sha256.clone()).await {
+                Ok(dest) => (req, dest, params),
+                Err(e) => return e.into_response(),
+            }
+        }
+        TranscribeBody::Multipart(multipart) => {
+            // the fields may follow the audio, so they are checked once everything is read
+            let (req, dest) = match receive_upload(multipart).await {
+                Ok(upload) => upload,
+                Err(e) => return e.into_response(),
+            };
+            if let Err(e) = check_callback_url(&key_info, &req.callback_url) {
+                let _ = fs::remove_file(&dest);
+                return e.into_response();
+            }
+            let params = transcribe_params(&req, query.timings);
+            if let Err(e) = ctx.task_manager.validate_params(&TaskType::Transcribe, &params) {
+                let _ = fs::remove_file(&dest);
+                return ApiError::from(e).into_response();
+            }
+            (req, dest, params)
+        }
+    };
+    // a multipart request counts its upload as the download
+    if let TaskParams::Transcribe(TranscribeParams { timings: Some(timings), .. }) = &mut params {
+        timings.download = download_started.elapsed().as_millis() as u64;
     }
 
-    // ensure download directory exists
-    let download_dir = PathBuf::from(AUDIO_PATH.as_str());
-    if let Err(e) = fs::create_dir_all(&download_dir) {
-        error!("Failed to create download directory: {}", e);
-        let response = HttpResponse::new(
-            500,
-            "Failed to create download directory".to_string(),
-            e.to_string()
-        );
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response();
+    // reject clips shorter than the minimum of the key, falling back to the global one.
+    // a failed probe is not the caller's fault, the task only loses the check
+    let min_audio_seconds = key_info.min_audio_seconds.unwrap_or(*MIN_AUDIO_SECONDS);
+    if let Err(e) = check_min_duration(&dest, min_audio_seconds).await {
+        match e.downcast_ref::<AudioTooShort>() {
+            Some(too_short) => {
+                info!("Rejecting {}: {}", req.audio_url, too_short);
+                let _ = fs::remove_file(&dest);
+                return ApiError::Validation(format!("Audio too short: {}", too_short)).into_response();
+            }
+            None => warn!("Failed to probe {}: {}", dest.display(), e),
+        }
     }
 
-    // download audio file with more detailed error logging
-    info!("Attempting to download audio from: {}", req.audio_url);
-    let dest = match download_audio(&req.audio_url, &download_dir).await {
-        Ok(dest) => {
-            info!("Successfully downloaded audio to: {:?}", dest);
-            dest
-        },
+    let task_config = TaskConfig{
+        task_type: TaskType::Transcribe,
+        input_path: dest.clone(),
+        callback_type: CallbackType::Http { url: req.callback_url, secret: req.callback_secret },
+        params,
+        priority: TaskPriority::Normal,
+        retry_count: 0,
+        max_retries: ctx.task_manager.retry_policy(&TaskType::Transcribe).max_attempts,
+        timeout: None,
+        notify_on_retry: false,
+        label: req.label,
+        api_key: Some(key_info.id.clone()),
+        request_id,
+    };
+
+    // subscribe before the task exists, so not even its first segment is missed
+    let events = req.stream.then(|| ctx.task_manager.events().subscribe());
+
+    let task = match ctx.task_manager.create_task(task_config).await {
+        Ok(task) => task,
         Err(e) => {
-            error!("Failed to download audio from {}: {}", req.audio_url, e);
-            let response = HttpResponse::new(
-                500,
-                "Failed to download audio".to_string(),
-                format!("Download error: {}", e)
-            );
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response();
+            let _ = fs::remove_file(&dest);
+            return ApiError::from(e.context("Failed to create task")).into_response();
         }
     };
 
-    let task_config = TaskConfig{
+    if let Some(events) = events {
+        info!("Streaming task {} of {}", task.id, req.audio_url);
+        return (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/x-ndjson")],
+            Body::from_stream(transcript_lines(TaskEventReceiver::new(events, task.id))),
+        ).into_response();
+    }
+
+    info!("Task added successfully: {}", req.audio_url);
+    (StatusCode::OK, Json(ApiResponse::success(TaskCreated::from(task)))).into_response()
+}
+
+// the task params of a transcription request, timed when the caller asked for timings
+fn transcribe_params(req: &TranscribeRequest, timings: bool) -> TaskParams {
+    TaskParams::Transcribe(TranscribeParams {
+        language: req.language.clone(),
+        speaker_diarization: req.speaker_diarization,
+        emotion_recognition: req.emotion_recognition,
+        filter_dirty_words: req.filter_dirty_words,
+        token_timestamps: req.token_timestamps,
+        single_segment: req.single_segment,
+        beam_size: req.beam_size,
+        chunk_seconds: req.chunk_seconds,
+        initial_prompt: req.initial_prompt.clone(),
+        gain: req.gain.clone(),
+        n_threads: req.n_threads,
+        temperature: req.temperature,
+        translate: req.translate,
+        model: req.model.clone(),
+        no_speech_threshold: req.no_speech_threshold,
+        min_segment_confidence: req.min_segment_confidence,
+        input_task: None,
+        timings: timings.then(PhaseTimings::default),
+    })
+}
+
+// Transcribe a short clip inline and return the result in the response
+pub async fn transcribe_sync(
+    State(ctx): State<Arc<AppContext>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    RequestId(request_id): RequestId,
+    Query(query): Query<TranscribeQuery>,
+    Json(req): Json<SyncTranscribeRequest>,
+) -> impl IntoResponse {
+    let mut params = TaskParams::Transcribe(TranscribeParams {
+        language: req.language,
+        speaker_diarization: false,
+        emotion_recognition: false,
+        filter_dirty_words: req.filter_dirty_words,
+        token_timestamps: req.token_timestamps,
+        single_segment: req.single_segment,
+        beam_size: req.beam_size,
+        chunk_seconds: None,
+        initial_prompt: req.initial_prompt,
+        gain: req.gain,
+        n_threads: None,
+        temperature: req.temperature,
+        translate: req.translate,
+        model: req.model,
+        no_speech_threshold: req.no_speech_threshold,
+        min_segment_confidence: req.min_segment_confidence,
+        input_task: None,
+        timings: query.timings.then(PhaseTimings::default),
+    });
+    if let Err(e) = ctx.task_manager.validate_params(&TaskType::Transcribe, &params) {
+        return ApiError::from(e).into_response();
+    }
+
+    let download_started = Instant::now();
+    let dest = match download_request_audio(&req.audio_url, req.expected_sha256).await {
+        Ok(dest) => dest,
+        Err(e) => return e.into_response(),
+    };
+    // the processor adds preprocessing and inference to the download measured here
+    if let TaskParams::Transcribe(TranscribeParams { timings: Some(timings), .. }) = &mut params {
+        timings.download = download_started.elapsed().as_millis() as u64;
+    }
+
+    // the request waits for the transcription, so long clips are turned away before it starts.
+    // without a known duration the clip could be arbitrarily long
+    let max_seconds = *SYNC_MAX_AUDIO_SECONDS;
+    let rejection = match crate::audio::probe(&dest).await {
+        Ok(info) if info.duration_secs > max_seconds => Some(ApiError::PayloadTooLarge(
+            format!("Audio is {:.1}s long, synchronous transcription accepts up to {:.1}s", info.duration_secs, max_seconds),
+        )),
+        Ok(_) => None,
+        Err(e) => Some(ApiError::Unprocessable(format!("Failed to determine the audio duration: {}", e))),
+    };
+    if let Some(e) = rejection {
+        info!("Rejecting synchronous transcription of {}: {}", req.audio_url, e);
+        let _ = fs::remove_file(&dest);
+        return e.into_response();
+    }
+
+    let task_config = TaskConfig {
         task_type: TaskType::Transcribe,
         input_path: dest,
-        callback_type: CallbackType::Http { url: req.callback_url },
-        params: TaskParams::Transcribe(TranscribeParams{
-            language: req.language,
-            speaker_diarization: req.speaker_diarization,
-            emotion_recognition: req.emotion_recognition,
-            filter_dirty_words: req.filter_dirty_words,
-        }),
+        callback_type: CallbackType::None,
+        params,
         priority: TaskPriority::Normal,
         retry_count: 0,
-        max_retries: 3,
+        max_retries: 0,
         timeout: None,
+        notify_on_retry: false,
+        label: None,
+        api_key: Some(key_info.id.clone()),
+        request_id,
     };
 
-    if let Err(e) = ctx.task_manager.create_task(task_config).await {
-        error!("Failed to create task: {}", e);
-        let response = HttpResponse::new(
-            500,
-            "Failed to create task".to_string(),
-            e.to_string()
-        );
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response();
+    let timeout = std::time::Duration::from_secs(*SYNC_TIMEOUT_SECS);
+    match ctx.task_manager.run_inline(task_config, timeout).await {
+        Ok(TaskResult::Transcribe(result)) => {
+            info!("Transcribed {} synchronously", req.audio_url);
+            (StatusCode::OK, Json(ApiResponse::success(result))).into_response()
+        }
+        Ok(_) => ApiError::Internal(anyhow::anyhow!("Task did not produce a transcription")).into_response(),
+        Err(e) if e.downcast_ref::<tokio::time::error::Elapsed>().is_some() => {
+            warn!("Synchronous transcription of {} timed out", req.audio_url);
+            ApiError::Timeout(format!("Transcription timed out after {}s", timeout.as_secs())).into_response()
+        }
+        // audio the processor can't use is the caller's, anything else failed on the server
+        Err(e) if FailureKind::of(&e) == FailureKind::Input => {
+            info!("Failed to transcribe {}: {:#}", req.audio_url, e);
+            ApiError::Unprocessable(format!("Failed to transcribe audio: {:#}", e)).into_response()
+        }
+        Err(e) => ApiError::from(e.context(format!("Failed to transcribe {}", req.audio_url))).into_response(),
     }
+}
 
-    info!("Task added successfully: {}", req.audio_url);
-    let response = HttpResponse::new(
-        0,
-        "Task added successfully".to_string(),
-        req.audio_url
-    );
-    (StatusCode::OK, Json(response)).into_response()
+// Denoise an audio url, the denoised wav is written by the noise reduction worker
+pub async fn denoise(
+    State(ctx): State<Arc<AppContext>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    RequestId(request_id): RequestId,
+    Json(req): Json<DenoiseRequest>,
+) -> impl IntoResponse {
+    // checked before anything is downloaded
+    if let Err(e) = check_callback_url(&key_info, &req.callback_url) {
+        return e.into_response();
+    }
+
+    let params = TaskParams::NoiseReduction(NoiseReductionParams {
+        strength: req.strength,
+        frame_size: req.frame_size,
+        overlap: req.overlap,
+    });
+    if let Err(e) = ctx.task_manager.validate_params(&TaskType::NoiseReduction, &params) {
+        return ApiError::from(e).into_response();
+    }
+
+    let dest = match download_request_audio(&req.audio_url, req.expected_sha256).await {
+        Ok(dest) => dest,
+        Err(e) => return e.into_response(),
+    };
+
+    let task_config = TaskConfig {
+        task_type: TaskType::NoiseReduction,
+        input_path: dest.clone(),
+        callback_type: CallbackType::Http { url: req.callback_url, secret: req.callback_secret },
+        params,
+        priority: TaskPriority::Normal,
+        retry_count: 0,
+        max_retries: ctx.task_manager.retry_policy(&TaskType::NoiseReduction).max_attempts,
+        timeout: None,
+        notify_on_retry: false,
+        label: req.label,
+        api_key: Some(key_info.id.clone()),
+        request_id,
+    };
+
+    match ctx.task_manager.create_task(task_config).await {
+        Ok(task) => {
+            info!("Noise reduction task added: {}", req.audio_url);
+            (StatusCode::OK, Json(ApiResponse::success(TaskCreated::from(task)))).into_response()
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&dest);
+            ApiError::from(e.context("Failed to create task")).into_response()
+        }
+    }
+}
+
+// Delete a speaker enrolled with the caller's key
+pub async fn delete_voiceprint(
+    State(ctx): State<Arc<AppContext>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    Path(speaker_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !ctx.voiceprints.delete(&key_info.id, &speaker_id).await? {
+        return Err(ApiError::NotFound(format!("Speaker {} is not enrolled", speaker_id)));
+    }
+    info!("Deleted the voiceprint of speaker {}", speaker_id);
+    Ok((StatusCode::OK, Json(ApiResponse::<()>::success(()))))
+}
+
+fn check_callback_url(key_info: &ApiKeyInfo, callback_url: &str) -> Result<(), ApiError> {
+    key_info.check_callback_url(callback_url).map_err(|e| {
+        info!("Rejecting callback {}: {}", callback_url, e);
+        ApiError::Forbidden(format!("Callback host not allowed: {}", e))
+    })
+}
+
+fn multipart_error(e: MultipartError) -> ApiError {
+    let message = format!("Invalid multipart body: {}", e.body_text());
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => ApiError::PayloadTooLarge(message),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => ApiError::UnsupportedMediaType(message),
+        _ => ApiError::Validation(message),
+    }
+}
+
+// save the `audio` part of a multipart transcription into the audio directory and read the
+// other fields as the request, the file is removed again when the request is rejected
+async fn receive_upload(mut multipart: Multipart) -> Result<(TranscribeRequest, PathBuf), ApiError> {
+    let mut upload = None;
+    let result = read_upload(&mut multipart, &mut upload).await.and_then(|mut fields| {
+        let Some((dest, digest)) = &upload else {
+            return Err(ApiError::Validation("Missing audio part".to_string()));
+        };
+        // the checksum may arrive after the audio, so it is compared once all fields are read
+        if let Some(expected) = fields.get("expected_sha256").and_then(|value| value.as_str()) {
+            if !digest.eq_ignore_ascii_case(expected.trim()) {
+                let e = ApiError::from(DownloadError::ChecksumMismatch {
+                    algorithm: "sha256",
+                    expected: expected.trim().to_ascii_lowercase(),
+                    actual: digest.clone(),
+                });
+                return Err(e.map_message(|message| format!("Failed to receive audio: {}", message)));
+            }
+        }
+        // only used to name the audio in the logs
+        fields.insert("audio_url".to_string(), serde_json::Value::String(dest.display().to_string()));
+        serde_json::from_value::<TranscribeRequest>(serde_json::Value::Object(fields))
+            .map(|req| (req, dest.clone()))
+            .map_err(|e| ApiError::Validation(format!("Invalid transcription params: {}", e)))
+    });
+
+    if result.is_err() {
+        if let Some((dest, _)) = &upload {
+            let _ = fs::remove_file(dest);
+        }
+    }
+    result
+}
+
+async fn read_upload(
+    multipart: &mut Multipart,
+    upload: &mut Option<(PathBuf, String)>,
+) -> Result<serde_json::Map<String, serde_json::Value>, ApiError> {
+    let mut fields = serde_json::Map::new();
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        let name = field.name().unwrap_or_default().to_string();
+        if name != "audio" {
+            let text = field.text().await.map_err(multipart_error)?;
+            let value = match UPLOAD_TEXT_FIELDS.contains(&name.as_str()) {
+                true => serde_json::Value::String(text),
+                false => serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text)),
+            };
+            fields.insert(name, value);
+            continue;
+        }
+        if upload.is_some() {
+            return Err(ApiError::Validation("Only one audio part is allowed".to_string()));
+        }
+
+        let filename = field.file_name().map(str::to_string);
+        let content_type = field.content_type().unwrap_or_default().to_string();
+        let download_dir = PathBuf::from(AUDIO_PATH.as_str());
+        match save_upload(field, &download_dir, filename.as_deref(), &content_type, *MAX_DOWNLOAD_BYTES).await {
+            Ok(saved) => {
+                info!("Received upload {:?} as {:?}", filename, saved.0);
+                *upload = Some(saved);
+            }
+            Err(e) => {
+                error!("Failed to receive upload {:?}: {}", filename, e);
+                let e = ApiError::from(e.context("Failed to receive audio"));
+                return Err(e.map_message(|message| format!("Failed to receive audio: {}", message)));
+            }
+        }
+    }
+    Ok(fields)
+}
+
+// download the audio of a request into the audio directory, failures are already
+// categorized for the caller
+async fn download_request_audio(audio_url: &str, expected_sha256: Option<String>) -> Result<PathBuf, ApiError> {
+    // ensure download directory exists
+    let download_dir = PathBuf::from(AUDIO_PATH.as_str());
+    if let Err(e) = fs::create_dir_all(&download_dir) {
+        return Err(ApiError::Internal(anyhow::Error::from(e).context("Failed to create download directory")));
+    }
+
+    // download audio file with more detailed error logging
+    info!("Attempting to download audio from: {}", audio_url);
+    let download_options = DownloadOptions::new(*MAX_DOWNLOAD_BYTES)
+        .with_probe(true)
+        .with_expected_sha256(expected_sha256);
+    match download_audio(audio_url, &download_dir, &download_options).await {
+        Ok(dest) => {
+            info!("Successfully downloaded audio to: {:?}", dest);
+            Ok(dest)
+        },
+        Err(e) => {
+            error!("Failed to download audio from {}: {}", audio_url, e);
+            let e = ApiError::from(e.context(format!("Failed to download audio from {}", audio_url)));
+            Err(e.map_message(|message| format!("Failed to download audio: {}", message)))
+        }
+    }
 }
 
+// turn the events of a task into ndjson lines: each segment as it is decoded, then a
+// summary with the final text once the task completes, or an error line when it fails, is
+// cancelled or times out. a reader too slow to keep up gets an error line instead of a
+// transcript with holes, the task result can still be fetched once it is done
+fn transcript_lines(events: TaskEventReceiver) -> impl Stream<Item = Result<String, Infallible>> {
+    futures::stream::unfold(Some((events, 0usize)), |state| async move {
+        let (mut events, mut segments) = state?;
+        loop {
+            let event = match events.recv_or_lagged().await? {
+                Ok(event) => event,
+                Err(lagged) => {
+                    let task_id = events.task_id().unwrap_or_default().to_string();
+                    let error = format!(
+                        "stream fell behind and missed {} events, fetch the task result instead",
+                        lagged.skipped
+                    );
+                    return Some((Ok(transcript_line(&TranscriptLine::Error { task_id, error })), None));
+                }
+            };
+            let (line, finished) = match event {
+                TaskEvent::Segment { segment, .. } => {
+                    segments += 1;
+                    (TranscriptLine::Segment(segment), false)
+                }
+                TaskEvent::Completed { task_id, result } => {
+                    let summary = match result.as_transcribe() {
+                        Some(result) => TranscriptLine::Summary {
+                            task_id,
+                            full_text: result.text.clone(),
+                            segments,
+                            duration: result.segments.last().map(|s| s.end_time).unwrap_or_default(),
+                            language: result.language.clone(),
+                            timings_ms: result.timings_ms.clone(),
+                        },
+                        None => TranscriptLine::Error {
+                            task_id,
+                            error: "task did not produce a transcription".to_string(),
+                        },
+                    };
+                    (summary, true)
+                }
+                TaskEvent::Failed { task_id, error } => (TranscriptLine::Error { task_id, error }, true),
+                // cancelled or timed out, there is no result to summarize
+                TaskEvent::StatusChanged { task_id, status } if status.is_terminal() => {
+                    let error = format!("task ended with status {}", status.name());
+                    (TranscriptLine::Error { task_id, error }, true)
+                }
+                _ => continue,
+            };
+
+            let state = (!finished).then_some((events, segments));
+            return Some((Ok(transcript_line(&line)), state));
+        }
+    })
+}
+
+// one ndjson line. the lines only hold strings and numbers, serializing them can't fail
+fn transcript_line(line: &TranscriptLine) -> String {
+    let mut text = serde_json::to_string(line).unwrap_or_default();
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use crate::schedule::test_support::StubProcessor;
+    use crate::schedule::types::{TaskResult, TranscribeResult};
+
+    fn segment(text: &str, start_time: f64, end_time: f64) -> TranscribeSegment {
+        TranscribeSegment {
+            text: text.to_string(),
+            speaker_id: None,
+            start_time,
+            end_time,
+            words: Vec::new(),
+            confidence: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_summary_follows_segments() {
+        let (sender, receiver) = tokio::sync::broadcast::channel(16);
+        let lines = transcript_lines(TaskEventReceiver::new(receiver, "task-a".to_string()));
+
+        let segments = vec![segment("hello", 0.0, 500.0), segment(" streaming", 500.0, 1200.0), segment(" world", 1200.0, 2000.0)];
+        for segment in &segments {
+            sender.send(TaskEvent::Segment { task_id: "task-a".to_string(), segment: segment.clone() }).unwrap();
+            // events of other tasks and progress are not part of the transcript
+            sender.send(TaskEvent::Segment { task_id: "task-b".to_string(), segment: segment.clone() }).unwrap();
+            sender.send(TaskEvent::Progress { task_id: "task-a".to_string(), percent: 50 }).unwrap();
+        }
+        sender.send(TaskEvent::Completed {
+            task_id: "task-a".to_string(),
+            result: TaskResult::Transcribe(TranscribeResult {
+                text: "hello streaming world".to_string(),
+                segments: segments.clone(),
+                detected_language: "en".to_string(),
+                language_probability: 0.9,
+                language: "en".to_string(),
+                ..Default::default()
+            }),
+        }).unwrap();
+        // the stream ends with the summary, later events are not read
+        sender.send(TaskEvent::Segment { task_id: "task-a".to_string(), segment: segment("late", 0.0, 1.0) }).unwrap();
+
+        let lines: Vec<serde_json::Value> = lines
+            .map(|line| {
+                let line = line.unwrap();
+                assert!(line.ends_with('\n'));
+                serde_json::from_str(&line).unwrap()
+            })
+            .collect()
+            .await;
+
+        assert_eq!(lines.len(), 4);
+        let (summary, streamed) = lines.split_last().unwrap();
+        for (line, segment) in streamed.iter().zip(&segments) {
+            assert_eq!(line["type"], "segment");
+            assert_eq!(line["text"], segment.text);
+        }
+        let aggregate: String = streamed.iter().map(|l| l["text"].as_str().unwrap()).collect();
+
+        assert_eq!(summary["type"], "summary");
+        assert_eq!(summary["task_id"], "task-a");
+        assert_eq!(summary["full_text"], aggregate);
+        assert_eq!(summary["segments"], 3);
+        assert_eq!(summary["duration"], 2000.0);
+        assert_eq!(summary["language"], "en");
+        // timings were not requested
+        assert!(summary.get("timings_ms").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failure_ends_stream() {
+        let (sender, receiver) = tokio::sync::broadcast::channel(16);
+        let lines = transcript_lines(TaskEventReceiver::new(receiver, "task-a".to_string()));
+
+        sender.send(TaskEvent::Segment { task_id: "task-a".to_string(), segment: segment("hello", 0.0, 500.0) }).unwrap();
+        sender.send(TaskEvent::Failed { task_id: "task-a".to_string(), error: "boom".to_string() }).unwrap();
+
+        let lines: Vec<serde_json::Value> = lines
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect()
+            .await;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["type"], "error");
+        assert_eq!(lines[1]["error"], "boom");
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_and_lag_end_stream() {
+        use crate::schedule::types::TaskStatus;
+
+        let (sender, receiver) = tokio::sync::broadcast::channel(16);
+        let lines = transcript_lines(TaskEventReceiver::new(receiver, "task-a".to_string()));
+        sender.send(TaskEvent::StatusChanged { task_id: "task-a".to_string(), status: TaskStatus::Processing }).unwrap();
+        sender.send(TaskEvent::StatusChanged { task_id: "task-a".to_string(), status: TaskStatus::Cancelled }).unwrap();
+        let lines: Vec<serde_json::Value> = lines
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect()
+            .await;
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["type"], "error");
+        assert_eq!(lines[0]["error"], "task ended with status Cancelled");
+
+        // a reader that fell behind is told so instead of getting a transcript with holes
+        let (sender, receiver) = tokio::sync::broadcast::channel(2);
+        let lines = transcript_lines(TaskEventReceiver::new(receiver, "task-a".to_string()));
+        for text in ["one", "two", "three", "four"] {
+            sender.send(TaskEvent::Segment { task_id: "task-a".to_string(), segment: segment(text, 0.0, 1.0) }).unwrap();
+        }
+        let lines: Vec<serde_json::Value> = lines
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect()
+            .await;
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["type"], "error");
+        assert_eq!(lines[0]["task_id"], "task-a");
+    }
+
+    fn multipart_body(boundary: &str, fields: &[(&str, &str)], filename: &str, audio: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend(format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                boundary, name, value
+            ).into_bytes());
+        }
+        body.extend(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"audio\"; filename=\"{}\"\r\nContent-Type: audio/wav\r\n\r\n",
+            boundary, filename
+        ).into_bytes());
+        body.extend_from_slice(audio);
+        body.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+        body
+    }
+
+    #[tokio::test]
+    async fn test_transcribe_multipart_upload() {
+        use crate::auth::{Auth, RateLimit};
+        use crate::schedule::{TaskManager, TaskScheduler};
+        use crate::storage::task::sqlite::SqliteTaskStorage;
+
+        let auth = Arc::new(Auth::new_with_memory_storage());
+        let key = auth.create_api_key(
+            "upload key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit { requests_per_minute: 1000, requests_per_hour: 10000, requests_per_day: 100000 },
+            None,
+        ).await.unwrap().key;
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        // accepts any transcription, the tasks are never run
+        task_manager.register_processor(Box::new(StubProcessor::new(TaskType::Transcribe)));
+        let task_manager = Arc::new(task_manager);
+        let ctx = Arc::new(AppContext {
+            auth,
+            task_manager: task_manager.clone(),
+            scheduler: Arc::new(TaskScheduler::new(task_manager.clone())),
+            voiceprints: Arc::new(crate::storage::voiceprint::InMemoryVoiceprintStorage::new()),
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, transcribe_router(ctx)).await.unwrap() });
+
+        // one second of a 16khz tone
+        let dir = tempfile::TempDir::new().unwrap();
+        let wav_path = dir.path().join("clip.wav");
+        let spec = hound::WavSpec { channels: 1, sample_rate: 16000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&wav_path, spec).unwrap();
+        for i in 0..16000 {
+            writer.write_sample(((i as f32 * 0.05).sin() * 8000.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let audio = fs::read(&wav_path).unwrap();
+
+        let client = reqwest::Client::new();
+        let upload = |fields: &[(&str, &str)], filename: &str| {
+            let boundary = "asr-upload-boundary";
+            client.post(format!("{}/transcribe", base))
+                .bearer_auth(&key)
+                .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary))
+                .body(multipart_body(boundary, fields, filename, &audio))
+                .send()
+        };
+        let fields = [
+            ("callback_url", "https://hooks.example.com/callback"),
+            ("language", "en"),
+            ("speaker_diarization", "false"),
+            ("emotion_recognition", "false"),
+            ("filter_dirty_words", "false"),
+            ("chunk_seconds", "30"),
+            ("label", "42"),
+        ];
+
+        let response = upload(&fields, &format!("{}.wav", uuid::Uuid::new_v4())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        // only the id and status come back, the config is read from the stored task
+        assert_eq!(body["data"]["status"], "Pending");
+        assert!(body["data"].get("config").is_none());
+        let task_id = body["data"]["task_id"].as_str().unwrap();
+        let task = task_manager.get_task(task_id).await.unwrap().unwrap();
+        let config = serde_json::to_value(&task.config).unwrap();
+        assert_eq!(config["params"]["params"]["language"], "en");
+        assert_eq!(config["params"]["params"]["chunk_seconds"], 30);
+        // text fields are never parsed as json
+        assert_eq!(config["label"], "42");
+        let input_path = PathBuf::from(config["input_path"].as_str().unwrap());
+        assert_eq!(fs::read(&input_path).unwrap(), audio);
+        fs::remove_file(&input_path).unwrap();
+
+        // a checksum mismatch removes the upload again
+        let filename = format!("{}.wav", uuid::Uuid::new_v4());
+        let mismatched: Vec<_> = fields.iter().copied().chain([("expected_sha256", "00")]).collect();
+        let response = upload(&mismatched, &filename).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(!PathBuf::from(AUDIO_PATH.as_str()).join(&filename).exists());
+    }
+}
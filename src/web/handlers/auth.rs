@@ -1,27 +1,66 @@
 #![warn(dead_code)]
 
 use axum::{
-    extract::State,
+    extract::{Extension, Query, State},
     http::StatusCode,
     Json,
     extract::Path,
+    middleware::from_fn_with_state,
     Router,
-    routing::{post, delete},
+    routing::{get, patch},
     response::IntoResponse,
 };
 use serde::{Deserialize, Serialize};
 use crate::Auth;
-use crate::auth::{Permission, RateLimit, ApiKeyInfo};
+use crate::web::{ApiError, ApiResponse};
+use crate::auth::{auth_middleware, AuthError, Permission, RateLimit, ApiKeyInfo, ApiKeyFilter, KeyStatus, RequireAuth};
 
 use std::sync::Arc;
 
 pub fn auth_router(auth: Arc<Auth>) -> Router {
+    // usage routes need a valid key, which may only look at itself unless it is an admin key
+    let usage = Router::new()
+        .route("/api-keys/:key/stats", get(get_key_stats))
+        .route("/api-keys/:key/usage", get(get_key_usage_report))
+        .route_layer(from_fn_with_state(
+            RequireAuth::new(auth.clone(), Permission::Transcribe),
+            auth_middleware,
+        ));
+
+    // key management routes for operators. the listing masks the keys, so updates and
+    // revocations address a key by its id. the first admin key comes from `bootstrap_admin_key`
+    let admin = Router::new()
+        .route("/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/api-keys/:key", patch(update_api_key).delete(revoke_api_key))
+        .route_layer(from_fn_with_state(
+            RequireAuth::new(auth.clone(), Permission::Admin),
+            auth_middleware,
+        ));
+
     Router::new()
-        .route("/api-keys", post(create_api_key))
-        .route("/api-keys/:api_key", delete(revoke_api_key))
+        .merge(usage)
+        .merge(admin)
         .with_state(auth)
 }
 
+// keep the scheme prefix and the last 4 characters, listings must not leak usable keys
+pub(crate) fn mask_key(api_key: &str) -> String {
+    let chars: Vec<char> = api_key.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let prefix: String = match api_key.find('-') {
+        Some(index) if index < 8 => api_key[..=index].to_string(),
+        _ => String::new(),
+    };
+    let suffix: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}****{}", prefix, suffix)
+}
+
+fn can_view_key(caller: &ApiKeyInfo, api_key: &str) -> bool {
+    caller.key == api_key || caller.permissions.iter().any(|p| p.implies(&Permission::Admin))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateApiKeyRequest {
     pub name: String,
@@ -35,97 +74,195 @@ pub struct ApiKeyResponse {
     pub key_info: ApiKeyInfo,
 }
 
-#[derive(Debug, Serialize)]
-struct ApiResponse<T> {
-    success: bool,
-    data: Option<T>,
-    error: Option<String>,
-}
-
-impl<T: Serialize> ApiResponse<T> {
-    fn success(data: T) -> Self {
-        Self {
-            success: true,
-            data: Some(data),
-            error: None,
-        }
-    }
-
-    fn error(error: String) -> Self {
-        Self {
-            success: false,
-            data: None,
-            error: Some(error),
-        }
-    }
-}
-
 async fn create_api_key(
     State(auth): State<Arc<Auth>>,
     Json(req): Json<CreateApiKeyRequest>,
-) -> impl IntoResponse {
-    match auth.create_api_key(
+) -> Result<impl IntoResponse, ApiError> {
+    let key_info = auth.create_api_key(
         req.name,
         req.permissions,
         req.rate_limit,
         req.expires_in_days,
-    ) {
-        Ok(key_info) => (
-            StatusCode::CREATED,
-            Json(ApiResponse::success(ApiKeyResponse { key_info }))
-        ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::error(e.to_string()))
-        ),
-    }
+    ).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::success(ApiKeyResponse { key_info }))
+    ))
 }
 
+// Revoke a key given its id, the `id` of the listing
 async fn revoke_api_key(
     State(auth): State<Arc<Auth>>,
-    Path(api_key): Path<String>,
-) -> impl IntoResponse {
-    match auth.revoke_api_key(&api_key) {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(ApiResponse::<()>::success(()))
-        ),
-        Err(e) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::error(e.to_string()))
-        ),
-    }
+    Path(key_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let api_key = auth.find_api_key(&key_id).await?.key;
+    auth.revoke_api_key(&api_key).await?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::<()>::success(()))
+    ))
 }
 
 async fn get_key_stats(
     State(auth): State<Arc<Auth>>,
+    Extension(caller): Extension<ApiKeyInfo>,
     Path(api_key): Path<String>,
-) -> impl IntoResponse {
-    match auth.get_key_stats(&api_key) {
-        Ok(stats) => (
-            StatusCode::OK,
-            Json(ApiResponse::success(stats))
-        ),
-        Err(e) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::error(e.to_string()))
-        ),
+) -> Result<impl IntoResponse, ApiError> {
+    if !can_view_key(&caller, &api_key) {
+        return Err(AuthError::InsufficientPermissions.into());
     }
+
+    let stats = auth.get_key_stats(&api_key).await?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(stats))
+    ))
 }
 
 
 async fn get_key_usage_report(
     State(auth): State<Arc<Auth>>,
+    Extension(caller): Extension<ApiKeyInfo>,
     Path(api_key): Path<String>,
-) -> impl IntoResponse {
-    match auth.get_key_usage_report(&api_key) {
-        Ok(report) => (
-            StatusCode::OK,
-            Json(ApiResponse::success(report))
-        ),
-        Err(e) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::error(e.to_string()))
-        ),
+) -> Result<impl IntoResponse, ApiError> {
+    if !can_view_key(&caller, &api_key) {
+        return Err(AuthError::InsufficientPermissions.into());
+    }
+
+    let report = auth.get_key_usage_report(&api_key).await?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(report))
+    ))
+}
+
+// List api keys, optionally only those with a status (`Active`, `Suspended`, `Expired`)
+// or expiring within the given number of days
+async fn list_api_keys(
+    State(auth): State<Arc<Auth>>,
+    Query(filter): Query<ApiKeyFilter>,
+) -> Result<impl IntoResponse, ApiError> {
+    if filter.expiring_within_days.is_some_and(|days| days < 0) {
+        return Err(ApiError::Validation("expiring_within_days must not be negative".to_string()));
+    }
+    let keys: Vec<ApiKeyInfo> = auth.list_api_keys_filtered(&filter).await?
+        .into_iter()
+        .map(|mut key_info| {
+            key_info.key = mask_key(&key_info.key);
+            key_info
+        })
+        .collect();
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(keys))
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateApiKeyRequest {
+    pub status: Option<KeyStatus>,
+    pub rate_limit: Option<RateLimit>,
+    pub permissions: Option<Vec<Permission>>,
+    // per key minimum audio duration in seconds, 0 accepts clips of any length
+    pub min_audio_seconds: Option<f64>,
+    // hosts callback urls of the key may point at, an empty list allows any host
+    pub allowed_callback_hosts: Option<Vec<String>>,
+}
+
+// Update a key given its id, the `id` of the listing
+async fn update_api_key(
+    State(auth): State<Arc<Auth>>,
+    Path(key_id): Path<String>,
+    Json(req): Json<UpdateApiKeyRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let api_key = auth.find_api_key(&key_id).await?.key;
+    let mut key_info = auth
+        .update_api_key(&api_key, req.status, req.rate_limit, req.permissions)
+        .await?;
+    if let Some(seconds) = req.min_audio_seconds {
+        key_info = auth.set_min_audio_seconds(&api_key, Some(seconds)).await?;
+    }
+    if let Some(hosts) = req.allowed_callback_hosts {
+        key_info = auth.set_allowed_callback_hosts(&api_key, hosts).await?;
+    }
+    key_info.key = mask_key(&key_info.key);
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(ApiKeyResponse { key_info }))
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_key() {
+        let key = "key-6f1c2e0a-58d4-4c1e-9f4b-2a7d3c9e8b10";
+        let masked = mask_key(key);
+        assert_eq!(masked, "key-****8b10");
+        assert!(!masked.contains("6f1c2e0a"));
+
+        // short keys are hidden entirely
+        assert_eq!(mask_key("test-key"), "********");
+        // no recognizable prefix
+        assert_eq!(mask_key("abcdefghijklmnop"), "****mnop");
+    }
+
+    #[tokio::test]
+    async fn test_only_admin_keys_manage_keys() {
+        let auth = Arc::new(Auth::new_with_memory_storage());
+        let rate_limit = RateLimit { requests_per_minute: 1000, requests_per_hour: 10000, requests_per_day: 100000 };
+        let admin = auth.create_api_key("admin".to_string(), vec![Permission::Admin], rate_limit.clone(), None).await.unwrap();
+        let user = auth.create_api_key("user".to_string(), vec![Permission::Transcribe], rate_limit, None).await.unwrap();
+
+        let router = Router::new().nest("/auth", auth_router(auth.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let client = reqwest::Client::new();
+        let new_key = serde_json::json!({
+            "name": "escalated",
+            "permissions": ["Admin"],
+            "rate_limit": { "requests_per_minute": 10, "requests_per_hour": 100, "requests_per_day": 1000 },
+            "expires_in_days": null,
+        });
+        let create = || client.post(format!("http://{}/auth/api-keys", addr)).json(&new_key);
+        let revoke = || client.delete(format!("http://{}/auth/api-keys/{}", addr, admin.id));
+
+        for (request, expected) in [
+            (create(), StatusCode::UNAUTHORIZED),
+            (create().bearer_auth(&user.key), StatusCode::FORBIDDEN),
+            (revoke(), StatusCode::UNAUTHORIZED),
+            (revoke().bearer_auth(&user.key), StatusCode::FORBIDDEN),
+        ] {
+            let request = request.build().unwrap();
+            let endpoint = format!("{} {}", request.method(), request.url().path());
+            assert_eq!(client.execute(request).await.unwrap().status(), expected, "{}", endpoint);
+        }
+        assert_eq!(auth.list_api_keys().await.unwrap().len(), 2);
+        assert_eq!(auth.find_api_key(&admin.id).await.unwrap().status, KeyStatus::Active);
+
+        // an admin key creates keys and revokes them by id
+        let response = create().bearer_auth(&admin.key).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = client
+            .delete(format!("http://{}/auth/api-keys/{}", addr, user.id))
+            .bearer_auth(&admin.key)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(auth.find_api_key(&user.id).await.unwrap().status, KeyStatus::Suspended);
+
+        // the raw key is not an id
+        let response = client
+            .delete(format!("http://{}/auth/api-keys/{}", addr, user.key))
+            .bearer_auth(&admin.key)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
-} 
\ No newline at end of file
+}
+
@@ -0,0 +1,74 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+use crate::schedule::{TaskManager, TaskType};
+use crate::web::ApiResponse;
+
+// how long `ffmpeg -version` may take before ffmpeg counts as unreachable
+const FFMPEG_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// liveness and readiness probes, without authentication so orchestrators can reach them
+pub fn health_router(task_manager: Arc<TaskManager>) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .with_state(task_manager)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Readiness {
+    pub storage: bool,
+    pub whisper: bool,
+    pub ffmpeg: bool,
+}
+
+// Liveness: the server answers requests
+pub async fn health() -> Json<ApiResponse<()>> {
+    Json(ApiResponse::success(()))
+}
+
+// Readiness: the task database answers, the whisper model is loaded and ffmpeg can be run.
+// 503 names the subsystems that are not ready
+pub async fn ready(State(task_manager): State<Arc<TaskManager>>) -> Response {
+    let mut failures = Vec::new();
+
+    if let Err(e) = task_manager.ping_storage().await {
+        failures.push(format!("storage: {}", e));
+    }
+    // the transcribe processor is registered once the default model has loaded
+    if !task_manager.has_processor(&TaskType::Transcribe) {
+        failures.push("whisper: model not loaded".to_string());
+    }
+    if let Err(e) = check_ffmpeg().await {
+        failures.push(format!("ffmpeg: {}", e));
+    }
+
+    if !failures.is_empty() {
+        let error = format!("Not ready: {}", failures.join("; "));
+        warn!("{}", error);
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(ApiResponse::<()>::error(error))).into_response();
+    }
+    let readiness = Readiness { storage: true, whisper: true, ffmpeg: true };
+    (StatusCode::OK, Json(ApiResponse::success(readiness))).into_response()
+}
+
+async fn check_ffmpeg() -> anyhow::Result<()> {
+    let output = tokio::process::Command::new("ffmpeg")
+        .arg("-version")
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(FFMPEG_CHECK_TIMEOUT, output).await
+        .map_err(|_| anyhow::anyhow!("ffmpeg -version timed out"))??;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("ffmpeg -version failed with status: {}", output.status));
+    }
+    Ok(())
+}
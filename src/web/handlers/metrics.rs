@@ -0,0 +1,86 @@
+use axum::{
+    extract::State,
+    http::header,
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use tracing::warn;
+use crate::utils::metrics;
+use crate::web::handlers::auth::mask_key;
+use crate::AppContext;
+
+// content type of the prometheus text exposition format
+const EXPOSITION_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// prometheus scrape endpoint, without authentication like the health probes
+pub fn metrics_router(ctx: Arc<AppContext>) -> Router {
+    metrics::init();
+    Router::new()
+        .route("/metrics", get(export))
+        .with_state(ctx)
+}
+
+// Export the counters recorded by the task manager and the workers. the gauges and the
+// per api key counts are read from storage on each scrape
+pub async fn export(State(ctx): State<Arc<AppContext>>) -> impl IntoResponse {
+    match ctx.task_manager.queue_depth().await {
+        Ok(pending) => metrics::set_queue_depth(pending),
+        Err(e) => warn!("Failed to count pending tasks for metrics: {}", e),
+    }
+
+    match ctx.auth.list_api_keys().await {
+        Ok(keys) => {
+            for key in keys {
+                match ctx.auth.get_key_stats(&key.key).await {
+                    Ok(stats) => metrics::set_api_key_requests(&mask_key(&key.key), &key.name, stats.total_requests),
+                    Err(e) => warn!("Failed to read stats of api key {} for metrics: {}", key.name, e),
+                }
+            }
+        }
+        Err(e) => warn!("Failed to list api keys for metrics: {}", e),
+    }
+
+    ([(header::CONTENT_TYPE, EXPOSITION_CONTENT_TYPE)], metrics::render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{Auth, Permission, RateLimit};
+    use crate::schedule::{TaskManager, TaskScheduler};
+    use crate::storage::task::sqlite::SqliteTaskStorage;
+
+    #[tokio::test]
+    async fn test_metrics_exposition() {
+        let auth = Arc::new(Auth::new_with_memory_storage());
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+        let task_manager = Arc::new(TaskManager::new(Arc::new(storage)));
+        let ctx = Arc::new(AppContext {
+            auth: auth.clone(),
+            task_manager: task_manager.clone(),
+            scheduler: Arc::new(TaskScheduler::new(task_manager)),
+            voiceprints: Arc::new(crate::storage::voiceprint::InMemoryVoiceprintStorage::new()),
+        });
+        let key = auth.create_api_key(
+            "metrics key".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit { requests_per_minute: 60, requests_per_hour: 1000, requests_per_day: 10000 },
+            None,
+        ).await.unwrap().key;
+        auth.verify_api_key(Some(&key), Permission::Transcribe).await.unwrap();
+
+        let router = metrics_router(ctx);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let response = reqwest::get(format!("http://{}/metrics", addr)).await.unwrap();
+        assert!(response.headers()[header::CONTENT_TYPE.as_str()].to_str().unwrap().starts_with("text/plain"));
+        let body = response.text().await.unwrap();
+        assert!(body.contains("asr_queue_depth 0"), "{}", body);
+        let requests = format!(r#"asr_api_key_requests_total{{key="{}",name="metrics key"}} 1"#, mask_key(&key));
+        assert!(body.contains(&requests), "{}", body);
+    }
+}
@@ -1,16 +1,154 @@
 use axum::Router;
 use std::sync::Arc;
 use crate::AppContext;
+use crate::web::request_id::with_request_id;
 
 pub mod asr;
 pub mod auth;
 pub mod schedule;
 pub mod callback_test;
+pub mod health;
+pub mod metrics;
+pub mod version;
 
 pub fn router(ctx: Arc<AppContext>) -> Router {
-    Router::new()
+    let router = Router::new()
         .nest("/asr", asr::transcribe_router(ctx.clone()))
         .nest("/auth", auth::auth_router(ctx.auth.clone()))
-        .nest("/schedule", schedule::schedule_router(ctx.task_manager.clone()))
+        .nest("/schedule", schedule::schedule_router(ctx.task_manager.clone(), ctx.auth.clone())
+            .merge(schedule::schedule_admin_router(ctx.clone())))
         .nest("/callback", callback_test::callback_router())
-} 
\ No newline at end of file
+        .merge(version::version_router())
+        .merge(health::health_router(ctx.task_manager.clone()))
+        .merge(metrics::metrics_router(ctx));
+    with_request_id(router)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use uuid::Uuid;
+    use crate::auth::{Auth, Permission, RateLimit};
+    use crate::schedule::{TaskManager, TaskScheduler};
+    use crate::schedule::processors::NoiseReductionProcessor;
+    use crate::storage::task::sqlite::SqliteTaskStorage;
+
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    async fn create_key(auth: &Auth, permission: Permission) -> String {
+        auth.create_api_key(
+            format!("{:?} key", permission),
+            vec![permission],
+            RateLimit {
+                requests_per_minute: 1000,
+                requests_per_hour: 10000,
+                requests_per_day: 100000,
+            },
+            None,
+        ).await.unwrap().key
+    }
+
+    #[tokio::test]
+    async fn test_every_endpoint_uses_the_api_response_envelope() {
+        let auth = Arc::new(Auth::new_with_memory_storage());
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        task_manager.register_processor(Box::new(NoiseReductionProcessor::new(std::env::temp_dir())));
+        let task_manager = Arc::new(task_manager);
+        let ctx = Arc::new(AppContext {
+            auth: auth.clone(),
+            task_manager: task_manager.clone(),
+            scheduler: Arc::new(TaskScheduler::new(task_manager)),
+            voiceprints: Arc::new(crate::storage::voiceprint::InMemoryVoiceprintStorage::new()),
+        });
+        let admin = create_key(&auth, Permission::Admin).await;
+        let user = create_key(&auth, Permission::Transcribe).await;
+        auth.set_allowed_callback_hosts(&user, vec!["hooks.example.com".to_string()]).await.unwrap();
+        let base = serve(router(ctx)).await;
+        let client = reqwest::Client::new();
+
+        let missing = format!("task-{}", Uuid::new_v4());
+        let transcribe = serde_json::json!({
+            "audio_url": "http://127.0.0.1:9/audio.wav",
+            "callback_url": "https://elsewhere.example.com/callback",
+            "speaker_diarization": false,
+            "emotion_recognition": false,
+            "filter_dirty_words": false,
+        });
+        let denoise = serde_json::json!({
+            "audio_url": "http://127.0.0.1:9/audio.wav",
+            "callback_url": "https://hooks.example.com/callback",
+            "strength": 2.0,
+        });
+        let workers = serde_json::json!({ "task_type": "Transcribe", "count": 2 });
+        let voiceprint = serde_json::json!({
+            "task_type": "VoiceprintRecognition",
+            "input_path": "/tmp/clip.wav",
+            "callback_type": { "type": "None" },
+            "params": { "type": "VoiceprintRecognition", "params": { "mode": "Identify" } },
+            "priority": "Normal",
+            "retry_count": 0,
+            "max_retries": 0,
+            "timeout": null,
+        });
+        let requests = [
+            (client.get(format!("{}/version", base)), StatusCode::OK),
+            (client.get(format!("{}/health", base)), StatusCode::OK),
+            // no transcribe processor is registered
+            (client.get(format!("{}/ready", base)), StatusCode::SERVICE_UNAVAILABLE),
+            (client.get(format!("{}/schedule/tasks", base)), StatusCode::UNAUTHORIZED),
+            (client.get(format!("{}/schedule/tasks", base)).bearer_auth(&user), StatusCode::OK),
+            (client.get(format!("{}/schedule/tasks/{}", base, missing)).bearer_auth(&user), StatusCode::GONE),
+            (client.get(format!("{}/schedule/tasks/{}/status", base, missing)).bearer_auth(&user), StatusCode::GONE),
+            (client.post(format!("{}/schedule/tasks/not-a-task/cancel", base)).bearer_auth(&user), StatusCode::NOT_FOUND),
+            // a valid task type without a registered processor is the server's shortcoming
+            (client.post(format!("{}/schedule/tasks", base)).bearer_auth(&user).json(&voiceprint), StatusCode::NOT_IMPLEMENTED),
+            (client.post(format!("{}/schedule/tasks/batch?strict=true", base)).bearer_auth(&user).json(&[&voiceprint]), StatusCode::NOT_IMPLEMENTED),
+            (client.post(format!("{}/schedule/tasks/batch", base)).bearer_auth(&user).json(&[&voiceprint]), StatusCode::OK),
+            (client.get(format!("{}/schedule/callbacks/failed", base)).bearer_auth(&admin), StatusCode::OK),
+            (client.get(format!("{}/schedule/callbacks/failed", base)).bearer_auth(&user), StatusCode::FORBIDDEN),
+            (client.post(format!("{}/schedule/workers", base)).bearer_auth(&user).json(&workers), StatusCode::FORBIDDEN),
+            // no processor of the type is registered
+            (client.post(format!("{}/schedule/workers", base)).bearer_auth(&admin).json(&workers), StatusCode::BAD_REQUEST),
+            (client.delete(format!("{}/schedule/tasks/{}", base, missing)).bearer_auth(&admin), StatusCode::BAD_REQUEST),
+            (client.delete(format!("{}/schedule/tasks/{}?purge=true", base, missing)).bearer_auth(&admin), StatusCode::GONE),
+            (client.get(format!("{}/auth/api-keys", base)).bearer_auth(&admin), StatusCode::OK),
+            (client.get(format!("{}/auth/api-keys/{}/stats", base, user)).bearer_auth(&user), StatusCode::OK),
+            (client.post(format!("{}/asr/transcribe", base)).bearer_auth(&user).json(&transcribe), StatusCode::FORBIDDEN),
+            (client.post(format!("{}/asr/denoise", base)).bearer_auth(&user).json(&denoise), StatusCode::BAD_REQUEST),
+        ];
+
+        for (request, expected) in requests {
+            let request = request.build().unwrap();
+            let endpoint = format!("{} {}", request.method(), request.url().path());
+            let response = client.execute(request).await.unwrap();
+            assert_eq!(response.status(), expected, "{}", endpoint);
+
+            let body: serde_json::Value = response.json().await.unwrap();
+            let fields: Vec<&str> = body.as_object().unwrap().keys().map(String::as_str).collect();
+            assert_eq!(fields, vec!["data", "error", "success"], "{}", endpoint);
+            assert_eq!(body["success"], expected.is_success(), "{}", endpoint);
+            if expected.is_success() {
+                assert!(body["error"].is_null(), "{}", endpoint);
+            } else {
+                assert!(body["data"].is_null(), "{}", endpoint);
+                assert!(body["error"].is_string(), "{}", endpoint);
+            }
+        }
+
+        // per config errors of a batch carry the status the config alone would have been rejected with
+        let body: serde_json::Value = client.post(format!("{}/schedule/tasks/batch", base))
+            .bearer_auth(&user)
+            .json(&[&voiceprint])
+            .send().await.unwrap()
+            .json().await.unwrap();
+        assert_eq!(body["data"]["errors"][0]["index"], 0);
+        assert_eq!(body["data"]["errors"][0]["status"], 501);
+    }
+}
@@ -1,121 +1,540 @@
 use axum::{
-    routing::{post, get},
+    routing::{delete, post, get},
     Router,
-    extract::{State, Path, Json},
-    response::IntoResponse,
-    http::StatusCode,
+    body::Body,
+    extract::{Extension, State, Path, Query, Json, ws::{Message, WebSocket, WebSocketUpgrade}},
+    middleware::from_fn_with_state,
+    response::{IntoResponse, Response},
+    http::{header, StatusCode},
 };
 use std::sync::Arc;
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::web::Pagination;
-use crate::schedule::types::{TaskConfig,  TaskPriority};
+use crate::web::{ApiError, ApiResponse, Pagination, RequestId};
+use crate::schedule::types::{CallbackType, Task, TaskConfig, TaskParams, TaskPriority, TaskResult, TaskStatus, TaskType};
+use crate::asr::compare::compare;
+use crate::asr::format::SubtitleFormat;
+use crate::schedule::callback::{Lagged, TaskEvent, TaskEventReceiver};
 use crate::schedule::scheduler::TaskManager;
+use crate::storage::task::TaskFilter;
+use crate::auth::{auth_middleware, ApiKeyInfo, Auth, Permission, RequireAuth};
+use crate::AppContext;
 use tracing::error;
 
-pub fn schedule_router(task_manager: Arc<TaskManager>) -> Router {
+// task routes, any key with the transcribe permission may use them
+pub fn schedule_router(task_manager: Arc<TaskManager>, auth: Arc<Auth>) -> Router {
     Router::new()
-        .route("/tasks", post(create_task))
+        .route("/tasks", post(create_task).get(list_tasks))
+        .route("/tasks/batch", post(create_tasks))
+        .route("/tasks/events", get(stream_events))
         .route("/tasks/:task_id", get(get_task))
         .route("/tasks/:task_id/status", get(get_task_status))
         .route("/tasks/:task_id/priority", post(update_task_priority))
+        .route("/tasks/:task_id/cancel", post(cancel_task))
+        .route("/tasks/:task_id/subtitles", get(get_task_subtitles))
+        .route("/tasks/:task_id/result", get(get_task_result))
+        .route("/tasks/:task_id/output", get(get_task_output))
+        .route("/tasks/:task_id/stream", get(stream_task_events))
         .route("/tasks/stats", get(get_task_stats))
+        .route_layer(from_fn_with_state(
+            RequireAuth::new(auth, Permission::Transcribe),
+            auth_middleware,
+        ))
         .with_state(task_manager)
 }
 
+// admin only routes, they need the auth service besides the task manager
+pub fn schedule_admin_router(ctx: Arc<AppContext>) -> Router {
+    Router::new()
+        .route("/tasks/cancel-by-label", post(cancel_tasks_by_label))
+        .route("/tasks/cancel-by-key", post(cancel_tasks_by_key))
+        .route("/tasks/compare", get(compare_task_results))
+        .route("/callbacks/failed", get(list_failed_callbacks))
+        .route("/workers", post(scale_workers))
+        .route("/tasks/:task_id", delete(delete_task))
+        .route_layer(from_fn_with_state(
+            RequireAuth::new(ctx.auth.clone(), Permission::Admin),
+            auth_middleware,
+        ))
+        .with_state(ctx)
+}
+
+// Create task endpoint
+async fn create_task(
+    State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    RequestId(request_id): RequestId,
+    Json(mut config): Json<TaskConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    config.request_id = request_id;
+    config.api_key = Some(key_info.id.clone());
+
+    // keys restricted to their own callback hosts can't point callbacks elsewhere
+    if let CallbackType::Http { url, .. } = &config.callback_type {
+        key_info.check_callback_url(url).map_err(ApiError::Forbidden)?;
+    }
+
+    let task = task_manager.create_task(config).await.context("Failed to create task")?;
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::success(task.redacted()))
+    ))
+}
+
+// most configs accepted by one batch request
+const MAX_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Default, Deserialize)]
+struct BatchQuery {
+    // reject the whole batch when any config is invalid instead of creating the valid ones
+    #[serde(default)]
+    strict: bool,
+}
+
 #[derive(Debug, Serialize)]
-struct ApiResponse<T> {
-    success: bool,
-    data: Option<T>,
-    error: Option<String>,
+struct BatchError {
+    // position of the config in the request
+    index: usize,
+    // the status the config would have been rejected with on its own
+    status: u16,
+    error: String,
+}
+
+impl BatchError {
+    fn new(index: usize, error: ApiError) -> Self {
+        if let ApiError::Internal(e) = &error {
+            error!("Failed to create task {} of a batch: {:#}", index, e);
+        }
+        Self { index, status: error.status().as_u16(), error: error.to_string() }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct BatchResponse {
+    // ids of the created tasks, in request order
+    task_ids: Vec<String>,
+    errors: Vec<BatchError>,
 }
 
-impl<T: Serialize> ApiResponse<T> {
-    fn success(data: T) -> Self {
-        Self {
-            success: true,
-            data: Some(data),
-            error: None,
+// Create many tasks in one storage transaction
+async fn create_tasks(
+    State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    RequestId(request_id): RequestId,
+    Query(query): Query<BatchQuery>,
+    Json(configs): Json<Vec<TaskConfig>>,
+) -> Result<impl IntoResponse, ApiError> {
+    if configs.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::PayloadTooLarge(format!("At most {} tasks per batch", MAX_BATCH_SIZE)));
+    }
+
+    // the checks `create_task` does, per config
+    let mut rejected: Vec<(usize, ApiError)> = Vec::new();
+    let mut accepted = Vec::with_capacity(configs.len());
+    for (index, mut config) in configs.into_iter().enumerate() {
+        config.request_id = request_id.clone();
+        config.api_key = Some(key_info.id.clone());
+        let checked = match &config.callback_type {
+            CallbackType::Http { url, .. } => key_info
+                .check_callback_url(url)
+                .map_err(|e| ApiError::Forbidden(format!("Callback host not allowed: {}", e))),
+            _ => Ok(()),
+        }.and_then(|_| task_manager
+            .validate_params(&config.task_type, &config.params)
+            .map_err(ApiError::from));
+        match checked {
+            Ok(()) => accepted.push((index, config)),
+            Err(error) => rejected.push((index, error)),
         }
     }
+    // a strict batch fails like its first rejected config would have, naming all of them
+    if query.strict && !rejected.is_empty() {
+        let errors: Vec<String> = rejected.iter()
+            .map(|(index, error)| format!("config {}: {}", index, error))
+            .collect();
+        let (_, first) = rejected.swap_remove(0);
+        return Err(first.map_message(|_| errors.join("; ")));
+    }
 
-    fn error(error: String) -> Self {
-        Self {
-            success: false,
-            data: None,
-            error: Some(error),
+    let mut response = BatchResponse {
+        task_ids: Vec::new(),
+        errors: rejected.into_iter().map(|(index, error)| BatchError::new(index, error)).collect(),
+    };
+    let (indexes, configs): (Vec<usize>, Vec<TaskConfig>) = accepted.into_iter().unzip();
+    for (index, result) in indexes.into_iter().zip(task_manager.create_tasks(configs).await) {
+        match result {
+            Ok(task) => response.task_ids.push(task.id),
+            Err(e) => response.errors.push(BatchError::new(index, e.into())),
         }
     }
+    response.errors.sort_by_key(|error| error.index);
+
+    // configs that failed are reported per entry, the batch itself went through
+    Ok((StatusCode::OK, Json(ApiResponse::success(response))))
 }
 
-// Create task endpoint
-async fn create_task(
+// Missing tasks with a well formed `task-<uuid>` id could have existed and been
+// removed by retention cleanup, so they get 410 Gone instead of 404
+fn task_not_found(task_id: &str) -> ApiError {
+    let well_formed = task_id
+        .strip_prefix("task-")
+        .is_some_and(|id| Uuid::parse_str(id).is_ok());
+    if well_formed {
+        ApiError::Gone("Task no longer exists".to_string())
+    } else {
+        ApiError::NotFound("Task not found".to_string())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ListTasksQuery {
+    index: Option<u64>,
+    size: Option<u64>,
+    detected_language: Option<String>,
+    status: Option<String>,
+    priority: Option<TaskPriority>,
+}
+
+// whether the key may see every task, not only the ones it submitted
+fn is_admin(key_info: &ApiKeyInfo) -> bool {
+    key_info.permissions.iter().any(|p| p.implies(&Permission::Admin))
+}
+
+// tasks of other keys are treated as missing
+fn owns_task(key_info: &ApiKeyInfo, task: &Task) -> bool {
+    is_admin(key_info) || task.config.api_key.as_deref() == Some(key_info.id.as_str())
+}
+
+// look up a task the key may act on, tasks of other keys answer like missing ones
+async fn owned_task(task_manager: &TaskManager, key_info: &ApiKeyInfo, task_id: &str) -> Result<Task, ApiError> {
+    match task_manager.get_task(task_id).await.context("Failed to get task")? {
+        Some(task) if owns_task(key_info, &task) => Ok(task),
+        _ => Err(task_not_found(task_id)),
+    }
+}
+
+// List tasks endpoint, optionally filtered by status, priority and the detected language of the result.
+// keys only see their own tasks unless they are admin keys. The page comes with the total number of matching tasks
+async fn list_tasks(
     State(task_manager): State<Arc<TaskManager>>,
-    Json(config): Json<TaskConfig>,
-) -> impl IntoResponse {
-    match task_manager.create_task(config).await {
-        Ok(task) => (
-            StatusCode::CREATED,
-            Json(ApiResponse::success(task))
-        ),
-        Err(e) => {
-            error!("Failed to create task: {}", e);
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse::error(e.to_string()))
-            )
+    Extension(key_info): Extension<ApiKeyInfo>,
+    Query(query): Query<ListTasksQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let default = Pagination::default();
+    let pagination = Pagination {
+        index: query.index.unwrap_or(default.index),
+        size: query.size.unwrap_or(default.size),
+    };
+    let status = match query.status {
+        // any failure reason matches `Failed`
+        Some(status) => match TaskStatus::ALL.iter().map(TaskStatus::name).find(|name| name.eq_ignore_ascii_case(&status)) {
+            Some(name) => Some(name.to_string()),
+            None => return Err(ApiError::Validation(format!("Unknown task status: {}", status))),
         },
-    }
+        None => None,
+    };
+    let filter = TaskFilter {
+        detected_language: query.detected_language,
+        status,
+        priority: query.priority.map(|priority| priority as i32),
+        api_key: (!is_admin(&key_info)).then(|| key_info.id.clone()),
+    };
+    let mut page = task_manager.list_tasks(&pagination, &filter).await.context("Failed to list tasks")?;
+    page.tasks = page.tasks.into_iter().map(Task::redacted).collect();
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(page))
+    ))
 }
 
 // Get task endpoint
 async fn get_task(
     State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
     Path(task_id): Path<String>,
-) -> impl IntoResponse {
-    match task_manager.get_task(&task_id).await {
-        Ok(Some(task)) => (
-            StatusCode::OK,
-            Json(ApiResponse::success(task))
-        ),
-        Ok(None) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::error("Task not found".to_string()))
-        ),
-        Err(e) => {
-            error!("Failed to get task: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(e.to_string()))
-            )
-        },
-    }
+) -> Result<impl IntoResponse, ApiError> {
+    let task = owned_task(&task_manager, &key_info, &task_id).await?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(task.redacted()))
+    ))
 }
 
 // Get task status endpoint
 async fn get_task_status(
     State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    Path(task_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let task = owned_task(&task_manager, &key_info, &task_id).await?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(task.status))
+    ))
+}
+
+// Stream the live progress and decoded segments of one task over a websocket.
+// the socket closes once the task reaches a terminal status
+async fn stream_task_events(
+    State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    Path(task_id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if let Err(e) = owned_task(&task_manager, &key_info, &task_id).await {
+        return e.into_response();
+    }
+
+    // subscribe before upgrading so no event is missed during the handshake
+    let events = task_manager.subscribe_task(&task_id);
+    ws.on_upgrade(move |socket| relay_task_events(socket, task_manager, events))
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    // only forward the events of this task
+    task_id: Option<String>,
+}
+
+// Stream the events of every task over a websocket, or of one task with `task_id`.
+// only admin keys may follow every task, the unfiltered stream stays open until the client closes it
+async fn stream_events(
+    State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    Query(query): Query<EventsQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    match query.task_id {
+        Some(task_id) => stream_task_events(State(task_manager), Extension(key_info), Path(task_id), ws).await,
+        None if !is_admin(&key_info) => {
+            ApiError::Forbidden("Streaming the events of every task needs an admin key".to_string()).into_response()
+        }
+        None => {
+            let events = task_manager.subscribe();
+            ws.on_upgrade(move |socket| relay_task_events(socket, task_manager, events))
+        }
+    }
+}
+
+async fn relay_task_events(mut socket: WebSocket, task_manager: Arc<TaskManager>, mut events: TaskEventReceiver) {
+    loop {
+        tokio::select! {
+            event = events.recv_or_lagged() => {
+                let event = match event {
+                    Some(Ok(event)) => event,
+                    Some(Err(lagged)) => match resync(&mut socket, &task_manager, &events, lagged).await {
+                        Some(event) => event,
+                        None => continue,
+                    },
+                    None => break,
+                };
+                // a single task's stream ends with its outcome, whether it completed, failed, was cancelled or timed out
+                let finished = events.task_id().is_some() && event.is_terminal();
+                let text = match serde_json::to_string(&event) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        error!("Failed to serialize task event: {}", e);
+                        continue;
+                    }
+                };
+                if socket.send(Message::Text(text)).await.is_err() || finished {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                // the client closed the socket or went away, anything else it sends is ignored
+                if matches!(message, None | Some(Err(_)) | Some(Ok(Message::Close(_)))) {
+                    break;
+                }
+            }
+        }
+    }
+    let _ = socket.close().await;
+}
+
+// tell a subscriber that fell behind how many events it missed, clients refetch what they
+// need. the outcome of a single task may have been among them, so when the task already
+// finished its current status is returned to be sent as the final event
+async fn resync(
+    socket: &mut WebSocket,
+    task_manager: &TaskManager,
+    events: &TaskEventReceiver,
+    lagged: Lagged,
+) -> Option<TaskEvent> {
+    let frame = serde_json::json!({ "event": "lagged", "skipped": lagged.skipped });
+    let _ = socket.send(Message::Text(frame.to_string())).await;
+
+    let task_id = events.task_id()?;
+    match task_manager.get_task_status(task_id).await {
+        Ok(Some(status)) if status.is_terminal() => Some(TaskEvent::StatusChanged { task_id: task_id.to_string(), status }),
+        Ok(_) => None,
+        Err(e) => {
+            error!("Failed to resync task {}: {}", task_id, e);
+            None
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SubtitlesQuery {
+    format: SubtitleFormat,
+}
+
+// Get task result as subtitles endpoint
+async fn get_task_subtitles(
+    State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    Path(task_id): Path<String>,
+    Query(query): Query<SubtitlesQuery>,
+) -> impl IntoResponse {
+    render_subtitles(&task_manager, &key_info, &task_id, query.format).await
+}
+
+#[derive(Debug, Deserialize)]
+struct ResultQuery {
+    // json when omitted
+    format: Option<SubtitleFormat>,
+}
+
+// Get task result endpoint, json results are streamed straight from storage
+async fn get_task_result(
+    State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
     Path(task_id): Path<String>,
+    Query(query): Query<ResultQuery>,
 ) -> impl IntoResponse {
-    match task_manager.get_task_status(&task_id).await {
-        Ok(Some(status)) => (
+    if let Some(format) = query.format {
+        return render_subtitles(&task_manager, &key_info, &task_id, format).await;
+    }
+    if let Err(e) = owned_task(&task_manager, &key_info, &task_id).await {
+        return e.into_response();
+    }
+
+    match task_manager.get_task_result_stream(&task_id).await {
+        Ok(Some(stream)) => (
             StatusCode::OK,
-            Json(ApiResponse::success(status))
-        ),
-        Ok(None) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::error("Task not found".to_string()))
-        ),
-        Err(e) => {
-            error!("Failed to get task status: {}", e);
+            [(header::CONTENT_TYPE, "application/json")],
+            Body::from_stream(stream),
+        ).into_response(),
+        Ok(None) => ApiError::NotFound("Task result not found".to_string()).into_response(),
+        Err(e) => ApiError::from(e.context("Failed to stream task result")).into_response(),
+    }
+}
+
+// Download the denoised wav of a completed noise reduction task
+async fn get_task_output(
+    State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    Path(task_id): Path<String>,
+) -> Response {
+    let task = match owned_task(&task_manager, &key_info, &task_id).await {
+        Ok(task) => task,
+        Err(e) => return e.into_response(),
+    };
+    let Some(result) = task.result.as_ref().and_then(TaskResult::as_noise_reduction) else {
+        return ApiError::Conflict("Task has no output file".to_string()).into_response();
+    };
+
+    let file = match tokio::fs::File::open(&result.output_path).await {
+        Ok(file) => file,
+        // removed by retention cleanup while the task row was kept
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return ApiError::Gone("Task output no longer exists".to_string()).into_response();
+        }
+        Err(e) => return ApiError::from(anyhow::Error::from(e).context("Failed to open task output")).into_response(),
+    };
+    let stream = futures::stream::try_unfold(file, |mut file| async move {
+        use tokio::io::AsyncReadExt;
+        let mut chunk = vec![0; OUTPUT_CHUNK_SIZE];
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok::<_, std::io::Error>(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((chunk, file)))
+    });
+
+    let filename = format!("{}.wav", download_base_name(&task));
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "audio/wav".to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition(&filename)),
+        ],
+        Body::from_stream(stream),
+    ).into_response()
+}
+
+// size of each read when streaming an output file
+const OUTPUT_CHUNK_SIZE: usize = 64 * 1024;
+
+async fn render_subtitles(task_manager: &TaskManager, key_info: &ApiKeyInfo, task_id: &str, format: SubtitleFormat) -> Response {
+    let task = match owned_task(task_manager, key_info, task_id).await {
+        Ok(task) => task,
+        Err(e) => return e.into_response(),
+    };
+
+    // per word cues need the word timings recorded at transcription time
+    let word_timestamps = matches!(&task.config.params, TaskParams::Transcribe(params) if params.token_timestamps);
+    if format.requires_word_timestamps() && !word_timestamps {
+        let message = "format=vtt-words requires a task transcribed with token_timestamps";
+        return ApiError::Validation(message.to_string()).into_response();
+    }
+
+    match task.result.as_ref().and_then(TaskResult::as_transcribe) {
+        Some(result) => {
+            let filename = format!("{}.{}", download_base_name(&task), format.extension());
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(e.to_string()))
-            )
+                StatusCode::OK,
+                [
+                    (header::CONTENT_TYPE, format.content_type().to_string()),
+                    (header::CONTENT_DISPOSITION, content_disposition(&filename)),
+                ],
+                format.render(result),
+            ).into_response()
         },
+        None => ApiError::Conflict("Task has no transcription result".to_string()).into_response(),
     }
 }
 
+// name of a downloaded result without extension: the original audio file name when the input
+// kept it, the task id otherwise. inputs are saved under their original name, with a uuid appended
+// on collision, or a bare uuid when the name was unknown
+fn download_base_name(task: &Task) -> String {
+    let stem = task.config.input_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    // a uuid has 36 characters, the separating dash makes 37
+    let stem = match stem.len().checked_sub(37).filter(|&index| stem.is_char_boundary(index)) {
+        Some(index) if stem[index..].starts_with('-') && Uuid::parse_str(&stem[index + 1..]).is_ok() => &stem[..index],
+        _ => stem,
+    };
+    if stem.trim().is_empty() || Uuid::parse_str(stem).is_ok() {
+        task.id.clone()
+    } else {
+        stem.to_string()
+    }
+}
+
+// attachment header with an ascii fallback name, non ascii names are also sent encoded (RFC 6266)
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() && c != '"' && c != '\\') || c == ' ' { c } else { '_' })
+        .collect();
+    if fallback == filename {
+        return format!("attachment; filename=\"{}\"", filename);
+    }
+    let encoded: String = filename
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
 #[derive(Debug, Deserialize)]
 struct UpdatePriorityRequest {
     priority: TaskPriority,
@@ -124,40 +543,544 @@ struct UpdatePriorityRequest {
 // Update task priority endpoint
 async fn update_task_priority(
     State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
     Path(task_id): Path<String>,
     Json(req): Json<UpdatePriorityRequest>,
-) -> impl IntoResponse {
-    match task_manager.update_task_priority(&task_id, req.priority).await {
-        Ok(_) => (
+) -> Result<impl IntoResponse, ApiError> {
+    owned_task(&task_manager, &key_info, &task_id).await?;
+    task_manager.update_task_priority(&task_id, req.priority).await.context("Failed to update task priority")?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::<()>::success(()))
+    ))
+}
+
+// Cancel task endpoint, stops a running task or removes a pending one from the queue.
+// a task that already finished is a conflict, tasks of other keys can only be cancelled by admin keys
+async fn cancel_task(
+    State(task_manager): State<Arc<TaskManager>>,
+    Extension(key_info): Extension<ApiKeyInfo>,
+    Path(task_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    owned_task(&task_manager, &key_info, &task_id).await?;
+    match task_manager.cancel_task(&task_id).await.context("Failed to cancel task")? {
+        Some(task) => Ok((
             StatusCode::OK,
-            Json(ApiResponse::<()>::success(()))
-        ),
-        Err(e) => {
-            error!("Failed to update task priority: {}", e);
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse::error(e.to_string()))
-            )
-        },
+            Json(ApiResponse::success(task.redacted()))
+        )),
+        None => Err(task_not_found(&task_id)),
     }
 }
 
-// Get task stats endpoint
+#[derive(Debug, Deserialize)]
+struct DeleteTaskQuery {
+    #[serde(default)]
+    purge: bool,
+}
+
+// Delete task endpoint, purging removes the task with its audio file and stored artifacts
+async fn delete_task(
+    State(ctx): State<Arc<AppContext>>,
+    Path(task_id): Path<String>,
+    Query(query): Query<DeleteTaskQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !query.purge {
+        return Err(ApiError::Validation("Tasks can only be deleted with purge=true".to_string()));
+    }
+
+    match ctx.task_manager.purge_task(&task_id).await.context("Failed to purge task")? {
+        Some(summary) => Ok((
+            StatusCode::OK,
+            Json(ApiResponse::success(summary))
+        )),
+        None => Err(task_not_found(&task_id)),
+    }
+}
+
+// Get task stats endpoint, counts over all stored tasks for admins and
+// over its own tasks for any other key
 async fn get_task_stats(
     State(task_manager): State<Arc<TaskManager>>,
-    Path(pagination): Path<Pagination>,
-) -> impl IntoResponse {
-    match task_manager.get_task_stats(&pagination).await {
-        Ok(stats) => (
-            StatusCode::OK,
-            Json(ApiResponse::success(stats)),
-        ),
-            Err(e) => {
-            error!("Failed to get task stats: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(e.to_string()))
-            )
-        },
+    Extension(key_info): Extension<ApiKeyInfo>,
+) -> Result<impl IntoResponse, ApiError> {
+    let filter = TaskFilter {
+        api_key: (!is_admin(&key_info)).then(|| key_info.id.clone()),
+        ..Default::default()
+    };
+    let stats = task_manager.get_task_stats(&filter).await.context("Failed to get task stats")?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(stats)),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct CancelByLabelRequest {
+    label: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CancelByKeyRequest {
+    // `id` of the key as listed by /api-keys
+    key_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CancelResponse {
+    cancelled: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScaleWorkersRequest {
+    task_type: TaskType,
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ScaleWorkersResponse {
+    task_type: TaskType,
+    // workers of the type after scaling, fewer than requested when the worker limit is reached
+    workers: usize,
+}
+
+// Scale workers endpoint, starts or stops workers of a task type without a restart
+async fn scale_workers(
+    State(ctx): State<Arc<AppContext>>,
+    Json(req): Json<ScaleWorkersRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !ctx.task_manager.has_processor(&req.task_type) {
+        return Err(ApiError::Validation(format!("No processor registered for task type {}", req.task_type)));
+    }
+
+    let workers = ctx.scheduler.scale_workers(req.task_type.clone(), req.count).await;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(ScaleWorkersResponse { task_type: req.task_type, workers }))
+    ))
+}
+
+// Cancel tasks by label endpoint
+async fn cancel_tasks_by_label(
+    State(ctx): State<Arc<AppContext>>,
+    Json(req): Json<CancelByLabelRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let cancelled = ctx.task_manager.cancel_tasks_by_label(&req.label).await
+        .context("Failed to cancel tasks by label")?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(CancelResponse { cancelled }))
+    ))
+}
+
+// Cancel tasks by api key endpoint
+async fn cancel_tasks_by_key(
+    State(ctx): State<Arc<AppContext>>,
+    Json(req): Json<CancelByKeyRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let cancelled = ctx.task_manager.cancel_tasks_by_api_key(&req.key_id).await
+        .context("Failed to cancel tasks by api key")?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(CancelResponse { cancelled }))
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct CompareQuery {
+    a: String,
+    b: String,
+}
+
+// Compare the transcription results of two tasks, task `a` is the reference.
+// Debug endpoint for measuring the effect of preprocessing or model parameter changes
+async fn compare_task_results(
+    State(ctx): State<Arc<AppContext>>,
+    Query(query): Query<CompareQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut results = Vec::with_capacity(2);
+    for task_id in [&query.a, &query.b] {
+        let task = ctx.task_manager.get_task(task_id).await
+            .context("Failed to get task")?
+            .ok_or_else(|| ApiError::NotFound(format!("Task {} not found", task_id)))?;
+        let result = task.result
+            .and_then(TaskResult::into_transcribe)
+            .ok_or_else(|| ApiError::Conflict(format!("Task {} has no transcription result", task_id)))?;
+        results.push(result);
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(compare(&results[0], &results[1])))
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct FailedCallbacksQuery {
+    index: Option<u64>,
+    size: Option<u64>,
+}
+
+// List callbacks that were not delivered after all retries, newest first
+async fn list_failed_callbacks(
+    State(ctx): State<Arc<AppContext>>,
+    Query(query): Query<FailedCallbacksQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let default = Pagination::default();
+    let pagination = Pagination {
+        index: query.index.unwrap_or(default.index),
+        size: query.size.unwrap_or(default.size),
+    };
+    let callbacks = ctx.task_manager.list_failed_callbacks(&pagination).await
+        .context("Failed to list failed callbacks")?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(callbacks))
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::test_support::{self, StubProcessor};
+    use crate::storage::task::sqlite::SqliteTaskStorage;
+
+    async fn task_manager() -> Arc<TaskManager> {
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+        Arc::new(TaskManager::new(Arc::new(storage)))
+    }
+
+    fn api_key(name: &str, permission: Permission) -> ApiKeyInfo {
+        let key = format!("key-{}", Uuid::new_v4());
+        ApiKeyInfo {
+            id: crate::auth::key_id(&key),
+            key,
+            name: name.to_string(),
+            permissions: vec![permission],
+            ..ApiKeyInfo::default()
+        }
+    }
+
+    // a key that may act on every task, for calling the handlers directly
+    fn admin_key() -> Extension<ApiKeyInfo> {
+        Extension(api_key("admin", Permission::Admin))
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_missing_well_formed_task_is_gone() {
+        let task_manager = task_manager().await;
+        let task_id = format!("task-{}", Uuid::new_v4());
+
+        let response = get_task(State(task_manager.clone()), admin_key(), Path(task_id.clone()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::GONE);
+
+        let response = get_task_status(State(task_manager), admin_key(), Path(task_id))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn test_task_stats_ignores_pagination() {
+        use crate::auth::{Permission, RateLimit};
+
+        let auth = Arc::new(Auth::new_with_memory_storage());
+        let key = auth.create_api_key(
+            "stats".to_string(),
+            vec![Permission::Transcribe],
+            RateLimit { requests_per_minute: 100, requests_per_hour: 1000, requests_per_day: 10000 },
+            None,
+        ).await.unwrap().key;
+        let router = Router::new().nest("/schedule", schedule_router(task_manager().await, auth));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        // a pagination left over from older clients is ignored
+        for query in ["?index=1&size=10", ""] {
+            let response = client
+                .get(format!("http://{}/schedule/tasks/stats{}", addr, query))
+                .bearer_auth(&key)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", query);
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(body["success"], true);
+            assert_eq!(body["data"]["pending"], 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_task_stats_are_scoped_to_the_key() {
+        use crate::storage::task::{entity::Model as TaskModel, TaskStorage};
+
+        let task_manager = task_manager().await;
+        let owner = api_key("owner", Permission::Transcribe);
+        for api_key in [Some(owner.id.clone()), Some("kid-other".to_string()), None] {
+            let mut task = transcribe_task();
+            task.config.api_key = api_key;
+            task_manager.storage.create(&TaskModel::from(task)).await.unwrap();
+        }
+
+        for (key_info, pending) in [(Extension(owner), 1), (admin_key(), 3)] {
+            let response = get_task_stats(State(task_manager.clone()), key_info)
+                .await
+                .into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["data"]["pending"], pending);
+        }
+    }
+
+    // a transcription with word timings, named after its original audio file
+    fn transcribe_task() -> Task {
+        use crate::schedule::types::TranscribeParams;
+
+        test_support::task(TaskConfig {
+            input_path: format!("./asr_data/audio/Weekly sync-{}.mp3", Uuid::new_v4()).into(),
+            params: TaskParams::Transcribe(TranscribeParams {
+                token_timestamps: true,
+                ..Default::default()
+            }),
+            ..test_support::task_config()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_event_streams_are_scoped_to_the_key() {
+        use crate::auth::{Permission, RateLimit};
+        use crate::storage::task::{entity::Model as TaskModel, TaskStorage};
+
+        let auth = Arc::new(Auth::new_with_memory_storage());
+        let rate_limit = RateLimit { requests_per_minute: 100, requests_per_hour: 1000, requests_per_day: 10000 };
+        let owner = auth.create_api_key("owner".to_string(), vec![Permission::Transcribe], rate_limit.clone(), None).await.unwrap();
+        let other = auth.create_api_key("other".to_string(), vec![Permission::Transcribe], rate_limit, None).await.unwrap();
+
+        let storage = Arc::new(SqliteTaskStorage::new("sqlite::memory:").await.unwrap());
+        let task_manager = Arc::new(TaskManager::new(storage.clone()));
+        let mut task = transcribe_task();
+        task.config.api_key = Some(owner.id.clone());
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+
+        let router = Router::new().nest("/schedule", schedule_router(task_manager, auth));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let upgrade = |path: String, key: &str| client
+            .get(format!("http://{}/schedule{}", addr, path))
+            .bearer_auth(key)
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_VERSION, "13")
+            .header(header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+            .send();
+
+        // every task's events need an admin key
+        let response = upgrade("/tasks/events".to_string(), &owner.key).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        // another key's task looks like one that was cleaned up
+        let response = upgrade(format!("/tasks/{}/stream", task.id), &other.key).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+        let response = upgrade(format!("/tasks/events?task_id={}", task.id), &other.key).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+        // the owner may follow it
+        let response = upgrade(format!("/tasks/{}/stream", task.id), &owner.key).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+    }
+
+    #[tokio::test]
+    async fn test_subtitle_downloads_are_attachments() {
+        use crate::schedule::types::{TaskStatus, TranscribeResult, TranscribeSegment};
+        use crate::storage::task::{entity::Model as TaskModel, TaskStorage};
+
+        let storage = Arc::new(SqliteTaskStorage::new("sqlite::memory:").await.unwrap());
+        let task_manager = Arc::new(TaskManager::new(storage.clone()));
+
+        let mut task = transcribe_task();
+        task.status = TaskStatus::Completed;
+        task.result = Some(TaskResult::Transcribe(TranscribeResult {
+            text: "hello".to_string(),
+            segments: vec![TranscribeSegment {
+                text: "hello".to_string(),
+                speaker_id: None,
+                start_time: 0.0,
+                end_time: 100.0,
+                words: Vec::new(),
+                confidence: None,
+            }],
+            detected_language: "en".to_string(),
+            language: "en".to_string(),
+            language_probability: 1.0,
+            ..Default::default()
+        }));
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+
+        for (format, content_type, filename) in [
+            (SubtitleFormat::Srt, "application/x-subrip", "Weekly sync.srt"),
+            (SubtitleFormat::Vtt, "text/vtt", "Weekly sync.vtt"),
+            (SubtitleFormat::VttWords, "text/vtt", "Weekly sync.vtt"),
+        ] {
+            let response = get_task_subtitles(
+                State(task_manager.clone()),
+                admin_key(),
+                Path(task.id.clone()),
+                Query(SubtitlesQuery { format }),
+            ).await.into_response();
+            assert_eq!(response.status(), StatusCode::OK, "{:?}", format);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+            assert_eq!(
+                response.headers()[header::CONTENT_DISPOSITION],
+                format!("attachment; filename=\"{}\"", filename).as_str(),
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert!(String::from_utf8_lossy(&body).contains("hello"));
+        }
+
+        // inputs saved without their original name fall back to the task id
+        task.config.input_path = format!("./asr_data/audio/{}.wav", Uuid::new_v4()).into();
+        assert_eq!(download_base_name(&task), task.id);
+        assert_eq!(
+            content_disposition("会议纪要.srt"),
+            "attachment; filename=\"____.srt\"; filename*=UTF-8''%E4%BC%9A%E8%AE%AE%E7%BA%AA%E8%A6%81.srt",
+        );
+    }
+
+    // the most tasks that ran at the same time, judged by their start and completion
+    fn max_overlap(tasks: &[Task]) -> usize {
+        tasks.iter()
+            .filter_map(|task| task.started_at)
+            .map(|start| tasks.iter()
+                .filter(|task| task.started_at.zip(task.completed_at)
+                    .is_some_and(|(started, completed)| started <= start && start < completed))
+                .count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    // `count` new tasks, once all of them completed
+    async fn process_batch(task_manager: &TaskManager, count: usize) -> Vec<Task> {
+        use crate::schedule::types::TaskStatus;
+
+        let mut ids = Vec::new();
+        for _ in 0..count {
+            ids.push(task_manager.create_task(transcribe_task().config).await.unwrap().id);
+        }
+
+        let mut tasks = Vec::new();
+        for _ in 0..200 {
+            tasks.clear();
+            for id in &ids {
+                tasks.push(task_manager.get_task(id).await.unwrap().unwrap());
+            }
+            if tasks.iter().all(|task| task.status == TaskStatus::Completed) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        assert!(tasks.iter().all(|task| task.status == TaskStatus::Completed));
+        tasks
+    }
+
+    #[tokio::test]
+    async fn test_scaling_workers_up_increases_throughput() {
+        use crate::schedule::TaskScheduler;
+
+        let storage = SqliteTaskStorage::new("sqlite::memory:").await.unwrap();
+        let mut task_manager = TaskManager::new(Arc::new(storage));
+        task_manager.register_processor(Box::new(StubProcessor::new(TaskType::Transcribe)
+            .with_delay(std::time::Duration::from_millis(300))));
+        let task_manager = Arc::new(task_manager);
+        let scheduler = Arc::new(TaskScheduler::new(task_manager.clone()).with_max_workers(4).with_poll_jitter(0.0));
+        let ctx = Arc::new(AppContext {
+            auth: Arc::new(Auth::new_with_memory_storage()),
+            task_manager: task_manager.clone(),
+            scheduler: scheduler.clone(),
+            voiceprints: Arc::new(crate::storage::voiceprint::InMemoryVoiceprintStorage::new()),
+        });
+        scheduler.spawn_worker(TaskType::Transcribe).await;
+        let runner = scheduler.clone();
+        let run = tokio::spawn(async move { runner.run().await });
+
+        // one worker takes the tasks one after another
+        let one_worker = process_batch(&task_manager, 6).await;
+        assert_eq!(max_overlap(&one_worker), 1);
+
+        let response = scale_workers(
+            State(ctx.clone()),
+            Json(ScaleWorkersRequest { task_type: TaskType::Transcribe, count: 3 }),
+        ).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["workers"], 3);
+
+        // three workers run tasks side by side, but never more than three at once
+        let three_workers = process_batch(&task_manager, 6).await;
+        let overlap = max_overlap(&three_workers);
+        assert!((2..=3).contains(&overlap), "{} tasks ran at once", overlap);
+
+        // scaling down stops the extra workers
+        let response = scale_workers(
+            State(ctx),
+            Json(ScaleWorkersRequest { task_type: TaskType::Transcribe, count: 1 }),
+        ).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(scheduler.worker_count(&TaskType::Transcribe).await, 1);
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), scheduler.shutdown()).await.unwrap().unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), run).await.unwrap().unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_updating_priority_of_missing_task_is_not_found() {
+        let task_manager = task_manager().await;
+        let request = UpdatePriorityRequest { priority: TaskPriority::High };
+        let response = update_task_priority(State(task_manager), admin_key(), Path("task-missing".to_string()), Json(request))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_task_id_is_not_found() {
+        let task_manager = task_manager().await;
+
+        for task_id in ["not-a-task", "task-123", "task-", &Uuid::new_v4().to_string()] {
+            let response = get_task(State(task_manager.clone()), admin_key(), Path(task_id.to_string()))
+                .await
+                .into_response();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", task_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_other_keys_cannot_cancel_a_task() {
+        use crate::storage::task::{entity::Model as TaskModel, TaskStorage};
+
+        let owner = api_key("owner", Permission::Transcribe);
+        let other = api_key("other", Permission::Transcribe);
+        let storage = Arc::new(SqliteTaskStorage::new("sqlite::memory:").await.unwrap());
+        let task_manager = Arc::new(TaskManager::new(storage.clone()));
+        let mut task = transcribe_task();
+        task.config.api_key = Some(owner.id.clone());
+        storage.create(&TaskModel::from(task.clone())).await.unwrap();
+
+        let response = cancel_task(State(task_manager.clone()), Extension(other), Path(task.id.clone()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(task_manager.get_task_status(&task.id).await.unwrap(), Some(TaskStatus::Pending));
+
+        let response = cancel_task(State(task_manager.clone()), Extension(owner), Path(task.id.clone()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(task_manager.get_task_status(&task.id).await.unwrap(), Some(TaskStatus::Cancelled));
+    }
+}
@@ -0,0 +1,42 @@
+use axum::{
+    routing::get,
+    Router,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use crate::web::ApiResponse;
+
+pub fn version_router() -> Router {
+    Router::new()
+        .route("/version", get(version))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VersionInfo {
+    pub git_hash: String,
+    pub version: String,
+    pub build_time: String,
+}
+
+// Get build version endpoint
+pub async fn version() -> Json<ApiResponse<VersionInfo>> {
+    Json(ApiResponse::success(VersionInfo {
+        git_hash: env!("GIT_HASH").trim().to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        build_time: env!("BUILD_TIME").to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_version() {
+        let Json(response) = version().await;
+        assert!(response.success);
+        let info = response.data.unwrap();
+        assert_eq!(info.git_hash, env!("GIT_HASH").trim());
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    }
+}
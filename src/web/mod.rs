@@ -3,19 +3,33 @@ use std::sync::Arc;
 use tokio::net::TcpListener;
 use tracing::info;
 
+mod error;
 pub mod handlers;
 mod pagination;
+pub mod request_id;
+mod response;
 
+pub use error::ApiError;
 pub use pagination::Pagination;
+pub use request_id::RequestId;
+pub use response::ApiResponse;
 
 use crate::AppContext;
 
 pub async fn start_server(ctx: Arc<AppContext>, addr: SocketAddr) -> anyhow::Result<()> {
+    start_server_with_shutdown(ctx, addr, std::future::pending()).await
+}
+
+/// 启动服务器，`signal` 完成后不再接收新连接，等待进行中的请求结束后返回
+pub async fn start_server_with_shutdown<F>(ctx: Arc<AppContext>, addr: SocketAddr, signal: F) -> anyhow::Result<()>
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
     let app = handlers::router(ctx);
 
     info!("Starting server on {}", addr);
     let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app).with_graceful_shutdown(signal).await?;
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,75 @@
+use std::convert::Infallible;
+use axum::{
+    async_trait,
+    body::Body,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderName, Request},
+    Router,
+};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use tracing::{info_span, Span};
+
+/// 关联一次请求的所有日志的请求头
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// 为每个请求分配 `X-Request-Id`，请求已带有时沿用，并在响应中原样返回。
+/// 请求的日志都记录在带有该 id 的 span 中
+pub fn with_request_id(router: Router) -> Router {
+    // 后添加的层先执行：先设置 id，再进入 span，最后把 id 复制到响应
+    router
+        .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER))
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(SetRequestIdLayer::new(REQUEST_ID_HEADER, MakeRequestUuid))
+}
+
+fn request_span(request: &Request<Body>) -> Span {
+    let request_id = header_value(request.headers()).unwrap_or_default();
+    info_span!("request", method = %request.method(), uri = %request.uri(), request_id = %request_id)
+}
+
+fn header_value(headers: &axum::http::HeaderMap) -> Option<String> {
+    headers.get(REQUEST_ID_HEADER)?.to_str().ok().map(str::to_string)
+}
+
+/// 当前请求的 id，不经过 `with_request_id` 且请求未带该头时为 None
+#[derive(Debug, Clone)]
+pub struct RequestId(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequestId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(RequestId(header_value(&parts.headers)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    #[tokio::test]
+    async fn test_request_id_is_generated_and_echoed() {
+        let router = with_request_id(Router::new().route("/id", get(|RequestId(id): RequestId| async move {
+            id.unwrap_or_default()
+        })));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/id", addr);
+
+        // an incoming id is kept
+        let response = client.get(&url).header("X-Request-Id", "req-42").send().await.unwrap();
+        assert_eq!(response.headers()["x-request-id"], "req-42");
+        assert_eq!(response.text().await.unwrap(), "req-42");
+
+        // otherwise one is generated, the handler sees the same id as the client
+        let response = client.get(&url).send().await.unwrap();
+        let generated = response.headers()["x-request-id"].to_str().unwrap().to_string();
+        assert!(uuid::Uuid::parse_str(&generated).is_ok(), "{}", generated);
+        assert_eq!(response.text().await.unwrap(), generated);
+    }
+}
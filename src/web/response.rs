@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+// envelope of every json response: `data` is set on success, `error` otherwise.
+// the http status code carries the kind of failure
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiResponse<T> {
+    pub success: bool,
+    pub data: Option<T>,
+    pub error: Option<String>,
+}
+
+impl<T: Serialize> ApiResponse<T> {
+    pub fn success(data: T) -> Self {
+        Self {
+            success: true,
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    pub fn error(error: String) -> Self {
+        Self {
+            success: false,
+            data: None,
+            error: Some(error),
+        }
+    }
+}